@@ -1,28 +1,170 @@
 use serde::{Deserialize, Serialize};
 
+// Development only - production mode refuses to start with these
+pub const DEFAULT_AGENT_JWT_SECRET: &str =
+    ".AAuhSb@n7&aCW5{_Il3B&SQZKz$[_1cuES+P<n2kUD)-b0um?41Hg^|gN<&1|)O1#}EW,Y^ce5X3WV;,0xTLf";
+pub const DEFAULT_SERVER_JWT_SECRET: &str =
+    ",yTAs+WEZfbsfWLzGNFt-Nj<GQX7:sC.;W5/_gE=fGfubL/oLW^lN#X1YcwM?Ry&-a:U7{USG(Ez-zU{:vCmn^";
+
+// Secrets shorter than this are rejected in production mode (HS512 wants at least 64 bytes)
+pub const MIN_JWT_SECRET_LENGTH: usize = 64;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ApiConfiguration {
     pub port: u16,
+    // Addresses the TLS listeners bind to - an IP uses `port`, `ip:port` picks its own. `::` is dual-stack
+    pub bind_addresses: Vec<String>,
+    // Addresses served without TLS e.g. a loopback admin port, parsed like `bind_addresses`
+    pub plaintext_bind_addresses: Vec<String>,
+    // Allow plaintext listeners on non-loopback addresses, tokens and logins then cross the network in clear text
+    pub allow_insecure_plaintext: bool,
+    // Generate a self-signed server certificate on first start if there is none
+    pub tls_self_signed: bool,
+    // How often the certificate files are checked for changes, 0 disables hot reloading
+    pub tls_reload_interval_secs: u64,
+    // Take the client IP from `X-Forwarded-For` / `Forwarded`, but only when the request came from a trusted proxy
     pub behind_proxy: bool,
+    // CIDRs (or single IPs) of the proxies whose forwarded headers are believed
+    pub trusted_proxies: Vec<String>,
+    // IPs or CIDRs agents may connect from (any when empty) and those they never may, applied to every tenant
+    pub agent_allowed_ips: Vec<String>,
+    pub agent_denied_ips: Vec<String>,
+    // IPs or CIDRs the admin API - everything but the agent socket and server info - may be used from, and never from
+    pub admin_allowed_ips: Vec<String>,
+    pub admin_denied_ips: Vec<String>,
+    // Refuse to start with built-in or weak secrets
+    pub production_mode: bool,
     pub request_timeout_secs: u64,
     pub agent_ping_interval: u64,
     pub agent_ping_timeout: u64,
+    pub agent_jwt_lifetime_secs: u64,
+    pub agent_revocation_check_interval: u64,
+    // How long agents get to finish a command unless the request sets its own timeout, 0 for no deadline
+    pub agent_command_timeout_secs: u64,
+    // The longest timeout a command request may set, longer ones are refused. 0 lets requests set any
+    // timeout, including none at all
+    pub agent_command_max_timeout_secs: u64,
+    // Failed agent connections allowed per claimed agent id within the failure window before it is banned,
+    // 0 only tracks them per client IP. Anyone can claim an id, so keep this high enough that a stranger can't lock an agent out
+    pub agent_connect_max_failures: u32,
+    // Failed agent connections allowed per client IP within the failure window before it is banned, 0 disables.
+    // It only starts backing off past half of them, many agents can share one address
+    pub agent_connect_max_failures_per_ip: u32,
+    pub agent_connect_failure_window_secs: u64,
+    // The first ban, each further ban of the same agent id or client IP doubles it, up to a day
+    pub agent_connect_ban_secs: u64,
+    // Bans of the same agent id or client IP after which each further ban raises an alert
+    pub agent_connect_alert_bans: u32,
     pub agent_jwt_secret: String,
     pub server_jwt_secret: String,
+    // PEM private key (Ed25519 or RSA) used to sign tokens, falls back to the HS512 secrets when unset
+    pub jwt_signing_key_file: Option<String>,
+    // PEM public keys still accepted when verifying tokens e.g. the previous signing key during rotation
+    pub jwt_verification_key_files: Vec<String>,
+    // PEM Ed25519 private key commands sent to agents are signed with, generated as .certs/command-key.pem when unset
+    pub command_signing_key_file: Option<String>,
+    pub mtls_mode: MtlsMode,
+    // PEM CA bundle agent certificates are verified against, defaults to .certs/ca.pem in the home folder
+    pub mtls_ca_file: Option<String>,
+    // Lifetime of the client certificates the built-in CA issues to enrolling agents
+    pub agent_certificate_lifetime_days: u64,
+    // Lifetime of the session tokens issued to users at login, signed with `server_jwt_secret`
+    pub session_jwt_lifetime_secs: u64,
+    // Lifetime of the single use refresh tokens a new session token is obtained with
+    pub refresh_token_lifetime_secs: u64,
+    // The user created on first start when there are none, along with the tenant it belongs to
+    pub bootstrap_admin_username: String,
+    // Generated and written to the home folder when unset
+    pub bootstrap_admin_password: Option<String>,
+    // The provider's own tenant, the only one that can manage other tenants
+    pub bootstrap_tenant: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtlsMode {
+    /// Client certificates are not requested
+    Disabled,
+    /// Agents presenting a certificate are identified by it, others fall back to their token
+    Optional,
+    /// Agents must present a certificate issued by the configured CA
+    Required,
 }
 
 impl ApiConfiguration {
     pub fn default() -> Self {
         ApiConfiguration {
             port: 8000,
+            bind_addresses: vec!["0.0.0.0".to_string()],
+            plaintext_bind_addresses: vec![],
+            allow_insecure_plaintext: false,
+            tls_self_signed: false,
+            tls_reload_interval_secs: 30,
             behind_proxy: false,
+            trusted_proxies: vec!["127.0.0.1/32".to_string(), "::1/128".to_string()],
+            agent_allowed_ips: vec![],
+            agent_denied_ips: vec![],
+            admin_allowed_ips: vec![],
+            admin_denied_ips: vec![],
+            production_mode: false,
             request_timeout_secs: 30,
             agent_ping_interval: 10,
             agent_ping_timeout: 5,
-            agent_jwt_secret: ".AAuhSb@n7&aCW5{_Il3B&SQZKz$[_1cuES+P<n2kUD)-b0um?41Hg^|gN<&1|)O1#}EW,Y^ce5X3WV;,0xTLf".to_string(),
-            server_jwt_secret: ",yTAs+WEZfbsfWLzGNFt-Nj<GQX7:sC.;W5/_gE=fGfubL/oLW^lN#X1YcwM?Ry&-a:U7{USG(Ez-zU{:vCmn^".to_string()
+            agent_jwt_lifetime_secs: 86400,
+            agent_revocation_check_interval: 30,
+            agent_command_timeout_secs: 3600,
+            agent_command_max_timeout_secs: 7 * 86400,
+            agent_connect_max_failures: 10,
+            agent_connect_max_failures_per_ip: 20,
+            agent_connect_failure_window_secs: 600,
+            agent_connect_ban_secs: 300,
+            agent_connect_alert_bans: 3,
+            agent_jwt_secret: DEFAULT_AGENT_JWT_SECRET.to_string(),
+            server_jwt_secret: DEFAULT_SERVER_JWT_SECRET.to_string(),
+            jwt_signing_key_file: None,
+            jwt_verification_key_files: vec![],
+            command_signing_key_file: None,
+            mtls_mode: MtlsMode::Disabled,
+            mtls_ca_file: None,
+            agent_certificate_lifetime_days: 90,
+            session_jwt_lifetime_secs: 900,
+            refresh_token_lifetime_secs: 2_592_000,
+            bootstrap_admin_username: "admin".to_string(),
+            bootstrap_admin_password: None,
+            bootstrap_tenant: "default".to_string(),
         }
     }
+
+    /// Describe every secret that is still a built-in default or too short to be safe
+    pub fn insecure_secrets(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        // The agent secret is unused once tokens are signed with a key pair
+        let mut secrets = vec![(
+            "API_SERVER_JWT_SECRET",
+            &self.server_jwt_secret,
+            DEFAULT_SERVER_JWT_SECRET,
+        )];
+        if self.jwt_signing_key_file.is_none() {
+            secrets.push((
+                "API_AGENT_JWT_SECRET",
+                &self.agent_jwt_secret,
+                DEFAULT_AGENT_JWT_SECRET,
+            ));
+        }
+
+        for (name, secret, default) in secrets {
+            if secret == default {
+                problems.push(format!("{} is using the built-in default", name));
+            } else if secret.len() < MIN_JWT_SECRET_LENGTH {
+                problems.push(format!(
+                    "{} is shorter than {} characters",
+                    name, MIN_JWT_SECRET_LENGTH
+                ));
+            }
+        }
+
+        problems
+    }
 }
 
 pub trait LoadApiConfiguration {
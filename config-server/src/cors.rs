@@ -8,6 +8,9 @@ pub struct CorsConfiguration {
     pub max_age_seconds: u64,
     pub allowed_headers: Vec<String>,
     pub allowed_methods: Vec<String>,
+    // How often multi-tenant mode reloads the tenant origins from the database, picking up
+    // tenants changed outside this server. 0 only reloads them when changed through its API.
+    pub tenant_origins_refresh_secs: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -49,6 +52,7 @@ impl Default for CorsConfiguration {
                 "DELETE".to_string(),
                 "OPTIONS".to_string(),
             ],
+            tenant_origins_refresh_secs: 60,
         }
     }
 }
@@ -5,10 +5,13 @@ pub mod rate_limiting;
 
 pub use crate::api::ApiConfiguration;
 pub use crate::api::LoadApiConfiguration;
+pub use crate::api::MtlsMode;
 pub use crate::cors::CorsConfiguration;
 pub use crate::cors::CorsMode;
 pub use crate::cors::LoadCorsConfiguration;
 pub use crate::logging::LoadLoggingConfiguration;
 pub use crate::logging::LoggingConfiguration;
 pub use crate::rate_limiting::LoadRateLimitingConfiguration;
+pub use crate::rate_limiting::RateLimitKey;
 pub use crate::rate_limiting::RateLimitingConfiguration;
+pub use crate::rate_limiting::RouteRateLimit;
@@ -3,8 +3,38 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RateLimitingConfiguration {
     pub burst_size: u32,
+    // Seconds between each request replenished, not requests per second
     pub per_second: u64,
     pub cleanup_duration: u64,
+    // What the limit above is counted per
+    pub key: RateLimitKey,
+    // Every request per client IP, counted before any credentials are looked up so bogus ones
+    // can't reach the database unthrottled
+    pub client_ip_burst_size: u32,
+    pub client_ip_per_second: u64,
+    // Stricter limits for routes handing out credentials - login, session refresh,
+    // agent tokens and API keys
+    pub token_issuance: RouteRateLimit,
+    // Commands sent to agents, groups of agents or broadcast
+    pub agent_commands: RouteRateLimit,
+}
+
+/// A limit applied to a group of routes on top of the overall limit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RouteRateLimit {
+    pub burst_size: u32,
+    pub per_second: u64,
+    pub key: RateLimitKey,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// Every client IP address gets its own limit
+    Ip,
+    /// Authenticated requests share a limit with the rest of their tenant
+    Tenant,
+    /// Authenticated requests get a limit per user or API key
+    Credential,
 }
 
 impl RateLimitingConfiguration {
@@ -13,6 +43,19 @@ impl RateLimitingConfiguration {
             burst_size: 200,
             per_second: 5,
             cleanup_duration: 60,
+            key: RateLimitKey::Credential,
+            client_ip_burst_size: 400,
+            client_ip_per_second: 1,
+            token_issuance: RouteRateLimit {
+                burst_size: 10,
+                per_second: 6,
+                key: RateLimitKey::Credential,
+            },
+            agent_commands: RouteRateLimit {
+                burst_size: 50,
+                per_second: 1,
+                key: RateLimitKey::Tenant,
+            },
         }
     }
 }
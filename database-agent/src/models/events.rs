@@ -0,0 +1,33 @@
+use crate::schema::events;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
+
+/// An event waiting to be picked up by the watchers, it starts out pending
+#[derive(Insertable, Debug)]
+#[diesel(table_name = events)]
+pub struct NewEvent {
+    pub event_type: String,
+    pub aggregate_type: String,
+    pub aggregate_id: String,
+    // JSON
+    pub payload: String,
+    // JSON
+    pub metadata: Option<String>,
+}
+
+/// Record an event for the watchers to process
+pub fn record_event(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    event: &NewEvent,
+) -> Result<(), Error> {
+    match diesel::insert_into(events::table)
+        .values(event)
+        .execute(connection)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
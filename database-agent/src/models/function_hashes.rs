@@ -1,4 +1,9 @@
-use diesel::prelude::*;
+use crate::schema::function_hashes;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Queryable, Selectable, Serialize, Debug)]
@@ -20,3 +25,17 @@ pub struct NewFunctionHash {
     pub description: Option<String>,
     pub source: String,
 }
+
+define_sql_function!(fn lower(value: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+/// Whether a hash is on the allowlist, hex hashes are compared regardless of case
+pub fn is_function_hash_allowed(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    hash: &str,
+) -> Result<bool, Error> {
+    let count: i64 = function_hashes::table
+        .filter(lower(function_hashes::function_hash).eq(hash.trim().to_lowercase()))
+        .count()
+        .get_result(connection)?;
+    Ok(count > 0)
+}
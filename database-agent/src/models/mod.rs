@@ -1,4 +1,5 @@
 pub mod connection_strings;
+pub mod events;
 pub mod function_hashes;
 pub mod properties;
 pub mod tags;
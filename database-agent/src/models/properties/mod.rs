@@ -5,4 +5,7 @@ mod types;
 pub use types::{NewProperty, Property, PropertyValue, TypedProperty};
 
 // Re-export repository functions
-pub use repository::{get_properties, get_property, get_property_count, get_property_value_or};
+pub use repository::{
+    delete_property, get_properties, get_property, get_property_count, get_property_value_or,
+    set_property_value,
+};
@@ -68,6 +68,47 @@ pub fn get_properties(
     }
 }
 
+/// Insert a property or replace the value of an existing one
+pub fn set_property_value(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    key: &str,
+    value: PropertyValue,
+    description: Option<String>,
+) -> Result<TypedProperty, Error> {
+    let new_property = value.to_new_property(key.to_string(), description);
+
+    match diesel::insert_into(properties::table)
+        .values(&new_property)
+        .on_conflict(properties::key)
+        .do_update()
+        .set((
+            properties::type_.eq(&new_property.type_),
+            properties::value_int.eq(new_property.value_int),
+            properties::value_string.eq(&new_property.value_string),
+            properties::value_bool.eq(new_property.value_bool),
+            properties::value_json.eq(&new_property.value_json),
+        ))
+        .returning(Property::as_returning())
+        .get_result(connection)
+    {
+        Ok(property) => property
+            .to_typed()
+            .ok_or_else(|| anyhow!("Invalid property value for {}", key)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Delete a property, returning true if it existed
+pub fn delete_property(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    key: &str,
+) -> Result<bool, Error> {
+    match diesel::delete(properties::table.filter(properties::key.eq(key))).execute(connection) {
+        Ok(deleted) => Ok(deleted > 0),
+        Err(e) => Err(e.into()),
+    }
+}
+
 /// Get a property value or return a default value
 ///
 /// # Examples
@@ -0,0 +1,8 @@
+CREATE TABLE agent_revocations (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    agent_id VARCHAR NOT NULL UNIQUE,
+    reason VARCHAR,
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_agent_revocations_agent_id ON agent_revocations(agent_id);
@@ -0,0 +1,12 @@
+CREATE TABLE agent_certificates (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    agent_id VARCHAR NOT NULL,
+    serial VARCHAR NOT NULL UNIQUE,
+    not_before BIGINT NOT NULL,
+    not_after BIGINT NOT NULL,
+    revoked_at BIGINT,
+    revocation_reason VARCHAR,
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_agent_certificates_agent_id ON agent_certificates(agent_id);
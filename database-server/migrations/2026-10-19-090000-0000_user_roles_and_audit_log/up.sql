@@ -0,0 +1,16 @@
+-- Users created before roles existed could do everything, the first of them becomes the owner
+ALTER TABLE users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'read_only';
+UPDATE users SET role = 'admin';
+UPDATE users SET role = 'owner' WHERE id = (SELECT MIN(id) FROM users);
+
+CREATE TABLE audit_log (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    tenant VARCHAR NOT NULL,
+    principal VARCHAR NOT NULL,
+    action VARCHAR NOT NULL,
+    resource VARCHAR,
+    granted BOOLEAN NOT NULL,
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_audit_log_tenant ON audit_log(tenant);
@@ -0,0 +1,17 @@
+DROP INDEX idx_agent_certificates_tenant;
+ALTER TABLE agent_certificates DROP COLUMN tenant;
+
+CREATE TABLE agent_revocations_global (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    agent_id VARCHAR NOT NULL UNIQUE,
+    reason VARCHAR,
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT OR IGNORE INTO agent_revocations_global (agent_id, reason, created_at)
+    SELECT agent_id, reason, created_at FROM agent_revocations;
+
+DROP TABLE agent_revocations;
+ALTER TABLE agent_revocations_global RENAME TO agent_revocations;
+
+CREATE UNIQUE INDEX idx_agent_revocations_agent_id ON agent_revocations(agent_id);
@@ -0,0 +1,22 @@
+-- Revocations and certificates recorded before the stores were partitioned by tenant
+-- keep applying to every tenant, which the '*' tenant stands for
+CREATE TABLE agent_revocations_by_tenant (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    tenant VARCHAR NOT NULL,
+    agent_id VARCHAR NOT NULL,
+    reason VARCHAR,
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (tenant, agent_id)
+);
+
+INSERT INTO agent_revocations_by_tenant (id, tenant, agent_id, reason, created_at)
+    SELECT id, '*', agent_id, reason, created_at FROM agent_revocations;
+
+DROP TABLE agent_revocations;
+ALTER TABLE agent_revocations_by_tenant RENAME TO agent_revocations;
+
+CREATE INDEX idx_agent_revocations_agent_id ON agent_revocations(agent_id);
+
+ALTER TABLE agent_certificates ADD COLUMN tenant VARCHAR NOT NULL DEFAULT '*';
+
+CREATE INDEX idx_agent_certificates_tenant ON agent_certificates(tenant);
@@ -0,0 +1,21 @@
+CREATE TABLE tenants (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    -- Identifies the tenant everywhere else, also its subdomain in multi-tenant mode
+    slug VARCHAR NOT NULL UNIQUE,
+    name VARCHAR NOT NULL,
+    -- Comma separated, allowed by CORS alongside the tenant's own subdomain
+    allowed_origins VARCHAR NOT NULL DEFAULT '',
+    -- How many agents may be connected at once, NULL for no limit
+    agent_seat_limit INTEGER,
+    -- active, suspended or deleting
+    status VARCHAR NOT NULL DEFAULT 'active',
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Every tenant already in use becomes an active tenant named after itself
+INSERT INTO tenants (slug, name)
+    SELECT tenant, tenant FROM users
+    UNION SELECT tenant, tenant FROM api_keys
+    UNION SELECT tenant, tenant FROM agent_certificates WHERE tenant <> '*'
+    UNION SELECT tenant, tenant FROM agent_revocations WHERE tenant <> '*';
@@ -0,0 +1,2 @@
+-- The client the permission check was made for, as seen through any trusted proxies
+ALTER TABLE audit_log ADD COLUMN client_ip VARCHAR;
@@ -0,0 +1,4 @@
+ALTER TABLE tenants DROP COLUMN admin_denied_ips;
+ALTER TABLE tenants DROP COLUMN admin_allowed_ips;
+ALTER TABLE tenants DROP COLUMN agent_denied_ips;
+ALTER TABLE tenants DROP COLUMN agent_allowed_ips;
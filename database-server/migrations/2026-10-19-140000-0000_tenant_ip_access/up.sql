@@ -0,0 +1,6 @@
+-- Comma separated IPs or CIDRs, checked on top of the server wide lists. An empty allow
+-- list allows every address that isn't denied.
+ALTER TABLE tenants ADD COLUMN agent_allowed_ips VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE tenants ADD COLUMN agent_denied_ips VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE tenants ADD COLUMN admin_allowed_ips VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE tenants ADD COLUMN admin_denied_ips VARCHAR NOT NULL DEFAULT '';
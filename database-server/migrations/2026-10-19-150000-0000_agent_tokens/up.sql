@@ -0,0 +1,14 @@
+-- Tokens not bound to an agent that agents have connected with. They can connect under any
+-- agent id, so revoking an agent revokes the tokens it used along with its id.
+CREATE TABLE agent_tokens (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    tenant VARCHAR NOT NULL,
+    agent_id VARCHAR NOT NULL,
+    jti VARCHAR NOT NULL,
+    expires_at BIGINT NOT NULL,
+    revoked_at BIGINT,
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (tenant, agent_id, jti)
+);
+
+CREATE INDEX idx_agent_tokens_jti ON agent_tokens(jti);
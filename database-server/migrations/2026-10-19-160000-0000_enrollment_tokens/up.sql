@@ -0,0 +1,9 @@
+-- Enrollment tokens are good for one certificate, each is kept here once used until it expires
+CREATE TABLE used_enrollment_tokens (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    tenant VARCHAR NOT NULL,
+    agent_id VARCHAR NOT NULL,
+    jti VARCHAR NOT NULL UNIQUE,
+    expires_at BIGINT NOT NULL,
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,18 @@
+DROP INDEX idx_users_username;
+
+CREATE TABLE users_global (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR NOT NULL UNIQUE,
+    password_hash VARCHAR NOT NULL,
+    tenant VARCHAR NOT NULL,
+    disabled BOOLEAN NOT NULL DEFAULT 0,
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    role VARCHAR NOT NULL DEFAULT 'read_only'
+);
+
+-- Where tenants share a username only the first user keeps it
+INSERT OR IGNORE INTO users_global (id, username, password_hash, tenant, disabled, created_at, role)
+    SELECT id, username, password_hash, tenant, disabled, created_at, role FROM users ORDER BY id;
+
+DROP TABLE users;
+ALTER TABLE users_global RENAME TO users;
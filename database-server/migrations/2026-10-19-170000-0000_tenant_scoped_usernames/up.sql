@@ -0,0 +1,19 @@
+-- Usernames only need to be unique within their tenant, so one tenant can't learn another's
+CREATE TABLE users_by_tenant (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR NOT NULL,
+    password_hash VARCHAR NOT NULL,
+    tenant VARCHAR NOT NULL,
+    disabled BOOLEAN NOT NULL DEFAULT 0,
+    created_at timestamp_with_timezone_text NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    role VARCHAR NOT NULL DEFAULT 'read_only',
+    UNIQUE (tenant, username)
+);
+
+INSERT INTO users_by_tenant (id, username, password_hash, tenant, disabled, created_at, role)
+    SELECT id, username, password_hash, tenant, disabled, created_at, role FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_by_tenant RENAME TO users;
+
+CREATE INDEX idx_users_username ON users(username);
@@ -0,0 +1,146 @@
+use crate::schema::agent_certificates;
+use crate::ALL_TENANTS;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
+use serde::Serialize;
+
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = agent_certificates)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AgentCertificate {
+    pub id: i32,
+    pub agent_id: String,
+    // Lowercase hex, no separators
+    pub serial: String,
+    // Validity and revocation times are seconds since the unix epoch
+    pub not_before: i64,
+    pub not_after: i64,
+    pub revoked_at: Option<i64>,
+    pub revocation_reason: Option<String>,
+    pub created_at: String,
+    pub tenant: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = agent_certificates)]
+pub struct NewAgentCertificate {
+    pub tenant: String,
+    pub agent_id: String,
+    pub serial: String,
+    pub not_before: i64,
+    pub not_after: i64,
+}
+
+/// Record a certificate issued by the built-in CA
+pub fn record_agent_certificate(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    new_certificate: NewAgentCertificate,
+) -> Result<AgentCertificate, Error> {
+    match diesel::insert_into(agent_certificates::table)
+        .values(&new_certificate)
+        .returning(AgentCertificate::as_returning())
+        .get_result(connection)
+    {
+        Ok(certificate) => Ok(certificate),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get every certificate issued to an agent of a tenant, newest first
+pub fn get_agent_certificates(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+    agent_id: &str,
+) -> Result<Vec<AgentCertificate>, Error> {
+    match agent_certificates::table
+        .filter(agent_certificates::agent_id.eq(agent_id))
+        .filter(agent_certificates::tenant.eq_any([tenant, ALL_TENANTS]))
+        .order(agent_certificates::id.desc())
+        .select(AgentCertificate::as_select())
+        .load(connection)
+    {
+        Ok(certificates) => Ok(certificates),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Revoke every unrevoked certificate issued to an agent of a tenant, returning how many were revoked
+pub fn revoke_agent_certificates(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+    agent_id: &str,
+    revoked_at: i64,
+    reason: Option<String>,
+) -> Result<usize, Error> {
+    match diesel::update(
+        agent_certificates::table
+            .filter(agent_certificates::agent_id.eq(agent_id))
+            .filter(agent_certificates::tenant.eq_any([tenant, ALL_TENANTS]))
+            .filter(agent_certificates::revoked_at.is_null()),
+    )
+    .set((
+        agent_certificates::revoked_at.eq(revoked_at),
+        agent_certificates::revocation_reason.eq(reason),
+    ))
+    .execute(connection)
+    {
+        Ok(revoked) => Ok(revoked),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Check whether an agent of a tenant holds a certificate that is neither revoked nor expired
+pub fn has_active_certificate(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+    agent_id: &str,
+    now: i64,
+) -> Result<bool, Error> {
+    match agent_certificates::table
+        .filter(agent_certificates::agent_id.eq(agent_id))
+        .filter(agent_certificates::tenant.eq_any([tenant, ALL_TENANTS]))
+        .filter(agent_certificates::revoked_at.is_null())
+        .filter(agent_certificates::not_after.gt(now))
+        .count()
+        .get_result::<i64>(connection)
+    {
+        Ok(count) => Ok(count > 0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get the tenant a certificate was issued to, if the built-in CA issued it
+pub fn get_certificate_tenant(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    serial: &str,
+) -> Result<Option<String>, Error> {
+    match agent_certificates::table
+        .filter(agent_certificates::serial.eq(serial))
+        .select(agent_certificates::tenant)
+        .first::<String>(connection)
+        .optional()
+    {
+        Ok(tenant) => Ok(tenant),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get the revoked certificates that have not yet expired i.e. the entries the CRL must carry
+pub fn get_revoked_certificates(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    now: i64,
+) -> Result<Vec<AgentCertificate>, Error> {
+    match agent_certificates::table
+        .filter(agent_certificates::revoked_at.is_not_null())
+        .filter(agent_certificates::not_after.gt(now))
+        .order(agent_certificates::revoked_at.asc())
+        .select(AgentCertificate::as_select())
+        .load(connection)
+    {
+        Ok(certificates) => Ok(certificates),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,101 @@
+use crate::schema::agent_revocations;
+use crate::ALL_TENANTS;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = agent_revocations)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AgentRevocation {
+    pub id: i32,
+    pub tenant: String,
+    pub agent_id: String,
+    pub reason: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = agent_revocations)]
+pub struct NewAgentRevocation {
+    pub tenant: String,
+    pub agent_id: String,
+    pub reason: Option<String>,
+}
+
+/// Add an agent to the revocation list, returning the stored revocation.
+/// Revoking an agent that is already revoked just updates the reason.
+pub fn revoke_agent(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    new_revocation: NewAgentRevocation,
+) -> Result<AgentRevocation, Error> {
+    match diesel::insert_into(agent_revocations::table)
+        .values(&new_revocation)
+        .on_conflict((agent_revocations::tenant, agent_revocations::agent_id))
+        .do_update()
+        .set(agent_revocations::reason.eq(&new_revocation.reason))
+        .returning(AgentRevocation::as_returning())
+        .get_result(connection)
+    {
+        Ok(revocation) => Ok(revocation),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove an agent from a tenant's revocation list, returning true if it was revoked.
+/// This also lifts a revocation that applied to every tenant.
+pub fn unrevoke_agent(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+    agent_id: &str,
+) -> Result<bool, Error> {
+    match diesel::delete(
+        agent_revocations::table
+            .filter(agent_revocations::agent_id.eq(agent_id))
+            .filter(agent_revocations::tenant.eq_any([tenant, ALL_TENANTS])),
+    )
+    .execute(connection)
+    {
+        Ok(deleted) => Ok(deleted > 0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get the revocation list that applies to a tenant
+pub fn get_agent_revocations(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+) -> Result<Vec<AgentRevocation>, Error> {
+    match agent_revocations::table
+        .filter(agent_revocations::tenant.eq_any([tenant, ALL_TENANTS]))
+        .order(agent_revocations::created_at.desc())
+        .select(AgentRevocation::as_select())
+        .load(connection)
+    {
+        Ok(revocations) => Ok(revocations),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Check whether an agent is on its tenant's revocation list
+///
+/// Callers decide what a lookup failure means - letting an agent in must fail closed,
+/// while dropping agents that are already connected shouldn't happen over a busy database.
+pub fn is_agent_revoked(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+    agent_id: &str,
+) -> Result<bool, Error> {
+    match agent_revocations::table
+        .filter(agent_revocations::agent_id.eq(agent_id))
+        .filter(agent_revocations::tenant.eq_any([tenant, ALL_TENANTS]))
+        .count()
+        .get_result::<i64>(connection)
+    {
+        Ok(count) => Ok(count > 0),
+        Err(e) => Err(e.into()),
+    }
+}
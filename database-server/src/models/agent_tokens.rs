@@ -0,0 +1,107 @@
+use crate::schema::agent_tokens;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
+
+#[derive(Insertable)]
+#[diesel(table_name = agent_tokens)]
+pub struct NewAgentToken {
+    pub tenant: String,
+    pub agent_id: String,
+    // The token's `jti` claim
+    pub jti: String,
+    // Seconds since the unix epoch
+    pub expires_at: i64,
+}
+
+/// Remember that an agent connected with a token, recording it again changes nothing
+pub fn record_agent_token(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    new_token: NewAgentToken,
+) -> Result<(), Error> {
+    match diesel::insert_into(agent_tokens::table)
+        .values(&new_token)
+        .on_conflict((
+            agent_tokens::tenant,
+            agent_tokens::agent_id,
+            agent_tokens::jti,
+        ))
+        .do_nothing()
+        .execute(connection)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Revoke every token an agent of a tenant has connected with, returning how many were revoked
+pub fn revoke_agent_tokens(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+    agent_id: &str,
+    revoked_at: i64,
+) -> Result<usize, Error> {
+    match diesel::update(
+        agent_tokens::table
+            .filter(agent_tokens::tenant.eq(tenant))
+            .filter(agent_tokens::agent_id.eq(agent_id))
+            .filter(agent_tokens::revoked_at.is_null()),
+    )
+    .set(agent_tokens::revoked_at.eq(revoked_at))
+    .execute(connection)
+    {
+        Ok(revoked) => Ok(revoked),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Lift the revocation of the tokens an agent of a tenant has connected with. A token that
+/// another revoked agent also used stays revoked.
+pub fn unrevoke_agent_tokens(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+    agent_id: &str,
+) -> Result<usize, Error> {
+    match diesel::update(
+        agent_tokens::table
+            .filter(agent_tokens::tenant.eq(tenant))
+            .filter(agent_tokens::agent_id.eq(agent_id)),
+    )
+    .set(agent_tokens::revoked_at.eq(None::<i64>))
+    .execute(connection)
+    {
+        Ok(lifted) => Ok(lifted),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Check whether a token was used by any agent that has since been revoked
+pub fn is_agent_token_revoked(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    jti: &str,
+) -> Result<bool, Error> {
+    match agent_tokens::table
+        .filter(agent_tokens::jti.eq(jti))
+        .filter(agent_tokens::revoked_at.is_not_null())
+        .count()
+        .get_result::<i64>(connection)
+    {
+        Ok(count) => Ok(count > 0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove tokens that expired before `now`, they can never be used again
+pub fn delete_expired_agent_tokens(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    now: i64,
+) -> Result<usize, Error> {
+    match diesel::delete(agent_tokens::table.filter(agent_tokens::expires_at.le(now)))
+        .execute(connection)
+    {
+        Ok(deleted) => Ok(deleted),
+        Err(e) => Err(e.into()),
+    }
+}
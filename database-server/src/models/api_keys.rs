@@ -0,0 +1,120 @@
+use crate::schema::api_keys;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
+use serde::Serialize;
+
+/// A key other systems call the API with. Only a hash of the key is stored.
+#[derive(Queryable, Selectable, Serialize, Debug, Clone)]
+#[diesel(table_name = api_keys)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ApiKey {
+    pub id: i32,
+    pub tenant: String,
+    pub name: String,
+    // The start of the key, enough to tell keys apart
+    pub prefix: String,
+    // Lowercase hex SHA-256 of the key, never sent to clients
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    // Comma separated permissions
+    pub scopes: String,
+    pub created_by: String,
+    // Seconds since the unix epoch
+    pub expires_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub created_at: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey {
+    pub tenant: String,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: String,
+    pub created_by: String,
+    pub expires_at: Option<i64>,
+}
+
+/// Record a newly created API key
+pub fn create_api_key(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    new_key: NewApiKey,
+) -> Result<ApiKey, Error> {
+    match diesel::insert_into(api_keys::table)
+        .values(&new_key)
+        .returning(ApiKey::as_returning())
+        .get_result(connection)
+    {
+        Ok(key) => Ok(key),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get every API key of a tenant, including revoked and expired ones
+pub fn get_tenant_api_keys(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+) -> Result<Vec<ApiKey>, Error> {
+    match api_keys::table
+        .filter(api_keys::tenant.eq(tenant))
+        .order(api_keys::id.desc())
+        .select(ApiKey::as_select())
+        .load(connection)
+    {
+        Ok(keys) => Ok(keys),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Find the unrevoked, unexpired key with this hash, recording that it was used
+pub fn use_api_key(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    key_hash: &str,
+    now: i64,
+) -> Result<Option<ApiKey>, Error> {
+    match diesel::update(
+        api_keys::table
+            .filter(api_keys::key_hash.eq(key_hash))
+            .filter(api_keys::revoked_at.is_null())
+            .filter(
+                api_keys::expires_at
+                    .is_null()
+                    .or(api_keys::expires_at.gt(now)),
+            ),
+    )
+    .set(api_keys::last_used_at.eq(now))
+    .returning(ApiKey::as_returning())
+    .get_result(connection)
+    .optional()
+    {
+        Ok(key) => Ok(key),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Revoke a tenant's API key, returning false if there is no such unrevoked key
+pub fn revoke_api_key(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+    key_id: i32,
+    now: i64,
+) -> Result<bool, Error> {
+    match diesel::update(
+        api_keys::table
+            .filter(api_keys::id.eq(key_id))
+            .filter(api_keys::tenant.eq(tenant))
+            .filter(api_keys::revoked_at.is_null()),
+    )
+    .set(api_keys::revoked_at.eq(now))
+    .execute(connection)
+    {
+        Ok(revoked) => Ok(revoked > 0),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,69 @@
+use crate::schema::audit_log;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
+use serde::Serialize;
+
+/// A record of a permission check, whether it was granted or denied
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = audit_log)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AuditEntry {
+    pub id: i32,
+    pub tenant: String,
+    // Who asked e.g. a username
+    pub principal: String,
+    // The permission that was checked
+    pub action: String,
+    // What it was checked against e.g. an agent id, if anything
+    pub resource: Option<String>,
+    pub granted: bool,
+    pub created_at: String,
+    // Where the request came from, if known
+    pub client_ip: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = audit_log)]
+pub struct NewAuditEntry {
+    pub tenant: String,
+    pub principal: String,
+    pub action: String,
+    pub resource: Option<String>,
+    pub granted: bool,
+    pub client_ip: Option<String>,
+}
+
+/// Append an entry to the audit log
+pub fn record_audit_entry(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    new_entry: NewAuditEntry,
+) -> Result<(), Error> {
+    match diesel::insert_into(audit_log::table)
+        .values(&new_entry)
+        .execute(connection)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get the most recent audit entries of a tenant, newest first
+pub fn get_tenant_audit_log(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+    limit: i64,
+) -> Result<Vec<AuditEntry>, Error> {
+    match audit_log::table
+        .filter(audit_log::tenant.eq(tenant))
+        .order(audit_log::id.desc())
+        .limit(limit)
+        .select(AuditEntry::as_select())
+        .load(connection)
+    {
+        Ok(entries) => Ok(entries),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,49 @@
+use crate::schema::used_enrollment_tokens;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
+
+#[derive(Insertable)]
+#[diesel(table_name = used_enrollment_tokens)]
+pub struct UsedEnrollmentToken {
+    pub tenant: String,
+    pub agent_id: String,
+    // The token's `jti` claim
+    pub jti: String,
+    // Seconds since the unix epoch
+    pub expires_at: i64,
+}
+
+/// Use up an enrollment token, returning false if it has been used before. Checking and
+/// recording in one statement stops two enrollments racing.
+pub fn use_enrollment_token(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    token: UsedEnrollmentToken,
+) -> Result<bool, Error> {
+    match diesel::insert_into(used_enrollment_tokens::table)
+        .values(&token)
+        .on_conflict(used_enrollment_tokens::jti)
+        .do_nothing()
+        .execute(connection)
+    {
+        Ok(inserted) => Ok(inserted > 0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Forget tokens that expired before `now`, they can't be used again anyway
+pub fn delete_expired_enrollment_tokens(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    now: i64,
+) -> Result<usize, Error> {
+    match diesel::delete(
+        used_enrollment_tokens::table.filter(used_enrollment_tokens::expires_at.le(now)),
+    )
+    .execute(connection)
+    {
+        Ok(deleted) => Ok(deleted),
+        Err(e) => Err(e.into()),
+    }
+}
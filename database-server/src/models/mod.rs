@@ -0,0 +1,9 @@
+pub mod agent_certificates;
+pub mod agent_revocations;
+pub mod agent_tokens;
+pub mod api_keys;
+pub mod audit_log;
+pub mod enrollment_tokens;
+pub mod refresh_tokens;
+pub mod tenants;
+pub mod users;
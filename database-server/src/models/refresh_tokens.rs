@@ -0,0 +1,118 @@
+use crate::schema::refresh_tokens;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
+
+/// A refresh token issued at login. Only a hash of the token is stored.
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = refresh_tokens)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    // Lowercase hex SHA-256 of the token
+    pub token_hash: String,
+    // Seconds since the unix epoch
+    pub expires_at: i64,
+    pub revoked_at: Option<i64>,
+    pub created_at: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct NewRefreshToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: i64,
+}
+
+/// Record a newly issued refresh token
+pub fn create_refresh_token(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    new_token: NewRefreshToken,
+) -> Result<RefreshToken, Error> {
+    match diesel::insert_into(refresh_tokens::table)
+        .values(&new_token)
+        .returning(RefreshToken::as_returning())
+        .get_result(connection)
+    {
+        Ok(token) => Ok(token),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Find a refresh token that is neither revoked nor expired, without using it up
+pub fn get_refresh_token(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    token_hash: &str,
+    now: i64,
+) -> Result<Option<RefreshToken>, Error> {
+    match refresh_tokens::table
+        .filter(refresh_tokens::token_hash.eq(token_hash))
+        .filter(refresh_tokens::revoked_at.is_null())
+        .filter(refresh_tokens::expires_at.gt(now))
+        .select(RefreshToken::as_select())
+        .first(connection)
+        .optional()
+    {
+        Ok(token) => Ok(token),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Revoke a refresh token so it can only ever be used once, returning it if it was
+/// still valid. Checking and revoking in one statement stops two refreshes racing.
+pub fn use_refresh_token(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    token_hash: &str,
+    now: i64,
+) -> Result<Option<RefreshToken>, Error> {
+    match diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(token_hash))
+            .filter(refresh_tokens::revoked_at.is_null())
+            .filter(refresh_tokens::expires_at.gt(now)),
+    )
+    .set(refresh_tokens::revoked_at.eq(now))
+    .returning(RefreshToken::as_returning())
+    .get_result(connection)
+    .optional()
+    {
+        Ok(token) => Ok(token),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Revoke every outstanding refresh token of a user, returning how many were revoked
+pub fn revoke_user_refresh_tokens(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    user_id: i32,
+    now: i64,
+) -> Result<usize, Error> {
+    match diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::user_id.eq(user_id))
+            .filter(refresh_tokens::revoked_at.is_null()),
+    )
+    .set(refresh_tokens::revoked_at.eq(now))
+    .execute(connection)
+    {
+        Ok(revoked) => Ok(revoked),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove tokens that expired before `now`, they can never be used again
+pub fn delete_expired_refresh_tokens(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    now: i64,
+) -> Result<usize, Error> {
+    match diesel::delete(refresh_tokens::table.filter(refresh_tokens::expires_at.le(now)))
+        .execute(connection)
+    {
+        Ok(deleted) => Ok(deleted),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,231 @@
+use crate::schema::{
+    agent_certificates, agent_revocations, agent_tokens, api_keys, refresh_tokens, tenants,
+    used_enrollment_tokens, users,
+};
+use anyhow::Error;
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+    sql_types::Text,
+};
+use serde::Serialize;
+
+#[derive(Queryable, Selectable, Serialize, Debug, Clone)]
+#[diesel(table_name = tenants)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Tenant {
+    pub id: i32,
+    // What users, keys, agents and everything else are stored against
+    pub slug: String,
+    pub name: String,
+    // Comma separated
+    pub allowed_origins: String,
+    // No limit when absent
+    pub agent_seat_limit: Option<i32>,
+    // active, suspended, deleting or deleted
+    pub status: String,
+    pub created_at: String,
+    pub updated_at: String,
+    // Comma separated IPs or CIDRs agents may connect from, any when empty
+    pub agent_allowed_ips: String,
+    pub agent_denied_ips: String,
+    // Comma separated IPs or CIDRs the tenant's admin API may be used from, any when empty
+    pub admin_allowed_ips: String,
+    pub admin_denied_ips: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = tenants)]
+pub struct NewTenant {
+    pub slug: String,
+    pub name: String,
+    pub allowed_origins: String,
+    pub agent_seat_limit: Option<i32>,
+    pub agent_allowed_ips: String,
+    pub agent_denied_ips: String,
+    pub admin_allowed_ips: String,
+    pub admin_denied_ips: String,
+}
+
+/// Everything about a tenant that can be changed after it was created
+#[derive(AsChangeset)]
+#[diesel(table_name = tenants)]
+#[diesel(treat_none_as_null = true)]
+pub struct TenantChanges {
+    pub name: String,
+    pub allowed_origins: String,
+    pub agent_seat_limit: Option<i32>,
+    pub status: String,
+    pub agent_allowed_ips: String,
+    pub agent_denied_ips: String,
+    pub admin_allowed_ips: String,
+    pub admin_denied_ips: String,
+}
+
+/// What purging a tenant removed
+#[derive(Debug, Default)]
+pub struct TenantPurge {
+    pub users: usize,
+    pub api_keys: usize,
+    pub revocations: usize,
+    pub agent_tokens: usize,
+    pub revoked_certificates: usize,
+}
+
+/// Add a tenant, returning the stored tenant
+pub fn create_tenant(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    new_tenant: NewTenant,
+) -> Result<Tenant, Error> {
+    match diesel::insert_into(tenants::table)
+        .values(&new_tenant)
+        .returning(Tenant::as_returning())
+        .get_result(connection)
+    {
+        Ok(tenant) => Ok(tenant),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Find a tenant by id
+pub fn get_tenant(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant_id: i32,
+) -> Result<Option<Tenant>, Error> {
+    match tenants::table
+        .find(tenant_id)
+        .select(Tenant::as_select())
+        .first(connection)
+        .optional()
+    {
+        Ok(tenant) => Ok(tenant),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Find a tenant by its slug
+pub fn get_tenant_by_slug(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    slug: &str,
+) -> Result<Option<Tenant>, Error> {
+    match tenants::table
+        .filter(tenants::slug.eq(slug))
+        .select(Tenant::as_select())
+        .first(connection)
+        .optional()
+    {
+        Ok(tenant) => Ok(tenant),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get every tenant, including those being deleted
+pub fn get_tenants(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+) -> Result<Vec<Tenant>, Error> {
+    match tenants::table
+        .order(tenants::slug.asc())
+        .select(Tenant::as_select())
+        .load(connection)
+    {
+        Ok(tenants) => Ok(tenants),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get the tenants with a status e.g. those waiting to be purged
+pub fn get_tenants_with_status(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    status: &str,
+) -> Result<Vec<Tenant>, Error> {
+    match tenants::table
+        .filter(tenants::status.eq(status))
+        .select(Tenant::as_select())
+        .load(connection)
+    {
+        Ok(tenants) => Ok(tenants),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Update a tenant, returning it as stored
+pub fn update_tenant(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant_id: i32,
+    changes: TenantChanges,
+) -> Result<Tenant, Error> {
+    match diesel::update(tenants::table.find(tenant_id))
+        .set((
+            &changes,
+            tenants::updated_at.eq(sql::<Text>("CURRENT_TIMESTAMP")),
+        ))
+        .returning(Tenant::as_returning())
+        .get_result(connection)
+    {
+        Ok(tenant) => Ok(tenant),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove everything stored for a tenant, leaving the tenant itself behind as a `deleted` tombstone
+///
+/// The tombstone keeps the slug from being reused, agent tokens carry it as their audience and
+/// would otherwise be accepted by a new tenant of the same name. Certificates issued to the
+/// tenant's agents are revoked rather than removed, so the CRL keeps carrying them until they
+/// expire, and the audit log is kept as the record of what was done to the tenant.
+pub fn purge_tenant(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    slug: &str,
+    now: i64,
+) -> Result<TenantPurge, Error> {
+    let purge = connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        let tenant_users = users::table
+            .filter(users::tenant.eq(slug))
+            .select(users::id);
+
+        diesel::delete(refresh_tokens::table.filter(refresh_tokens::user_id.eq_any(tenant_users)))
+            .execute(connection)?;
+
+        let purge = TenantPurge {
+            users: diesel::delete(users::table.filter(users::tenant.eq(slug)))
+                .execute(connection)?,
+            api_keys: diesel::delete(api_keys::table.filter(api_keys::tenant.eq(slug)))
+                .execute(connection)?,
+            revocations: diesel::delete(
+                agent_revocations::table.filter(agent_revocations::tenant.eq(slug)),
+            )
+            .execute(connection)?,
+            agent_tokens: diesel::delete(agent_tokens::table.filter(agent_tokens::tenant.eq(slug)))
+                .execute(connection)?
+                + diesel::delete(
+                    used_enrollment_tokens::table.filter(used_enrollment_tokens::tenant.eq(slug)),
+                )
+                .execute(connection)?,
+            revoked_certificates: diesel::update(
+                agent_certificates::table
+                    .filter(agent_certificates::tenant.eq(slug))
+                    .filter(agent_certificates::revoked_at.is_null()),
+            )
+            .set((
+                agent_certificates::revoked_at.eq(now),
+                agent_certificates::revocation_reason.eq("tenant deleted"),
+            ))
+            .execute(connection)?,
+        };
+
+        diesel::update(tenants::table.filter(tenants::slug.eq(slug)))
+            .set((
+                tenants::status.eq("deleted"),
+                tenants::updated_at.eq(sql::<Text>("CURRENT_TIMESTAMP")),
+            ))
+            .execute(connection)?;
+
+        Ok(purge)
+    });
+
+    match purge {
+        Ok(purge) => Ok(purge),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,141 @@
+use crate::schema::users;
+use anyhow::Error;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+};
+use serde::Serialize;
+
+#[derive(Queryable, Selectable, Serialize, Debug, Clone)]
+#[diesel(table_name = users)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    // Argon2 PHC string, never sent to clients
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub tenant: String,
+    pub disabled: bool,
+    pub created_at: String,
+    // owner, admin, operator or read_only
+    pub role: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+pub struct NewUser {
+    pub username: String,
+    pub password_hash: String,
+    pub tenant: String,
+    pub role: String,
+}
+
+/// Add a user, returning the stored user
+pub fn create_user(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    new_user: NewUser,
+) -> Result<User, Error> {
+    match diesel::insert_into(users::table)
+        .values(&new_user)
+        .returning(User::as_returning())
+        .get_result(connection)
+    {
+        Ok(user) => Ok(user),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Find a user by id
+pub fn get_user(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    user_id: i32,
+) -> Result<Option<User>, Error> {
+    match users::table
+        .find(user_id)
+        .select(User::as_select())
+        .first(connection)
+        .optional()
+    {
+        Ok(user) => Ok(user),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Find a user by the name they log in with. Usernames are only unique within a tenant, so
+/// without one a name that more than one tenant uses finds nobody.
+pub fn get_user_by_username(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: Option<&str>,
+    username: &str,
+) -> Result<Option<User>, Error> {
+    let mut query = users::table
+        .filter(users::username.eq(username))
+        .into_boxed();
+    if let Some(tenant) = tenant {
+        query = query.filter(users::tenant.eq(tenant));
+    }
+
+    match query.select(User::as_select()).limit(2).load(connection) {
+        Ok(mut users) if users.len() == 1 => Ok(users.pop()),
+        Ok(_) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get every user belonging to a tenant
+pub fn get_tenant_users(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    tenant: &str,
+) -> Result<Vec<User>, Error> {
+    match users::table
+        .filter(users::tenant.eq(tenant))
+        .order(users::username.asc())
+        .select(User::as_select())
+        .load(connection)
+    {
+        Ok(users) => Ok(users),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Count every user, across all tenants
+pub fn count_users(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+) -> Result<i64, Error> {
+    match users::table.count().get_result(connection) {
+        Ok(count) => Ok(count),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replace a user's password hash
+pub fn set_user_password(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    user_id: i32,
+    password_hash: &str,
+) -> Result<(), Error> {
+    match diesel::update(users::table.find(user_id))
+        .set(users::password_hash.eq(password_hash))
+        .execute(connection)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Change a user's role
+pub fn set_user_role(
+    connection: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    user_id: i32,
+    role: &str,
+) -> Result<User, Error> {
+    match diesel::update(users::table.find(user_id))
+        .set(users::role.eq(role))
+        .returning(User::as_returning())
+        .get_result(connection)
+    {
+        Ok(user) => Ok(user),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,129 @@
+diesel::table! {
+    agent_revocations (id) {
+        id -> Integer,
+        tenant -> Text,
+        agent_id -> Text,
+        reason -> Nullable<Text>,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    agent_certificates (id) {
+        id -> Integer,
+        agent_id -> Text,
+        serial -> Text,
+        not_before -> BigInt,
+        not_after -> BigInt,
+        revoked_at -> Nullable<BigInt>,
+        revocation_reason -> Nullable<Text>,
+        created_at -> Text,
+        tenant -> Text,
+    }
+}
+
+diesel::table! {
+    agent_tokens (id) {
+        id -> Integer,
+        tenant -> Text,
+        agent_id -> Text,
+        jti -> Text,
+        expires_at -> BigInt,
+        revoked_at -> Nullable<BigInt>,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    tenants (id) {
+        id -> Integer,
+        slug -> Text,
+        name -> Text,
+        allowed_origins -> Text,
+        agent_seat_limit -> Nullable<Integer>,
+        status -> Text,
+        created_at -> Text,
+        updated_at -> Text,
+        agent_allowed_ips -> Text,
+        agent_denied_ips -> Text,
+        admin_allowed_ips -> Text,
+        admin_denied_ips -> Text,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Integer,
+        username -> Text,
+        password_hash -> Text,
+        tenant -> Text,
+        disabled -> Bool,
+        created_at -> Text,
+        role -> Text,
+    }
+}
+
+diesel::table! {
+    api_keys (id) {
+        id -> Integer,
+        tenant -> Text,
+        name -> Text,
+        prefix -> Text,
+        key_hash -> Text,
+        scopes -> Text,
+        created_by -> Text,
+        expires_at -> Nullable<BigInt>,
+        revoked_at -> Nullable<BigInt>,
+        last_used_at -> Nullable<BigInt>,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Integer,
+        tenant -> Text,
+        principal -> Text,
+        action -> Text,
+        resource -> Nullable<Text>,
+        granted -> Bool,
+        created_at -> Text,
+        client_ip -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        token_hash -> Text,
+        expires_at -> BigInt,
+        revoked_at -> Nullable<BigInt>,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    used_enrollment_tokens (id) {
+        id -> Integer,
+        tenant -> Text,
+        agent_id -> Text,
+        jti -> Text,
+        expires_at -> BigInt,
+        created_at -> Text,
+    }
+}
+
+diesel::joinable!(refresh_tokens -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    agent_certificates,
+    agent_revocations,
+    agent_tokens,
+    api_keys,
+    audit_log,
+    refresh_tokens,
+    tenants,
+    used_enrollment_tokens,
+    users,
+);
@@ -1,22 +1,29 @@
-use axum::Router;
+use axum::{middleware, Router};
 use database_agent::{models::properties::PropertyValue, SqlitePool};
 use ractor::{Actor, ActorProcessingErr, ActorRef};
+use runtime_shared::RuntimeProperties;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
 use tracing::{info, instrument};
 
 use crate::{
     actors::api::{
+        auth::{require_api_token, ApiTokens},
         messages::ApiMessage,
         routes::api_router,
         state::{ApiActorState, ApiState},
     },
-    ACTOR_AGENT_API_NAME, DEFAULT_PROPERTY_API_PORT, PROPERTY_API_PORT,
+    actors::connection_manager::confirmation::PendingConfirmations,
+    ACTOR_AGENT_API_NAME, API_READ_ONLY_TOKENS_FILE, API_TOKENS_FILE, API_TOKENS_FOLDER,
+    DEFAULT_PROPERTY_API_PORT, DEFAULT_PROPERTY_API_SOCKET_MODE, PROPERTY_API_PORT,
+    PROPERTY_API_SOCKET_MODE, PROPERTY_API_SOCKET_PATH, PROPERTY_API_TOKEN_GROUP,
 };
-use runtime_shared::api_server::APIServer;
+use runtime_shared::api_server::{APIServer, ListenAddress};
 
 #[derive(Debug)]
 pub struct ApiStartupArguments {
     pub db_pool: SqlitePool,
+    pub(crate) pending_confirmations: Arc<PendingConfirmations>,
 }
 
 #[derive(Debug)]
@@ -24,7 +31,16 @@ pub struct ApiActor {}
 
 impl ApiActor {
     fn router(state: ApiState) -> Router {
-        Router::new().merge(api_router()).with_state(state.into())
+        let state = Arc::new(state);
+
+        // Every route requires one of the local API tokens
+        Router::new()
+            .merge(api_router())
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                require_api_token,
+            ))
+            .with_state(state)
     }
 }
 
@@ -41,8 +57,24 @@ impl Actor for ApiActor {
     ) -> Result<Self::State, ActorProcessingErr> {
         let mut state = ApiActorState::new();
 
+        // Load the bearer tokens local tools authenticate with, creating them on first start
+        let token_group = PropertyValue::get_string_or(
+            args.db_pool.get()?,
+            PROPERTY_API_TOKEN_GROUP,
+            String::new(),
+        );
+        let tokens_folder = RuntimeProperties::global()
+            .folders()
+            .home()
+            .join(API_TOKENS_FOLDER);
+        let api_tokens = ApiTokens::load_or_create(
+            &tokens_folder.join(API_TOKENS_FILE),
+            &tokens_folder.join(API_READ_ONLY_TOKENS_FILE),
+            &token_group,
+        )?;
+
         //Initialise the shared Axum State
-        let api_state = ApiState::new(args.db_pool);
+        let api_state = ApiState::new(args.db_pool, api_tokens, args.pending_confirmations);
 
         // Create the API Router
         let app = Self::router(api_state.clone());
@@ -60,8 +92,32 @@ impl Actor for ApiActor {
             api_port.try_into().unwrap(),
         );
 
+        // Listen on a unix socket instead of the port when one has been configured
+        let socket_path = PropertyValue::get_string_or(
+            api_state.db_pool.get()?,
+            PROPERTY_API_SOCKET_PATH,
+            String::new(),
+        );
+        let address = match socket_path.is_empty() {
+            true => ListenAddress::from(socket),
+            #[cfg(unix)]
+            false => {
+                let socket_mode = PropertyValue::get_string_or(
+                    api_state.db_pool.get()?,
+                    PROPERTY_API_SOCKET_MODE,
+                    DEFAULT_PROPERTY_API_SOCKET_MODE.to_string(),
+                );
+                ListenAddress::Unix {
+                    path: socket_path.into(),
+                    mode: u32::from_str_radix(&socket_mode, 8)?,
+                }
+            }
+            #[cfg(not(unix))]
+            false => return Err("unix sockets are not supported on this platform".into()),
+        };
+
         // Start the API Server
-        match APIServer::new(socket, app).start().await {
+        match APIServer::new(address, app).start().await {
             Ok(server_shutdown_handle) => {
                 state.server_handle = Some(server_shutdown_handle);
 
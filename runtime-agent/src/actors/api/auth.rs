@@ -0,0 +1,337 @@
+use crate::actors::api::{routes::v1::responses::ApiResponse, state::ApiState};
+use anyhow::{anyhow, Error};
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+#[cfg(unix)]
+use runtime_shared::api_server::unix::UnixPeer;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tracing::{info, warn};
+
+// The admin tokens file is for its owner alone, the read-only one for the agent group too
+const OWNER_ONLY_MODE: u32 = 0o600;
+const GROUP_READABLE_MODE: u32 = 0o640;
+
+/// What a local API token is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TokenScope {
+    /// GET requests only
+    ReadOnly,
+    /// Any request, including those that change the agent configuration
+    Admin,
+}
+
+/// A bearer token accepted by the local API. Only the name is ever logged.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct ApiToken {
+    pub name: String,
+    pub scope: TokenScope,
+    pub token: String,
+}
+
+impl std::fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiToken")
+            .field("name", &self.name)
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+/// The caller a request was authenticated as, available to handlers as an extension
+#[derive(Debug, Clone)]
+pub(crate) struct ApiCaller {
+    pub name: String,
+    pub scope: TokenScope,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ApiTokens {
+    tokens: Vec<ApiToken>,
+}
+
+impl ApiTokens {
+    /// Load the tokens files, generating an admin and a read-only token the first time. The
+    /// admin tokens file is only readable by its owner, the read-only one also by `token_group`
+    /// (when set), so reading the read-only token never gives away the admin one.
+    pub fn load_or_create(
+        tokens_file: &Path,
+        read_only_tokens_file: &Path,
+        token_group: &str,
+    ) -> Result<Self, Error> {
+        create_tokens_file(tokens_file, "admin", TokenScope::Admin, OWNER_ONLY_MODE)?;
+        create_tokens_file(
+            read_only_tokens_file,
+            "read-only",
+            TokenScope::ReadOnly,
+            GROUP_READABLE_MODE,
+        )?;
+
+        // Re-apply the ownership every start, the group may have been changed since
+        restrict_tokens_file(tokens_file, "", OWNER_ONLY_MODE)?;
+        restrict_tokens_file(read_only_tokens_file, token_group, GROUP_READABLE_MODE)?;
+
+        let mut tokens = read_tokens_file(tokens_file)?;
+        let read_only_tokens = read_tokens_file(read_only_tokens_file)?;
+        if read_only_tokens
+            .iter()
+            .any(|token| token.scope != TokenScope::ReadOnly)
+        {
+            return Err(anyhow!(
+                "{} is readable by the token group, it may only hold read-only tokens",
+                read_only_tokens_file.display()
+            ));
+        }
+        tokens.extend(read_only_tokens);
+
+        Ok(Self { tokens })
+    }
+
+    /// Find the token presented, comparing in constant time
+    pub fn authenticate(&self, presented: &str) -> Option<ApiCaller> {
+        self.tokens
+            .iter()
+            .find(|token| bool::from(token.token.as_bytes().ct_eq(presented.as_bytes())))
+            .map(|token| ApiCaller {
+                name: token.name.clone(),
+                scope: token.scope,
+            })
+    }
+}
+
+/// Middleware requiring a bearer token (or a trusted unix socket peer) on every request.
+/// Read-only tokens may only read, and every mutating request is logged along with the caller.
+pub(crate) async fn require_api_token(
+    State(state): State<Arc<ApiState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let presented = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let caller = presented
+        .and_then(|token| state.api_tokens.authenticate(token.trim()))
+        .or_else(|| unix_peer_caller(&request));
+
+    let Some(caller) = caller else {
+        warn!(method = %request.method(), path = %request.uri().path(), "unauthenticated local API request");
+        return unauthorised(StatusCode::UNAUTHORIZED, "a valid bearer token is required");
+    };
+
+    let mutating = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+
+    if mutating {
+        if caller.scope != TokenScope::Admin {
+            warn!(token = %caller.name, method = %request.method(), path = %request.uri().path(), "read-only token attempted a change");
+            return unauthorised(StatusCode::FORBIDDEN, "this token is read-only");
+        }
+
+        info!(token = %caller.name, method = %request.method(), path = %request.uri().path(), "local API change requested");
+    }
+
+    request.extensions_mut().insert(caller);
+    next.run(request).await
+}
+
+// Over a unix socket the kernel tells us who is calling - root and the agent's own user
+// are trusted as admins without a token, anyone else the socket permissions let in needs one
+#[cfg(unix)]
+fn unix_peer_caller(request: &Request) -> Option<ApiCaller> {
+    use axum::extract::ConnectInfo;
+
+    let ConnectInfo(peer) = request.extensions().get::<ConnectInfo<UnixPeer>>()?;
+
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let agent_uid = unsafe { libc::geteuid() };
+    if peer.uid != 0 && peer.uid != agent_uid {
+        return None;
+    }
+
+    Some(ApiCaller {
+        name: format!("uid:{}", peer.uid),
+        scope: TokenScope::Admin,
+    })
+}
+
+#[cfg(not(unix))]
+fn unix_peer_caller(_request: &Request) -> Option<ApiCaller> {
+    None
+}
+
+fn unauthorised(status: StatusCode, message: &str) -> Response {
+    (status, Json(ApiResponse::<Value>::err(message))).into_response()
+}
+
+// A tokens file holding a single newly generated token, unless there is one already
+fn create_tokens_file(path: &Path, name: &str, scope: TokenScope, mode: u32) -> Result<(), Error> {
+    if path.exists() {
+        return Ok(());
+    }
+
+    let tokens = vec![ApiToken {
+        name: name.to_string(),
+        scope,
+        token: generate_token(),
+    }];
+
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    write_tokens_file(path, &serde_json::to_string_pretty(&tokens)?, mode)?;
+    info!(file = %path.display(), token = %name, "generated local API token");
+
+    Ok(())
+}
+
+fn read_tokens_file(path: &Path) -> Result<Vec<ApiToken>, Error> {
+    let tokens: Vec<ApiToken> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    if tokens.is_empty() {
+        return Err(anyhow!("{} contains no tokens", path.display()));
+    }
+
+    Ok(tokens)
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn write_tokens_file(path: &Path, contents: &str, mode: u32) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(path)?;
+        file.write_all(contents.as_bytes())?;
+    }
+
+    #[cfg(not(unix))]
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+// Owner (normally root) read/write, with the group mode the agent group read, nobody else
+#[cfg(unix)]
+fn restrict_tokens_file(path: &Path, token_group: &str, mode: u32) -> Result<(), Error> {
+    use std::ffi::CString;
+    use std::os::unix::fs::PermissionsExt;
+
+    if !token_group.is_empty() {
+        let group_name = CString::new(token_group)?;
+        // SAFETY: getgrnam is given a valid C string and the result is only read before returning
+        let group = unsafe { libc::getgrnam(group_name.as_ptr()) };
+        if group.is_null() {
+            return Err(anyhow!("unknown API token group {}", token_group));
+        }
+        let gid = unsafe { (*group).gr_gid };
+
+        std::os::unix::fs::chown(path, None, Some(gid))?;
+    }
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_tokens_file(_path: &Path, _token_group: &str, _mode: u32) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    fn test_folder(name: &str) -> PathBuf {
+        let folder =
+            std::env::temp_dir().join(format!("api-tokens-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    fn token(path: &Path) -> String {
+        read_tokens_file(path).unwrap().remove(0).token
+    }
+
+    #[test]
+    fn keeps_the_admin_token_from_the_token_group() {
+        let folder = test_folder("create");
+        let (admin, read_only) = (folder.join("admin.json"), folder.join("read_only.json"));
+
+        let tokens = ApiTokens::load_or_create(&admin, &read_only, "").unwrap();
+
+        assert_eq!(mode(&admin), 0o600);
+        assert_eq!(mode(&read_only), 0o640);
+        assert_eq!(
+            tokens
+                .authenticate(&token(&admin))
+                .map(|caller| caller.scope),
+            Some(TokenScope::Admin)
+        );
+        assert_eq!(
+            tokens
+                .authenticate(&token(&read_only))
+                .map(|caller| caller.scope),
+            Some(TokenScope::ReadOnly)
+        );
+        assert!(tokens.authenticate("not a token").is_none());
+    }
+
+    #[test]
+    fn restores_the_file_modes_and_keeps_the_tokens() {
+        let folder = test_folder("restrict");
+        let (admin, read_only) = (folder.join("admin.json"), folder.join("read_only.json"));
+        ApiTokens::load_or_create(&admin, &read_only, "").unwrap();
+        let admin_token = token(&admin);
+        fs::set_permissions(&admin, fs::Permissions::from_mode(0o644)).unwrap();
+
+        ApiTokens::load_or_create(&admin, &read_only, "").unwrap();
+
+        assert_eq!(mode(&admin), 0o600);
+        assert_eq!(token(&admin), admin_token);
+    }
+
+    #[test]
+    fn refuses_admin_tokens_in_the_group_readable_file() {
+        let folder = test_folder("misplaced");
+        let (admin, read_only) = (folder.join("admin.json"), folder.join("read_only.json"));
+        ApiTokens::load_or_create(&admin, &read_only, "").unwrap();
+        fs::copy(&admin, &read_only).unwrap();
+
+        assert!(ApiTokens::load_or_create(&admin, &read_only, "").is_err());
+    }
+}
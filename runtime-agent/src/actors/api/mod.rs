@@ -1,4 +1,5 @@
 pub(crate) mod actor;
+pub(crate) mod auth;
 pub(crate) mod messages;
 mod pagination;
 pub(crate) mod routes;
@@ -12,7 +12,7 @@ use std::sync::Arc;
 
 use crate::actors::api::{
     routes::v1::routes::{
-        connection_strings::v1_connection_strings_router,
+        commands::v1_commands_router, connection_strings::v1_connection_strings_router,
         function_hashes::v1_function_hashes_router, info::v1_info_router,
         properties::v1_properties_router,
     },
@@ -35,5 +35,6 @@ fn v1_router() -> Router<Arc<ApiState>> {
         .merge(v1_connection_strings_router())
         .merge(v1_function_hashes_router())
         .merge(v1_properties_router())
+        .merge(v1_commands_router())
         .layer(Extension(v1_state))
 }
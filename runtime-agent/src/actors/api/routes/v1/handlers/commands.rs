@@ -0,0 +1,40 @@
+use crate::actors::api::{routes::v1::responses::ApiResponse, state::ApiState};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::{
+    extract::{Json, State},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::info;
+
+// POST /commands/pending/{command_id}
+#[derive(Deserialize)]
+pub struct ConfirmCommandRequest {
+    approve: bool,
+}
+
+/// The commands the local policy is holding back, soonest to expire first
+pub async fn v1_get_pending_commands(State(state): State<Arc<ApiState>>) -> impl IntoResponse {
+    ApiResponse::ok(state.pending_confirmations.list())
+}
+
+/// Approve or reject a command waiting for local confirmation
+pub async fn v1_confirm_pending_command(
+    State(state): State<Arc<ApiState>>,
+    Path(command_id): Path<String>,
+    Json(payload): Json<ConfirmCommandRequest>,
+) -> Response {
+    // Unknown, or already answered, cancelled or expired
+    if !state
+        .pending_confirmations
+        .respond(&command_id, payload.approve)
+    {
+        let error = format!("command {} is not waiting for confirmation", command_id);
+        return (StatusCode::NOT_FOUND, ApiResponse::<String>::err(error)).into_response();
+    }
+
+    info!(%command_id, approve = payload.approve, "pending command confirmed locally");
+    ApiResponse::ok(command_id).into_response()
+}
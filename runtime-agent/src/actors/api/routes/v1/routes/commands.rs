@@ -0,0 +1,14 @@
+use crate::actors::api::routes::v1::handlers::commands::*;
+use crate::actors::api::state::ApiState;
+use axum::routing::{get, post};
+use axum::Router;
+use std::sync::Arc;
+
+pub fn v1_commands_router() -> Router<Arc<ApiState>> {
+    Router::new()
+        .route("/commands/pending", get(v1_get_pending_commands))
+        .route(
+            "/commands/pending/{command_id}",
+            post(v1_confirm_pending_command),
+        )
+}
@@ -1,3 +1,4 @@
+pub(crate) mod commands;
 pub(crate) mod connection_strings;
 pub(crate) mod function_hashes;
 pub(crate) mod info;
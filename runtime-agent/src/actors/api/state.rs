@@ -1,18 +1,31 @@
+use crate::actors::api::auth::ApiTokens;
+use crate::actors::connection_manager::confirmation::PendingConfirmations;
 use database_agent::SqlitePool;
+use runtime_shared::api_server::handle::ServerHandle;
 use runtime_shared::RuntimeProperties;
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub(crate) struct ApiState {
     pub id: String,
     pub db_pool: SqlitePool,
+    pub api_tokens: ApiTokens,
+    // Commands the local policy is holding back for someone here to approve or reject
+    pub pending_confirmations: Arc<PendingConfirmations>,
 }
 
 impl ApiState {
-    pub fn new(db_pool: SqlitePool) -> Self {
+    pub fn new(
+        db_pool: SqlitePool,
+        api_tokens: ApiTokens,
+        pending_confirmations: Arc<PendingConfirmations>,
+    ) -> Self {
         let runtime_properties = RuntimeProperties::global();
         Self {
             id: format!("agent:{}", runtime_properties.id()),
             db_pool,
+            api_tokens,
+            pending_confirmations,
         }
     }
 }
@@ -34,7 +47,7 @@ impl V1ApiState {
 
 #[derive(Debug)]
 pub struct ApiActorState {
-    pub server_handle: Option<axum_server::Handle>,
+    pub server_handle: Option<ServerHandle>,
 }
 
 impl ApiActorState {
@@ -0,0 +1,147 @@
+use ractor::{Actor, ActorProcessingErr, ActorRef};
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+
+use crate::actors::connection_manager::{
+    arguments::ConnectionManagerArguments,
+    connection_string::{activate_connection_string, AgentConnectionStrings},
+    messages::ConnectionManagerMessage,
+    session::{connect, run_session},
+    state::ConnectionManagerState,
+};
+use crate::{
+    ACTOR_CONNECTION_MANAGER_NAME, CONNECTION_STRING_PENDING_STATUS,
+    DEFAULT_PROPERTY_CONNECTION_RETRY_INTERVAL, PROPERTY_CONNECTION_RETRY_INTERVAL,
+};
+use database_agent::models::properties::PropertyValue;
+
+#[derive(Debug)]
+pub struct ConnectionManager;
+
+impl Actor for ConnectionManager {
+    type State = ConnectionManagerState;
+    type Msg = ConnectionManagerMessage;
+    type Arguments = ConnectionManagerArguments;
+
+    #[instrument(name = "Connection Manager - Pre Start", level = "trace")]
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        args: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        let retry_interval = PropertyValue::get_int_or(
+            args.db_pool.get()?,
+            PROPERTY_CONNECTION_RETRY_INTERVAL,
+            DEFAULT_PROPERTY_CONNECTION_RETRY_INTERVAL,
+        );
+
+        Ok(ConnectionManagerState::new(
+            args.db_pool,
+            retry_interval.max(1) as u64,
+            args.pending_confirmations,
+            args.job_runner,
+        ))
+    }
+
+    #[instrument(name = "Connection Manager - Post Start", level = "trace")]
+    async fn post_start(
+        &self,
+        myself: ActorRef<Self::Msg>,
+        _state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        info!(name = ACTOR_CONNECTION_MANAGER_NAME, "started successfully");
+
+        myself.cast(ConnectionManagerMessage::Connect)?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "Connection Manager - Post Stop", level = "trace")]
+    async fn post_stop(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        if let Some(session) = state.session.take() {
+            session.abort();
+        }
+        info!(name = ACTOR_CONNECTION_MANAGER_NAME, "stopped");
+
+        Ok(())
+    }
+
+    #[instrument(name = "Connection Manager - Process Message", level = "trace")]
+    async fn handle(
+        &self,
+        myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            ConnectionManagerMessage::Connect => {
+                if state.session.is_some() {
+                    return Ok(());
+                }
+
+                let connection_strings = match AgentConnectionStrings::load(&state.db_pool) {
+                    Ok(connection_strings) if !connection_strings.is_empty() => connection_strings,
+                    Ok(_) => {
+                        info!("no connection strings configured, waiting to retry");
+                        schedule_connect(&myself, state.retry_interval);
+                        return Ok(());
+                    }
+                    Err(error) => {
+                        error!(errorMsg = %error, "unable to load connection strings");
+                        schedule_connect(&myself, state.retry_interval);
+                        return Ok(());
+                    }
+                };
+
+                let db_pool = state.db_pool.clone();
+                let seen_commands = state.seen_commands.clone();
+                let pending_confirmations = state.pending_confirmations.clone();
+                let job_runner = state.job_runner.clone();
+                let manager = myself.clone();
+                state.session = Some(tokio::spawn(async move {
+                    let reason = match connect(&db_pool, &connection_strings).await {
+                        Ok((socket, connection_string, token)) => {
+                            // A pending connection string becomes active once it has worked
+                            if connection_string.status == CONNECTION_STRING_PENDING_STATUS {
+                                if let Err(error) =
+                                    activate_connection_string(&db_pool, connection_string.id)
+                                {
+                                    error!(errorMsg = %error, "unable to activate connection string");
+                                }
+                            }
+                            run_session(
+                                socket,
+                                token,
+                                db_pool,
+                                seen_commands,
+                                pending_confirmations,
+                                job_runner,
+                            )
+                            .await
+                        }
+                        Err(error) => Some(error.to_string()),
+                    };
+
+                    let _ = manager.cast(ConnectionManagerMessage::Disconnected { reason });
+                }));
+            }
+            ConnectionManagerMessage::Disconnected { reason } => {
+                warn!(?reason, "disconnected from server, waiting to reconnect");
+                state.session = None;
+                schedule_connect(&myself, state.retry_interval);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn schedule_connect(myself: &ActorRef<ConnectionManagerMessage>, retry_interval: u64) {
+    myself.send_after(Duration::from_secs(retry_interval), || {
+        ConnectionManagerMessage::Connect
+    });
+}
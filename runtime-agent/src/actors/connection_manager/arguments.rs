@@ -0,0 +1,12 @@
+use crate::actors::connection_manager::confirmation::PendingConfirmations;
+use crate::actors::job_runner::messages::JobRunnerMessage;
+use database_agent::SqlitePool;
+use ractor::ActorRef;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct ConnectionManagerArguments {
+    pub db_pool: SqlitePool,
+    pub(crate) pending_confirmations: Arc<PendingConfirmations>,
+    pub job_runner: Option<ActorRef<JobRunnerMessage>>,
+}
@@ -0,0 +1,522 @@
+use crate::actors::connection_manager::scripts::RUN_SCRIPT_VERB;
+use crate::{PROPERTY_POLICY_COMMANDS, PROPERTY_POLICY_FILE};
+use anyhow::{anyhow, Context, Error};
+use database_agent::models::properties::PropertyValue;
+use database_agent::SqlitePool;
+use runtime_shared::protocol::{CommandError, CommandErrorCode};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// Verbs under this prefix work on files, the paths they name must pass the policy's path rules
+const FILE_VERB_PREFIX: &str = "file.";
+
+// The payload fields a file command may name a path in
+const PATH_FIELDS: [&str; 3] = ["path", "source", "destination"];
+
+/// What the policy does with a verb
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum VerbAction {
+    Allow,
+    /// Only run once someone at the agent has approved it
+    Confirm,
+    Deny,
+}
+
+/// The agent's local say over which commands the server may run on it, read from the file named
+/// by `policy::file` or else the JSON in `policy::commands`. Verbs match exactly, `*` matches
+/// every verb and `prefix.*` every verb starting `prefix.`. Disabled beats confirm beats allowed.
+///
+/// ```json
+/// {
+///     "allowed": ["file.read", "system.*"],
+///     "confirm": ["file.write", "service.restart"],
+///     "disabled": ["shell.exec"],
+///     "unlisted": "deny",
+///     "paths": { "allowed": ["/var/log", "/opt/app"], "denied": ["/opt/app/secrets"] }
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+pub(crate) struct CommandPolicy {
+    #[serde(default)]
+    allowed: Vec<String>,
+    #[serde(default)]
+    confirm: Vec<String>,
+    #[serde(default)]
+    disabled: Vec<String>,
+    // What happens to verbs the policy doesn't mention
+    #[serde(default = "deny")]
+    unlisted: VerbAction,
+    #[serde(default)]
+    paths: PathRules,
+}
+
+/// Where file commands may reach, nowhere under a denied folder and, when there are allowed
+/// folders, only under one of them
+#[derive(Debug, Default, Deserialize)]
+struct PathRules {
+    #[serde(default)]
+    allowed: Vec<PathBuf>,
+    #[serde(default)]
+    denied: Vec<PathBuf>,
+}
+
+fn deny() -> VerbAction {
+    VerbAction::Deny
+}
+
+impl CommandPolicy {
+    /// Without a policy the agent runs whatever the server sends
+    pub fn unrestricted() -> Self {
+        Self {
+            allowed: vec![],
+            confirm: vec![],
+            disabled: vec![],
+            unlisted: VerbAction::Allow,
+            paths: PathRules::default(),
+        }
+    }
+
+    /// Read the policy as it is now, so local changes apply to the very next command
+    pub fn load(db_pool: &SqlitePool) -> Result<Self, Error> {
+        let policy_file =
+            PropertyValue::get_string_or(db_pool.get()?, PROPERTY_POLICY_FILE, String::new());
+
+        let policy: Self = if !policy_file.is_empty() {
+            let policy = fs::read_to_string(&policy_file)
+                .with_context(|| format!("unable to read policy file {}", policy_file))?;
+            serde_json::from_str(&policy)
+                .with_context(|| format!("invalid policy file {}", policy_file))?
+        } else {
+            match PropertyValue::get_json_or(
+                db_pool.get()?,
+                PROPERTY_POLICY_COMMANDS,
+                serde_json::Value::Null,
+            ) {
+                serde_json::Value::Null => return Ok(Self::unrestricted()),
+                policy => serde_json::from_value(policy)
+                    .with_context(|| format!("invalid {} property", PROPERTY_POLICY_COMMANDS))?,
+            }
+        };
+
+        policy.with_resolved_paths()
+    }
+
+    // Rules are compared against resolved paths, so they are resolved the same way
+    fn with_resolved_paths(mut self) -> Result<Self, Error> {
+        for rule in self.paths.allowed.iter_mut().chain(&mut self.paths.denied) {
+            *rule = resolve_path(rule).ok_or_else(|| {
+                anyhow!(
+                    "policy path {} must be absolute, and can't step back out of a folder that doesn't exist",
+                    rule.display()
+                )
+            })?;
+        }
+
+        Ok(self)
+    }
+
+    /// Whether a command may run straight away or needs confirming first, or why it may not
+    pub fn decide(
+        &self,
+        verb: &str,
+        payload: &serde_json::Value,
+    ) -> Result<VerbAction, CommandError> {
+        if matches_any(&self.disabled, verb) {
+            return Err(command_error(
+                CommandErrorCode::VerbDisabled,
+                format!("{} is disabled on this agent", verb),
+            ));
+        }
+
+        let action = if matches_any(&self.confirm, verb) {
+            VerbAction::Confirm
+        } else if matches_any(&self.allowed, verb) {
+            VerbAction::Allow
+        } else {
+            self.unlisted
+        };
+
+        if action == VerbAction::Deny {
+            return Err(command_error(
+                CommandErrorCode::VerbNotAllowed,
+                format!("{} is not allowed on this agent", verb),
+            ));
+        }
+
+        // A script read from a file reaches the file system just as a file command does
+        if verb.starts_with(FILE_VERB_PREFIX)
+            || (verb == RUN_SCRIPT_VERB && payload.get("path").is_some())
+        {
+            self.paths.check(payload)?;
+        }
+
+        Ok(action)
+    }
+}
+
+impl PathRules {
+    fn is_empty(&self) -> bool {
+        self.allowed.is_empty() && self.denied.is_empty()
+    }
+
+    fn check(&self, payload: &serde_json::Value) -> Result<(), CommandError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let paths = PATH_FIELDS
+            .iter()
+            .filter_map(|field| payload.get(field).and_then(|path| path.as_str()))
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            return Err(command_error(
+                CommandErrorCode::InvalidPayload,
+                "file commands must name a path, source or destination".to_string(),
+            ));
+        }
+
+        for path in paths {
+            let permitted = resolve_path(Path::new(path)).is_some_and(|resolved| {
+                !self
+                    .denied
+                    .iter()
+                    .any(|denied| resolved.starts_with(denied))
+                    && (self.allowed.is_empty()
+                        || self
+                            .allowed
+                            .iter()
+                            .any(|allowed| resolved.starts_with(allowed)))
+            });
+            if !permitted {
+                return Err(command_error(
+                    CommandErrorCode::PathDenied,
+                    format!("{} is outside the paths this agent permits", path),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn command_error(code: CommandErrorCode, message: String) -> CommandError {
+    CommandError { code, message }
+}
+
+fn matches_any(patterns: &[String], verb: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => verb.starts_with(prefix),
+            None => pattern == verb,
+        })
+}
+
+// Where an absolute path really leads, so neither `..` nor a symlink can be used to step outside
+// a permitted folder. The longest part of the path that exists is resolved by the filesystem as
+// given, which follows each symlink before any `..` after it steps back out. None for relative
+// paths, and for paths that can't be resolved before they are used.
+fn resolve_path(path: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() {
+        return None;
+    }
+
+    let mut existing = path;
+    let mut missing = vec![];
+    let mut resolved = loop {
+        if let Ok(canonical) = fs::canonicalize(existing) {
+            break canonical;
+        }
+        let mut components = existing.components();
+        missing.push(components.next_back()?);
+        existing = components.as_path();
+    };
+
+    // Whatever doesn't exist yet e.g. a file about to be written is appended as given. A `..` in
+    // it would only be resolved once the folders before it exist, and a name that is there
+    // without resolving is a dangling symlink that leads who knows where.
+    for component in missing.into_iter().rev() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                if fs::symlink_metadata(&resolved).is_ok() {
+                    return None;
+                }
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    Some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::os::unix::fs::symlink;
+
+    // A fresh folder per test, resolved so symlinked temp folders don't get in the way
+    fn test_folder(name: &str) -> PathBuf {
+        let folder =
+            std::env::temp_dir().join(format!("command-policy-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        fs::canonicalize(folder).unwrap()
+    }
+
+    fn policy(policy: serde_json::Value) -> CommandPolicy {
+        serde_json::from_value::<CommandPolicy>(policy)
+            .unwrap()
+            .with_resolved_paths()
+            .unwrap()
+    }
+
+    fn action(result: Result<VerbAction, CommandError>) -> Option<VerbAction> {
+        result.ok()
+    }
+
+    fn code(result: Result<VerbAction, CommandError>) -> Option<CommandErrorCode> {
+        result.err().map(|error| error.code)
+    }
+
+    #[test]
+    fn unrestricted_allows_everything() {
+        let policy = CommandPolicy::unrestricted();
+
+        assert_eq!(
+            action(policy.decide("shell.exec", &json!({}))),
+            Some(VerbAction::Allow)
+        );
+        assert_eq!(
+            action(policy.decide("file.read", &json!({ "path": "/etc/shadow" }))),
+            Some(VerbAction::Allow)
+        );
+    }
+
+    #[test]
+    fn disabled_beats_confirm_beats_allowed() {
+        let policy = policy(json!({
+            "allowed": ["system.*", "service.restart", "service.stop"],
+            "confirm": ["service.*"],
+            "disabled": ["service.stop"],
+        }));
+
+        assert_eq!(
+            action(policy.decide("system.info", &json!({}))),
+            Some(VerbAction::Allow)
+        );
+        assert_eq!(
+            action(policy.decide("service.restart", &json!({}))),
+            Some(VerbAction::Confirm)
+        );
+        assert_eq!(
+            code(policy.decide("service.stop", &json!({}))),
+            Some(CommandErrorCode::VerbDisabled)
+        );
+    }
+
+    #[test]
+    fn unlisted_verbs_follow_the_policy_default() {
+        let denying = policy(json!({ "allowed": ["system.info"] }));
+        let confirming = policy(json!({ "allowed": ["system.info"], "unlisted": "confirm" }));
+
+        assert_eq!(
+            code(denying.decide("reboot", &json!({}))),
+            Some(CommandErrorCode::VerbNotAllowed)
+        );
+        assert_eq!(
+            action(confirming.decide("reboot", &json!({}))),
+            Some(VerbAction::Confirm)
+        );
+    }
+
+    #[test]
+    fn patterns_match_exactly_or_by_prefix() {
+        let policy = policy(json!({ "allowed": ["system.*", "file.read"] }));
+
+        assert_eq!(
+            action(policy.decide("system.disk", &json!({}))),
+            Some(VerbAction::Allow)
+        );
+        assert!(policy.decide("system", &json!({})).is_err());
+        assert!(policy.decide("file.read_all", &json!({})).is_err());
+        assert!(policy.decide("systems.info", &json!({})).is_err());
+    }
+
+    #[test]
+    fn file_commands_must_stay_within_the_permitted_paths() {
+        let folder = test_folder("paths");
+        fs::create_dir_all(folder.join("app/secrets")).unwrap();
+        let policy = policy(json!({
+            "allowed": ["file.*"],
+            "paths": {
+                "allowed": [folder.join("app")],
+                "denied": [folder.join("app/secrets")],
+            },
+        }));
+        let read = |path: PathBuf| policy.decide("file.read", &json!({ "path": path }));
+
+        assert_eq!(
+            action(read(folder.join("app/config.toml"))),
+            Some(VerbAction::Allow)
+        );
+        assert_eq!(
+            action(read(folder.join("app/new/file.txt"))),
+            Some(VerbAction::Allow)
+        );
+        assert_eq!(
+            code(read(folder.join("app/secrets/key.pem"))),
+            Some(CommandErrorCode::PathDenied)
+        );
+        assert_eq!(
+            code(read(folder.join("app/../other.txt"))),
+            Some(CommandErrorCode::PathDenied)
+        );
+        assert_eq!(
+            code(read(folder.join("elsewhere.txt"))),
+            Some(CommandErrorCode::PathDenied)
+        );
+        assert_eq!(
+            code(read(PathBuf::from("app/config.toml"))),
+            Some(CommandErrorCode::PathDenied)
+        );
+    }
+
+    #[test]
+    fn file_commands_check_every_path_they_name() {
+        let folder = test_folder("fields");
+        let policy = policy(json!({
+            "allowed": ["file.*"],
+            "paths": { "allowed": [folder] },
+        }));
+
+        assert_eq!(
+            code(policy.decide(
+                "file.copy",
+                &json!({ "source": folder.join("a"), "destination": "/etc/cron.d/a" })
+            )),
+            Some(CommandErrorCode::PathDenied)
+        );
+        assert_eq!(
+            code(policy.decide("file.read", &json!({ "name": "a" }))),
+            Some(CommandErrorCode::InvalidPayload)
+        );
+        // Only file commands are held to the path rules
+        assert_eq!(
+            code(policy.decide("system.info", &json!({}))),
+            Some(CommandErrorCode::VerbNotAllowed)
+        );
+    }
+
+    #[test]
+    fn scripts_read_from_files_must_stay_within_the_permitted_paths() {
+        let folder = test_folder("scripts");
+        let policy = policy(json!({
+            "allowed": [RUN_SCRIPT_VERB],
+            "paths": { "allowed": [folder] },
+        }));
+
+        assert_eq!(
+            action(policy.decide(RUN_SCRIPT_VERB, &json!({ "path": folder.join("job.sh") }))),
+            Some(VerbAction::Allow)
+        );
+        assert_eq!(
+            code(policy.decide(RUN_SCRIPT_VERB, &json!({ "path": "/etc/shadow" }))),
+            Some(CommandErrorCode::PathDenied)
+        );
+        assert_eq!(
+            action(policy.decide(RUN_SCRIPT_VERB, &json!({ "script": "echo hello" }))),
+            Some(VerbAction::Allow)
+        );
+    }
+
+    #[test]
+    fn symlinks_out_of_a_permitted_folder_are_followed() {
+        let folder = test_folder("symlinks");
+        fs::create_dir_all(folder.join("app")).unwrap();
+        fs::create_dir_all(folder.join("outside/inner")).unwrap();
+        symlink(folder.join("outside/inner"), folder.join("app/link")).unwrap();
+        let policy = policy(json!({
+            "allowed": ["file.*"],
+            "paths": { "allowed": [folder.join("app")] },
+        }));
+        let read = |path: PathBuf| policy.decide("file.read", &json!({ "path": path }));
+
+        assert_eq!(
+            code(read(folder.join("app/link/file.txt"))),
+            Some(CommandErrorCode::PathDenied)
+        );
+        // Textually this is app/x, but the `..` steps back out of the link's target
+        assert_eq!(
+            code(read(folder.join("app/link/../x"))),
+            Some(CommandErrorCode::PathDenied)
+        );
+    }
+
+    #[test]
+    fn resolve_path_refuses_relative_paths() {
+        assert_eq!(resolve_path(Path::new("etc/passwd")), None);
+        assert_eq!(resolve_path(Path::new("../etc/passwd")), None);
+    }
+
+    #[test]
+    fn resolve_path_removes_dots_from_the_existing_part() {
+        let folder = test_folder("dots");
+        fs::create_dir_all(folder.join("a/b")).unwrap();
+
+        assert_eq!(
+            resolve_path(&folder.join("a/./b/../b")),
+            Some(folder.join("a/b"))
+        );
+        assert_eq!(resolve_path(&folder.join("a/b/..")), Some(folder.join("a")));
+    }
+
+    #[test]
+    fn resolve_path_follows_symlinks_before_stepping_back_out() {
+        let folder = test_folder("follow");
+        fs::create_dir_all(folder.join("app")).unwrap();
+        fs::create_dir_all(folder.join("etc/foo")).unwrap();
+        symlink(folder.join("etc/foo"), folder.join("app/link")).unwrap();
+
+        assert_eq!(
+            resolve_path(&folder.join("app/link/x")),
+            Some(folder.join("etc/foo/x"))
+        );
+        assert_eq!(
+            resolve_path(&folder.join("app/link/../x")),
+            Some(folder.join("etc/x"))
+        );
+    }
+
+    #[test]
+    fn resolve_path_appends_what_does_not_exist_yet() {
+        let folder = test_folder("missing");
+
+        assert_eq!(
+            resolve_path(&folder.join("new/./file.txt")),
+            Some(folder.join("new/file.txt"))
+        );
+    }
+
+    #[test]
+    fn resolve_path_refuses_parent_dirs_in_what_does_not_exist() {
+        let folder = test_folder("missing-parent");
+        fs::create_dir_all(folder.join("app")).unwrap();
+
+        assert_eq!(resolve_path(&folder.join("app/new/../../x")), None);
+        assert_eq!(resolve_path(&folder.join("missing/..")), None);
+    }
+
+    #[test]
+    fn resolve_path_refuses_dangling_symlinks() {
+        let folder = test_folder("dangling");
+        symlink(folder.join("nowhere/file"), folder.join("dangling")).unwrap();
+
+        assert_eq!(resolve_path(&folder.join("dangling")), None);
+        assert_eq!(resolve_path(&folder.join("dangling/file")), None);
+    }
+}
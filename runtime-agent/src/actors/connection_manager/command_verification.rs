@@ -0,0 +1,522 @@
+use crate::{
+    actors::connection_manager::commands::IncomingCommand,
+    DEFAULT_PROPERTY_CONNECTION_COMMAND_MAX_AGE, PROPERTY_CONNECTION_COMMAND_MAX_AGE,
+    PROPERTY_CONNECTION_COMMAND_PUBLIC_KEY,
+};
+use anyhow::{anyhow, Error};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use database_agent::models::properties::PropertyValue;
+use database_agent::SqlitePool;
+use ed25519_dalek::{pkcs8::DecodePublicKey, Signature, VerifyingKey};
+use runtime_shared::protocol::{cancel_signing_bytes, command_signing_bytes, CommandRecipient};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// The nonces of commands accepted recently, kept across reconnects so a command captured
+/// on one connection can't be replayed on the next
+#[derive(Debug, Default)]
+pub(crate) struct SeenCommands {
+    // Nonce to the time the command was issued
+    nonces: Mutex<HashMap<String, u64>>,
+}
+
+impl SeenCommands {
+    // Remember the nonce, false if it has been seen before. Nonces are forgotten once their
+    // command would be refused as stale anyway.
+    fn first_sighting(&self, nonce: &str, issued_at: u64, max_age: u64, now: u64) -> bool {
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.retain(|_, issued_at| issued_at.saturating_add(max_age) >= now);
+
+        if nonces.contains_key(nonce) {
+            return false;
+        }
+        nonces.insert(nonce.to_string(), issued_at);
+        true
+    }
+}
+
+/// Checks commands were signed by the server whose public key has been pinned in the
+/// agent's properties, for this agent, and that they are neither stale nor replayed
+#[derive(Debug)]
+pub(crate) struct CommandVerifier {
+    key: Option<VerifyingKey>,
+    max_age: u64,
+    seen: Arc<SeenCommands>,
+    // Who we are, a command signed for any other agent is refused
+    tenant: String,
+    agent_id: String,
+}
+
+impl CommandVerifier {
+    /// Read the pinned key from the properties, without one every command is refused
+    pub fn load(
+        db_pool: &SqlitePool,
+        seen: Arc<SeenCommands>,
+        tenant: &str,
+        agent_id: &str,
+    ) -> Self {
+        let public_key = db_pool
+            .get()
+            .map(|db_conn| {
+                PropertyValue::get_string_or(
+                    db_conn,
+                    PROPERTY_CONNECTION_COMMAND_PUBLIC_KEY,
+                    String::new(),
+                )
+            })
+            .unwrap_or_default();
+        let max_age = db_pool
+            .get()
+            .map(|db_conn| {
+                PropertyValue::get_int_or(
+                    db_conn,
+                    PROPERTY_CONNECTION_COMMAND_MAX_AGE,
+                    DEFAULT_PROPERTY_CONNECTION_COMMAND_MAX_AGE,
+                )
+            })
+            .unwrap_or(DEFAULT_PROPERTY_CONNECTION_COMMAND_MAX_AGE);
+
+        let key = match parse_public_key(public_key.trim()) {
+            Ok(key) => key,
+            Err(error) => {
+                error!(errorMsg = %error, "invalid command public key, commands will be refused");
+                None
+            }
+        };
+
+        Self {
+            key,
+            max_age: max_age.max(1) as u64,
+            seen,
+            tenant: tenant.to_string(),
+            agent_id: agent_id.to_string(),
+        }
+    }
+
+    /// Accept a command only if the pinned key signed it for us, recently, and it hasn't been
+    /// seen before
+    pub fn verify(
+        &self,
+        command: &IncomingCommand,
+        issued_at: u64,
+        nonce: &str,
+        signature: &str,
+    ) -> Result<(), Error> {
+        let signed = command_signing_bytes(
+            self.recipient(),
+            &command.command_id,
+            &command.verb,
+            &command.payload,
+            issued_at,
+            nonce,
+            command.deadline,
+        );
+        self.check(&signed, issued_at, nonce, signature)
+    }
+
+    /// Accept the cancel of a command on exactly the same terms as a command
+    pub fn verify_cancel(
+        &self,
+        command_id: &str,
+        issued_at: u64,
+        nonce: &str,
+        signature: &str,
+    ) -> Result<(), Error> {
+        let signed = cancel_signing_bytes(self.recipient(), command_id, issued_at, nonce);
+        self.check(&signed, issued_at, nonce, signature)
+    }
+
+    fn recipient(&self) -> CommandRecipient<'_> {
+        CommandRecipient {
+            tenant: &self.tenant,
+            agent_id: &self.agent_id,
+        }
+    }
+
+    fn check(
+        &self,
+        signed: &[u8],
+        issued_at: u64,
+        nonce: &str,
+        signature: &str,
+    ) -> Result<(), Error> {
+        let Some(key) = &self.key else {
+            return Err(anyhow!("no command public key has been pinned"));
+        };
+
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .ok()
+            .and_then(|signature| Signature::from_slice(&signature).ok())
+            .ok_or_else(|| anyhow!("unreadable signature"))?;
+        key.verify_strict(signed, &signature).map_err(|_| {
+            anyhow!("signature does not match the pinned key, or it was meant for another agent")
+        })?;
+
+        // Clocks drift, so a command may also be issued a little ahead of us
+        let now = now();
+        if issued_at.saturating_add(self.max_age) < now
+            || issued_at > now.saturating_add(self.max_age)
+        {
+            return Err(anyhow!(
+                "issued at {} is outside the {} seconds allowed",
+                issued_at,
+                self.max_age
+            ));
+        }
+
+        if !self
+            .seen
+            .first_sighting(nonce, issued_at, self.max_age, now)
+        {
+            return Err(anyhow!("replayed nonce"));
+        }
+
+        Ok(())
+    }
+}
+
+// The key as the server logs it (base64url, no padding), or its PEM public key file contents
+fn parse_public_key(public_key: &str) -> Result<Option<VerifyingKey>, Error> {
+    if public_key.is_empty() {
+        return Ok(None);
+    }
+
+    if public_key.starts_with("-----BEGIN") {
+        return VerifyingKey::from_public_key_pem(public_key)
+            .map(Some)
+            .map_err(|_| anyhow!("expected an Ed25519 public key"));
+    }
+
+    let bytes: [u8; 32] = URL_SAFE_NO_PAD
+        .decode(public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("expected a base64url encoded Ed25519 public key"))?;
+
+    Ok(Some(VerifyingKey::from_bytes(&bytes)?))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{
+        pkcs8::{spki::der::pem::LineEnding, EncodePublicKey},
+        Signer, SigningKey,
+    };
+
+    const TENANT: &str = "acme";
+    const AGENT_ID: &str = "agent-1";
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32])
+    }
+
+    fn verifier(key: &SigningKey) -> CommandVerifier {
+        CommandVerifier {
+            key: Some(key.verifying_key()),
+            max_age: 60,
+            seen: Arc::new(SeenCommands::default()),
+            tenant: TENANT.to_string(),
+            agent_id: AGENT_ID.to_string(),
+        }
+    }
+
+    fn command() -> IncomingCommand {
+        IncomingCommand {
+            command_id: "command-1".to_string(),
+            verb: "file.read".to_string(),
+            payload: serde_json::json!({ "path": "/tmp/a.txt" }),
+            deadline: Some(now() + 30),
+        }
+    }
+
+    fn sign_for(
+        key: &SigningKey,
+        recipient: CommandRecipient,
+        command: &IncomingCommand,
+        issued_at: u64,
+        nonce: &str,
+    ) -> String {
+        let signature = key.sign(&command_signing_bytes(
+            recipient,
+            &command.command_id,
+            &command.verb,
+            &command.payload,
+            issued_at,
+            nonce,
+            command.deadline,
+        ));
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    }
+
+    fn sign(key: &SigningKey, command: &IncomingCommand, issued_at: u64, nonce: &str) -> String {
+        let recipient = CommandRecipient {
+            tenant: TENANT,
+            agent_id: AGENT_ID,
+        };
+        sign_for(key, recipient, command, issued_at, nonce)
+    }
+
+    #[test]
+    fn accepts_a_fresh_command_signed_for_us() {
+        let key = signing_key();
+        let command = command();
+        let signature = sign(&key, &command, now(), "nonce-1");
+
+        assert!(verifier(&key)
+            .verify(&command, now(), "nonce-1", &signature)
+            .is_ok());
+    }
+
+    #[test]
+    fn refuses_commands_without_a_pinned_key() {
+        let key = signing_key();
+        let mut verifier = verifier(&key);
+        verifier.key = None;
+        let command = command();
+        let signature = sign(&key, &command, now(), "nonce-1");
+
+        assert!(verifier
+            .verify(&command, now(), "nonce-1", &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn refuses_a_command_signed_with_another_key() {
+        let command = command();
+        let signature = sign(
+            &SigningKey::from_bytes(&[8u8; 32]),
+            &command,
+            now(),
+            "nonce-1",
+        );
+
+        assert!(verifier(&signing_key())
+            .verify(&command, now(), "nonce-1", &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn refuses_an_unreadable_signature() {
+        assert!(verifier(&signing_key())
+            .verify(&command(), now(), "nonce-1", "not a signature")
+            .is_err());
+    }
+
+    #[test]
+    fn refuses_a_tampered_payload_verb_or_deadline() {
+        let key = signing_key();
+        let verifier = verifier(&key);
+        let issued_at = now();
+        let signature = sign(&key, &command(), issued_at, "nonce-1");
+
+        let mut payload = command();
+        payload.payload = serde_json::json!({ "path": "/etc/shadow" });
+        let mut verb = command();
+        verb.verb = "shell.exec".to_string();
+        let mut deadline = command();
+        deadline.deadline = deadline.deadline.map(|deadline| deadline + 3600);
+        let mut no_deadline = command();
+        no_deadline.deadline = None;
+
+        for tampered in [payload, verb, deadline, no_deadline] {
+            assert!(verifier
+                .verify(&tampered, issued_at, "nonce-1", &signature)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn refuses_a_tampered_issued_at_or_nonce() {
+        let key = signing_key();
+        let verifier = verifier(&key);
+        let command = command();
+        let issued_at = now();
+        let signature = sign(&key, &command, issued_at, "nonce-1");
+
+        assert!(verifier
+            .verify(&command, issued_at + 1, "nonce-1", &signature)
+            .is_err());
+        assert!(verifier
+            .verify(&command, issued_at, "nonce-2", &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn refuses_a_command_signed_for_another_agent_or_tenant() {
+        let key = signing_key();
+        let verifier = verifier(&key);
+        let command = command();
+
+        for recipient in [
+            CommandRecipient {
+                tenant: TENANT,
+                agent_id: "agent-2",
+            },
+            CommandRecipient {
+                tenant: "other",
+                agent_id: AGENT_ID,
+            },
+        ] {
+            let signature = sign_for(&key, recipient, &command, now(), "nonce-1");
+            assert!(verifier
+                .verify(&command, now(), "nonce-1", &signature)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn refuses_a_stale_command() {
+        let key = signing_key();
+        let command = command();
+        let issued_at = now() - 61;
+        let signature = sign(&key, &command, issued_at, "nonce-1");
+
+        assert!(verifier(&key)
+            .verify(&command, issued_at, "nonce-1", &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn refuses_a_command_from_too_far_in_the_future() {
+        let key = signing_key();
+        let command = command();
+        let issued_at = now() + 61;
+        let signature = sign(&key, &command, issued_at, "nonce-1");
+
+        assert!(verifier(&key)
+            .verify(&command, issued_at, "nonce-1", &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn allows_a_little_clock_skew() {
+        let key = signing_key();
+        let verifier = verifier(&key);
+        let command = command();
+
+        for (nonce, issued_at) in [("nonce-1", now() - 30), ("nonce-2", now() + 30)] {
+            let signature = sign(&key, &command, issued_at, nonce);
+            assert!(verifier
+                .verify(&command, issued_at, nonce, &signature)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn refuses_a_replayed_nonce_across_verifiers() {
+        let key = signing_key();
+        let command = command();
+        let issued_at = now();
+        let signature = sign(&key, &command, issued_at, "nonce-1");
+
+        // A reconnect builds a new verifier, but shares what has been seen
+        let first = verifier(&key);
+        let mut second = verifier(&key);
+        second.seen = first.seen.clone();
+
+        assert!(first
+            .verify(&command, issued_at, "nonce-1", &signature)
+            .is_ok());
+        assert!(first
+            .verify(&command, issued_at, "nonce-1", &signature)
+            .is_err());
+        assert!(second
+            .verify(&command, issued_at, "nonce-1", &signature)
+            .is_err());
+    }
+
+    fn sign_cancel(
+        key: &SigningKey,
+        recipient: CommandRecipient,
+        issued_at: u64,
+        nonce: &str,
+    ) -> String {
+        let signature = key.sign(&cancel_signing_bytes(
+            recipient,
+            "command-1",
+            issued_at,
+            nonce,
+        ));
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    }
+
+    #[test]
+    fn accepts_a_fresh_cancel_signed_for_us() {
+        let key = signing_key();
+        let recipient = CommandRecipient {
+            tenant: TENANT,
+            agent_id: AGENT_ID,
+        };
+        let signature = sign_cancel(&key, recipient, now(), "nonce-1");
+
+        assert!(verifier(&key)
+            .verify_cancel("command-1", now(), "nonce-1", &signature)
+            .is_ok());
+    }
+
+    #[test]
+    fn refuses_a_cancel_for_another_command_or_agent_or_replayed() {
+        let key = signing_key();
+        let verifier = verifier(&key);
+        let issued_at = now();
+        let ours = CommandRecipient {
+            tenant: TENANT,
+            agent_id: AGENT_ID,
+        };
+        let signature = sign_cancel(&key, ours, issued_at, "nonce-1");
+
+        assert!(verifier
+            .verify_cancel("command-2", issued_at, "nonce-1", &signature)
+            .is_err());
+
+        let theirs = CommandRecipient {
+            tenant: TENANT,
+            agent_id: "agent-2",
+        };
+        let other_signature = sign_cancel(&key, theirs, issued_at, "nonce-2");
+        assert!(verifier
+            .verify_cancel("command-1", issued_at, "nonce-2", &other_signature)
+            .is_err());
+
+        assert!(verifier
+            .verify_cancel("command-1", issued_at, "nonce-1", &signature)
+            .is_ok());
+        assert!(verifier
+            .verify_cancel("command-1", issued_at, "nonce-1", &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn refuses_a_command_signature_passed_off_as_a_cancel() {
+        let key = signing_key();
+        let command = command();
+        let signature = sign(&key, &command, now(), "nonce-1");
+
+        assert!(verifier(&key)
+            .verify_cancel(&command.command_id, now(), "nonce-1", &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn parses_raw_and_pem_public_keys() {
+        let key = signing_key().verifying_key();
+        let raw = URL_SAFE_NO_PAD.encode(key.as_bytes());
+        let pem = key.to_public_key_pem(LineEnding::LF).unwrap();
+
+        assert_eq!(parse_public_key(&raw).unwrap(), Some(key));
+        assert_eq!(parse_public_key(pem.trim()).unwrap(), Some(key));
+        assert_eq!(parse_public_key("").unwrap(), None);
+        assert!(parse_public_key("not a key").is_err());
+    }
+}
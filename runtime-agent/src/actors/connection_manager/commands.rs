@@ -0,0 +1,328 @@
+use crate::{
+    actors::connection_manager::{
+        command_policy::{command_error, CommandPolicy, VerbAction},
+        confirmation::{PendingCommand, PendingConfirmations},
+        scripts::{Script, RUN_SCRIPT_VERB},
+    },
+    actors::job_runner::messages::JobRunnerMessage,
+    DEFAULT_PROPERTY_POLICY_CONFIRM_TIMEOUT, PROPERTY_POLICY_CONFIRM_TIMEOUT,
+};
+use anyhow::Error;
+use database_agent::models::events::{record_event, NewEvent};
+use database_agent::models::properties::PropertyValue;
+use database_agent::SqlitePool;
+use ractor::ActorRef;
+use runtime_shared::protocol::{CommandError, CommandErrorCode, Inbound};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{error, info, warn};
+
+/// A command from the server whose signature has been checked
+#[derive(Debug)]
+pub(crate) struct IncomingCommand {
+    pub command_id: String,
+    pub verb: String,
+    pub payload: serde_json::Value,
+    // Seconds since the unix epoch the command must have finished by
+    pub deadline: Option<u64>,
+}
+
+impl IncomingCommand {
+    /// What is left of the time the command was given, None when it has no deadline
+    pub fn until_deadline(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| Duration::from_secs(deadline.saturating_sub(now())))
+    }
+
+    fn past_deadline(&self) -> bool {
+        self.until_deadline().is_some_and(|left| left.is_zero())
+    }
+}
+
+/// Apply the local policy to a verified command - run it, hold it back for confirmation,
+/// or refuse it with a `Result` error and a `command.denied` event
+pub(crate) fn handle_command(
+    db_pool: &SqlitePool,
+    confirmations: &Arc<PendingConfirmations>,
+    job_runner: &Option<ActorRef<JobRunnerMessage>>,
+    tx: &UnboundedSender<Inbound>,
+    command: IncomingCommand,
+) {
+    // An unreadable policy refuses everything rather than fall back to running anything
+    let decision = CommandPolicy::load(db_pool)
+        .map_err(|error| {
+            error!(errorMsg = %error, "unable to load the command policy");
+            command_error(
+                CommandErrorCode::PolicyInvalid,
+                format!("the command policy could not be read, {}", error),
+            )
+        })
+        .and_then(|policy| policy.decide(&command.verb, &command.payload));
+
+    match decision {
+        Ok(_) if command.past_deadline() => refuse_late_command(db_pool, tx, command),
+        Ok(VerbAction::Confirm) => await_confirmation(
+            db_pool.clone(),
+            confirmations.clone(),
+            job_runner.clone(),
+            tx.clone(),
+            command,
+        ),
+        Ok(_) => run_command(db_pool, job_runner, tx, command),
+        Err(command_error) => refuse_command(
+            db_pool,
+            tx,
+            command.command_id,
+            &command.verb,
+            command_error,
+        ),
+    }
+}
+
+/// Stop a command the server has cancelled, whether it is still waiting to be confirmed or
+/// already running
+pub(crate) fn cancel_command(
+    confirmations: &PendingConfirmations,
+    job_runner: &Option<ActorRef<JobRunnerMessage>>,
+    command_id: String,
+) {
+    // Whoever is waiting on the confirmation refuses the command as cancelled
+    if confirmations.forget(&command_id) {
+        info!(%command_id, "command cancelled while awaiting local confirmation");
+        return;
+    }
+
+    match job_runner {
+        Some(job_runner) => {
+            info!(%command_id, "command cancelled by the server");
+            let _ = job_runner.cast(JobRunnerMessage::Cancel { job_id: command_id });
+        }
+        None => warn!(%command_id, "no job runner to cancel the command on"),
+    }
+}
+
+fn run_command(
+    db_pool: &SqlitePool,
+    job_runner: &Option<ActorRef<JobRunnerMessage>>,
+    tx: &UnboundedSender<Inbound>,
+    command: IncomingCommand,
+) {
+    if command.past_deadline() {
+        return refuse_late_command(db_pool, tx, command);
+    }
+
+    match command.verb.as_str() {
+        RUN_SCRIPT_VERB => run_script(db_pool, job_runner, tx, command),
+        verb => {
+            let message = format!("{} is not a command this agent can run", verb);
+            refuse_command(
+                db_pool,
+                tx,
+                command.command_id,
+                verb,
+                command_error(CommandErrorCode::UnknownVerb, message),
+            );
+        }
+    }
+}
+
+// Acknowledge the script straight away and send its result once it has finished
+fn run_script(
+    db_pool: &SqlitePool,
+    job_runner: &Option<ActorRef<JobRunnerMessage>>,
+    tx: &UnboundedSender<Inbound>,
+    command: IncomingCommand,
+) {
+    let script = Script::prepare(db_pool, &command.payload, command.until_deadline());
+    let command_id = command.command_id;
+    let script = match script {
+        Ok(script) => script,
+        Err(command_error) => {
+            return refuse_command(db_pool, tx, command_id, RUN_SCRIPT_VERB, command_error)
+        }
+    };
+
+    info!(%command_id, hash = %script.hash(), "running script");
+    let _ = tx.send(Inbound::Ack {
+        command_id: command_id.clone(),
+    });
+
+    let job_runner = job_runner.clone();
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        let (error, output) = script.run(job_runner, &tx, command_id.clone()).await;
+        match &error {
+            None => info!(%command_id, exit_code = %output["exit_code"], "script finished"),
+            Some(error) => {
+                warn!(%command_id, code = ?error.code, reason = %error.message, "script failed")
+            }
+        }
+        let _ = tx.send(Inbound::Result {
+            command_id,
+            error,
+            output: Some(output),
+        });
+    });
+}
+
+fn refuse_late_command(
+    db_pool: &SqlitePool,
+    tx: &UnboundedSender<Inbound>,
+    command: IncomingCommand,
+) {
+    let message = format!("{} was not started before its deadline", command.verb);
+    refuse_command(
+        db_pool,
+        tx,
+        command.command_id,
+        &command.verb,
+        command_error(CommandErrorCode::TimedOut, message),
+    );
+}
+
+fn refuse_command(
+    db_pool: &SqlitePool,
+    tx: &UnboundedSender<Inbound>,
+    command_id: String,
+    verb: &str,
+    command_error: CommandError,
+) {
+    warn!(%command_id, %verb, code = ?command_error.code, reason = %command_error.message, "command denied");
+
+    raise_event(
+        db_pool,
+        "command.denied",
+        &command_id,
+        json!({ "verb": verb, "error": command_error }),
+    );
+
+    let _ = tx.send(Inbound::Result {
+        command_id,
+        error: Some(command_error),
+        output: None,
+    });
+}
+
+// Wait in the background for someone at the agent to decide, refusing the command if nobody
+// does before the confirmation timeout or the command's deadline, or the server cancels it
+fn await_confirmation(
+    db_pool: SqlitePool,
+    confirmations: Arc<PendingConfirmations>,
+    job_runner: Option<ActorRef<JobRunnerMessage>>,
+    tx: UnboundedSender<Inbound>,
+    command: IncomingCommand,
+) {
+    let timeout = db_pool
+        .get()
+        .map_or(DEFAULT_PROPERTY_POLICY_CONFIRM_TIMEOUT, |db_conn| {
+            PropertyValue::get_int_or(
+                db_conn,
+                PROPERTY_POLICY_CONFIRM_TIMEOUT,
+                DEFAULT_PROPERTY_POLICY_CONFIRM_TIMEOUT,
+            )
+        });
+    let timeout = Duration::from_secs(timeout.max(1) as u64);
+    let until_deadline = command.until_deadline().filter(|left| *left < timeout);
+    let timeout = until_deadline.unwrap_or(timeout);
+    let expires_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .saturating_add(timeout)
+        .as_secs();
+
+    let decision = confirmations.request(PendingCommand {
+        command_id: command.command_id.clone(),
+        verb: command.verb.clone(),
+        payload: command.payload.clone(),
+        expires_at,
+    });
+
+    let (command_id, verb) = (command.command_id.clone(), command.verb.clone());
+
+    info!(%command_id, %verb, expires_at, "command awaiting local confirmation");
+    raise_event(
+        &db_pool,
+        "command.confirmation_requested",
+        &command_id,
+        json!({ "verb": verb, "payload": command.payload, "expires_at": expires_at }),
+    );
+
+    tokio::spawn(async move {
+        match tokio::time::timeout(timeout, decision).await {
+            Ok(Ok(true)) => {
+                info!(%command_id, %verb, "command approved locally");
+                run_command(&db_pool, &job_runner, &tx, command);
+            }
+            Ok(Ok(false)) => refuse_command(
+                &db_pool,
+                &tx,
+                command_id,
+                &verb,
+                command_error(
+                    CommandErrorCode::Rejected,
+                    format!("{} was rejected on the agent", verb),
+                ),
+            ),
+            // Forgotten without a decision, the server cancelled it
+            Ok(Err(_)) => refuse_command(
+                &db_pool,
+                &tx,
+                command_id,
+                &verb,
+                command_error(
+                    CommandErrorCode::Cancelled,
+                    format!("{} was cancelled before it was confirmed", verb),
+                ),
+            ),
+            Err(_) => {
+                confirmations.forget(&command_id);
+                let command_error = match until_deadline {
+                    Some(_) => command_error(
+                        CommandErrorCode::TimedOut,
+                        format!(
+                            "{} was not confirmed on the agent before its deadline",
+                            verb
+                        ),
+                    ),
+                    None => command_error(
+                        CommandErrorCode::ConfirmationTimedOut,
+                        format!("{} was not confirmed on the agent in time", verb),
+                    ),
+                };
+                refuse_command(&db_pool, &tx, command_id, &verb, command_error);
+            }
+        }
+    });
+}
+
+fn raise_event(
+    db_pool: &SqlitePool,
+    event_type: &str,
+    command_id: &str,
+    payload: serde_json::Value,
+) {
+    let event = NewEvent {
+        event_type: event_type.to_string(),
+        aggregate_type: "command".to_string(),
+        aggregate_id: command_id.to_string(),
+        payload: payload.to_string(),
+        metadata: None,
+    };
+
+    let result = db_pool
+        .get()
+        .map_err(Error::from)
+        .and_then(|mut db_conn| record_event(&mut db_conn, &event));
+    if let Err(error) = result {
+        error!(errorMsg = %error, %event_type, %command_id, "unable to record command event");
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
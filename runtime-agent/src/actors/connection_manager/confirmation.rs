@@ -0,0 +1,59 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+/// A command the policy holds back until someone at the agent approves or rejects it
+#[derive(Debug, Serialize, Clone)]
+pub(crate) struct PendingCommand {
+    pub command_id: String,
+    pub verb: String,
+    pub payload: serde_json::Value,
+    // Seconds since the unix epoch, the command is refused if still waiting then
+    pub expires_at: u64,
+}
+
+/// Commands awaiting local confirmation, shared between the connection manager that holds
+/// them back and the local API they are approved or rejected through
+#[derive(Debug, Default)]
+pub(crate) struct PendingConfirmations {
+    pending: Mutex<HashMap<String, (PendingCommand, oneshot::Sender<bool>)>>,
+}
+
+impl PendingConfirmations {
+    /// Hold a command back, the receiver gets the decision
+    pub fn request(&self, command: PendingCommand) -> oneshot::Receiver<bool> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(command.command_id.clone(), (command, sender));
+        receiver
+    }
+
+    pub fn list(&self) -> Vec<PendingCommand> {
+        let mut commands = self
+            .pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(command, _)| command.clone())
+            .collect::<Vec<_>>();
+        commands.sort_by_key(|command| command.expires_at);
+        commands
+    }
+
+    /// Approve or reject a waiting command, false if there is no such command
+    pub fn respond(&self, command_id: &str, approve: bool) -> bool {
+        match self.pending.lock().unwrap().remove(command_id) {
+            Some((_, sender)) => sender.send(approve).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Stop waiting on a command that has expired or been cancelled, without a decision.
+    /// False if there is no such command.
+    pub fn forget(&self, command_id: &str) -> bool {
+        self.pending.lock().unwrap().remove(command_id).is_some()
+    }
+}
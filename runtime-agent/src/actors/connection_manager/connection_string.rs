@@ -1,22 +1,75 @@
-use serde::{Deserialize, Serialize};
+use crate::{
+    CONNECTION_STRING_ACTIVE_STATUS, CONNECTION_STRING_INACTIVE_STATUS,
+    CONNECTION_STRING_PENDING_STATUS, PROPERTY_CONNECTION_TOKEN,
+};
+use anyhow::Error;
+use database_agent::models::connection_strings::ConnectionStrings;
+use database_agent::models::properties::delete_property;
+use database_agent::schema::connection_strings;
+use database_agent::SqlitePool;
+use diesel::prelude::*;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The connection strings the agent can use to reach the server
+/// - `current` is the active connection string that last connected successfully
+/// - `new` is a pending connection string that has not been tried yet
+#[derive(Debug, Default)]
 pub struct AgentConnectionStrings {
-    pub current: Option<String>,
-    pub new: Option<String>,
+    pub current: Option<ConnectionStrings>,
+    pub new: Option<ConnectionStrings>,
 }
 
-impl Default for AgentConnectionStrings {
-    fn default() -> Self {
-        AgentConnectionStrings {
-            current: None,
-            new: None,
-        }
+impl AgentConnectionStrings {
+    pub fn load(db_pool: &SqlitePool) -> Result<Self, Error> {
+        let mut db_conn = db_pool.get()?;
+
+        let current = connection_strings::table
+            .filter(connection_strings::status.eq(CONNECTION_STRING_ACTIVE_STATUS))
+            .order(connection_strings::id.desc())
+            .select(ConnectionStrings::as_select())
+            .first(&mut db_conn)
+            .optional()?;
+
+        let new = connection_strings::table
+            .filter(connection_strings::status.eq(CONNECTION_STRING_PENDING_STATUS))
+            .order(connection_strings::id.desc())
+            .select(ConnectionStrings::as_select())
+            .first(&mut db_conn)
+            .optional()?;
+
+        Ok(Self { current, new })
     }
-}
 
-impl AgentConnectionStrings {
     pub fn is_empty(&self) -> bool {
         self.current.is_none() && self.new.is_none()
     }
+
+    /// Connection strings to try, newest first
+    pub fn candidates(&self) -> Vec<&ConnectionStrings> {
+        self.new.iter().chain(self.current.iter()).collect()
+    }
+}
+
+/// Make a pending connection string the active one once it has connected,
+/// retiring the previous active string and any token issued through it
+pub fn activate_connection_string(db_pool: &SqlitePool, id: i32) -> Result<(), Error> {
+    let mut db_conn = db_pool.get()?;
+
+    db_conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::update(
+            connection_strings::table
+                .filter(connection_strings::status.eq(CONNECTION_STRING_ACTIVE_STATUS)),
+        )
+        .set(connection_strings::status.eq(CONNECTION_STRING_INACTIVE_STATUS))
+        .execute(conn)?;
+
+        diesel::update(connection_strings::table.find(id))
+            .set(connection_strings::status.eq(CONNECTION_STRING_ACTIVE_STATUS))
+            .execute(conn)?;
+
+        Ok(())
+    })?;
+
+    delete_property(&mut db_conn, PROPERTY_CONNECTION_TOKEN)?;
+
+    Ok(())
 }
@@ -0,0 +1,130 @@
+use crate::{PROPERTY_CONNECTION_CLIENT_CERT_FILE, PROPERTY_CONNECTION_CLIENT_KEY_FILE};
+use anyhow::{anyhow, Error};
+use database_agent::models::properties::{set_property_value, PropertyValue};
+use database_agent::SqlitePool;
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+use runtime_shared::api_server::client_cert::ClientCertificate;
+use runtime_shared::RuntimeProperties;
+use rustls::pki_types::{pem::PemObject, CertificateDer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CLIENT_CERT_FILE: &str = "agent.pem";
+const CLIENT_KEY_FILE: &str = "agent-key.pem";
+
+// Ask for a new certificate once the current one is this close to expiring
+const CERTIFICATE_RENEW_BEFORE_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// A key pair waiting for the server to sign the CSR we sent for it
+pub(crate) struct PendingEnrollment {
+    key_pair: KeyPair,
+}
+
+impl PendingEnrollment {
+    /// Generate a fresh key pair and the PEM CSR to send with `Inbound::Enroll`
+    pub fn new() -> Result<(Self, String), Error> {
+        let key_pair = KeyPair::generate()?;
+
+        // The server overrides the subject, but the CSR still has to carry one
+        let mut params = CertificateParams::default();
+        let mut distinguished_name = DistinguishedName::new();
+        distinguished_name.push(DnType::CommonName, RuntimeProperties::global().id());
+        params.distinguished_name = distinguished_name;
+
+        let csr = params.serialize_request(&key_pair)?.pem()?;
+
+        Ok((Self { key_pair }, csr))
+    }
+
+    /// Save the issued certificate with its key and present it from the next connection on
+    pub fn complete(self, db_pool: &SqlitePool, certificate: &str) -> Result<(), Error> {
+        let certs_folder = certs_folder();
+        fs::create_dir_all(&certs_folder)?;
+
+        let cert_file = certs_folder.join(CLIENT_CERT_FILE);
+        let key_file = certs_folder.join(CLIENT_KEY_FILE);
+        write_private_key(&key_file, &self.key_pair.serialize_pem())?;
+        fs::write(&cert_file, certificate)?;
+
+        let mut db_conn = db_pool.get()?;
+        set_property_value(
+            &mut db_conn,
+            PROPERTY_CONNECTION_CLIENT_CERT_FILE,
+            PropertyValue::String(cert_file.to_string_lossy().to_string()),
+            Some("Client certificate issued by the server".to_string()),
+        )?;
+        set_property_value(
+            &mut db_conn,
+            PROPERTY_CONNECTION_CLIENT_KEY_FILE,
+            PropertyValue::String(key_file.to_string_lossy().to_string()),
+            Some("Private key for the client certificate".to_string()),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Whether we should enroll for a (new) client certificate on this connection.
+/// Certificates we were given by hand, rather than issued via enrollment, are left alone.
+pub(crate) fn needs_certificate(db_pool: &SqlitePool) -> bool {
+    let Ok(db_conn) = db_pool.get() else {
+        return false;
+    };
+
+    let cert_file =
+        PropertyValue::get_string_or(db_conn, PROPERTY_CONNECTION_CLIENT_CERT_FILE, String::new());
+    if cert_file.is_empty() {
+        return true;
+    }
+
+    if Path::new(&cert_file) != certs_folder().join(CLIENT_CERT_FILE) {
+        return false;
+    }
+
+    match certificate_expiry(&cert_file) {
+        Ok(not_after) => not_after - CERTIFICATE_RENEW_BEFORE_SECS <= now(),
+        Err(_) => true,
+    }
+}
+
+fn certificate_expiry(cert_file: &str) -> Result<i64, Error> {
+    let der = CertificateDer::from_pem_file(cert_file)?;
+    let certificate =
+        ClientCertificate::from_der(&der).ok_or_else(|| anyhow!("unreadable certificate"))?;
+
+    Ok(certificate.not_after)
+}
+
+fn certs_folder() -> PathBuf {
+    RuntimeProperties::global().folders().home().join(".certs")
+}
+
+// Only the agent itself may read its private key
+fn write_private_key(path: &Path, pem: &str) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(pem.as_bytes())?;
+    }
+
+    #[cfg(not(unix))]
+    fs::write(path, pem)?;
+
+    Ok(())
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
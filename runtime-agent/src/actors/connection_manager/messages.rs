@@ -0,0 +1,7 @@
+#[derive(Debug)]
+pub enum ConnectionManagerMessage {
+    /// Attempt to connect to the server using the stored connection strings
+    Connect,
+    /// The session with the server has ended
+    Disconnected { reason: Option<String> },
+}
@@ -1,5 +1,12 @@
 pub mod actor;
 pub mod arguments;
+mod command_policy;
+mod command_verification;
+mod commands;
+pub(crate) mod confirmation;
 mod connection_string;
+mod enrollment;
 pub mod messages;
+mod scripts;
+mod session;
 mod state;
@@ -0,0 +1,262 @@
+use crate::{
+    actors::connection_manager::command_policy::command_error,
+    actors::job_runner::{
+        job::{Job, JobOutput},
+        messages::JobRunnerMessage,
+    },
+    DEFAULT_PROPERTY_SCRIPTS_MAX_RUNTIME, PROPERTY_SCRIPTS_MAX_RUNTIME,
+};
+use database_agent::models::function_hashes::is_function_hash_allowed;
+use database_agent::models::properties::PropertyValue;
+use database_agent::SqlitePool;
+use ractor::rpc::CallResult;
+use ractor::ActorRef;
+use runtime_shared::protocol::{CommandError, CommandErrorCode, Inbound};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::{error, warn};
+
+/// The verb that runs a script, only if its hash is in the function hashes
+pub(crate) const RUN_SCRIPT_VERB: &str = "run_script";
+
+// Scripts without a `#!` line are run with this
+const DEFAULT_INTERPRETER: &str = "/bin/sh";
+
+// What the script is staged as in the job's working directory
+const SCRIPT_FILE: &str = "script";
+
+/// The payload of a `run_script` command, the script itself or the path of a file holding it
+///
+/// ```json
+/// { "script": "#!/bin/sh\necho hello", "args": ["world"], "timeout_secs": 30 }
+/// { "path": "/opt/scripts/rotate-logs.sh" }
+/// ```
+#[derive(Debug, Deserialize)]
+struct RunScript {
+    script: Option<String>,
+    path: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    // Capped by the `scripts::max_runtime` property
+    timeout_secs: Option<u64>,
+}
+
+/// A script whose hash is on the allowlist, ready to run
+#[derive(Debug)]
+pub(crate) struct Script {
+    content: Vec<u8>,
+    hash: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl Script {
+    /// Hash the script the command carries or names and check the hash is in the function
+    /// hashes, the error is why the command is refused. It gets no longer to run than is left
+    /// until the command's deadline.
+    pub fn prepare(
+        db_pool: &SqlitePool,
+        payload: &serde_json::Value,
+        until_deadline: Option<Duration>,
+    ) -> Result<Self, CommandError> {
+        let request: RunScript = serde_json::from_value(payload.clone()).map_err(|error| {
+            command_error(
+                CommandErrorCode::InvalidPayload,
+                format!("invalid {} payload, {}", RUN_SCRIPT_VERB, error),
+            )
+        })?;
+
+        let content = match (request.script, request.path.as_deref()) {
+            (Some(script), None) => script.into_bytes(),
+            (None, Some(path)) => fs::read(path).map_err(|error| {
+                // Whether the file is there is none of the server's business, only logged here
+                warn!(%path, errorMsg = %error, "unable to read script");
+                no_allowed_script(path)
+            })?,
+            _ => {
+                return Err(command_error(
+                    CommandErrorCode::InvalidPayload,
+                    format!("{} needs either a script or a path", RUN_SCRIPT_VERB),
+                ))
+            }
+        };
+
+        let hash = format!("{:x}", Sha256::digest(&content));
+        let script_path = request.path;
+        let allowed = db_pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut db_conn| is_function_hash_allowed(&mut db_conn, &hash))
+            .map_err(|error| {
+                error!(errorMsg = %error, "unable to read the function hashes");
+                command_error(
+                    CommandErrorCode::ExecutionFailed,
+                    format!("the function hashes could not be read, {}", error),
+                )
+            })?;
+        if !allowed {
+            // Nor is the hash of a file that isn't an allowed script
+            if let Some(path) = &script_path {
+                warn!(%path, %hash, "script hash is not in the function hashes");
+                return Err(no_allowed_script(path));
+            }
+            return Err(command_error(
+                CommandErrorCode::HashNotAllowed,
+                format!("script hash {} is not in the function hashes", hash),
+            ));
+        }
+
+        let max_runtime = db_pool
+            .get()
+            .map_or(DEFAULT_PROPERTY_SCRIPTS_MAX_RUNTIME, |db_conn| {
+                PropertyValue::get_int_or(
+                    db_conn,
+                    PROPERTY_SCRIPTS_MAX_RUNTIME,
+                    DEFAULT_PROPERTY_SCRIPTS_MAX_RUNTIME,
+                )
+            })
+            .max(1) as u64;
+
+        let timeout = Duration::from_secs(
+            request
+                .timeout_secs
+                .unwrap_or(max_runtime)
+                .clamp(1, max_runtime),
+        );
+
+        Ok(Self {
+            content,
+            hash,
+            args: request.args,
+            timeout: until_deadline.map_or(timeout, |until_deadline| timeout.min(until_deadline)),
+        })
+    }
+
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Run the script as a job, streaming its output to the server as it goes. The output
+    /// returned holds its exit code, output and how long it took, as far as it got when it
+    /// timed out or was cancelled.
+    pub async fn run(
+        self,
+        job_runner: Option<ActorRef<JobRunnerMessage>>,
+        tx: &UnboundedSender<Inbound>,
+        command_id: String,
+    ) -> (Option<CommandError>, serde_json::Value) {
+        let Some(job_runner) = job_runner else {
+            return (
+                Some(command_error(
+                    CommandErrorCode::ExecutionFailed,
+                    "the job runner is not running".to_string(),
+                )),
+                json!({ "hash": self.hash }),
+            );
+        };
+
+        // The interpreter is taken from the `#!` line, so the staged copy of exactly the bytes
+        // that were hashed doesn't need to be made executable
+        let (interpreter, interpreter_arg) = interpreter(&self.content);
+
+        let (output_tx, mut output_rx) = mpsc::unbounded_channel::<JobOutput>();
+        let forwarder = {
+            let tx = tx.clone();
+            let command_id = command_id.clone();
+            tokio::spawn(async move {
+                while let Some(JobOutput { stream, data }) = output_rx.recv().await {
+                    let _ = tx.send(Inbound::Output {
+                        command_id: command_id.clone(),
+                        stream,
+                        data,
+                    });
+                }
+            })
+        };
+
+        let job = Job {
+            job_id: command_id,
+            program: interpreter,
+            args: interpreter_arg
+                .into_iter()
+                .chain([SCRIPT_FILE.to_string()])
+                .chain(self.args)
+                .collect(),
+            files: vec![(SCRIPT_FILE.to_string(), self.content)],
+            timeout: self.timeout,
+            output: Some(output_tx),
+        };
+
+        let outcome = match job_runner
+            .call(|reply| JobRunnerMessage::Run { job, reply }, None)
+            .await
+        {
+            Ok(CallResult::Success(outcome)) => outcome,
+            Ok(_) => Err("the job runner did not reply".to_string()),
+            Err(error) => Err(error.to_string()),
+        };
+
+        // The job's end of the channel has gone with it, so every chunk goes out before the result
+        let _ = forwarder.await;
+
+        match outcome {
+            Ok(outcome) => {
+                let error = if outcome.cancelled {
+                    Some(command_error(
+                        CommandErrorCode::Cancelled,
+                        "script was cancelled before it finished".to_string(),
+                    ))
+                } else {
+                    outcome.timed_out.then(|| {
+                        command_error(
+                            CommandErrorCode::TimedOut,
+                            format!(
+                                "script ran for longer than {} seconds",
+                                self.timeout.as_secs()
+                            ),
+                        )
+                    })
+                };
+                let mut output = serde_json::to_value(&outcome).unwrap_or_default();
+                output["hash"] = json!(self.hash);
+                (error, output)
+            }
+            Err(error) => (
+                Some(command_error(
+                    CommandErrorCode::ExecutionFailed,
+                    format!("unable to run script, {}", error),
+                )),
+                json!({ "hash": self.hash }),
+            ),
+        }
+    }
+}
+
+// Unreadable files and files that aren't allowed scripts are refused alike
+fn no_allowed_script(path: &str) -> CommandError {
+    command_error(
+        CommandErrorCode::HashNotAllowed,
+        format!("{} is not an allowed script", path),
+    )
+}
+
+// The interpreter and its optional single argument named on the script's `#!` line
+fn interpreter(content: &[u8]) -> (String, Option<String>) {
+    let shebang = content
+        .strip_prefix(b"#!")
+        .and_then(|rest| rest.split(|byte| *byte == b'\n').next())
+        .map(|line| String::from_utf8_lossy(line).trim().to_string())
+        .filter(|line| !line.is_empty());
+
+    match shebang {
+        Some(line) => match line.split_once(char::is_whitespace) {
+            Some((interpreter, arg)) => (interpreter.to_string(), Some(arg.trim().to_string())),
+            None => (line, None),
+        },
+        None => (DEFAULT_INTERPRETER.to_string(), None),
+    }
+}
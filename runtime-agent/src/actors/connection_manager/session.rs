@@ -0,0 +1,374 @@
+use crate::{
+    actors::connection_manager::{
+        command_verification::{CommandVerifier, SeenCommands},
+        commands::{cancel_command, handle_command, IncomingCommand},
+        confirmation::PendingConfirmations,
+        connection_string::AgentConnectionStrings,
+        enrollment::{needs_certificate, PendingEnrollment},
+    },
+    actors::job_runner::messages::JobRunnerMessage,
+    CONNECTION_STRING_ACTIVE_STATUS, PROPERTY_CONNECTION_CA_FILE,
+    PROPERTY_CONNECTION_CLIENT_CERT_FILE, PROPERTY_CONNECTION_CLIENT_KEY_FILE,
+    PROPERTY_CONNECTION_TOKEN,
+};
+use anyhow::{anyhow, Error};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use database_agent::models::connection_strings::ConnectionStrings;
+use database_agent::models::properties::{set_property_value, PropertyValue};
+use database_agent::schema::tags;
+use database_agent::SqlitePool;
+use diesel::prelude::*;
+use futures_util::{SinkExt, StreamExt};
+use ractor::ActorRef;
+use runtime_shared::{
+    protocol::{Inbound, Outbound},
+    RuntimeProperties,
+};
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use rustls::{ClientConfig, RootCertStore};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    connect_async_tls_with_config, tungstenite::Message, Connector, MaybeTlsStream, WebSocketStream,
+};
+use tracing::{error, info, warn};
+use url::Url;
+
+pub(crate) type AgentSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// How long to wait before asking again when a refresh request goes unanswered
+const TOKEN_REFRESH_RETRY_SECS: u64 = 60;
+
+/// The token the agent presents to the server along with its lifetime,
+/// read from the (unverified) claims so the agent knows when to refresh it
+#[derive(Debug, Clone)]
+pub(crate) struct AgentToken {
+    pub token: String,
+    pub issued_at: u64,
+    pub expires_at: u64,
+    // The tenant the token was issued in, commands are signed for it
+    pub tenant: String,
+}
+
+#[derive(Deserialize)]
+struct AgentTokenClaims {
+    iat: u64,
+    exp: u64,
+    #[serde(default)]
+    aud: String,
+}
+
+impl AgentToken {
+    pub fn parse(token: &str) -> Option<Self> {
+        let payload = token.split('.').nth(1)?;
+        let decoded = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let claims: AgentTokenClaims = serde_json::from_slice(&decoded).ok()?;
+
+        Some(Self {
+            token: token.to_string(),
+            issued_at: claims.iat,
+            expires_at: claims.exp,
+            tenant: claims.aud,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now() >= self.expires_at
+    }
+
+    /// Refresh once 80% of the token lifetime has passed
+    pub fn refresh_at(&self) -> u64 {
+        self.issued_at + self.expires_at.saturating_sub(self.issued_at) * 4 / 5
+    }
+}
+
+/// Try each connection string in turn, returning the first socket the server accepts
+pub(crate) async fn connect<'a>(
+    db_pool: &SqlitePool,
+    connection_strings: &'a AgentConnectionStrings,
+) -> Result<(AgentSocket, &'a ConnectionStrings, AgentToken), Error> {
+    let stored_token = stored_token(db_pool);
+    let groups = agent_groups(db_pool);
+    let connector = Connector::Rustls(Arc::new(tls_client_config(db_pool)?));
+
+    for candidate in connection_strings.candidates() {
+        let mut url = match Url::parse(&candidate.value) {
+            Ok(url) => url,
+            Err(error) => {
+                warn!(connection_string = candidate.id, error = %error, "invalid connection string");
+                continue;
+            }
+        };
+
+        // Prefer a refreshed token, but only for the connection string that issued it
+        let url_token = url
+            .query_pairs()
+            .find(|(key, _)| key == "token")
+            .and_then(|(_, value)| AgentToken::parse(&value));
+        let token = match (&stored_token, candidate.status.as_str()) {
+            (Some(token), CONNECTION_STRING_ACTIVE_STATUS) if !token.is_expired() => {
+                Some(token.clone())
+            }
+            _ => url_token,
+        };
+        let Some(token) = token else {
+            warn!(
+                connection_string = candidate.id,
+                "connection string has no usable token"
+            );
+            continue;
+        };
+
+        // Identify ourselves to the server
+        let retained: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| !matches!(key.as_ref(), "id" | "token" | "groups"))
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(retained)
+            .append_pair("id", RuntimeProperties::global().id())
+            .append_pair("token", &token.token)
+            .append_pair("groups", &groups.join(","));
+
+        match connect_async_tls_with_config(url.as_str(), None, false, Some(connector.clone()))
+            .await
+        {
+            Ok((socket, _)) => {
+                info!(connection_string = candidate.id, "connected to server");
+                return Ok((socket, candidate, token));
+            }
+            Err(error) => {
+                warn!(connection_string = candidate.id, error = %error, "unable to connect to server");
+            }
+        }
+    }
+
+    Err(anyhow!("no connection string could reach the server"))
+}
+
+/// Service the connection until either side closes it, returning the reason if one was given
+pub(crate) async fn run_session(
+    socket: AgentSocket,
+    mut token: AgentToken,
+    db_pool: SqlitePool,
+    seen_commands: Arc<SeenCommands>,
+    pending_confirmations: Arc<PendingConfirmations>,
+    job_runner: Option<ActorRef<JobRunnerMessage>>,
+) -> Option<String> {
+    let (mut sender, mut receiver) = socket.split();
+
+    // outbound channel + writer task
+    let (tx, mut rx) = mpsc::unbounded_channel::<Inbound>();
+    let write_task = tokio::spawn(async move {
+        while let Some(inbound) = rx.recv().await {
+            let text = serde_json::to_string(&inbound).unwrap();
+            if sender.send(Message::Text(text.into())).await.is_err() {
+                break;
+            }
+        }
+        let _ = sender.close().await;
+    });
+
+    let mut refresh_at = token.refresh_at();
+    let mut disconnect_reason = None;
+
+    // Re-read each connection so a newly pinned key takes effect on reconnect
+    let command_verifier = CommandVerifier::load(
+        &db_pool,
+        seen_commands,
+        &token.tenant,
+        RuntimeProperties::global().id(),
+    );
+
+    // Ask the server for a client certificate if we have none or ours is about to expire
+    let mut pending_enrollment = None;
+    if needs_certificate(&db_pool) {
+        match PendingEnrollment::new() {
+            Ok((enrollment, csr)) => {
+                info!("requesting a client certificate from the server");
+                let _ = tx.send(Inbound::Enroll { csr });
+                pending_enrollment = Some(enrollment);
+            }
+            Err(error) => {
+                error!(errorMsg = %error, "unable to create a certificate signing request")
+            }
+        }
+    }
+
+    loop {
+        let refresh_in = Duration::from_secs(refresh_at.saturating_sub(now()));
+
+        tokio::select! {
+            msg = receiver.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(frame))) => {
+                        disconnect_reason = frame.map(|f| f.reason.to_string());
+                        break;
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(error)) => {
+                        disconnect_reason = Some(error.to_string());
+                        break;
+                    }
+                    None => break,
+                };
+
+                let outbound = match serde_json::from_str::<Outbound>(&text) {
+                    Ok(outbound) => outbound,
+                    Err(error) => {
+                        warn!(error = %error, "unrecognised message from server");
+                        continue;
+                    }
+                };
+
+                match outbound {
+                    Outbound::Ping { nonce } => {
+                        let _ = tx.send(Inbound::Pong { nonce });
+                    }
+                    Outbound::Command { command_id, verb, payload, issued_at, nonce, deadline, signature } => {
+                        let command = IncomingCommand { command_id, verb, payload, deadline };
+                        if let Err(error) = command_verifier.verify(&command, issued_at, &nonce, &signature) {
+                            error!(command_id = %command.command_id, verb = %command.verb, errorMsg = %error, "command refused");
+                            continue;
+                        }
+                        handle_command(&db_pool, &pending_confirmations, &job_runner, &tx, command);
+                    }
+                    Outbound::Cancel { command_id, issued_at, nonce, signature } => {
+                        if let Err(error) = command_verifier.verify_cancel(&command_id, issued_at, &nonce, &signature) {
+                            error!(%command_id, errorMsg = %error, "cancel refused");
+                            continue;
+                        }
+                        cancel_command(&pending_confirmations, &job_runner, command_id);
+                    }
+                    Outbound::Disconnect { reason } => {
+                        info!(?reason, "server requested disconnect");
+                        disconnect_reason = reason;
+                        break;
+                    }
+                    Outbound::Token { token: refreshed, .. } => {
+                        match AgentToken::parse(&refreshed) {
+                            Some(refreshed) => {
+                                store_token(&db_pool, &refreshed);
+                                token = refreshed;
+                                refresh_at = token.refresh_at();
+                                info!(expires_at = token.expires_at, "agent token refreshed");
+                            }
+                            None => error!("server sent an unreadable token"),
+                        }
+                    }
+                    Outbound::Certificate { certificate, expires_at, .. } => {
+                        match pending_enrollment.take() {
+                            Some(enrollment) => match enrollment.complete(&db_pool, &certificate) {
+                                Ok(()) => info!(expires_at, "client certificate issued"),
+                                Err(error) => error!(errorMsg = %error, "unable to store client certificate"),
+                            },
+                            None => warn!("server sent a certificate we did not ask for"),
+                        }
+                    }
+                    Outbound::EnrollFailed { reason } => {
+                        pending_enrollment = None;
+                        info!(%reason, "server did not issue a client certificate");
+                    }
+                }
+            }
+            _ = tokio::time::sleep(refresh_in) => {
+                info!(expires_at = token.expires_at, "requesting agent token refresh");
+                let _ = tx.send(Inbound::RefreshToken);
+                refresh_at = now() + TOKEN_REFRESH_RETRY_SECS;
+            }
+        }
+    }
+
+    // let the writer flush anything still queued, then close the socket
+    drop(tx);
+    let _ = write_task.await;
+
+    disconnect_reason
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn stored_token(db_pool: &SqlitePool) -> Option<AgentToken> {
+    let db_conn = db_pool.get().ok()?;
+
+    match PropertyValue::get_string_or(db_conn, PROPERTY_CONNECTION_TOKEN, String::new()) {
+        token if token.is_empty() => None,
+        token => AgentToken::parse(&token),
+    }
+}
+
+fn store_token(db_pool: &SqlitePool, token: &AgentToken) {
+    let result = db_pool.get().map_err(Error::from).and_then(|mut db_conn| {
+        set_property_value(
+            &mut db_conn,
+            PROPERTY_CONNECTION_TOKEN,
+            PropertyValue::String(token.token.clone()),
+            Some("Agent token issued by the server".to_string()),
+        )
+    });
+
+    if let Err(error) = result {
+        error!(errorMsg = %error, "unable to store refreshed agent token");
+    }
+}
+
+// Agents join the server groups matching their tags
+fn agent_groups(db_pool: &SqlitePool) -> Vec<String> {
+    match db_pool.get() {
+        Ok(mut db_conn) => tags::table
+            .select(tags::name)
+            .load::<String>(&mut db_conn)
+            .unwrap_or_default(),
+        Err(_) => vec![],
+    }
+}
+
+// Trust the public web PKI plus an optional private CA for self-hosted servers,
+// presenting our client certificate if we have been issued one
+fn tls_client_config(db_pool: &SqlitePool) -> Result<ClientConfig, Error> {
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    let ca_file =
+        PropertyValue::get_string_or(db_pool.get()?, PROPERTY_CONNECTION_CA_FILE, String::new());
+    if !ca_file.is_empty() {
+        for certificate in CertificateDer::pem_file_iter(&ca_file)? {
+            roots.add(certificate?)?;
+        }
+    }
+
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+
+    // Present the certificate issued to this agent when the server uses mutual TLS
+    let client_cert_file = PropertyValue::get_string_or(
+        db_pool.get()?,
+        PROPERTY_CONNECTION_CLIENT_CERT_FILE,
+        String::new(),
+    );
+    let client_key_file = PropertyValue::get_string_or(
+        db_pool.get()?,
+        PROPERTY_CONNECTION_CLIENT_KEY_FILE,
+        String::new(),
+    );
+    if client_cert_file.is_empty() || client_key_file.is_empty() {
+        return Ok(builder.with_no_client_auth());
+    }
+
+    let certificates =
+        CertificateDer::pem_file_iter(&client_cert_file)?.collect::<Result<Vec<_>, _>>()?;
+    let private_key = PrivateKeyDer::from_pem_file(&client_key_file)?;
+
+    Ok(builder.with_client_auth_cert(certificates, private_key)?)
+}
@@ -0,0 +1,38 @@
+use crate::actors::connection_manager::command_verification::SeenCommands;
+use crate::actors::connection_manager::confirmation::PendingConfirmations;
+use crate::actors::job_runner::messages::JobRunnerMessage;
+use database_agent::SqlitePool;
+use ractor::ActorRef;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+#[derive(Debug)]
+pub struct ConnectionManagerState {
+    pub db_pool: SqlitePool,
+    pub session: Option<JoinHandle<()>>,
+    pub retry_interval: u64,
+    // Outlives each session so commands can't be replayed after a reconnect
+    pub(crate) seen_commands: Arc<SeenCommands>,
+    // Commands the local policy holds back until they are confirmed through the local API
+    pub(crate) pending_confirmations: Arc<PendingConfirmations>,
+    // Scripts and other long running commands run as jobs through it
+    pub job_runner: Option<ActorRef<JobRunnerMessage>>,
+}
+
+impl ConnectionManagerState {
+    pub(crate) fn new(
+        db_pool: SqlitePool,
+        retry_interval: u64,
+        pending_confirmations: Arc<PendingConfirmations>,
+        job_runner: Option<ActorRef<JobRunnerMessage>>,
+    ) -> Self {
+        Self {
+            db_pool,
+            session: None,
+            retry_interval,
+            seen_commands: Arc::new(SeenCommands::default()),
+            pending_confirmations,
+            job_runner,
+        }
+    }
+}
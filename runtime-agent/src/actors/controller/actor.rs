@@ -6,15 +6,24 @@ use tracing::{error, info, instrument};
 
 use crate::actors::api::actor::{ApiActor, ApiStartupArguments};
 use crate::actors::api::messages::ApiMessage;
+use crate::actors::connection_manager::actor::ConnectionManager;
+use crate::actors::connection_manager::arguments::ConnectionManagerArguments;
+use crate::actors::connection_manager::confirmation::PendingConfirmations;
+use crate::actors::connection_manager::messages::ConnectionManagerMessage;
 use crate::actors::controller::arguments::AgentControllerArguments;
 use crate::actors::controller::messages::AgentControllerMessage;
 use crate::actors::controller::state::AgentControllerState;
+use crate::actors::job_runner::actor::JobRunner;
+use crate::actors::job_runner::arguments::JobRunnerArguments;
+use crate::actors::job_runner::messages::JobRunnerMessage;
 
 use crate::{
-    ACTOR_AGENT_API_NAME, DATABASE_NAME, DEFAULT_PROPERTY_LOGGING_FORMAT,
-    DEFAULT_PROPERTY_LOGGING_LEVEL, PROPERTY_LOGGING_FORMAT, PROPERTY_LOGGING_LEVEL,
+    ACTOR_AGENT_API_NAME, ACTOR_CONNECTION_MANAGER_NAME, ACTOR_JOB_RUNNER_NAME, DATABASE_NAME,
+    DEFAULT_PROPERTY_LOGGING_FORMAT, DEFAULT_PROPERTY_LOGGING_LEVEL, PROPERTY_LOGGING_FORMAT,
+    PROPERTY_LOGGING_LEVEL,
 };
 use runtime_shared::{initialise_logging, RuntimeProperties};
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Controller;
@@ -31,6 +40,9 @@ impl Actor for Controller {
         _myself: ActorRef<Self::Msg>,
         arguments: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
+        // Ensure TLS crypto provider is installed before any TLS operations
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
         // Initialise our state
         let mut state = AgentControllerState::new();
 
@@ -85,9 +97,29 @@ impl Actor for Controller {
     ) -> Result<(), ActorProcessingErr> {
         info!("Agent Controller has started");
 
+        // Commands held back by the local policy are confirmed through the API
+        let pending_confirmations = Arc::new(PendingConfirmations::default());
+
         // Start the API Server as a linked actor i.e. Controller is the supervisor
-        state.spawned_actors.api_server =
-            start_agent_api_server(myself, state.db_pool.clone().unwrap()).await;
+        state.spawned_actors.api_server = start_agent_api_server(
+            myself.clone(),
+            state.db_pool.clone().unwrap(),
+            pending_confirmations.clone(),
+        )
+        .await;
+
+        // Start the Job Runner that commands run their jobs through
+        state.spawned_actors.job_runner =
+            start_job_runner(myself.clone(), state.db_pool.clone().unwrap()).await;
+
+        // Start the Connection Manager to keep the agent connected to the server
+        state.spawned_actors.connection_manager = start_connection_manager(
+            myself,
+            state.db_pool.clone().unwrap(),
+            pending_confirmations,
+            state.spawned_actors.job_runner.clone(),
+        )
+        .await;
 
         Ok(())
     }
@@ -107,13 +139,17 @@ impl Actor for Controller {
 async fn start_agent_api_server(
     controller: ActorRef<AgentControllerMessage>,
     db_pool: SqlitePool,
+    pending_confirmations: Arc<PendingConfirmations>,
 ) -> Option<ActorRef<ApiMessage>> {
     // Start the API Server as a linked actor i.e. Controller is the supervisor
     match controller
         .spawn_linked(
             Some(ACTOR_AGENT_API_NAME.to_string()),
             ApiActor {},
-            ApiStartupArguments { db_pool },
+            ApiStartupArguments {
+                db_pool,
+                pending_confirmations,
+            },
         )
         .await
     {
@@ -125,3 +161,55 @@ async fn start_agent_api_server(
         }
     }
 }
+
+#[instrument(name = "Agent Controller - Start Connection Manager", level = "trace")]
+async fn start_connection_manager(
+    controller: ActorRef<AgentControllerMessage>,
+    db_pool: SqlitePool,
+    pending_confirmations: Arc<PendingConfirmations>,
+    job_runner: Option<ActorRef<JobRunnerMessage>>,
+) -> Option<ActorRef<ConnectionManagerMessage>> {
+    // Start the Connection Manager as a linked actor i.e. Controller is the supervisor
+    match controller
+        .spawn_linked(
+            Some(ACTOR_CONNECTION_MANAGER_NAME.to_string()),
+            ConnectionManager {},
+            ConnectionManagerArguments {
+                db_pool,
+                pending_confirmations,
+                job_runner,
+            },
+        )
+        .await
+    {
+        Ok(result) => Some(result.0),
+
+        Err(error) => {
+            error!(errorMsg = %error, "Error spawning {}", ACTOR_CONNECTION_MANAGER_NAME);
+            None
+        }
+    }
+}
+
+#[instrument(name = "Agent Controller - Start Job Runner", level = "trace")]
+async fn start_job_runner(
+    controller: ActorRef<AgentControllerMessage>,
+    db_pool: SqlitePool,
+) -> Option<ActorRef<JobRunnerMessage>> {
+    // Start the Job Runner as a linked actor i.e. Controller is the supervisor
+    match controller
+        .spawn_linked(
+            Some(ACTOR_JOB_RUNNER_NAME.to_string()),
+            JobRunner {},
+            JobRunnerArguments { db_pool },
+        )
+        .await
+    {
+        Ok(result) => Some(result.0),
+
+        Err(error) => {
+            error!(errorMsg = %error, "Error spawning {}", ACTOR_JOB_RUNNER_NAME);
+            None
+        }
+    }
+}
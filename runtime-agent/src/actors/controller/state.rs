@@ -1,4 +1,6 @@
 use crate::actors::api::messages::ApiMessage;
+use crate::actors::connection_manager::messages::ConnectionManagerMessage;
+use crate::actors::job_runner::messages::JobRunnerMessage;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::SqliteConnection;
 use ractor::ActorRef;
@@ -7,6 +9,8 @@ use tracing_appender::non_blocking::WorkerGuard;
 #[derive(Debug)]
 pub struct Actors {
     pub api_server: Option<ActorRef<ApiMessage>>,
+    pub connection_manager: Option<ActorRef<ConnectionManagerMessage>>,
+    pub job_runner: Option<ActorRef<JobRunnerMessage>>,
 }
 
 #[derive(Debug)]
@@ -22,7 +26,11 @@ impl AgentControllerState {
     pub fn new() -> Self {
         Self {
             tracing_worker_guards: vec![],
-            spawned_actors: Actors { api_server: None },
+            spawned_actors: Actors {
+                api_server: None,
+                connection_manager: None,
+                job_runner: None,
+            },
             db_pool: None,
         }
     }
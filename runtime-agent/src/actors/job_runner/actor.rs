@@ -0,0 +1,161 @@
+use ractor::{Actor, ActorProcessingErr, ActorRef};
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{error, info, instrument, warn};
+
+use crate::actors::job_runner::{
+    arguments::JobRunnerArguments,
+    job::JobOutcome,
+    messages::JobRunnerMessage,
+    sandbox::{job_folder_name, remove_job_folder, retention, run_job, sweep, SandboxSettings},
+    state::JobRunnerState,
+};
+use crate::ACTOR_JOB_RUNNER_NAME;
+use runtime_shared::RuntimeProperties;
+
+// How often job folders are checked against the retention period
+const SWEEP_INTERVAL: Duration = Duration::from_secs(300);
+
+// A cancel can overtake the job it is for, which is refused if it arrives within this long
+const EARLY_CANCEL_RETENTION: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct JobRunner;
+
+impl Actor for JobRunner {
+    type State = JobRunnerState;
+    type Msg = JobRunnerMessage;
+    type Arguments = JobRunnerArguments;
+
+    #[instrument(name = "Job Runner - Pre Start", level = "trace")]
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        args: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        Ok(JobRunnerState::new(
+            args.db_pool,
+            RuntimeProperties::global().folders().jobs().clone(),
+        ))
+    }
+
+    #[instrument(name = "Job Runner - Post Start", level = "trace")]
+    async fn post_start(
+        &self,
+        myself: ActorRef<Self::Msg>,
+        _state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        info!(name = ACTOR_JOB_RUNNER_NAME, "started successfully");
+
+        // Anything left from before a restart is swept straight away
+        myself.cast(JobRunnerMessage::Sweep)?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "Job Runner - Process Message", level = "trace")]
+    async fn handle(
+        &self,
+        myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            JobRunnerMessage::Run { job, reply } => {
+                let settings = match SandboxSettings::load(&state.db_pool) {
+                    Ok(settings) => settings,
+                    Err(error) => {
+                        error!(errorMsg = %error, job_id = %job.job_id, "unable to load the job sandbox settings");
+                        let _ = reply.send(Err(format!("invalid job sandbox settings, {}", error)));
+                        return Ok(());
+                    }
+                };
+
+                let folder = match job_folder_name(&job.job_id) {
+                    Ok(folder) => folder.to_string(),
+                    Err(error) => {
+                        let _ = reply.send(Err(error.to_string()));
+                        return Ok(());
+                    }
+                };
+
+                let cancelled_at = state.cancelled_early.remove(&folder);
+                if cancelled_at.is_some_and(|at| at.elapsed() < EARLY_CANCEL_RETENTION) {
+                    info!(job_id = %job.job_id, "job was cancelled before it started");
+                    let _ = reply.send(Ok(JobOutcome {
+                        exit_code: None,
+                        stdout: String::new(),
+                        stderr: String::new(),
+                        truncated: false,
+                        timed_out: false,
+                        cancelled: true,
+                        duration_ms: 0,
+                    }));
+                    return Ok(());
+                }
+                let cancel = match state.running.entry(folder.clone()) {
+                    Entry::Occupied(_) => {
+                        let _ = reply.send(Err(format!("job {} is already running", job.job_id)));
+                        return Ok(());
+                    }
+                    Entry::Vacant(entry) => entry.insert(Arc::new(Notify::new())).clone(),
+                };
+
+                let jobs_folder = state.jobs_folder.clone();
+                tokio::spawn(async move {
+                    let job_id = job.job_id.clone();
+                    let outcome =
+                        run_job(&jobs_folder, &settings, job, cancel)
+                            .await
+                            .map_err(|error| {
+                                error!(errorMsg = %error, %job_id, "job failed to run");
+                                format!("{:#}", error)
+                            });
+                    let _ = reply.send(outcome);
+                    let _ = myself.cast(JobRunnerMessage::Finished { folder });
+                });
+            }
+            JobRunnerMessage::Cancel { job_id } => {
+                // Stays in running until it has actually stopped
+                let Ok(folder) = job_folder_name(&job_id) else {
+                    warn!(%job_id, "no job to cancel");
+                    return Ok(());
+                };
+                match state.running.get(folder) {
+                    Some(cancel) => {
+                        info!(%job_id, "cancelling job");
+                        cancel.notify_one();
+                    }
+                    None => {
+                        // Its Run may still be on the way, or it has already finished
+                        info!(%job_id, "job not running, refusing it should it still start");
+                        state
+                            .cancelled_early
+                            .retain(|_, at| at.elapsed() < EARLY_CANCEL_RETENTION);
+                        state
+                            .cancelled_early
+                            .insert(folder.to_string(), Instant::now());
+                    }
+                }
+            }
+            JobRunnerMessage::Finished { folder } => {
+                state.running.remove(&folder);
+                if retention(&state.db_pool).is_zero() {
+                    remove_job_folder(&state.jobs_folder, &folder);
+                }
+            }
+            JobRunnerMessage::Sweep => {
+                sweep(
+                    &state.jobs_folder,
+                    retention(&state.db_pool),
+                    &state.running,
+                );
+                myself.send_after(SWEEP_INTERVAL, || JobRunnerMessage::Sweep);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,6 @@
+use database_agent::SqlitePool;
+
+#[derive(Debug)]
+pub struct JobRunnerArguments {
+    pub db_pool: SqlitePool,
+}
@@ -0,0 +1,40 @@
+use runtime_shared::protocol::OutputStream;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Something to run in the sandbox, staged in its own working directory under the jobs folder
+#[derive(Debug)]
+pub struct Job {
+    pub job_id: String,
+    pub program: String,
+    // Relative paths resolve against the job's working directory
+    pub args: Vec<String>,
+    // Written into the working directory before the job starts, file name to contents
+    pub files: Vec<(String, Vec<u8>)>,
+    pub timeout: Duration,
+    // Where output is streamed to while the job runs, up to `jobs::max_streamed_output`
+    pub output: Option<UnboundedSender<JobOutput>>,
+}
+
+/// A chunk of a running job's output, split only between whole characters
+#[derive(Debug)]
+pub struct JobOutput {
+    pub stream: OutputStream,
+    pub data: String,
+}
+
+/// How a job ended and what it wrote
+#[derive(Debug, Serialize)]
+pub struct JobOutcome {
+    // None when the job was ended by a signal, including when it timed out or was cancelled
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    // Output beyond `jobs::max_output` was dropped
+    pub truncated: bool,
+    pub timed_out: bool,
+    // Stopped by a `Cancel` before it finished
+    pub cancelled: bool,
+    pub duration_ms: u64,
+}
@@ -0,0 +1,18 @@
+use crate::actors::job_runner::job::{Job, JobOutcome};
+use ractor::RpcReplyPort;
+
+#[derive(Debug)]
+pub enum JobRunnerMessage {
+    /// Stage and run a job, replying with its outcome once it has finished
+    Run {
+        job: Job,
+        reply: RpcReplyPort<Result<JobOutcome, String>>,
+    },
+    /// Stop a running job, killing everything it started. It finishes as cancelled, as does a
+    /// job that only starts shortly after.
+    Cancel { job_id: String },
+    /// A job has finished and its working directory is no longer in use
+    Finished { folder: String },
+    /// Remove working directories that have outlived the retention period
+    Sweep,
+}
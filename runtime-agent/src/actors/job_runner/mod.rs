@@ -0,0 +1,7 @@
+pub mod actor;
+pub mod arguments;
+pub mod job;
+pub mod messages;
+#[cfg(unix)]
+mod sandbox;
+mod state;
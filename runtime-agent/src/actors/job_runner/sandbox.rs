@@ -0,0 +1,775 @@
+use crate::actors::job_runner::job::{Job, JobOutcome, JobOutput};
+use crate::{
+    DEFAULT_PROPERTY_JOBS_MAX_OUTPUT, DEFAULT_PROPERTY_JOBS_MAX_STREAMED_OUTPUT,
+    DEFAULT_PROPERTY_JOBS_RETENTION, PROPERTY_JOBS_ENV_PASSTHROUGH, PROPERTY_JOBS_LIMITS,
+    PROPERTY_JOBS_MAX_OUTPUT, PROPERTY_JOBS_MAX_STREAMED_OUTPUT, PROPERTY_JOBS_RETENTION,
+    PROPERTY_JOBS_USER,
+};
+use anyhow::{anyhow, Context, Error};
+use database_agent::models::properties::PropertyValue;
+use database_agent::SqlitePool;
+use runtime_shared::protocol::OutputStream;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::{chown, PermissionsExt};
+use std::path::{Component, Path};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, io, mem, ptr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{watch, Notify};
+use tracing::{error, info, warn};
+
+// The job runs in here, everything else in the job's folder is the agent's record of it
+const WORK_FOLDER: &str = "work";
+const STDOUT_FILE: &str = "stdout.log";
+const STDERR_FILE: &str = "stderr.log";
+const RESULT_FILE: &str = "result.json";
+
+// Once the job has gone its output gets this long to drain, anything it started that left its
+// process group keeps the pipes open and isn't waited on any longer
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Job ids are used as folder names, well within what any file system allows
+const MAX_JOB_ID_LENGTH: usize = 128;
+
+// Jobs get this rather than whatever PATH the agent was started with
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Resource limits applied to every job, read from the `jobs::limits` property
+///
+/// ```json
+/// { "cpu_secs": 60, "memory_bytes": 1073741824, "file_size_bytes": 104857600, "open_files": 256, "processes": 64 }
+/// ```
+#[derive(Debug, Deserialize)]
+struct ResourceLimits {
+    // Seconds of CPU time, defaults to the job's timeout
+    cpu_secs: Option<u64>,
+    // Address space
+    #[serde(default = "default_memory_bytes")]
+    memory_bytes: u64,
+    #[serde(default = "default_file_size_bytes")]
+    file_size_bytes: u64,
+    #[serde(default = "default_open_files")]
+    open_files: u64,
+    // Counted across everything the job's user runs, so only set it with a dedicated user
+    processes: Option<u64>,
+}
+
+fn default_memory_bytes() -> u64 {
+    4 * 1024 * 1024 * 1024
+}
+
+fn default_file_size_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_open_files() -> u64 {
+    1024
+}
+
+/// The unprivileged user named by `jobs::user` that jobs run as
+#[derive(Debug)]
+struct JobUser {
+    name: String,
+    uid: u32,
+    gid: u32,
+}
+
+/// How jobs are confined, read as each job starts so property changes apply to the next one
+#[derive(Debug)]
+pub(crate) struct SandboxSettings {
+    user: Option<JobUser>,
+    limits: ResourceLimits,
+    // Per stream, in bytes
+    max_output: usize,
+    // Per stream, in bytes. Streamed output isn't kept, so it may go well beyond `max_output`
+    max_streamed_output: usize,
+    // Variables from the agent's own environment that jobs may see, everything else is scrubbed
+    env_passthrough: Vec<String>,
+}
+
+impl SandboxSettings {
+    pub fn load(db_pool: &SqlitePool) -> Result<Self, Error> {
+        let user = PropertyValue::get_string_or(db_pool.get()?, PROPERTY_JOBS_USER, String::new());
+        let user = match user.trim() {
+            "" => None,
+            name => Some(lookup_user(name)?),
+        };
+
+        let limits = match PropertyValue::get_json_or(
+            db_pool.get()?,
+            PROPERTY_JOBS_LIMITS,
+            serde_json::Value::Null,
+        ) {
+            serde_json::Value::Null => serde_json::Value::Object(Default::default()),
+            limits => limits,
+        };
+        let limits = serde_json::from_value(limits)
+            .with_context(|| format!("invalid {} property", PROPERTY_JOBS_LIMITS))?;
+
+        let max_output = PropertyValue::get_int_or(
+            db_pool.get()?,
+            PROPERTY_JOBS_MAX_OUTPUT,
+            DEFAULT_PROPERTY_JOBS_MAX_OUTPUT,
+        );
+
+        let max_streamed_output = PropertyValue::get_int_or(
+            db_pool.get()?,
+            PROPERTY_JOBS_MAX_STREAMED_OUTPUT,
+            DEFAULT_PROPERTY_JOBS_MAX_STREAMED_OUTPUT,
+        );
+
+        let env_passthrough = serde_json::from_value(PropertyValue::get_json_or(
+            db_pool.get()?,
+            PROPERTY_JOBS_ENV_PASSTHROUGH,
+            serde_json::Value::Array(vec![]),
+        ))
+        .with_context(|| format!("invalid {} property", PROPERTY_JOBS_ENV_PASSTHROUGH))?;
+
+        Ok(Self {
+            user,
+            limits,
+            max_output: max_output.max(0) as usize,
+            max_streamed_output: max_streamed_output.max(0) as usize,
+            env_passthrough,
+        })
+    }
+
+    fn environment(&self, job: &Job, work_folder: &Path) -> Vec<(String, String)> {
+        let work_folder = work_folder.to_string_lossy().to_string();
+        let mut environment = vec![
+            ("PATH".to_string(), DEFAULT_PATH.to_string()),
+            ("HOME".to_string(), work_folder.clone()),
+            ("TMPDIR".to_string(), work_folder),
+            ("LANG".to_string(), "C.UTF-8".to_string()),
+            ("JOB_ID".to_string(), job.job_id.clone()),
+        ];
+        if let Some(user) = &self.user {
+            environment.push(("USER".to_string(), user.name.clone()));
+            environment.push(("LOGNAME".to_string(), user.name.clone()));
+        }
+
+        // Passed through variables win, so an agent can hand its jobs e.g. a proxy or its own PATH
+        environment.extend(
+            self.env_passthrough
+                .iter()
+                .filter_map(|name| env::var(name).ok().map(|value| (name.clone(), value))),
+        );
+        environment
+    }
+}
+
+/// How long a finished job's folder is kept for, from the `jobs::retention` property
+pub(crate) fn retention(db_pool: &SqlitePool) -> Duration {
+    let retention = db_pool
+        .get()
+        .map_or(DEFAULT_PROPERTY_JOBS_RETENTION, |db_conn| {
+            PropertyValue::get_int_or(
+                db_conn,
+                PROPERTY_JOBS_RETENTION,
+                DEFAULT_PROPERTY_JOBS_RETENTION,
+            )
+        });
+    Duration::from_secs(retention.max(0) as u64)
+}
+
+/// The folder a job is staged in under the jobs folder. Job ids come from the server, so only
+/// those that can't step outside it or be mistaken for another job's are taken as they are.
+pub(crate) fn job_folder_name(job_id: &str) -> Result<&str, Error> {
+    let valid = !job_id.is_empty()
+        && job_id.len() <= MAX_JOB_ID_LENGTH
+        && job_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(anyhow!("invalid job id {:?}", job_id));
+    }
+    Ok(job_id)
+}
+
+/// Stage a job in its own folder and run it there, confined by the sandbox settings, until it
+/// exits, runs out of time or `cancel` is notified
+pub(crate) async fn run_job(
+    jobs_folder: &Path,
+    settings: &SandboxSettings,
+    job: Job,
+    cancel: Arc<Notify>,
+) -> Result<JobOutcome, Error> {
+    let job_folder = jobs_folder.join(job_folder_name(&job.job_id)?);
+    let work_folder = job_folder.join(WORK_FOLDER);
+    stage(jobs_folder, &job_folder, &work_folder, settings, &job)?;
+
+    let mut command = Command::new(&job.program);
+    command
+        .args(&job.args)
+        .current_dir(&work_folder)
+        .env_clear()
+        .envs(settings.environment(&job, &work_folder))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        // Its own process group, so whatever the job starts can be killed along with it
+        .process_group(0);
+    if let Some(user) = &settings.user {
+        command.uid(user.uid).gid(user.gid);
+    }
+
+    let limits = [
+        (
+            libc::RLIMIT_CPU,
+            Some(
+                settings
+                    .limits
+                    .cpu_secs
+                    .unwrap_or(job.timeout.as_secs().max(1)),
+            ),
+        ),
+        (libc::RLIMIT_AS, Some(settings.limits.memory_bytes)),
+        (libc::RLIMIT_FSIZE, Some(settings.limits.file_size_bytes)),
+        (libc::RLIMIT_NOFILE, Some(settings.limits.open_files)),
+        (libc::RLIMIT_NPROC, settings.limits.processes),
+        (libc::RLIMIT_CORE, Some(0)),
+    ];
+    // Safety: only getrlimit and setrlimit are called between fork and exec, both are
+    // async-signal-safe and nothing is allocated
+    unsafe {
+        command.pre_exec(move || {
+            for (resource, limit) in limits {
+                let Some(limit) = limit else { continue };
+                let mut current: libc::rlimit = mem::zeroed();
+                if libc::getrlimit(resource, &mut current) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // Limits can only be lowered without privileges
+                let limit = (limit as libc::rlim_t).min(current.rlim_max);
+                let limit = libc::rlimit {
+                    rlim_cur: limit,
+                    rlim_max: limit,
+                };
+                if libc::setrlimit(resource, &limit) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+
+    let started = Instant::now();
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("unable to start {}", job.program))?;
+    let process_group = child.id().map(|id| id as libc::pid_t);
+    info!(job_id = %job.job_id, program = %job.program, ?process_group, "job started");
+
+    let streamer = |stream| {
+        job.output.clone().map(|sender| Streamer {
+            stream,
+            sender,
+            remaining: settings.max_streamed_output,
+            pending: vec![],
+        })
+    };
+    let (stop_capture, capture_stopped) = watch::channel(false);
+    let stdout = tokio::spawn(capture(
+        child.stdout.take(),
+        settings.max_output,
+        streamer(OutputStream::Stdout),
+        capture_stopped.clone(),
+    ));
+    let stderr = tokio::spawn(capture(
+        child.stderr.take(),
+        settings.max_output,
+        streamer(OutputStream::Stderr),
+        capture_stopped,
+    ));
+
+    let (mut timed_out, mut cancelled) = (false, false);
+    let status = tokio::select! {
+        status = child.wait() => Some(status),
+        _ = tokio::time::sleep(job.timeout) => {
+            timed_out = true;
+            None
+        }
+        _ = cancel.notified() => {
+            cancelled = true;
+            None
+        }
+    };
+
+    // Nothing the job started may outlive it, its output only ends once they have all gone
+    if let Some(process_group) = process_group {
+        unsafe {
+            libc::killpg(process_group, libc::SIGKILL);
+        }
+    }
+
+    let status = match status {
+        Some(status) => Some(status?),
+        None => {
+            let _ = child.wait().await;
+            None
+        }
+    };
+
+    let mut captured = Box::pin(async { (stdout.await, stderr.await) });
+    let (stdout, stderr) = match tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, &mut captured).await {
+        Ok(captured) => captured,
+        Err(_) => {
+            warn!(job_id = %job.job_id, "job output still open after it was killed, something it started left its process group");
+            let _ = stop_capture.send(true);
+            captured.await
+        }
+    };
+    let (stdout, stdout_truncated) = stdout?;
+    let (stderr, stderr_truncated) = stderr?;
+
+    let outcome = JobOutcome {
+        exit_code: status.and_then(|status| status.code()),
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        truncated: stdout_truncated || stderr_truncated,
+        timed_out,
+        cancelled,
+        duration_ms: started.elapsed().as_millis() as u64,
+    };
+    info!(job_id = %job.job_id, exit_code = ?outcome.exit_code, timed_out, cancelled, duration_ms = outcome.duration_ms, "job finished");
+
+    // The record is kept beside the working directory, where the job itself can't reach it
+    let record = fs::write(job_folder.join(STDOUT_FILE), &stdout)
+        .and_then(|_| fs::write(job_folder.join(STDERR_FILE), &stderr))
+        .and_then(|_| {
+            fs::write(
+                job_folder.join(RESULT_FILE),
+                serde_json::to_vec_pretty(&outcome)?,
+            )
+        });
+    if let Err(error) = record {
+        error!(errorMsg = %error, job_id = %job.job_id, "unable to record job outcome");
+    }
+
+    Ok(outcome)
+}
+
+/// Remove the folders of jobs that finished longer ago than the retention period
+pub(crate) fn sweep(
+    jobs_folder: &Path,
+    retention: Duration,
+    running: &HashMap<String, Arc<Notify>>,
+) {
+    let Ok(entries) = fs::read_dir(jobs_folder) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let folder = entry.file_name().to_string_lossy().to_string();
+        if running.contains_key(&folder) {
+            continue;
+        }
+
+        let expired = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().unwrap_or_default() >= retention);
+        if expired {
+            remove_job_folder(jobs_folder, &folder);
+        }
+    }
+}
+
+pub(crate) fn remove_job_folder(jobs_folder: &Path, folder: &str) {
+    let path = jobs_folder.join(folder);
+    let removed = if path.is_dir() {
+        fs::remove_dir_all(&path)
+    } else {
+        fs::remove_file(&path)
+    };
+    match removed {
+        Ok(()) => info!(%folder, "job folder removed"),
+        Err(error) => error!(errorMsg = %error, %folder, "unable to remove job folder"),
+    }
+}
+
+// The job's folder only lets the job's user through to its working directory, which it owns
+fn stage(
+    jobs_folder: &Path,
+    job_folder: &Path,
+    work_folder: &Path,
+    settings: &SandboxSettings,
+    job: &Job,
+) -> Result<(), Error> {
+    fs::create_dir_all(jobs_folder)?;
+    fs::create_dir(job_folder)
+        .with_context(|| format!("unable to stage job {}", job_folder.display()))?;
+    fs::set_permissions(job_folder, fs::Permissions::from_mode(0o711))?;
+    fs::create_dir(work_folder)?;
+    fs::set_permissions(work_folder, fs::Permissions::from_mode(0o700))?;
+
+    for (name, contents) in &job.files {
+        let mut components = Path::new(name).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(anyhow!("job file {} must be a plain file name", name));
+        }
+
+        let file = work_folder.join(name);
+        fs::write(&file, contents)?;
+        if let Some(user) = &settings.user {
+            chown(&file, Some(user.uid), Some(user.gid))?;
+        }
+    }
+
+    if let Some(user) = &settings.user {
+        chown(work_folder, Some(user.uid), Some(user.gid))?;
+    }
+
+    Ok(())
+}
+
+// Sends a job's output on as it arrives, holding back a character split across reads
+struct Streamer {
+    stream: OutputStream,
+    sender: UnboundedSender<JobOutput>,
+    // Bytes that may still be streamed
+    remaining: usize,
+    pending: Vec<u8>,
+}
+
+impl Streamer {
+    fn send(&mut self, bytes: &[u8]) {
+        let bytes = &bytes[..bytes.len().min(self.remaining)];
+        self.remaining -= bytes.len();
+        self.pending.extend_from_slice(bytes);
+
+        // Everything up to an incomplete character at the end, invalid bytes are replaced
+        let complete = match std::str::from_utf8(&self.pending) {
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            _ => self.pending.len(),
+        };
+        let data = self.pending.drain(..complete).collect::<Vec<_>>();
+        self.forward(&data);
+    }
+
+    fn flush(&mut self) {
+        let data = mem::take(&mut self.pending);
+        self.forward(&data);
+    }
+
+    fn forward(&self, data: &[u8]) {
+        if !data.is_empty() {
+            let _ = self.sender.send(JobOutput {
+                stream: self.stream,
+                data: String::from_utf8_lossy(data).into_owned(),
+            });
+        }
+    }
+}
+
+// Keep up to the cap and drain the rest, so a job isn't blocked writing output nobody reads
+async fn capture(
+    pipe: Option<impl AsyncRead + Unpin>,
+    max_output: usize,
+    mut streamer: Option<Streamer>,
+    mut stopped: watch::Receiver<bool>,
+) -> (Vec<u8>, bool) {
+    let mut output = vec![];
+    let mut truncated = false;
+    let Some(mut pipe) = pipe else {
+        return (output, truncated);
+    };
+
+    let mut buffer = [0u8; 8192];
+    loop {
+        let read = tokio::select! {
+            read = pipe.read(&mut buffer) => read,
+            // Whatever hasn't been read by now is lost
+            _ = stopped.wait_for(|stopped| *stopped) => {
+                truncated = true;
+                break;
+            }
+        };
+        match read {
+            Ok(0) | Err(_) => break,
+            Ok(read) => {
+                let room = max_output.saturating_sub(output.len());
+                output.extend_from_slice(&buffer[..read.min(room)]);
+                truncated |= read > room;
+                if let Some(streamer) = &mut streamer {
+                    streamer.send(&buffer[..read]);
+                }
+            }
+        }
+    }
+
+    if let Some(streamer) = &mut streamer {
+        streamer.flush();
+    }
+    (output, truncated)
+}
+
+fn lookup_user(name: &str) -> Result<JobUser, Error> {
+    let c_name = CString::new(name)?;
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut found = ptr::null_mut();
+
+    let code = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if code != 0 || found.is_null() {
+        return Err(anyhow!("unknown {} {}", PROPERTY_JOBS_USER, name));
+    }
+
+    Ok(JobUser {
+        name: name.to_string(),
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    // A fresh jobs folder per test, resolved so a symlinked temp folder doesn't change what the
+    // job sees as its working directory
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("sandbox-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        fs::canonicalize(folder).unwrap()
+    }
+
+    fn settings(max_output: usize, max_streamed_output: usize) -> SandboxSettings {
+        SandboxSettings {
+            user: None,
+            limits: serde_json::from_value(serde_json::json!({})).unwrap(),
+            max_output,
+            max_streamed_output,
+            env_passthrough: vec![],
+        }
+    }
+
+    fn job(job_id: &str, script: &str, timeout: Duration) -> Job {
+        Job {
+            job_id: job_id.to_string(),
+            program: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            files: vec![],
+            timeout,
+            output: None,
+        }
+    }
+
+    #[test]
+    fn job_folder_name_takes_only_plain_ids() {
+        assert_eq!(
+            job_folder_name("0b9f1e2c-5d4a-4f1e-9c3b-2a7d8e6f1a0b").unwrap(),
+            "0b9f1e2c-5d4a-4f1e-9c3b-2a7d8e6f1a0b"
+        );
+        assert_eq!(job_folder_name("a_b").unwrap(), "a_b");
+        for job_id in [
+            "",
+            "a/b",
+            "..",
+            ".",
+            "a b",
+            "ä",
+            &"a".repeat(MAX_JOB_ID_LENGTH + 1),
+        ] {
+            assert!(job_folder_name(job_id).is_err(), "{:?}", job_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn job_is_staged_in_its_own_folder_and_recorded_beside_it() {
+        let jobs_folder = test_folder("staged");
+        let mut job = job(
+            "staged",
+            "cat input.txt; pwd; echo oops >&2",
+            Duration::from_secs(10),
+        );
+        job.files = vec![("input.txt".to_string(), b"hello\n".to_vec())];
+
+        let outcome = run_job(
+            &jobs_folder,
+            &settings(1024, 0),
+            job,
+            Arc::new(Notify::new()),
+        )
+        .await
+        .unwrap();
+
+        let job_folder = jobs_folder.join("staged");
+        let work_folder = job_folder.join(WORK_FOLDER);
+        assert_eq!(outcome.exit_code, Some(0));
+        assert_eq!(
+            outcome.stdout,
+            format!("hello\n{}\n", work_folder.display())
+        );
+        assert_eq!(outcome.stderr, "oops\n");
+        assert!(!outcome.truncated && !outcome.timed_out && !outcome.cancelled);
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&job_folder), 0o711);
+        assert_eq!(mode(&work_folder), 0o700);
+        assert_eq!(
+            fs::read_to_string(job_folder.join(STDOUT_FILE)).unwrap(),
+            outcome.stdout
+        );
+        assert_eq!(
+            fs::read_to_string(job_folder.join(STDERR_FILE)).unwrap(),
+            "oops\n"
+        );
+        assert!(job_folder.join(RESULT_FILE).is_file());
+    }
+
+    #[tokio::test]
+    async fn job_files_must_stay_in_the_working_directory() {
+        let jobs_folder = test_folder("files");
+
+        for name in ["../escape", "sub/file", "/etc/passwd"] {
+            let mut job = job("files", "true", Duration::from_secs(10));
+            job.files = vec![(name.to_string(), b"x".to_vec())];
+            let _ = fs::remove_dir_all(jobs_folder.join("files"));
+
+            let error = run_job(
+                &jobs_folder,
+                &settings(1024, 0),
+                job,
+                Arc::new(Notify::new()),
+            )
+            .await
+            .unwrap_err();
+            assert!(error.to_string().contains("plain file name"), "{}", error);
+        }
+        assert!(!jobs_folder.join("escape").exists());
+    }
+
+    #[tokio::test]
+    async fn output_beyond_the_caps_is_dropped() {
+        let jobs_folder = test_folder("caps");
+        let (sender, mut receiver) = unbounded_channel();
+        let mut job = job(
+            "caps",
+            "printf '%0100d' 0; printf '%0100d' 0 >&2",
+            Duration::from_secs(10),
+        );
+        job.output = Some(sender);
+
+        let outcome = run_job(
+            &jobs_folder,
+            &settings(10, 25),
+            job,
+            Arc::new(Notify::new()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(outcome.stdout.len(), 10);
+        assert_eq!(outcome.stderr.len(), 10);
+        assert!(outcome.truncated);
+
+        let (mut stdout, mut stderr) = (0, 0);
+        while let Ok(output) = receiver.try_recv() {
+            match output.stream {
+                OutputStream::Stdout => stdout += output.data.len(),
+                OutputStream::Stderr => stderr += output.data.len(),
+            }
+        }
+        assert_eq!((stdout, stderr), (25, 25));
+    }
+
+    #[tokio::test]
+    async fn timed_out_job_is_killed_with_everything_it_started() {
+        let jobs_folder = test_folder("timeout");
+        let job = job(
+            "timeout",
+            "sleep 30 & echo $! > sleep.pid; wait",
+            Duration::from_millis(500),
+        );
+
+        let started = Instant::now();
+        let outcome = run_job(
+            &jobs_folder,
+            &settings(1024, 0),
+            job,
+            Arc::new(Notify::new()),
+        )
+        .await
+        .unwrap();
+
+        assert!(outcome.timed_out && !outcome.cancelled);
+        assert_eq!(outcome.exit_code, None);
+        assert!(started.elapsed() < OUTPUT_DRAIN_TIMEOUT);
+
+        // Gone, or at most a zombie nobody has reaped yet
+        let pid = fs::read_to_string(
+            jobs_folder
+                .join("timeout")
+                .join(WORK_FOLDER)
+                .join("sleep.pid"),
+        )
+        .unwrap();
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+        assert!(stat.is_empty() || stat.contains(") Z "), "{}", stat);
+    }
+
+    #[tokio::test]
+    async fn cancelled_job_finishes_as_cancelled() {
+        let jobs_folder = test_folder("cancel");
+        let cancel = Arc::new(Notify::new());
+        // A permit is stored, so a cancel sent before the job waits on it still counts
+        cancel.notify_one();
+
+        let outcome = run_job(
+            &jobs_folder,
+            &settings(1024, 0),
+            job("cancel", "sleep 30", Duration::from_secs(60)),
+            cancel,
+        )
+        .await
+        .unwrap();
+
+        assert!(outcome.cancelled && !outcome.timed_out);
+    }
+
+    #[test]
+    fn sweep_removes_only_expired_jobs_that_are_not_running() {
+        let jobs_folder = test_folder("sweep");
+        let expired = SystemTime::now() - Duration::from_secs(3600);
+        for folder in ["old", "recent", "running"] {
+            fs::create_dir(jobs_folder.join(folder)).unwrap();
+            if folder != "recent" {
+                fs::File::open(jobs_folder.join(folder))
+                    .unwrap()
+                    .set_modified(expired)
+                    .unwrap();
+            }
+        }
+        let running = HashMap::from([("running".to_string(), Arc::new(Notify::new()))]);
+
+        sweep(&jobs_folder, Duration::from_secs(60), &running);
+
+        assert!(!jobs_folder.join("old").exists());
+        assert!(jobs_folder.join("recent").exists());
+        assert!(jobs_folder.join("running").exists());
+    }
+}
@@ -0,0 +1,28 @@
+use database_agent::SqlitePool;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Notify;
+
+#[derive(Debug)]
+pub struct JobRunnerState {
+    pub db_pool: SqlitePool,
+    pub jobs_folder: PathBuf,
+    // The working directories of jobs still running, the sweep leaves these alone, and what
+    // cancels each job
+    pub running: HashMap<String, Arc<Notify>>,
+    // Jobs cancelled before they were started and when, so they are refused if they still come
+    pub cancelled_early: HashMap<String, Instant>,
+}
+
+impl JobRunnerState {
+    pub fn new(db_pool: SqlitePool, jobs_folder: PathBuf) -> Self {
+        Self {
+            db_pool,
+            jobs_folder,
+            running: HashMap::new(),
+            cancelled_early: HashMap::new(),
+        }
+    }
+}
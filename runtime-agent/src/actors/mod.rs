@@ -1,3 +1,4 @@
 pub mod api;
 pub mod connection_manager;
 pub mod controller;
+pub mod job_runner;
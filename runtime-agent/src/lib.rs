@@ -2,21 +2,55 @@ pub mod actors;
 
 // Global Constants
 pub const DATABASE_NAME: &str = "agent.db";
+pub const API_TOKENS_FOLDER: &str = ".auth";
+pub const API_TOKENS_FILE: &str = "api_tokens.json";
+pub const API_READ_ONLY_TOKENS_FILE: &str = "api_tokens.read_only.json";
 
 // Constants used by the agent controller
 pub(crate) const ACTOR_AGENT_API_NAME: &str = "Agent Api";
+pub(crate) const ACTOR_CONNECTION_MANAGER_NAME: &str = "Connection Manager";
+pub(crate) const ACTOR_JOB_RUNNER_NAME: &str = "Job Runner";
 pub(crate) const CONNECTION_STRING_PENDING_STATUS: &str = "pending";
 pub(crate) const CONNECTION_STRING_ACTIVE_STATUS: &str = "active";
+pub(crate) const CONNECTION_STRING_INACTIVE_STATUS: &str = "inactive";
 
 // Default Property names used for configuration
 pub(crate) const PROPERTY_API_PORT: &str = "api_port";
+pub(crate) const PROPERTY_API_TOKEN_GROUP: &str = "api::token_group";
+pub(crate) const PROPERTY_API_SOCKET_PATH: &str = "api::socket_path";
+pub(crate) const PROPERTY_API_SOCKET_MODE: &str = "api::socket_mode";
 pub(crate) const PROPERTY_LOGGING_FORMAT: &str = "logging::format";
 pub(crate) const PROPERTY_LOGGING_LEVEL: &str = "logging::level";
+pub(crate) const PROPERTY_CONNECTION_TOKEN: &str = "connection::token";
+pub(crate) const PROPERTY_CONNECTION_CA_FILE: &str = "connection::ca_file";
+pub(crate) const PROPERTY_CONNECTION_CLIENT_CERT_FILE: &str = "connection::client_cert_file";
+pub(crate) const PROPERTY_CONNECTION_CLIENT_KEY_FILE: &str = "connection::client_key_file";
+pub(crate) const PROPERTY_CONNECTION_RETRY_INTERVAL: &str = "connection::retry_interval";
+pub(crate) const PROPERTY_CONNECTION_COMMAND_PUBLIC_KEY: &str = "connection::command_public_key";
+pub(crate) const PROPERTY_CONNECTION_COMMAND_MAX_AGE: &str = "connection::command_max_age";
+pub(crate) const PROPERTY_POLICY_FILE: &str = "policy::file";
+pub(crate) const PROPERTY_POLICY_COMMANDS: &str = "policy::commands";
+pub(crate) const PROPERTY_POLICY_CONFIRM_TIMEOUT: &str = "policy::confirm_timeout";
+pub(crate) const PROPERTY_SCRIPTS_MAX_RUNTIME: &str = "scripts::max_runtime";
+pub(crate) const PROPERTY_JOBS_USER: &str = "jobs::user";
+pub(crate) const PROPERTY_JOBS_LIMITS: &str = "jobs::limits";
+pub(crate) const PROPERTY_JOBS_MAX_OUTPUT: &str = "jobs::max_output";
+pub(crate) const PROPERTY_JOBS_MAX_STREAMED_OUTPUT: &str = "jobs::max_streamed_output";
+pub(crate) const PROPERTY_JOBS_ENV_PASSTHROUGH: &str = "jobs::env_passthrough";
+pub(crate) const PROPERTY_JOBS_RETENTION: &str = "jobs::retention";
 
 // Property defaults, if property names not loaded into the database
 pub(crate) const DEFAULT_PROPERTY_API_PORT: i32 = 8174;
+pub(crate) const DEFAULT_PROPERTY_API_SOCKET_MODE: &str = "660";
 pub(crate) const DEFAULT_PROPERTY_LOGGING_FORMAT: &str = "pretty";
 pub(crate) const DEFAULT_PROPERTY_LOGGING_LEVEL: &str = "error";
+pub(crate) const DEFAULT_PROPERTY_CONNECTION_RETRY_INTERVAL: i32 = 10;
+pub(crate) const DEFAULT_PROPERTY_CONNECTION_COMMAND_MAX_AGE: i32 = 60;
+pub(crate) const DEFAULT_PROPERTY_POLICY_CONFIRM_TIMEOUT: i32 = 300;
+pub(crate) const DEFAULT_PROPERTY_SCRIPTS_MAX_RUNTIME: i32 = 300;
+pub(crate) const DEFAULT_PROPERTY_JOBS_MAX_OUTPUT: i32 = 65536;
+pub(crate) const DEFAULT_PROPERTY_JOBS_MAX_STREAMED_OUTPUT: i32 = 8388608;
+pub(crate) const DEFAULT_PROPERTY_JOBS_RETENTION: i32 = 86400;
 
 pub use crate::actors::controller::actor::Controller as AgentRuntimeController;
 pub use crate::actors::controller::arguments::AgentControllerArguments;
@@ -1,24 +1,46 @@
-use crate::actors::api::{state::ApiActorState, utils::get_request_id_header_name};
+use crate::actors::api::{
+    bootstrap_admin::ensure_bootstrap_admin,
+    certificate_authority::CertificateAuthority,
+    client_ip::{make_request_span, resolve_client_ip, TrustedProxies},
+    command_signer::CommandSigner,
+    rate_limiting::{client_ip_rate_limit_layer, rate_limit_layer, RouteRateLimits},
+    self_signed::ensure_self_signed_certificate,
+    state::{ApiActorState, V1ApiState},
+    utils::get_request_id_header_name,
+    v1::handlers::agent::connect_guard::{prune_connect_failures, AgentConnectGuard},
+    v1::handlers::agent::enrollment::{publish_revocation_list, republish_revocation_list},
+    v1::handlers::agent::output::sweep_command_outputs,
+    v1::handlers::agent::revocation::start_revocation_monitor,
+    v1::handlers::tenants::{purge_deleted_tenants, refresh_tenant_origins, watch_tenant_origins},
+    v1::ip_access::IpAccessRules,
+    v1::jwt::JwtKeySet,
+    v1::tenant::{resolve_tenant, TenantDomain},
+};
 use crate::actors::{
     api::{cors::to_cors_layer, messages::ApiMessage, state::ApiState, v1::routes::api_router},
     controller::ACTOR_API_SERVER_NAME,
 };
-use axum::Router;
-use config_server::{ApiConfiguration, CorsConfiguration, RateLimitingConfiguration};
+use axum::{
+    http::header::{AUTHORIZATION, COOKIE},
+    middleware, Router,
+};
+use config_server::{
+    ApiConfiguration, CorsConfiguration, CorsMode, MtlsMode, RateLimitingConfiguration,
+};
+use database_server::SqlitePool;
 use ractor::{Actor, ActorProcessingErr, ActorRef};
-use runtime_shared::api_server::APIServer;
+use runtime_shared::api_server::{tls::TlsReloader, APIServer, Listener};
 use runtime_shared::RuntimeProperties;
-use std::net::SocketAddr;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::Notify;
 use tower::ServiceBuilder;
-use tower_governor::{
-    governor::GovernorConfigBuilder, key_extractor::SmartIpKeyExtractor, GovernorLayer,
-};
 use tower_http::request_id::MakeRequestUuid;
+use tower_http::sensitive_headers::SetSensitiveRequestHeadersLayer;
 use tower_http::timeout::TimeoutLayer;
-use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
 use tower_http::{
     compression::CompressionLayer,
     request_id::{PropagateRequestIdLayer, SetRequestIdLayer},
@@ -30,6 +52,7 @@ pub struct ApiStartupArguments {
     pub api_config: ApiConfiguration,
     pub cors: CorsConfiguration,
     pub rate_limiting: RateLimitingConfiguration,
+    pub db_pool: SqlitePool,
 }
 
 #[derive(Debug)]
@@ -38,44 +61,41 @@ pub struct ApiActor {}
 impl ApiActor {
     fn router(
         state: ApiState,
+        v1_state: Arc<V1ApiState>,
         cors: CorsConfiguration,
         port: u16,
+        trusted_proxies: TrustedProxies,
         rate_limiting: RateLimitingConfiguration,
         api_request_timeout_seconds: u64,
     ) -> Router {
         // Create the CORS layer based on passed in configuration
-        let cors_layer = to_cors_layer(cors, port);
-
-        // Setup the standard rate limiting configuration
-        // - We are utilising [tower-governor](https://github.com/benwis/tower-governor/)
-        // - This is limited per client IP Address
-        let rate_limiter_config = GovernorConfigBuilder::default()
-            .per_second(rate_limiting.per_second)
-            .burst_size(rate_limiting.burst_size)
-            .key_extractor(SmartIpKeyExtractor)
-            .use_headers()
-            .finish()
-            .unwrap();
-
-        // Initialise a seperate background task to cleanup old tracking entries in RAM
-        // - This avoids over consumption of RAM when tracking previous API requests
-        let rate_limiter = rate_limiter_config.limiter().clone();
-        let interval = Duration::from_secs(rate_limiting.cleanup_duration);
-        std::thread::spawn(move || loop {
-            std::thread::sleep(interval);
-            tracing::info!("rate limiting storage size: {}", rate_limiter.len());
-            rate_limiter.retain_recent();
-        });
+        let cors_layer = to_cors_layer(cors, port, state.tenant_origins.clone());
+
+        // Requests are counted per client IP, tenant or credential as configured, with
+        // stricter limits on the route groups that need them
+        let rate_limit_layer = rate_limit_layer(&rate_limiting);
+        let client_ip_rate_limit_layer = client_ip_rate_limit_layer(&rate_limiting);
+        let route_rate_limits = RouteRateLimits::new(&rate_limiting);
 
         // Leverage servicebuilder for our common middleware across all routes
         let middleware_stack = ServiceBuilder::new()
             // Set `x-request-id` header on all requests
             // Won't override the header if a request id is already set
             .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid).clone())
+            // Work out who the client is, believing forwarded headers from trusted proxies only
+            .layer(middleware::from_fn_with_state(
+                Arc::new(trusted_proxies),
+                resolve_client_ip,
+            ))
+            // Keep session tokens and API keys out of the traced request headers
+            .layer(SetSensitiveRequestHeadersLayer::new([
+                AUTHORIZATION,
+                COOKIE,
+            ]))
             // Log requests and response using tracing
             .layer(
                 TraceLayer::new_for_http()
-                    .make_span_with(DefaultMakeSpan::new().include_headers(true))
+                    .make_span_with(make_request_span)
                     .on_response(DefaultOnResponse::new().include_headers(true)),
             )
             // Propogate the `x-request-id` headers from request to response
@@ -83,21 +103,69 @@ impl ApiActor {
             // Ensures our server is adequately protected
             .layer(cors_layer)
             // Compress responses based on the `Accept-Encoding` header
-            .layer(CompressionLayer::new())
-            // Add rate limiting
-            .layer(GovernorLayer::new(rate_limiter_config));
+            .layer(CompressionLayer::new());
+
+        let state: Arc<ApiState> = state.into();
 
         Router::new()
-            .merge(api_router())
+            .merge(api_router(state.clone(), v1_state, route_rate_limits))
+            // Rate limited once the tenant and credentials are known, so requests can be
+            // counted per either
+            .layer(rate_limit_layer)
+            // Every request is tied to its tenant before any handler sees it
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                resolve_tenant,
+            ))
+            // Every client IP is limited before its credentials cost any database lookups
+            .layer(client_ip_rate_limit_layer)
             .layer(middleware_stack)
             // Add a timeout layer to timeout requests if they take too long
             .layer(TimeoutLayer::new(Duration::from_secs(
                 api_request_timeout_seconds,
             )))
-            .with_state(state.into())
+            .with_state(state)
     }
 }
 
+// Reload the TLS configuration when asked to, or when the files change on disk.
+// A failed reload leaves the listeners serving their previous configuration.
+async fn watch_tls_files(
+    tls_reloader: TlsReloader,
+    tls_reload: Arc<Notify>,
+    interval_seconds: u64,
+) {
+    let check_interval = Duration::from_secs(interval_seconds.max(1));
+
+    loop {
+        let reloaded = tokio::select! {
+            _ = tls_reload.notified() => tls_reloader.reload(),
+            _ = tokio::time::sleep(check_interval), if interval_seconds > 0 => {
+                tls_reloader.reload_if_changed().map(|_| ())
+            }
+        };
+
+        if let Err(error) = reloaded {
+            error!(errorMsg=%error, "unable to reload the TLS configuration");
+        }
+    }
+}
+
+// Either a full `ip:port`, or just an IP listening on the default port
+fn parse_bind_address(address: &str, default_port: u16) -> Result<SocketAddr, ActorProcessingErr> {
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+
+    let ip = address
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map_err(|error| format!("invalid bind address {} - {}", address, error))?;
+
+    Ok(SocketAddr::new(ip, default_port))
+}
+
 impl Actor for ApiActor {
     type State = ApiActorState;
     type Msg = ApiMessage;
@@ -111,45 +179,226 @@ impl Actor for ApiActor {
     ) -> Result<Self::State, ActorProcessingErr> {
         let mut state = ApiActorState::new();
 
+        // Load the keys agent tokens are signed and verified with
+        let agent_jwt_keys = JwtKeySet::load(
+            args.api_config.jwt_signing_key_file.as_deref(),
+            &args.api_config.jwt_verification_key_files,
+            &args.api_config.agent_jwt_secret,
+        )?;
+
+        // Forwarded headers are only believed from these
+        let trusted_proxies = TrustedProxies::from_config(&args.api_config)?;
+        let ip_access = IpAccessRules::from_config(&args.api_config)?;
+        let agent_connect_guard = AgentConnectGuard::new(&args.api_config);
+
+        let runtime_properties = RuntimeProperties::global();
+        let certs_folder = PathBuf::new()
+            .join(runtime_properties.folders().home())
+            .join(".certs");
+
+        let command_signer = CommandSigner::load_or_create(
+            args.api_config.command_signing_key_file.as_deref(),
+            &certs_folder,
+        )?;
+
+        // Make sure there is someone who can log in
+        ensure_bootstrap_admin(
+            &args.db_pool,
+            &args.api_config.bootstrap_admin_username,
+            args.api_config.bootstrap_admin_password.as_deref(),
+            &args.api_config.bootstrap_tenant,
+            &PathBuf::new()
+                .join(runtime_properties.folders().home())
+                .join(".auth")
+                .join("bootstrap_admin_password"),
+        )?;
+
+        // Act as the CA for agent certificates, unless an external CA has been configured
+        let certificate_authority = match (args.api_config.mtls_mode, &args.api_config.mtls_ca_file)
+        {
+            (MtlsMode::Optional | MtlsMode::Required, None) => {
+                let certificate_authority = CertificateAuthority::load_or_create(
+                    &certs_folder,
+                    args.api_config.agent_certificate_lifetime_days,
+                )?;
+                publish_revocation_list(&certificate_authority, &args.db_pool)?;
+                Some(certificate_authority)
+            }
+            _ => None,
+        };
+        let client_ca_files = certificate_authority.as_ref().map(|certificate_authority| {
+            (
+                certificate_authority.ca_pem_file().to_path_buf(),
+                certificate_authority.crl_pem_file().to_path_buf(),
+            )
+        });
+
         //Initialise the shared Axum State
         let api_state = ApiState::new(
             args.api_config.server_jwt_secret,
-            args.api_config.agent_jwt_secret,
+            agent_jwt_keys,
             args.api_config.agent_ping_interval,
             args.api_config.agent_ping_timeout,
-        );
+            args.api_config.agent_jwt_lifetime_secs,
+            args.api_config.mtls_mode,
+            args.db_pool.clone(),
+        )
+        .with_session_lifetimes(
+            args.api_config.session_jwt_lifetime_secs,
+            args.api_config.refresh_token_lifetime_secs,
+        )
+        .with_agent_command_timeouts(
+            args.api_config.agent_command_timeout_secs,
+            args.api_config.agent_command_max_timeout_secs,
+        )
+        .with_certificate_authority(certificate_authority)
+        .with_command_signer(command_signer)
+        .with_tenant_domain(TenantDomain::from_cors(&args.cors))
+        .with_provider_tenant(&args.api_config.bootstrap_tenant)
+        .with_ip_access(ip_access)
+        .with_agent_connect_guard(agent_connect_guard);
+        let tls_reload = api_state.tls_reload.clone();
+        let agent_connect_guard = api_state.agent_connect_guard.clone();
+
+        // Load the origins of the active tenants for CORS and finish any interrupted tenant purge
+        refresh_tenant_origins(&api_state);
+        state.tenant_purger = Some(tokio::spawn(purge_deleted_tenants(Arc::new(
+            api_state.clone(),
+        ))));
+
+        // Connected agents are shared between the versioned routes and the revocation monitor
+        let v1_state = Arc::new(V1ApiState::new());
+        let agent_registry = v1_state.agent_registry.clone();
+        let command_outputs = v1_state.command_outputs.clone();
 
         // Create the API Router
         // - Ensuring we pass in the required shared state and cors configuration
         let app = Self::router(
             api_state.clone(),
+            v1_state,
             args.cors.clone(),
             args.api_config.port.clone(),
+            trusted_proxies,
             args.rate_limiting,
             args.api_config.request_timeout_secs,
         );
 
-        let runtime_properties = RuntimeProperties::global();
+        // Verify agent client certificates against our CA, rejecting any we have revoked
+        let client_ca = match (client_ca_files, &args.api_config.mtls_ca_file) {
+            (Some((ca_file, crl_file)), _) => Some((ca_file, Some(crl_file))),
+            (None, Some(ca_file)) if args.api_config.mtls_mode != MtlsMode::Disabled => {
+                Some((PathBuf::from(ca_file), None))
+            }
+            _ => None,
+        };
 
-        let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), args.api_config.port);
-        match APIServer::new(socket, app)
-            .add_certs(
-                PathBuf::new()
-                    .join(runtime_properties.folders().home())
-                    .join(".certs")
-                    .join("cert.pem"),
-                PathBuf::new()
-                    .join(runtime_properties.folders().home())
-                    .join(".certs")
-                    .join("key.pem"),
-            )
-            .await?
-            .start()
-            .await
-        {
+        let tls_addresses = args
+            .api_config
+            .bind_addresses
+            .iter()
+            .map(|address| parse_bind_address(address, args.api_config.port))
+            .collect::<Result<Vec<_>, _>>()?;
+        let plaintext_addresses = args
+            .api_config
+            .plaintext_bind_addresses
+            .iter()
+            .map(|address| parse_bind_address(address, args.api_config.port))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Plaintext listeners serve the whole API, so bearer tokens and logins would travel in clear text
+        if !args.api_config.allow_insecure_plaintext {
+            if let Some(address) = plaintext_addresses
+                .iter()
+                .find(|address| !address.ip().is_loopback())
+            {
+                return Err(format!(
+                    "plaintext bind address {} is not a loopback address, set allow_insecure_plaintext to serve it anyway",
+                    address
+                )
+                .into());
+            }
+        }
+
+        // An IPv6 wildcard would also claim the port of an IPv4 listener next to it
+        let all_addresses = [tls_addresses.as_slice(), plaintext_addresses.as_slice()].concat();
+        let ipv6_only = |address: &SocketAddr| {
+            address.is_ipv6()
+                && all_addresses
+                    .iter()
+                    .any(|other| other.is_ipv4() && other.port() == address.port())
+        };
+
+        let certificate_file = certs_folder.join("cert.pem");
+        let private_key_file = certs_folder.join("key.pem");
+        if args.api_config.tls_self_signed && !tls_addresses.is_empty() {
+            ensure_self_signed_certificate(&certificate_file, &private_key_file)?;
+        }
+
+        let mut listeners = vec![];
+        for address in &tls_addresses {
+            let mut listener = Listener::new(*address)
+                .with_certs(certificate_file.clone(), private_key_file.clone())
+                .ipv6_only(ipv6_only(address));
+
+            if let Some((ca_file, crl_file)) = &client_ca {
+                listener = listener.with_client_ca(ca_file.clone());
+                if let Some(crl_file) = crl_file {
+                    listener = listener.with_crl(crl_file.clone());
+                }
+            }
+            listeners.push(listener);
+        }
+        for address in &plaintext_addresses {
+            listeners.push(Listener::new(*address).ipv6_only(ipv6_only(address)));
+        }
+
+        let server = APIServer::from_listeners(listeners, app)?;
+        let tls_reloader = server.tls_reloader();
+
+        match server.start().await {
             Ok(server_shutdown_handle) => {
                 state.server_shutdown_handle = Some(server_shutdown_handle);
 
+                // Pick up newly published CRLs and replaced certificates without restarting the listeners
+                if let Some(tls_reloader) = tls_reloader {
+                    state.tls_reloader = Some(tokio::spawn(watch_tls_files(
+                        tls_reloader,
+                        tls_reload,
+                        args.api_config.tls_reload_interval_secs,
+                    )));
+                }
+
+                state.revocation_monitor = Some(tokio::spawn(start_revocation_monitor(
+                    agent_registry,
+                    args.db_pool,
+                    args.api_config.agent_revocation_check_interval,
+                )));
+
+                state.agent_connect_pruner =
+                    Some(tokio::spawn(prune_connect_failures(agent_connect_guard)));
+
+                state.command_output_sweeper =
+                    Some(tokio::spawn(sweep_command_outputs(command_outputs)));
+
+                // Our own CRL goes stale unless republished before its next update
+                if let Some(certificate_authority) = api_state.certificate_authority.clone() {
+                    state.crl_publisher = Some(tokio::spawn(republish_revocation_list(
+                        certificate_authority,
+                        api_state.db_pool.clone(),
+                        api_state.tls_reload.clone(),
+                    )));
+                }
+
+                // Only multi-tenant mode checks origins against the tenants
+                if matches!(args.cors.mode, CorsMode::MultiTenant { .. })
+                    && args.cors.tenant_origins_refresh_secs > 0
+                {
+                    state.tenant_origins_watcher = Some(tokio::spawn(watch_tenant_origins(
+                        Arc::new(api_state),
+                        args.cors.tenant_origins_refresh_secs,
+                    )));
+                }
+
                 Ok(state)
             }
             Err(error) => Err(error.into()),
@@ -180,6 +429,34 @@ impl Actor for ApiActor {
             let _ = tx.send(());
         }
 
+        if let Some(monitor) = state.revocation_monitor.take() {
+            monitor.abort();
+        }
+
+        if let Some(reloader) = state.tls_reloader.take() {
+            reloader.abort();
+        }
+
+        if let Some(watcher) = state.tenant_origins_watcher.take() {
+            watcher.abort();
+        }
+
+        if let Some(pruner) = state.agent_connect_pruner.take() {
+            pruner.abort();
+        }
+
+        if let Some(sweeper) = state.command_output_sweeper.take() {
+            sweeper.abort();
+        }
+
+        if let Some(publisher) = state.crl_publisher.take() {
+            publisher.abort();
+        }
+
+        if let Some(purger) = state.tenant_purger.take() {
+            purger.abort();
+        }
+
         info!(name = ACTOR_API_SERVER_NAME, "stopped");
 
         Ok(())
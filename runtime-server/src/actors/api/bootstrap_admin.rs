@@ -0,0 +1,101 @@
+use crate::actors::api::v1::{auth::hash_password, rbac::Role};
+use anyhow::Error;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use database_server::{
+    models::{
+        tenants::{create_tenant, get_tenant_by_slug, NewTenant},
+        users::{count_users, create_user, NewUser},
+    },
+    SqlitePool,
+};
+use rand::RngCore;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Create the provider tenant and its first user when there are none, so a fresh install can
+/// be logged into. Without a configured password one is generated and written to
+/// `password_file`, readable by the server user only.
+pub(crate) fn ensure_bootstrap_admin(
+    db_pool: &SqlitePool,
+    username: &str,
+    password: Option<&str>,
+    tenant: &str,
+    password_file: &Path,
+) -> Result<(), Error> {
+    let mut db_conn = db_pool.get()?;
+    if get_tenant_by_slug(&mut db_conn, tenant)?.is_none() {
+        create_tenant(
+            &mut db_conn,
+            NewTenant {
+                slug: tenant.to_string(),
+                name: tenant.to_string(),
+                allowed_origins: String::new(),
+                agent_seat_limit: None,
+                agent_allowed_ips: String::new(),
+                agent_denied_ips: String::new(),
+                admin_allowed_ips: String::new(),
+                admin_denied_ips: String::new(),
+            },
+        )?;
+        info!(%tenant, "created the provider tenant");
+    }
+
+    if count_users(&mut db_conn)? > 0 {
+        return Ok(());
+    }
+
+    let password = match password {
+        Some(password) => password.to_string(),
+        None => {
+            let mut bytes = [0u8; 18];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let password = URL_SAFE_NO_PAD.encode(bytes);
+
+            if let Some(folder) = password_file.parent() {
+                fs::create_dir_all(folder)?;
+            }
+            write_password_file(password_file, &password)?;
+            warn!(
+                user = %username,
+                file = %password_file.display(),
+                "generated a password for the bootstrap admin, change it via /api/v1/auth/password and remove the file"
+            );
+            password
+        }
+    };
+
+    let user = create_user(
+        &mut db_conn,
+        NewUser {
+            username: username.to_string(),
+            password_hash: hash_password(&password)?,
+            tenant: tenant.to_string(),
+            role: Role::Owner.as_str().to_string(),
+        },
+    )?;
+    info!(user = %user.username, tenant = %user.tenant, "created the bootstrap admin user");
+
+    Ok(())
+}
+
+fn write_password_file(path: &Path, password: &str) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(password.as_bytes())?;
+    }
+
+    #[cfg(not(unix))]
+    fs::write(path, password)?;
+
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use anyhow::{anyhow, Context, Error};
+use database_server::AgentCertificate;
+use rand::Rng;
+use rcgen::{
+    BasicConstraints, CertificateParams, CertificateRevocationListParams,
+    CertificateSigningRequestParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    Issuer, KeyIdMethod, KeyPair, KeyUsagePurpose, RevokedCertParams, SerialNumber,
+};
+use runtime_shared::RuntimeProperties;
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+const CA_CERTIFICATE_FILE: &str = "ca.pem";
+const CA_PRIVATE_KEY_FILE: &str = "ca-key.pem";
+const CRL_FILE: &str = "crl.pem";
+
+const CA_LIFETIME_DAYS: i64 = 3650;
+// Clients are allowed to treat a CRL as stale after this, so it is republished well before
+const CRL_LIFETIME_DAYS: i64 = 7;
+
+/// A certificate issued to an agent by the built-in CA
+pub(crate) struct IssuedCertificate {
+    pub pem: String,
+    // Lowercase hex, no separators - matches `AgentCertificate::serial`
+    pub serial: String,
+    pub not_before: i64,
+    pub not_after: i64,
+}
+
+/// A small CA that signs agent client certificates and publishes a CRL of revoked ones.
+/// The CA certificate and key live in the `.certs` folder, next to the server certificate.
+pub(crate) struct CertificateAuthority {
+    issuer: Issuer<'static, KeyPair>,
+    ca_pem: String,
+    ca_pem_file: PathBuf,
+    crl_pem_file: PathBuf,
+    certificate_lifetime_days: u64,
+}
+
+// Keep the signing key out of any debug output
+impl std::fmt::Debug for CertificateAuthority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertificateAuthority")
+            .field("ca_pem_file", &self.ca_pem_file)
+            .field("crl_pem_file", &self.crl_pem_file)
+            .field("certificate_lifetime_days", &self.certificate_lifetime_days)
+            .finish()
+    }
+}
+
+impl CertificateAuthority {
+    /// Load the CA from the certs folder, generating a new one the first time
+    pub fn load_or_create(
+        certs_folder: &Path,
+        certificate_lifetime_days: u64,
+    ) -> Result<Self, Error> {
+        let ca_pem_file = certs_folder.join(CA_CERTIFICATE_FILE);
+        let ca_key_file = certs_folder.join(CA_PRIVATE_KEY_FILE);
+
+        if !ca_pem_file.exists() || !ca_key_file.exists() {
+            create_ca(certs_folder, &ca_pem_file, &ca_key_file)?;
+        }
+
+        let ca_pem = fs::read_to_string(&ca_pem_file)
+            .with_context(|| format!("unable to read {}", ca_pem_file.display()))?;
+        let ca_key = fs::read_to_string(&ca_key_file)
+            .with_context(|| format!("unable to read {}", ca_key_file.display()))?;
+
+        let key_pair = KeyPair::from_pem(&ca_key)?;
+        let issuer = Issuer::from_ca_cert_pem(&ca_pem, key_pair)?;
+
+        Ok(Self {
+            issuer,
+            ca_pem,
+            ca_pem_file,
+            crl_pem_file: certs_folder.join(CRL_FILE),
+            certificate_lifetime_days,
+        })
+    }
+
+    pub fn ca_pem(&self) -> &str {
+        &self.ca_pem
+    }
+
+    pub fn ca_pem_file(&self) -> &Path {
+        &self.ca_pem_file
+    }
+
+    pub fn crl_pem_file(&self) -> &Path {
+        &self.crl_pem_file
+    }
+
+    /// Read an agent's CSR and check its signature, before anything is spent on signing it
+    pub fn parse_agent_csr(csr_pem: &str) -> Result<CertificateSigningRequestParams, Error> {
+        CertificateSigningRequestParams::from_pem(csr_pem)
+            .map_err(|error| anyhow!("invalid certificate signing request - {}", error))
+    }
+
+    /// Sign an agent's CSR. Only the public key is taken from the request - the subject is
+    /// always the agent id the server authenticated, so an agent cannot claim another identity.
+    pub fn sign_agent_csr(
+        &self,
+        mut csr: CertificateSigningRequestParams,
+        agent_id: &str,
+    ) -> Result<IssuedCertificate, Error> {
+        let serial = random_serial();
+        let now = OffsetDateTime::now_utc();
+        // Allow for a little clock skew between server and agent
+        let not_before = now - Duration::minutes(5);
+        let not_after = now + Duration::days(self.certificate_lifetime_days as i64);
+
+        let mut distinguished_name = DistinguishedName::new();
+        distinguished_name.push(DnType::CommonName, agent_id);
+
+        let mut params = CertificateParams::default();
+        params.distinguished_name = distinguished_name;
+        params.serial_number = Some(SerialNumber::from_slice(&serial));
+        params.not_before = not_before;
+        params.not_after = not_after;
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.use_authority_key_identifier_extension = true;
+        csr.params = params;
+
+        let certificate = csr.signed_by(&self.issuer)?;
+
+        Ok(IssuedCertificate {
+            pem: certificate.pem(),
+            serial: to_hex(&serial),
+            not_before: not_before.unix_timestamp(),
+            not_after: not_after.unix_timestamp(),
+        })
+    }
+
+    /// Write a fresh CRL listing the revoked certificates
+    pub fn publish_crl(&self, revoked: &[AgentCertificate]) -> Result<(), Error> {
+        let now = OffsetDateTime::now_utc();
+
+        let mut revoked_certs = vec![];
+        for certificate in revoked {
+            revoked_certs.push(RevokedCertParams {
+                serial_number: SerialNumber::from_slice(&from_hex(&certificate.serial)?),
+                revocation_time: OffsetDateTime::from_unix_timestamp(
+                    certificate.revoked_at.unwrap_or(now.unix_timestamp()),
+                )?,
+                reason_code: None,
+                invalidity_date: None,
+            });
+        }
+
+        let params = CertificateRevocationListParams {
+            this_update: now,
+            next_update: now + Duration::days(CRL_LIFETIME_DAYS),
+            // Must increase with every CRL we publish
+            crl_number: SerialNumber::from_slice(&now.unix_timestamp().to_be_bytes()),
+            issuing_distribution_point: None,
+            revoked_certs,
+            key_identifier_method: KeyIdMethod::Sha256,
+        };
+        let crl = params.signed_by(&self.issuer)?;
+
+        // Write then rename so the TLS layer never reads a half written file
+        let temporary_file = self.crl_pem_file.with_extension("pem.tmp");
+        fs::write(&temporary_file, crl.pem()?)?;
+        fs::rename(&temporary_file, &self.crl_pem_file)?;
+
+        info!(
+            revoked = revoked.len(),
+            "published agent certificate revocation list"
+        );
+
+        Ok(())
+    }
+}
+
+fn create_ca(certs_folder: &Path, ca_pem_file: &Path, ca_key_file: &Path) -> Result<(), Error> {
+    fs::create_dir_all(certs_folder)?;
+
+    let now = OffsetDateTime::now_utc();
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(
+        DnType::CommonName,
+        format!("{} Agent CA", RuntimeProperties::global().app_name()),
+    );
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = distinguished_name;
+    params.serial_number = Some(SerialNumber::from_slice(&random_serial()));
+    params.not_before = now - Duration::minutes(5);
+    params.not_after = now + Duration::days(CA_LIFETIME_DAYS);
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+    ];
+
+    let key_pair = KeyPair::generate()?;
+    let certificate = params.self_signed(&key_pair)?;
+
+    write_private_key(ca_key_file, &key_pair.serialize_pem())?;
+    fs::write(ca_pem_file, certificate.pem())?;
+
+    info!(certificate = %ca_pem_file.display(), "created agent certificate authority");
+
+    Ok(())
+}
+
+// Private keys are only ever readable by the server user
+pub(crate) fn write_private_key(path: &Path, pem: &str) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(pem.as_bytes())?;
+    }
+
+    #[cfg(not(unix))]
+    fs::write(path, pem)?;
+
+    Ok(())
+}
+
+// Positive and without a leading zero byte, so the DER encoding matches these bytes exactly
+fn random_serial() -> [u8; 16] {
+    let mut serial: [u8; 16] = rand::thread_rng().gen();
+    serial[0] = (serial[0] & 0x7f).max(1);
+    serial
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| anyhow!("invalid certificate serial {}", hex))
+        })
+        .collect()
+}
@@ -0,0 +1,375 @@
+use anyhow::{anyhow, Error};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use config_server::ApiConfiguration;
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+use tracing::Span;
+
+/// The address of the client a request came from, present on every request served over TCP.
+/// Only differs from the connection's peer when the peer is a trusted proxy.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClientIp(pub IpAddr);
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The proxies whose `X-Forwarded-For` and `Forwarded` headers are believed
+#[derive(Debug, Default)]
+pub(crate) struct TrustedProxies {
+    networks: Vec<IpNetwork>,
+}
+
+impl TrustedProxies {
+    /// Nothing is trusted unless the server is configured to be behind a proxy
+    pub fn from_config(api_config: &ApiConfiguration) -> Result<Self, Error> {
+        if !api_config.behind_proxy {
+            return Ok(Self::default());
+        }
+
+        let networks = api_config
+            .trusted_proxies
+            .iter()
+            .map(|network| network.parse())
+            .collect::<Result<Vec<_>, Error>>()
+            .map_err(|error| anyhow!("invalid trusted proxy, {}", error))?;
+        Ok(Self { networks })
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    // Walk the forwarded hops back from the nearest one, the first that isn't a trusted proxy
+    // is the client. Anything further along was added by the client itself and can't be believed.
+    fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.trusts(client) {
+            return client;
+        }
+
+        for hop in forwarded_hops(headers).into_iter().rev() {
+            // An obfuscated or unknown hop ends the chain at the last proxy we know about
+            let Some(hop) = hop else {
+                break;
+            };
+            client = hop.to_canonical();
+            if !self.trusts(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Resolve the client IP of every request before it is traced, rate limited or audited
+pub(crate) async fn resolve_client_ip(
+    State(trusted_proxies): State<Arc<TrustedProxies>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| peer.ip());
+
+    if let Some(peer) = peer {
+        let client_ip = trusted_proxies.client_ip(peer, request.headers());
+        request.extensions_mut().insert(ClientIp(client_ip));
+    }
+
+    next.run(request).await
+}
+
+/// The span each request is traced in, carrying the client IP alongside the request itself
+pub(crate) fn make_request_span(request: &Request<Body>) -> Span {
+    let client_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map(ClientIp::to_string)
+        .unwrap_or_default();
+
+    tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        client_ip = %client_ip,
+        headers = ?request.headers(),
+    )
+}
+
+// The addresses each proxy forwarded the request for, furthest first. `Forwarded` is used
+// when present, otherwise `X-Forwarded-For`.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded = headers.get_all("forwarded").iter().collect::<Vec<_>>();
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim().trim_matches('"')))
+            })
+            .collect();
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+// "192.0.2.1", "192.0.2.1:4711", "2001:db8::1" or "[2001:db8::1]:4711"
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|address| address.ip()))
+        .or_else(|_| node.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+/// A block of addresses e.g. "10.0.0.0/8", a single address is a block of one
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = Error;
+
+    fn from_str(network: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("{} is not an IP address or CIDR", network);
+
+        let (address, prefix) = match network.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (network, None),
+        };
+        let parsed = address.parse::<IpAddr>().map_err(|_| invalid())?;
+
+        // An IPv4-mapped network e.g. "::ffff:10.0.0.0/104" is kept as the IPv4 network it maps
+        let address = parsed.to_canonical();
+        let mapped_bits = match (parsed, address) {
+            (IpAddr::V6(_), IpAddr::V4(_)) => 96,
+            _ => 0,
+        };
+
+        let max_prefix = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .and_then(|prefix| prefix.checked_sub(mapped_bits))
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(invalid)?,
+            None => max_prefix,
+        };
+
+        Ok(Self { address, prefix })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn network(network: &str) -> IpNetwork {
+        network.parse().unwrap()
+    }
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    fn trusted(networks: &[&str]) -> TrustedProxies {
+        TrustedProxies {
+            networks: networks.iter().map(|n| network(n)).collect(),
+        }
+    }
+
+    #[test]
+    fn forwarded_hops_reads_x_forwarded_for_across_headers() {
+        let hops = forwarded_hops(&headers(&[
+            ("x-forwarded-for", "192.0.2.1, 198.51.100.2:4711"),
+            ("x-forwarded-for", "[2001:db8::1]:80, garbage"),
+        ]));
+
+        assert_eq!(
+            hops,
+            vec![
+                Some(ip("192.0.2.1")),
+                Some(ip("198.51.100.2")),
+                Some(ip("2001:db8::1")),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn forwarded_hops_prefers_forwarded_and_keeps_obfuscated_nodes() {
+        let hops = forwarded_hops(&headers(&[
+            ("x-forwarded-for", "203.0.113.9"),
+            (
+                "forwarded",
+                "for=192.0.2.60;proto=http, For=\"[2001:db8:cafe::17]:4711\"",
+            ),
+            (
+                "forwarded",
+                "for=_hidden, for=unknown;by=203.0.113.43, proto=https",
+            ),
+        ]));
+
+        assert_eq!(
+            hops,
+            vec![
+                Some(ip("192.0.2.60")),
+                Some(ip("2001:db8:cafe::17")),
+                None,
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn client_ip_ignores_forwarded_headers_from_untrusted_peers() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        let spoofed = headers(&[("x-forwarded-for", "192.0.2.1")]);
+
+        assert_eq!(
+            proxies.client_ip(ip("203.0.113.5"), &spoofed),
+            ip("203.0.113.5")
+        );
+        assert_eq!(
+            TrustedProxies::default().client_ip(ip("10.0.0.1"), &spoofed),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn client_ip_stops_at_the_first_untrusted_hop() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        // The client put a spoofed hop in front of its own address
+        let chain = headers(&[("x-forwarded-for", "192.0.2.66, 203.0.113.5, 10.0.0.2")]);
+
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &chain), ip("203.0.113.5"));
+    }
+
+    #[test]
+    fn client_ip_ends_at_the_last_known_proxy_on_an_obfuscated_hop() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        let chain = headers(&[("forwarded", "for=192.0.2.66, for=_hidden, for=10.0.0.2")]);
+
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &chain), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn client_ip_handles_ipv4_mapped_peers_and_hops() {
+        let proxies = trusted(&["127.0.0.1"]);
+        let chain = headers(&[("x-forwarded-for", "::ffff:192.0.2.1")]);
+
+        assert_eq!(
+            proxies.client_ip(ip("::ffff:127.0.0.1"), &chain),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            proxies.client_ip(ip("::ffff:203.0.113.5"), &chain),
+            ip("203.0.113.5")
+        );
+    }
+
+    #[test]
+    fn ip_network_parses_single_addresses_as_full_prefixes() {
+        assert!(network("192.0.2.1").contains(ip("192.0.2.1")));
+        assert!(!network("192.0.2.1").contains(ip("192.0.2.2")));
+        assert!(network("2001:db8::1").contains(ip("2001:db8::1")));
+        assert!(!network("2001:db8::1").contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn ip_network_matches_its_prefix() {
+        assert!(network("192.0.2.1/32").contains(ip("192.0.2.1")));
+        assert!(!network("192.0.2.1/32").contains(ip("192.0.2.0")));
+        assert!(network("10.0.0.0/8").contains(ip("10.255.0.1")));
+        assert!(!network("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(network("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!network("2001:db8::1/128").contains(ip("2001:db8::")));
+        assert!(network("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+    }
+
+    #[test]
+    fn ip_network_zero_prefix_covers_its_whole_family() {
+        assert!(network("0.0.0.0/0").contains(ip("203.0.113.5")));
+        assert!(!network("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(network("::/0").contains(ip("2001:db8::1")));
+        assert!(!network("::/0").contains(ip("203.0.113.5")));
+    }
+
+    #[test]
+    fn ip_network_treats_ipv4_mapped_addresses_as_ipv4() {
+        assert!(network("192.0.2.0/24").contains(ip("::ffff:192.0.2.7")));
+        assert!(network("::ffff:192.0.2.0/120").contains(ip("192.0.2.7")));
+        assert!(!network("::ffff:192.0.2.0/120").contains(ip("192.0.3.7")));
+        assert!(network("::ffff:192.0.2.7").contains(ip("192.0.2.7")));
+    }
+
+    #[test]
+    fn ip_network_rejects_invalid_networks() {
+        for invalid in [
+            "",
+            "not-an-ip",
+            "192.0.2.1/33",
+            "2001:db8::/129",
+            "192.0.2.1/",
+            "192.0.2.1/-1",
+            "::ffff:192.0.2.0/95",
+        ] {
+            assert!(invalid.parse::<IpNetwork>().is_err(), "{} parsed", invalid);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::actors::api::certificate_authority::write_private_key;
+use anyhow::{anyhow, Context, Error};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{
+    pkcs8::{spki::der::pem::LineEnding, DecodePrivateKey, EncodePrivateKey, EncodePublicKey},
+    Signer, SigningKey,
+};
+use rand::{Rng, RngCore};
+use runtime_shared::protocol::{
+    cancel_signing_bytes, command_signing_bytes, CommandRecipient, Outbound,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+const COMMAND_SIGNING_KEY_FILE: &str = "command-key.pem";
+const COMMAND_PUBLIC_KEY_FILE: &str = "command-key.pub.pem";
+
+/// Signs the commands sent to agents with an Ed25519 key. Agents pin its public key, so a
+/// command injected by anyone between us and them - a proxy or TLS terminator - is refused.
+pub(crate) struct CommandSigner {
+    key: SigningKey,
+    key_file: PathBuf,
+}
+
+// Keep the signing key out of any debug output
+impl std::fmt::Debug for CommandSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandSigner")
+            .field("key_file", &self.key_file)
+            .field("public_key", &self.public_key())
+            .finish()
+    }
+}
+
+/// A command ready to be signed for each agent it is sent to
+#[derive(Debug)]
+pub(crate) struct UnsignedCommand {
+    pub command_id: String,
+    pub verb: String,
+    pub payload: serde_json::Value,
+    // Seconds since the unix epoch the command must finish by
+    pub deadline: Option<u64>,
+}
+
+impl CommandSigner {
+    /// Load the configured key, or the one in the certs folder - generating it the first time
+    pub fn load_or_create(key_file: Option<&str>, certs_folder: &Path) -> Result<Self, Error> {
+        let key_file = match key_file {
+            Some(key_file) => PathBuf::from(key_file),
+            None => {
+                let key_file = certs_folder.join(COMMAND_SIGNING_KEY_FILE);
+                if !key_file.exists() {
+                    create_key(certs_folder, &key_file)?;
+                }
+                key_file
+            }
+        };
+
+        let pem = fs::read_to_string(&key_file)
+            .with_context(|| format!("unable to read {}", key_file.display()))?;
+        let key = SigningKey::from_pkcs8_pem(&pem).map_err(|_| {
+            anyhow!(
+                "invalid command signing key {}, expected an Ed25519 private key",
+                key_file.display()
+            )
+        })?;
+
+        let signer = Self { key, key_file };
+        info!(
+            key_file = %signer.key_file.display(),
+            public_key = %signer.public_key(),
+            "loaded command signing key, agents must pin the public key to accept commands"
+        );
+
+        Ok(signer)
+    }
+
+    /// The raw public key, base64url without padding, as pinned by agents
+    pub fn public_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.key.verifying_key().as_bytes())
+    }
+
+    /// Build the command for one agent, stamped with the current time and a fresh nonce, and
+    /// sign it along with its deadline and the agent it is meant for
+    pub fn sign(&self, recipient: CommandRecipient, command: &UnsignedCommand) -> Outbound {
+        let (issued_at, nonce) = stamp();
+        let signature = self.key.sign(&command_signing_bytes(
+            recipient,
+            &command.command_id,
+            &command.verb,
+            &command.payload,
+            issued_at,
+            &nonce,
+            command.deadline,
+        ));
+
+        Outbound::Command {
+            command_id: command.command_id.clone(),
+            verb: command.verb.clone(),
+            payload: command.payload.clone(),
+            issued_at,
+            nonce,
+            deadline: command.deadline,
+            signature: URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+        }
+    }
+
+    /// Build the cancel of a command for one agent, stamped and signed just like a command
+    pub fn sign_cancel(&self, recipient: CommandRecipient, command_id: &str) -> Outbound {
+        let (issued_at, nonce) = stamp();
+        let signature = self.key.sign(&cancel_signing_bytes(
+            recipient, command_id, issued_at, &nonce,
+        ));
+
+        Outbound::Cancel {
+            command_id: command_id.to_string(),
+            issued_at,
+            nonce,
+            signature: URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+        }
+    }
+}
+
+// The current time and a fresh nonce, which agents use to refuse stale or replayed messages
+fn stamp() -> (u64, String) {
+    let issued_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    (issued_at, URL_SAFE_NO_PAD.encode(nonce))
+}
+
+// The public key is written alongside the private one, ready to be pinned by agents
+fn create_key(certs_folder: &Path, key_file: &Path) -> Result<(), Error> {
+    fs::create_dir_all(certs_folder)?;
+
+    let key = SigningKey::from_bytes(&rand::thread_rng().gen());
+    let private_pem = key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|error| anyhow!("unable to encode command signing key - {}", error))?;
+    let public_pem = key
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|error| anyhow!("unable to encode command public key - {}", error))?;
+
+    write_private_key(key_file, &private_pem)?;
+    fs::write(certs_folder.join(COMMAND_PUBLIC_KEY_FILE), public_pem)?;
+
+    info!(key_file = %key_file.display(), "created command signing key");
+
+    Ok(())
+}
@@ -1,16 +1,53 @@
 use axum::http::{HeaderName, HeaderValue, Method};
 use config_server::{CorsConfiguration, CorsMode};
-use tower_http::cors::CorsLayer;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use tracing::warn;
 
+/// Origins of the active tenants - their subdomains and the custom origins they were given e.g.
+/// their own portal domains. Replaced whenever tenants change and reloaded from the database
+/// periodically, so the CORS layer never needs rebuilding.
+#[derive(Debug, Default)]
+pub(crate) struct TenantOrigins {
+    origins: RwLock<HashSet<String>>,
+}
+
+impl TenantOrigins {
+    pub fn contains(&self, origin: &str) -> bool {
+        self.origins.read().unwrap().contains(origin)
+    }
+
+    /// Returns whether anything changed
+    pub fn replace(&self, origins: HashSet<String>) -> bool {
+        let mut current = self.origins.write().unwrap();
+        let changed = *current != origins;
+        *current = origins;
+        changed
+    }
+}
+
 /// Convert this configuration to a tower-http CorsLayer
-pub fn to_cors_layer(config: CorsConfiguration, api_port: u16) -> CorsLayer {
+pub(crate) fn to_cors_layer(
+    config: CorsConfiguration,
+    api_port: u16,
+    tenant_origins: Arc<TenantOrigins>,
+) -> CorsLayer {
     let origins = resolve_allowed_origins(&config, api_port);
 
     let mut cors = CorsLayer::new();
 
-    // Set origins
-    if origins.contains(&"*".to_string()) {
+    // Set origins - tenant origins are checked as each request arrives
+    if let CorsMode::MultiTenant { .. } = config.mode {
+        cors = cors.allow_origin(AllowOrigin::predicate(move |origin, _| {
+            // Tenant origins are stored lowercase, schemes and hosts are case insensitive
+            let origin = origin.to_str().unwrap_or_default().to_ascii_lowercase();
+            origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(&origin))
+                || tenant_origins.contains(&origin)
+        }));
+    } else if origins.contains(&"*".to_string()) {
         cors = cors.allow_origin(tower_http::cors::Any);
     } else {
         let header_values: Result<Vec<HeaderValue>, _> = origins
@@ -84,10 +121,7 @@ fn resolve_allowed_origins(config: &CorsConfiguration, api_port: u16) -> Vec<Str
                 origins.extend([format!("http://localhost:{}", api_port)]);
             }
 
-            // Add tenant subdomains (#TODO)
-            // This would query our database for active tenants
-            origins.extend(get_active_tenant_domains());
-
+            // Tenant origins are checked against `TenantOrigins` as requests arrive
             origins
         }
         CorsMode::SingleFrontend {
@@ -104,8 +138,3 @@ fn resolve_allowed_origins(config: &CorsConfiguration, api_port: u16) -> Vec<Str
         }
     }
 }
-
-fn get_active_tenant_domains() -> Vec<String> {
-    // Placeholder implementation - you would typically query your database here
-    vec![]
-}
@@ -1,7 +1,13 @@
 pub(crate) mod actor;
+mod bootstrap_admin;
+pub(crate) mod certificate_authority;
+pub(crate) mod client_ip;
+mod command_signer;
 pub(crate) mod cors;
 mod jwt;
 pub(crate) mod messages;
+mod rate_limiting;
+mod self_signed;
 mod state;
 mod utils;
 pub(crate) mod v1;
@@ -0,0 +1,145 @@
+use crate::actors::api::{
+    client_ip::ClientIp,
+    v1::auth::{Authentication, Credential},
+};
+use axum::{body::Body, http::Request};
+use config_server::{RateLimitKey, RateLimitingConfiguration, RouteRateLimit};
+use governor::middleware::StateInformationMiddleware;
+use std::time::Duration;
+use tower_governor::{
+    governor::GovernorConfigBuilder, key_extractor::KeyExtractor, GovernorError, GovernorLayer,
+};
+
+pub(crate) type RateLimitLayer =
+    GovernorLayer<RequestKeyExtractor, StateInformationMiddleware, Body>;
+
+/// The stricter limits layered onto groups of routes, on top of the overall limit
+#[derive(Clone)]
+pub(crate) struct RouteRateLimits {
+    pub token_issuance: RateLimitLayer,
+    pub agent_commands: RateLimitLayer,
+}
+
+impl RouteRateLimits {
+    pub fn new(rate_limiting: &RateLimitingConfiguration) -> Self {
+        Self {
+            token_issuance: route_rate_limit_layer(
+                "token issuance",
+                &rate_limiting.token_issuance,
+                rate_limiting.cleanup_duration,
+            ),
+            agent_commands: route_rate_limit_layer(
+                "agent commands",
+                &rate_limiting.agent_commands,
+                rate_limiting.cleanup_duration,
+            ),
+        }
+    }
+}
+
+/// Counts requests per client IP, tenant or credential
+///
+/// Tenant and credential keys need the request's credentials to have been checked already, so
+/// those limits must sit inside the tenant resolution middleware. Anonymous requests are always counted per client IP - the
+/// tenant they name is only a claim, and must not let anyone use up a tenant's limit. The client
+/// IP only comes from forwarded headers sent by a trusted proxy, so those can't dodge it either.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RequestKeyExtractor {
+    key: RateLimitKey,
+}
+
+impl KeyExtractor for RequestKeyExtractor {
+    type Key = String;
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        let principal = match req.extensions().get::<Authentication>() {
+            Some(Authentication(Ok(principal))) => Some(principal),
+            _ => None,
+        };
+
+        match (self.key, principal) {
+            (RateLimitKey::Tenant, Some(principal)) => Ok(format!("tenant:{}", principal.tenant)),
+            (RateLimitKey::Credential, Some(principal)) => match principal.credential {
+                Credential::Session { user_id, .. } => Ok(format!("user:{}", user_id)),
+                Credential::ApiKey { key_id, .. } => Ok(format!("api_key:{}", key_id)),
+            },
+            _ => req
+                .extensions()
+                .get::<ClientIp>()
+                .map(|client_ip| format!("ip:{}", client_ip))
+                .ok_or(GovernorError::UnableToExtractKey),
+        }
+    }
+}
+
+/// The overall limit every request is subject to
+pub(crate) fn rate_limit_layer(rate_limiting: &RateLimitingConfiguration) -> RateLimitLayer {
+    governor_layer(
+        "overall",
+        rate_limiting.burst_size,
+        rate_limiting.per_second,
+        rate_limiting.key,
+        rate_limiting.cleanup_duration,
+    )
+}
+
+/// The limit per client IP, applied before the tenant resolution middleware looks up any
+/// credentials
+pub(crate) fn client_ip_rate_limit_layer(
+    rate_limiting: &RateLimitingConfiguration,
+) -> RateLimitLayer {
+    governor_layer(
+        "client ip",
+        rate_limiting.client_ip_burst_size,
+        rate_limiting.client_ip_per_second,
+        RateLimitKey::Ip,
+        rate_limiting.cleanup_duration,
+    )
+}
+
+fn route_rate_limit_layer(
+    name: &'static str,
+    limit: &RouteRateLimit,
+    cleanup_duration: u64,
+) -> RateLimitLayer {
+    governor_layer(
+        name,
+        limit.burst_size,
+        limit.per_second,
+        limit.key,
+        cleanup_duration,
+    )
+}
+
+// Setup a rate limiter configuration using [tower-governor](https://github.com/benwis/tower-governor/)
+fn governor_layer(
+    name: &'static str,
+    burst_size: u32,
+    per_second: u64,
+    key: RateLimitKey,
+    cleanup_duration: u64,
+) -> RateLimitLayer {
+    let rate_limiter_config = GovernorConfigBuilder::default()
+        .per_second(per_second)
+        .burst_size(burst_size)
+        .key_extractor(RequestKeyExtractor { key })
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    // Initialise a seperate background task to cleanup old tracking entries in RAM
+    // - This avoids over consumption of RAM when tracking previous API requests
+    let rate_limiter = rate_limiter_config.limiter().clone();
+    let interval = Duration::from_secs(cleanup_duration);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        tracing::info!(
+            "{} rate limiting storage size: {}",
+            name,
+            rate_limiter.len()
+        );
+        rate_limiter.retain_recent();
+    });
+
+    GovernorLayer::new(rate_limiter_config)
+}
@@ -0,0 +1,60 @@
+use crate::actors::api::certificate_authority::write_private_key;
+use anyhow::Error;
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+use runtime_shared::RuntimeProperties;
+use std::fs;
+use std::path::Path;
+use time::{Duration, OffsetDateTime};
+use tracing::warn;
+
+const SELF_SIGNED_LIFETIME_DAYS: i64 = 365;
+
+/// Generate a self-signed server certificate when there is none yet, so a fresh dev or lab
+/// install comes up over HTTPS. Returns whether one was generated. Replacing the files
+/// later with a real certificate is picked up without a restart.
+pub(crate) fn ensure_self_signed_certificate(
+    certificate_pem_file: &Path,
+    private_key_pem_file: &Path,
+) -> Result<bool, Error> {
+    if certificate_pem_file.exists() && private_key_pem_file.exists() {
+        return Ok(false);
+    }
+
+    let runtime_properties = RuntimeProperties::global();
+    let host_name = runtime_properties.host_name().to_string();
+
+    let mut params = CertificateParams::new(vec![
+        "localhost".to_string(),
+        host_name.clone(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ])?;
+
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, host_name);
+    distinguished_name.push(
+        DnType::OrganizationName,
+        format!("{} (self-signed)", runtime_properties.app_name()),
+    );
+    params.distinguished_name = distinguished_name;
+
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::minutes(5);
+    params.not_after = now + Duration::days(SELF_SIGNED_LIFETIME_DAYS);
+
+    let key_pair = KeyPair::generate()?;
+    let certificate = params.self_signed(&key_pair)?;
+
+    if let Some(folder) = certificate_pem_file.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    write_private_key(private_key_pem_file, &key_pair.serialize_pem())?;
+    fs::write(certificate_pem_file, certificate.pem())?;
+
+    warn!(
+        certificate = %certificate_pem_file.display(),
+        "generated a self-signed server certificate, replace it before going to production"
+    );
+
+    Ok(true)
+}
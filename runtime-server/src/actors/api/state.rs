@@ -1,27 +1,67 @@
-use crate::actors::api::v1::handlers::agent::types::AgentRegistry;
+use crate::actors::api::certificate_authority::CertificateAuthority;
+use crate::actors::api::command_signer::CommandSigner;
+use crate::actors::api::cors::TenantOrigins;
+use crate::actors::api::v1::handlers::agent::connect_guard::AgentConnectGuard;
+use crate::actors::api::v1::handlers::agent::output::CommandOutputs;
+use crate::actors::api::v1::handlers::agent::types::{AgentRegistry, TenantAgentRegistry};
+use crate::actors::api::v1::ip_access::IpAccessRules;
+use crate::actors::api::v1::jwt::JwtKeySet;
+use crate::actors::api::v1::tenant::TenantDomain;
 use axum::extract::ws::Message;
-use dashmap::DashMap;
+use config_server::{ApiConfiguration, MtlsMode};
+use database_server::SqlitePool;
+use runtime_shared::api_server::handle::ServerHandle;
 use runtime_shared::RuntimeProperties;
 use std::sync::Arc;
 use tokio::sync::broadcast;
-use tokio::sync::{broadcast::Sender, Mutex};
+use tokio::sync::{broadcast::Sender, Mutex, Notify};
 
 #[derive(Clone, Debug)]
 pub(crate) struct ApiState {
     pub id: String,
     pub broadcast_tx: Arc<Mutex<Sender<Message>>>,
-    pub agent_jwt_secret: String,
+    pub agent_jwt_keys: Arc<JwtKeySet>,
     pub server_jwt_secret: String,
+    pub session_jwt_lifetime_secs: u64,
+    pub refresh_token_lifetime_secs: u64,
     pub agent_ping_interval: u64,
     pub agent_ping_timeout: u64,
+    pub agent_jwt_lifetime_secs: u64,
+    // Commands sent without a timeout of their own get this long to finish, 0 for no deadline
+    pub agent_command_timeout_secs: u64,
+    // The longest timeout a command request may ask for, 0 for no limit
+    pub agent_command_max_timeout_secs: u64,
+    pub agent_mtls_mode: MtlsMode,
+    // Only present when we issue agent certificates ourselves
+    pub certificate_authority: Option<Arc<CertificateAuthority>>,
+    // Commands can't be sent to agents without it
+    pub command_signer: Option<Arc<CommandSigner>>,
+    // Notified whenever the TLS configuration needs rebuilding e.g. a new CRL was published
+    pub tls_reload: Arc<Notify>,
+    // Notified whenever a tenant is marked for deletion, wakes the purge worker
+    pub tenant_purge: Arc<Notify>,
+    // Only set in multi-tenant mode, where tenants can be resolved from their subdomain
+    pub tenant_domain: Option<TenantDomain>,
+    // The tenant of whoever runs this server, the only one allowed to manage tenants
+    pub provider_tenant: String,
+    // Kept current as tenants change, CORS allows these in multi-tenant mode
+    pub tenant_origins: Arc<TenantOrigins>,
+    // The server wide IP allow and deny lists, each tenant can add its own
+    pub ip_access: Arc<IpAccessRules>,
+    // Failed agent connections per client IP and agent id, for backoff and bans
+    pub agent_connect_guard: Arc<AgentConnectGuard>,
+    pub db_pool: SqlitePool,
 }
 
 impl ApiState {
     pub fn new(
         server_jwt_secret: String,
-        agent_jwt_secret: String,
+        agent_jwt_keys: JwtKeySet,
         agent_ping_interval: u64,
         agent_ping_timeout: u64,
+        agent_jwt_lifetime_secs: u64,
+        agent_mtls_mode: MtlsMode,
+        db_pool: SqlitePool,
     ) -> Self {
         let (tx, _) = broadcast::channel(32);
         let runtime_properties = RuntimeProperties::global();
@@ -30,27 +70,109 @@ impl ApiState {
             id: format!("api:{}", runtime_properties.id()),
             broadcast_tx: Arc::new(Mutex::new(tx)),
             server_jwt_secret,
-            agent_jwt_secret,
+            session_jwt_lifetime_secs: 900,
+            refresh_token_lifetime_secs: 2_592_000,
+            agent_jwt_keys: Arc::new(agent_jwt_keys),
             agent_ping_interval,
             agent_ping_timeout,
+            agent_jwt_lifetime_secs,
+            agent_command_timeout_secs: ApiConfiguration::default().agent_command_timeout_secs,
+            agent_command_max_timeout_secs: ApiConfiguration::default()
+                .agent_command_max_timeout_secs,
+            agent_mtls_mode,
+            certificate_authority: None,
+            command_signer: None,
+            tls_reload: Arc::new(Notify::new()),
+            tenant_purge: Arc::new(Notify::new()),
+            tenant_domain: None,
+            provider_tenant: "default".to_string(),
+            tenant_origins: Arc::new(TenantOrigins::default()),
+            ip_access: Arc::new(IpAccessRules::default()),
+            agent_connect_guard: Arc::new(AgentConnectGuard::new(&ApiConfiguration::default())),
+            db_pool,
         }
     }
+
+    /// How long the session and refresh tokens issued to users at login last
+    pub fn with_session_lifetimes(
+        mut self,
+        session_jwt_lifetime_secs: u64,
+        refresh_token_lifetime_secs: u64,
+    ) -> Self {
+        self.session_jwt_lifetime_secs = session_jwt_lifetime_secs;
+        self.refresh_token_lifetime_secs = refresh_token_lifetime_secs;
+        self
+    }
+
+    /// How long commands sent to agents get to finish when the request doesn't say, and the
+    /// longest a request may say
+    pub fn with_agent_command_timeouts(
+        mut self,
+        agent_command_timeout_secs: u64,
+        agent_command_max_timeout_secs: u64,
+    ) -> Self {
+        self.agent_command_timeout_secs = agent_command_timeout_secs;
+        self.agent_command_max_timeout_secs = agent_command_max_timeout_secs;
+        self
+    }
+
+    /// Issue agent certificates with the built-in CA
+    pub fn with_certificate_authority(
+        mut self,
+        certificate_authority: Option<CertificateAuthority>,
+    ) -> Self {
+        self.certificate_authority = certificate_authority.map(Arc::new);
+        self
+    }
+
+    /// Sign the commands sent to agents so they can verify where they came from
+    pub fn with_command_signer(mut self, command_signer: CommandSigner) -> Self {
+        self.command_signer = Some(Arc::new(command_signer));
+        self
+    }
+
+    /// The tenant allowed to manage every other tenant
+    pub fn with_provider_tenant(mut self, provider_tenant: &str) -> Self {
+        self.provider_tenant = provider_tenant.to_string();
+        self
+    }
+
+    /// Resolve tenants from the subdomain of requests made to the tenant domain
+    pub fn with_tenant_domain(mut self, tenant_domain: Option<TenantDomain>) -> Self {
+        self.tenant_domain = tenant_domain;
+        self
+    }
+
+    /// Restrict where agents may connect from and the admin API may be used from
+    pub fn with_ip_access(mut self, ip_access: IpAccessRules) -> Self {
+        self.ip_access = Arc::new(ip_access);
+        self
+    }
+
+    /// How failed agent connections are backed off and banned
+    pub fn with_agent_connect_guard(mut self, agent_connect_guard: AgentConnectGuard) -> Self {
+        self.agent_connect_guard = Arc::new(agent_connect_guard);
+        self
+    }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct V1ApiState {
     pub id: String,
     pub agent_registry: AgentRegistry,
+    // What agents stream back while their commands run, for API clients to tail
+    pub command_outputs: Arc<CommandOutputs>,
 }
 
 impl V1ApiState {
     pub fn new() -> Self {
         let runtime_properties = RuntimeProperties::global();
-        let agent_registry: AgentRegistry = Arc::new(DashMap::new());
+        let agent_registry: AgentRegistry = Arc::new(TenantAgentRegistry::default());
 
         Self {
             id: format!("api:v1:{}", runtime_properties.id()),
             agent_registry,
+            command_outputs: Arc::new(CommandOutputs::default()),
         }
     }
 }
@@ -58,7 +180,14 @@ impl V1ApiState {
 #[derive(Debug)]
 pub struct ApiActorState {
     pub shutdown_tx: Option<tokio::sync::oneshot::Sender<()>>,
-    pub server_shutdown_handle: Option<axum_server::Handle>,
+    pub server_shutdown_handle: Option<ServerHandle>,
+    pub revocation_monitor: Option<tokio::task::JoinHandle<()>>,
+    pub tls_reloader: Option<tokio::task::JoinHandle<()>>,
+    pub tenant_origins_watcher: Option<tokio::task::JoinHandle<()>>,
+    pub agent_connect_pruner: Option<tokio::task::JoinHandle<()>>,
+    pub command_output_sweeper: Option<tokio::task::JoinHandle<()>>,
+    pub crl_publisher: Option<tokio::task::JoinHandle<()>>,
+    pub tenant_purger: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl ApiActorState {
@@ -66,6 +195,13 @@ impl ApiActorState {
         Self {
             shutdown_tx: None,
             server_shutdown_handle: None,
+            revocation_monitor: None,
+            tls_reloader: None,
+            tenant_origins_watcher: None,
+            agent_connect_pruner: None,
+            command_output_sweeper: None,
+            crl_publisher: None,
+            tenant_purger: None,
         }
     }
 }
@@ -0,0 +1,250 @@
+use crate::actors::api::{
+    client_ip::ClientIp,
+    state::ApiState,
+    v1::{
+        errors::ApiError,
+        handlers::tenants::require_active_tenant,
+        jwt::session::validate_session_jwt,
+        rbac::{Permission, Role},
+    },
+};
+use anyhow::{anyhow, Error};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use database_server::models::{api_keys::use_api_key, users::get_user};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Prefix of every API key, so they can be told apart from session tokens
+pub(crate) const API_KEY_PREFIX: &str = "ak_";
+
+/// Who a request was made by - a user with a session token, or another system with an API key.
+/// Taking this as a handler argument is what makes a route require authentication,
+/// requests without either are rejected with a 401.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Principal {
+    // The username, or `api_key:<name>` for an API key
+    pub name: String,
+    pub tenant: String,
+    pub credential: Credential,
+    // Where the request was made from, recorded in the audit log
+    #[serde(skip)]
+    pub client_ip: Option<ClientIp>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Credential {
+    Session {
+        user_id: i32,
+        role: Role,
+    },
+    ApiKey {
+        key_id: i32,
+        scopes: Vec<Permission>,
+    },
+}
+
+impl fmt::Display for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::Session { role, .. } => write!(f, "the {} role", role.as_str()),
+            Credential::ApiKey { .. } => f.write_str("this API key"),
+        }
+    }
+}
+
+impl Principal {
+    pub fn allows(&self, permission: Permission) -> bool {
+        match &self.credential {
+            Credential::Session { role, .. } => role.allows(permission),
+            Credential::ApiKey { scopes, .. } => scopes.contains(&permission),
+        }
+    }
+
+    /// The logged in user, for operations on the caller's own account
+    pub fn user_id(&self) -> Result<i32, ApiError> {
+        match self.credential {
+            Credential::Session { user_id, .. } => Ok(user_id),
+            Credential::ApiKey { .. } => Err(ApiError::Forbidden(
+                "only a logged in user can do this".to_string(),
+            )),
+        }
+    }
+}
+
+/// Outcome of checking a request's credentials, kept with the request so they are only
+/// checked once however many times a `Principal` is extracted
+#[derive(Clone)]
+pub(crate) struct Authentication(pub Result<Principal, ApiError>);
+
+impl FromRequestParts<Arc<ApiState>> for Principal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ApiState>,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(Authentication(authentication)) = parts.extensions.get::<Authentication>() {
+            return authentication.clone();
+        }
+
+        let authentication = authenticate(parts, state).await;
+        parts
+            .extensions
+            .insert(Authentication(authentication.clone()));
+        authentication
+    }
+}
+
+async fn authenticate(parts: &Parts, state: &ApiState) -> Result<Principal, ApiError> {
+    let principal = check_credentials(parts, state).await?;
+
+    // Suspending a tenant shuts out its sessions and API keys straight away
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+    require_active_tenant(&mut db_conn, &principal.tenant).inspect_err(|error| {
+        warn!(principal = %principal.name, error = %error, path = %parts.uri.path(), "credentials of an inactive tenant presented");
+    })?;
+
+    Ok(principal)
+}
+
+async fn check_credentials(parts: &Parts, state: &ApiState) -> Result<Principal, ApiError> {
+    let client_ip = parts.extensions.get::<ClientIp>().copied();
+    let token = parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .ok_or_else(|| ApiError::Unauthorized("a bearer token is required".to_string()))?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    if token.starts_with(API_KEY_PREFIX) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let Some(key) = use_api_key(&mut db_conn, &hash_token(token), now)
+            .map_err(|error| ApiError::Internal(error.to_string()))?
+        else {
+            warn!(path = %parts.uri.path(), "invalid, revoked or expired API key presented");
+            return Err(ApiError::Unauthorized(
+                "invalid, revoked or expired API key".to_string(),
+            ));
+        };
+
+        return Ok(Principal {
+            name: format!("api_key:{}", key.name),
+            tenant: key.tenant,
+            credential: Credential::ApiKey {
+                key_id: key.id,
+                scopes: parse_scopes(&key.scopes),
+            },
+            client_ip,
+        });
+    }
+
+    let claims = validate_session_jwt(token, &state.server_jwt_secret).map_err(|error| {
+        warn!(error = %error, path = %parts.uri.path(), "invalid session token presented");
+        ApiError::Unauthorized("invalid session token".to_string())
+    })?;
+
+    let user_id = claims
+        .sub
+        .parse::<i32>()
+        .map_err(|_| ApiError::Unauthorized("invalid session token".to_string()))?;
+
+    // Disabling a user takes effect straight away rather than when their token expires
+    let user = get_user(&mut db_conn, user_id)
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+        .filter(|user| !user.disabled)
+        .ok_or_else(|| {
+            ApiError::Unauthorized("user is disabled or no longer exists".to_string())
+        })?;
+
+    Ok(Principal {
+        name: user.username,
+        tenant: user.tenant,
+        credential: Credential::Session {
+            user_id: user.id,
+            role: Role::parse(&user.role),
+        },
+        client_ip,
+    })
+}
+
+/// Scopes are stored comma separated, anything no longer known is dropped
+pub(crate) fn parse_scopes(scopes: &str) -> Vec<Permission> {
+    scopes
+        .split(',')
+        .filter_map(|scope| Permission::parse(scope.trim()))
+        .collect()
+}
+
+/// A random 256 bit secret, base64url encoded
+pub(crate) fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Refresh tokens and API keys are random, so a plain SHA-256 is enough to keep them out of the database
+pub(crate) fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Hash a password into an Argon2id PHC string with a random salt
+pub(crate) fn hash_password(password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|error| anyhow!("unable to hash password - {}", error))
+}
+
+/// Check a password against a stored hash. Without a hash (an unknown user) a dummy hash is
+/// checked instead, so the response time does not reveal which usernames exist.
+pub(crate) fn verify_password(password: &str, password_hash: Option<&str>) -> bool {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let known_user = password_hash.is_some();
+    let password_hash = match password_hash {
+        Some(password_hash) => password_hash,
+        None => DUMMY_HASH.get_or_init(|| hash_password("dummy password").unwrap_or_default()),
+    };
+
+    let Ok(parsed) = PasswordHash::new(password_hash) else {
+        return false;
+    };
+
+    let verified = Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .is_ok();
+
+    verified && known_user
+}
@@ -0,0 +1,276 @@
+use crate::actors::api::{
+    command_signer::{CommandSigner, UnsignedCommand},
+    state::{ApiState, V1ApiState},
+    v1::{
+        auth::Principal,
+        errors::ApiError,
+        handlers::agent::{
+            broadcast, send_to_agent, send_to_group,
+            types::{AgentEntry, AgentInfo},
+        },
+        rbac::{authorize, Permission},
+        responses::ApiResponse,
+    },
+};
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use runtime_shared::protocol::{CommandRecipient, Outbound};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug)]
+pub struct CommandRequest {
+    verb: String,
+    #[serde(default)]
+    payload: serde_json::Value,
+    // Seconds the command gets to finish, defaults to the server's agent command timeout and may
+    // not exceed its maximum
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct CommandSent {
+    command_id: String,
+    // How many agents the command was handed to, acks arrive asynchronously
+    agents: usize,
+    // Seconds since the unix epoch, agents stop the command if it runs past it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deadline: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct CancelSent {
+    command_id: String,
+}
+
+#[derive(Serialize)]
+pub struct ConnectedAgent {
+    id: String,
+    groups: Vec<String>,
+    // RFC 3339
+    last_seen: String,
+}
+
+#[instrument(name = "Connected Agent List", level = "trace", skip(state, v1_state))]
+pub async fn get_agents_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Extension(v1_state): Extension<Arc<V1ApiState>>,
+) -> Result<ApiResponse<Vec<ConnectedAgent>>, ApiError> {
+    authorize(&state, &user, Permission::ViewAgents, None)?;
+
+    // Snapshot first so no registry guard is held across an await
+    let entries = v1_state.agent_registry.tenant_agents(&user.tenant);
+
+    let mut agents = Vec::with_capacity(entries.len());
+    for AgentEntry { info, .. } in entries {
+        agents.push(ConnectedAgent {
+            id: info.id.clone(),
+            groups: info.groups.clone(),
+            last_seen: info.last_seen.lock().await.to_rfc3339(),
+        });
+    }
+
+    Ok(ApiResponse::ok(agents))
+}
+
+#[instrument(name = "Command Agent", level = "trace", skip(state, v1_state))]
+pub async fn command_agent_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Extension(v1_state): Extension<Arc<V1ApiState>>,
+    Path(agent_id): Path<String>,
+    Json(payload): Json<CommandRequest>,
+) -> Result<ApiResponse<CommandSent>, ApiError> {
+    authorize(&state, &user, Permission::CommandAgents, Some(&agent_id))?;
+
+    let (command_signer, command) = to_command(&state, payload)?;
+    let sent = send_to_agent(
+        &v1_state.agent_registry,
+        &user.tenant,
+        &agent_id,
+        outbound_command(&v1_state, command_signer, &command),
+    );
+    if sent.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "agent {} is not connected",
+            agent_id
+        )));
+    }
+
+    info!(agent = %agent_id, command_id = %command.command_id, user = %user.name, "command sent to agent");
+    Ok(ApiResponse::ok(watch_command(
+        &v1_state, &user, command, sent,
+    )))
+}
+
+#[instrument(name = "Command Agent Group", level = "trace", skip(state, v1_state))]
+pub async fn command_group_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Extension(v1_state): Extension<Arc<V1ApiState>>,
+    Path(group): Path<String>,
+    Json(payload): Json<CommandRequest>,
+) -> Result<ApiResponse<CommandSent>, ApiError> {
+    authorize(&state, &user, Permission::CommandGroups, Some(&group))?;
+
+    let (command_signer, command) = to_command(&state, payload)?;
+    let sent = send_to_group(
+        &v1_state.agent_registry,
+        &user.tenant,
+        &group,
+        outbound_command(&v1_state, command_signer, &command),
+    );
+
+    info!(%group, command_id = %command.command_id, agents = sent.len(), user = %user.name, "command sent to agent group");
+    Ok(ApiResponse::ok(watch_command(
+        &v1_state, &user, command, sent,
+    )))
+}
+
+#[instrument(name = "Broadcast Command", level = "trace", skip(state, v1_state))]
+pub async fn broadcast_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Extension(v1_state): Extension<Arc<V1ApiState>>,
+    Json(payload): Json<CommandRequest>,
+) -> Result<ApiResponse<CommandSent>, ApiError> {
+    authorize(&state, &user, Permission::Broadcast, None)?;
+
+    let (command_signer, command) = to_command(&state, payload)?;
+    let sent = broadcast(
+        &v1_state.agent_registry,
+        &user.tenant,
+        outbound_command(&v1_state, command_signer, &command),
+    );
+
+    info!(command_id = %command.command_id, agents = sent.len(), user = %user.name, "command broadcast to agents");
+    Ok(ApiResponse::ok(watch_command(
+        &v1_state, &user, command, sent,
+    )))
+}
+
+/// Ask an agent to stop a command it is still running, it reports the command as cancelled
+#[instrument(name = "Cancel Agent Command", level = "trace", skip(state, v1_state))]
+pub async fn cancel_command_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Extension(v1_state): Extension<Arc<V1ApiState>>,
+    Path((agent_id, command_id)): Path<(String, String)>,
+) -> Result<ApiResponse<CancelSent>, ApiError> {
+    authorize(&state, &user, Permission::CommandAgents, Some(&agent_id))?;
+
+    let command_signer = command_signer(&state)?;
+    let cancel = |agent: &AgentInfo| command_signer.sign_cancel(recipient(agent), &command_id);
+    if send_to_agent(&v1_state.agent_registry, &user.tenant, &agent_id, cancel).is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "agent {} is not connected",
+            agent_id
+        )));
+    }
+
+    info!(agent = %agent_id, %command_id, user = %user.name, "command cancel sent to agent");
+    Ok(ApiResponse::ok(CancelSent { command_id }))
+}
+
+// Commands with a deadline are marked timed out on any agent that doesn't report back by then
+fn watch_command(
+    v1_state: &Arc<V1ApiState>,
+    user: &Principal,
+    command: UnsignedCommand,
+    sent: Vec<String>,
+) -> CommandSent {
+    let agents = sent.len();
+    if let Some(deadline) = command.deadline.filter(|_| agents > 0) {
+        v1_state.command_outputs.watch_deadline(
+            user.tenant.clone(),
+            sent,
+            command.command_id.clone(),
+            deadline,
+        );
+    }
+
+    CommandSent {
+        command_id: command.command_id,
+        agents,
+        deadline: command.deadline,
+    }
+}
+
+// The command as sent to each agent, signed for it, and with its output expected from then on
+fn outbound_command<'a>(
+    v1_state: &'a V1ApiState,
+    command_signer: &'a CommandSigner,
+    command: &'a UnsignedCommand,
+) -> impl Fn(&AgentInfo) -> Outbound + 'a {
+    move |agent| {
+        v1_state
+            .command_outputs
+            .expect(&agent.tenant, &agent.id, &command.command_id);
+        command_signer.sign(recipient(agent), command)
+    }
+}
+
+fn recipient(agent: &AgentInfo) -> CommandRecipient<'_> {
+    CommandRecipient {
+        tenant: &agent.tenant,
+        agent_id: &agent.id,
+    }
+}
+
+// Every command is signed, for each agent it is sent to, and agents refuse any that aren't
+fn to_command(
+    state: &ApiState,
+    request: CommandRequest,
+) -> Result<(&CommandSigner, UnsignedCommand), ApiError> {
+    let command_signer = command_signer(state)?;
+    let command_id = Uuid::new_v4().to_string();
+
+    // No timeout at all is longer than any limit
+    let max_timeout_secs = state.agent_command_max_timeout_secs;
+    if let Some(timeout_secs) = request.timeout_secs {
+        if max_timeout_secs > 0 && (timeout_secs == 0 || timeout_secs > max_timeout_secs) {
+            return Err(ApiError::BadRequest(format!(
+                "timeout_secs must be between 1 and {}",
+                max_timeout_secs
+            )));
+        }
+    }
+
+    let timeout_secs = request
+        .timeout_secs
+        .unwrap_or(state.agent_command_timeout_secs);
+    let deadline = match timeout_secs {
+        0 => None,
+        timeout_secs => Some(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                .checked_add(timeout_secs)
+                .ok_or_else(|| ApiError::BadRequest("timeout_secs is too large".to_string()))?,
+        ),
+    };
+
+    Ok((
+        command_signer,
+        UnsignedCommand {
+            command_id,
+            verb: request.verb,
+            payload: request.payload,
+            deadline,
+        },
+    ))
+}
+
+fn command_signer(state: &ApiState) -> Result<&CommandSigner, ApiError> {
+    state
+        .command_signer
+        .as_deref()
+        .ok_or_else(|| ApiError::Internal("no key to sign agent commands with".to_string()))
+}
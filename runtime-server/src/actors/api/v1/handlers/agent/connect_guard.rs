@@ -0,0 +1,416 @@
+use crate::actors::api::v1::{errors::ApiError, ip_access::SECURITY_TARGET};
+use config_server::ApiConfiguration;
+use dashmap::DashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+// No ban lasts longer than this, however often it is repeated
+const MAX_BAN: Duration = Duration::from_secs(86_400);
+
+// Backoff after a failure doubles up to this, bans take over from there
+const MAX_BACKOFF_EXPONENT: u32 = 5;
+
+// A client IP is shared by every agent behind it, so it only starts backing off once this share
+// of its failures has been reached, one broken agent must not slow down its neighbours
+const IP_BACKOFF_AFTER_DIVISOR: u32 = 2;
+
+// IPv6 clients are counted per /64, the smallest network one is usually given
+const IPV6_PREFIX_SEGMENTS: usize = 4;
+
+// How often offenders that have gone quiet are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Tracks failed agent connections per client IP and per verified agent id. Every failure of an
+/// agent id, and those of a client IP past half its limit, make the next attempt wait a little
+/// longer, and too many within the failure window earn a temporary ban that doubles each time it
+/// is repeated.
+#[derive(Debug)]
+pub(crate) struct AgentConnectGuard {
+    max_failures: u32,
+    max_failures_per_ip: u32,
+    failure_window: Duration,
+    ban: Duration,
+    alert_bans: u32,
+    offenders: DashMap<Offender, FailureRecord>,
+}
+
+/// Who a failed connection is counted against
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Offender {
+    // IPv6 addresses are cut down to their /64
+    Ip(IpAddr),
+    // The tenant the request named, if any, and the agent id its token was bound to
+    Agent(String, String),
+}
+
+impl fmt::Display for Offender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Offender::Ip(IpAddr::V6(ip)) => write!(f, "ip {}/64", ip),
+            Offender::Ip(ip) => write!(f, "ip {}", ip),
+            Offender::Agent(tenant, id) if tenant.is_empty() => write!(f, "agent {}", id),
+            Offender::Agent(tenant, id) => write!(f, "agent {}/{}", tenant, id),
+        }
+    }
+}
+
+impl Offender {
+    fn ip(ip: IpAddr) -> Self {
+        match ip.to_canonical() {
+            IpAddr::V6(ip) => {
+                let mut segments = ip.segments();
+                segments[IPV6_PREFIX_SEGMENTS..].fill(0);
+                Offender::Ip(IpAddr::V6(Ipv6Addr::from(segments)))
+            }
+            ip => Offender::Ip(ip),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FailureRecord {
+    // Failures since the window started
+    failures: u32,
+    window_started: Instant,
+    // Attempts are refused until then, whether backing off or banned
+    retry_after: Instant,
+    bans: u32,
+    last_failure: Instant,
+}
+
+impl AgentConnectGuard {
+    pub fn new(api_config: &ApiConfiguration) -> Self {
+        Self {
+            max_failures: api_config.agent_connect_max_failures,
+            max_failures_per_ip: api_config.agent_connect_max_failures_per_ip,
+            failure_window: Duration::from_secs(api_config.agent_connect_failure_window_secs),
+            ban: Duration::from_secs(api_config.agent_connect_ban_secs),
+            alert_bans: api_config.agent_connect_alert_bans,
+            offenders: DashMap::new(),
+        }
+    }
+
+    /// Refuse a client IP or agent id still backing off or banned
+    pub fn check(
+        &self,
+        client_ip: Option<IpAddr>,
+        tenant: Option<&str>,
+        agent_id: Option<&str>,
+    ) -> Result<(), ApiError> {
+        let now = Instant::now();
+
+        for offender in self.offenders_of(client_ip, tenant, agent_id) {
+            let Some(record) = self.offenders.get(&offender) else {
+                continue;
+            };
+            if record.retry_after > now {
+                let wait = record.retry_after.duration_since(now).as_secs().max(1);
+                return Err(ApiError::TooManyRequests(format!(
+                    "too many failed connection attempts, try again in {} seconds",
+                    wait
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count a failed connection against the client IP, and against the agent id when the
+    /// caller has verified a token bound to it. An id that is merely claimed must not be passed,
+    /// or anyone could get an agent banned by failing in its name.
+    pub fn record_failure(
+        &self,
+        client_ip: Option<IpAddr>,
+        tenant: Option<&str>,
+        agent_id: Option<&str>,
+        error: &ApiError,
+    ) {
+        let now = Instant::now();
+
+        for offender in self.offenders_of(client_ip, tenant, agent_id) {
+            let (max_failures, backoff_after) = match offender {
+                Offender::Ip(_) => (
+                    self.max_failures_per_ip,
+                    self.max_failures_per_ip / IP_BACKOFF_AFTER_DIVISOR,
+                ),
+                Offender::Agent(..) => (self.max_failures, 0),
+            };
+
+            let mut record =
+                self.offenders
+                    .entry(offender.clone())
+                    .or_insert_with(|| FailureRecord {
+                        failures: 0,
+                        window_started: now,
+                        retry_after: now,
+                        bans: 0,
+                        last_failure: now,
+                    });
+
+            if now.duration_since(record.window_started) > self.failure_window {
+                record.failures = 0;
+                record.window_started = now;
+            }
+            record.failures += 1;
+            record.last_failure = now;
+
+            if record.failures < max_failures {
+                if record.failures > backoff_after {
+                    let exponent = record.failures - backoff_after - 1;
+                    let backoff = 1 << exponent.min(MAX_BACKOFF_EXPONENT);
+                    record.retry_after = now + Duration::from_secs(backoff);
+                }
+                continue;
+            }
+
+            record.bans += 1;
+            record.failures = 0;
+            record.window_started = now;
+
+            let ban = self
+                .ban
+                .saturating_mul(1 << (record.bans - 1).min(16))
+                .min(MAX_BAN);
+            record.retry_after = now + ban;
+
+            warn!(target: SECURITY_TARGET, %offender, bans = record.bans, ban_secs = ban.as_secs(), last_error = %error, "agent connections banned after repeated failures");
+            if self.alert_bans > 0 && record.bans >= self.alert_bans {
+                error!(target: SECURITY_TARGET, alert = true, %offender, bans = record.bans, "repeated agent connection abuse, possible brute force");
+            }
+        }
+    }
+
+    /// Forget the failures of an agent id once it connects. Those of its client IP stay,
+    /// one good agent must not clear the way for others behind the same address.
+    pub fn record_success(&self, tenant: Option<&str>, agent_id: &str) {
+        self.offenders.remove(&Offender::Agent(
+            tenant.unwrap_or_default().to_string(),
+            agent_id.to_string(),
+        ));
+    }
+
+    /// Drop offenders no longer backing off or banned that have been quiet for a full window
+    /// after their longest possible ban, so the table doesn't grow without end
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let quiet_for = self.failure_window + MAX_BAN;
+
+        self.offenders.retain(|_, record| {
+            record.retry_after > now || now.duration_since(record.last_failure) < quiet_for
+        });
+    }
+
+    // Agent ids only count when a limit has been set for them
+    fn offenders_of(
+        &self,
+        client_ip: Option<IpAddr>,
+        tenant: Option<&str>,
+        agent_id: Option<&str>,
+    ) -> Vec<Offender> {
+        let mut offenders = vec![];
+        if let Some(client_ip) = client_ip.filter(|_| self.max_failures_per_ip > 0) {
+            offenders.push(Offender::ip(client_ip));
+        }
+        if let Some(agent_id) = agent_id.filter(|_| self.max_failures > 0) {
+            offenders.push(Offender::Agent(
+                tenant.unwrap_or_default().to_string(),
+                agent_id.to_string(),
+            ));
+        }
+        offenders
+    }
+}
+
+/// Forget offenders that have gone quiet, for as long as the server runs
+pub(crate) async fn prune_connect_failures(agent_connect_guard: Arc<AgentConnectGuard>) {
+    loop {
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+        agent_connect_guard.prune();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> AgentConnectGuard {
+        AgentConnectGuard {
+            max_failures: 3,
+            max_failures_per_ip: 4,
+            failure_window: Duration::from_secs(600),
+            ban: Duration::from_secs(300),
+            alert_bans: 2,
+            offenders: DashMap::new(),
+        }
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn error() -> ApiError {
+        ApiError::Unauthorized("invalid token".to_string())
+    }
+
+    // Lets the test move on as if the offender had waited out its backoff or ban
+    fn wait_out(guard: &AgentConnectGuard, offender: &Offender) {
+        if let Some(mut record) = guard.offenders.get_mut(offender) {
+            record.retry_after = Instant::now();
+        }
+    }
+
+    fn retry_in(guard: &AgentConnectGuard, offender: &Offender) -> Duration {
+        let record = guard.offenders.get(offender).unwrap();
+        record
+            .retry_after
+            .saturating_duration_since(record.last_failure)
+    }
+
+    #[test]
+    fn agent_backs_off_from_its_first_failure() {
+        let guard = guard();
+        let agent = Offender::Agent("acme".to_string(), "agent-1".to_string());
+
+        guard.record_failure(None, Some("acme"), Some("agent-1"), &error());
+        assert_eq!(retry_in(&guard, &agent), Duration::from_secs(1));
+        assert!(guard.check(None, Some("acme"), Some("agent-1")).is_err());
+        assert!(guard.check(None, Some("other"), Some("agent-1")).is_ok());
+
+        wait_out(&guard, &agent);
+        guard.record_failure(None, Some("acme"), Some("agent-1"), &error());
+        assert_eq!(retry_in(&guard, &agent), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn client_ip_backs_off_only_past_half_its_limit() {
+        let guard = guard();
+        let client = Offender::ip(ip("192.0.2.1"));
+
+        for _ in 0..2 {
+            guard.record_failure(Some(ip("192.0.2.1")), None, None, &error());
+            assert!(guard.check(Some(ip("192.0.2.1")), None, None).is_ok());
+        }
+
+        guard.record_failure(Some(ip("192.0.2.1")), None, None, &error());
+        assert_eq!(retry_in(&guard, &client), Duration::from_secs(1));
+        assert!(guard.check(Some(ip("192.0.2.1")), None, None).is_err());
+        assert!(guard.check(Some(ip("192.0.2.2")), None, None).is_ok());
+    }
+
+    #[test]
+    fn repeated_bans_double_up_to_a_day() {
+        let mut guard = guard();
+        guard.ban = Duration::from_secs(30_000);
+        let agent = Offender::Agent(String::new(), "agent-1".to_string());
+
+        let mut bans = vec![];
+        for _ in 0..3 {
+            for _ in 0..guard.max_failures {
+                wait_out(&guard, &agent);
+                guard.record_failure(None, None, Some("agent-1"), &error());
+            }
+            bans.push(retry_in(&guard, &agent).as_secs());
+        }
+
+        assert_eq!(bans, vec![30_000, 60_000, MAX_BAN.as_secs()]);
+        assert_eq!(guard.offenders.get(&agent).unwrap().bans, 3);
+        assert!(guard.check(None, None, Some("agent-1")).is_err());
+    }
+
+    #[test]
+    fn failures_outside_the_window_start_it_over() {
+        let guard = guard();
+        let agent = Offender::Agent(String::new(), "agent-1".to_string());
+
+        for _ in 0..guard.max_failures - 1 {
+            wait_out(&guard, &agent);
+            guard.record_failure(None, None, Some("agent-1"), &error());
+        }
+        guard.offenders.get_mut(&agent).unwrap().window_started =
+            Instant::now() - guard.failure_window - Duration::from_secs(1);
+
+        wait_out(&guard, &agent);
+        guard.record_failure(None, None, Some("agent-1"), &error());
+
+        let record = guard.offenders.get(&agent).unwrap();
+        assert_eq!((record.failures, record.bans), (1, 0));
+    }
+
+    #[test]
+    fn ipv6_clients_are_counted_per_64() {
+        let guard = guard();
+
+        for host in ["2001:db8:1:2::1", "2001:db8:1:2::2", "2001:db8:1:2:ffff::3"] {
+            guard.record_failure(Some(ip(host)), None, None, &error());
+        }
+
+        assert!(guard
+            .check(Some(ip("2001:db8:1:2::9")), None, None)
+            .is_err());
+        assert!(guard.check(Some(ip("2001:db8:1:3::1")), None, None).is_ok());
+        assert_eq!(
+            Offender::ip(ip("2001:db8:1:2::1")).to_string(),
+            "ip 2001:db8:1:2::/64"
+        );
+        assert_eq!(
+            Offender::ip(ip("::ffff:192.0.2.1")),
+            Offender::Ip(ip("192.0.2.1"))
+        );
+    }
+
+    #[test]
+    fn unverified_agent_ids_are_not_tracked_without_a_limit() {
+        let mut guard = guard();
+        guard.max_failures = 0;
+        guard.max_failures_per_ip = 0;
+
+        guard.record_failure(Some(ip("192.0.2.1")), None, Some("agent-1"), &error());
+
+        assert!(guard.offenders.is_empty());
+    }
+
+    #[test]
+    fn success_forgets_the_agent_but_not_its_ip() {
+        let guard = guard();
+
+        for _ in 0..3 {
+            guard.record_failure(
+                Some(ip("192.0.2.1")),
+                Some("acme"),
+                Some("agent-1"),
+                &error(),
+            );
+        }
+        guard.record_success(Some("acme"), "agent-1");
+
+        assert!(guard.check(None, Some("acme"), Some("agent-1")).is_ok());
+        assert!(guard.check(Some(ip("192.0.2.1")), None, None).is_err());
+    }
+
+    #[test]
+    fn prune_keeps_banned_and_recent_offenders() {
+        let guard = guard();
+        let quiet = Instant::now() - guard.failure_window - MAX_BAN - Duration::from_secs(1);
+        for (host, retry_after) in [
+            ("192.0.2.1", quiet),
+            ("192.0.2.2", Instant::now() + MAX_BAN),
+            ("192.0.2.3", Instant::now()),
+        ] {
+            guard.record_failure(Some(ip(host)), None, None, &error());
+            let mut record = guard.offenders.get_mut(&Offender::ip(ip(host))).unwrap();
+            record.retry_after = retry_after;
+            if host != "192.0.2.3" {
+                record.last_failure = quiet;
+            }
+        }
+
+        guard.prune();
+
+        assert!(!guard.offenders.contains_key(&Offender::ip(ip("192.0.2.1"))));
+        assert!(guard.offenders.contains_key(&Offender::ip(ip("192.0.2.2"))));
+        assert!(guard.offenders.contains_key(&Offender::ip(ip("192.0.2.3"))));
+    }
+}
@@ -0,0 +1,279 @@
+use crate::actors::api::{
+    certificate_authority::CertificateAuthority,
+    state::ApiState,
+    v1::{
+        auth::Principal,
+        errors::ApiError,
+        rbac::{authorize, Permission},
+        responses::ApiResponse,
+    },
+};
+use anyhow::Error;
+use axum::extract::{
+    ws::{Message, WebSocket},
+    Path, State,
+};
+use database_server::{
+    models::{
+        agent_certificates::{
+            get_agent_certificates, get_revoked_certificates, has_active_certificate,
+            record_agent_certificate, NewAgentCertificate,
+        },
+        agent_revocations::is_agent_revoked,
+        enrollment_tokens::{
+            delete_expired_enrollment_tokens, use_enrollment_token, UsedEnrollmentToken,
+        },
+    },
+    AgentCertificate, SqlitePool,
+};
+use futures_util::{SinkExt, StreamExt};
+use runtime_shared::protocol::{Inbound, Outbound};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::{error, info, instrument, warn};
+
+// How long an agent without a certificate has to submit its CSR
+const ENROLLMENT_TIMEOUT_SECONDS: u64 = 30;
+
+// Well within the lifetime of a CRL, so one is never seen past its next update
+const CRL_REPUBLISH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[instrument(name = "Agent Certificate List", level = "trace")]
+pub async fn get_agent_certificates_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Path(agent_id): Path<String>,
+) -> Result<ApiResponse<Vec<AgentCertificate>>, ApiError> {
+    authorize(&state, &user, Permission::ViewAgents, Some(&agent_id))?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    match get_agent_certificates(&mut db_conn, &user.tenant, &agent_id) {
+        Ok(certificates) => Ok(ApiResponse::ok(certificates)),
+        Err(error) => Err(ApiError::Internal(error.to_string())),
+    }
+}
+
+/// What entitles an agent to a certificate
+#[derive(Debug, Clone)]
+pub(crate) enum Enrollment {
+    /// Its first certificate, paid for with the one-time enrollment token it connected with
+    Token { jti: String, expires_at: i64 },
+    /// A new certificate for an agent that authenticated with its current one
+    Renewal,
+}
+
+/// Sign an agent's CSR with the built-in CA and record the issued certificate against the
+/// agent's tenant. Always answers with a message for the agent, `EnrollFailed` explains any refusal.
+pub(crate) fn enroll_agent(
+    state: &ApiState,
+    tenant: &str,
+    agent_id: &str,
+    csr: &str,
+    enrollment: Enrollment,
+) -> Outbound {
+    match issue_certificate(state, tenant, agent_id, csr, enrollment) {
+        Ok(outbound) => {
+            info!(agent = %agent_id, "agent certificate issued");
+            outbound
+        }
+        Err(error) => {
+            warn!(agent = %agent_id, error = %error, "agent enrollment refused");
+            Outbound::EnrollFailed {
+                reason: error.to_string(),
+            }
+        }
+    }
+}
+
+fn issue_certificate(
+    state: &ApiState,
+    tenant: &str,
+    agent_id: &str,
+    csr: &str,
+    enrollment: Enrollment,
+) -> Result<Outbound, ApiError> {
+    let Some(certificate_authority) = &state.certificate_authority else {
+        return Err(ApiError::NotFound(
+            "this server does not issue agent certificates".to_string(),
+        ));
+    };
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    if is_agent_revoked(&mut db_conn, tenant, agent_id)
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+    {
+        return Err(ApiError::Forbidden("agent has been revoked".to_string()));
+    }
+
+    // A malformed request must not cost the agent its one-time enrollment token
+    let csr = CertificateAuthority::parse_agent_csr(csr)
+        .map_err(|error| ApiError::BadRequest(error.to_string()))?;
+
+    // Otherwise a token holder could take over an agent that is already enrolled
+    if let Enrollment::Token { jti, expires_at } = enrollment {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        if has_active_certificate(&mut db_conn, tenant, agent_id, now)
+            .map_err(|error| ApiError::Internal(error.to_string()))?
+        {
+            return Err(ApiError::Forbidden(
+                "agent already holds a certificate, it can only be renewed over a connection authenticated with it"
+                    .to_string(),
+            ));
+        }
+
+        let _ = delete_expired_enrollment_tokens(&mut db_conn, now);
+        let unused = use_enrollment_token(
+            &mut db_conn,
+            UsedEnrollmentToken {
+                tenant: tenant.to_string(),
+                agent_id: agent_id.to_string(),
+                jti,
+                expires_at,
+            },
+        )
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+        if !unused {
+            return Err(ApiError::Forbidden(
+                "enrollment token has already been used".to_string(),
+            ));
+        }
+    }
+
+    let issued = certificate_authority
+        .sign_agent_csr(csr, agent_id)
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    record_agent_certificate(
+        &mut db_conn,
+        NewAgentCertificate {
+            tenant: tenant.to_string(),
+            agent_id: agent_id.to_string(),
+            serial: issued.serial,
+            not_before: issued.not_before,
+            not_after: issued.not_after,
+        },
+    )
+    .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    Ok(Outbound::Certificate {
+        certificate: issued.pem,
+        ca: certificate_authority.ca_pem().to_string(),
+        expires_at: issued.not_after as u64,
+    })
+}
+
+/// Rebuild the CRL from the store and have the TLS acceptor pick it up
+pub(crate) fn publish_revocation_list(
+    certificate_authority: &CertificateAuthority,
+    db_pool: &SqlitePool,
+) -> Result<(), Error> {
+    let mut db_conn = db_pool.get()?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let revoked = get_revoked_certificates(&mut db_conn, now)?;
+
+    certificate_authority.publish_crl(&revoked)
+}
+
+/// Republish the CRL on a timer, without revocations it would otherwise go stale
+#[instrument(name = "CRL Publisher", level = "trace", skip_all)]
+pub(crate) async fn republish_revocation_list(
+    certificate_authority: Arc<CertificateAuthority>,
+    db_pool: SqlitePool,
+    tls_reload: Arc<Notify>,
+) {
+    let mut interval = tokio::time::interval(CRL_REPUBLISH_INTERVAL);
+    // The first tick is immediate, and the CRL has only just been published at startup
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        match publish_revocation_list(&certificate_authority, &db_pool) {
+            Ok(()) => {
+                info!("certificate revocation list republished");
+                tls_reload.notify_one();
+            }
+            Err(error) => {
+                error!(errorMsg = %error, "unable to republish the certificate revocation list")
+            }
+        }
+    }
+}
+
+/// A socket for an agent that authenticated with its enrollment token but has no client
+/// certificate yet. It may only enroll - the connection is closed once a certificate has been issued.
+#[instrument(name = "Handle Agent Enrollment Socket", level = "trace")]
+pub(crate) async fn handle_enrollment_socket(
+    socket: WebSocket,
+    tenant: String,
+    agent_id: String,
+    enrollment: Enrollment,
+    state: Arc<ApiState>,
+) {
+    let (mut sender, mut receiver) = socket.split();
+
+    let enrollment = tokio::time::timeout(Duration::from_secs(ENROLLMENT_TIMEOUT_SECONDS), async {
+        while let Some(Ok(msg)) = receiver.next().await {
+            match msg {
+                Message::Text(t) => match serde_json::from_str::<Inbound>(&t) {
+                    Ok(Inbound::Enroll { csr }) => {
+                        return Some(enroll_agent(
+                            &state,
+                            &tenant,
+                            &agent_id,
+                            &csr,
+                            enrollment.clone(),
+                        ))
+                    }
+                    Ok(Inbound::Disconnect { .. }) => return None,
+                    _ => {}
+                },
+                Message::Close(_) => return None,
+                _ => {}
+            }
+        }
+        None
+    })
+    .await;
+
+    let reason = match enrollment {
+        Ok(Some(outbound)) => {
+            if let Err(error) = sender
+                .send(Message::Text(
+                    serde_json::to_string(&outbound).unwrap().into(),
+                ))
+                .await
+            {
+                error!(agent = %agent_id, errorMsg = %error, "unable to send enrollment result");
+            }
+            "enrollment complete"
+        }
+        Ok(None) => "enrollment abandoned",
+        Err(_) => "enrollment timed out",
+    };
+
+    info!(agent = %agent_id, reason, "closing enrollment socket");
+    let _ = sender
+        .send(Message::Text(
+            serde_json::to_string(&Outbound::Disconnect {
+                reason: Some(reason.to_string()),
+            })
+            .unwrap()
+            .into(),
+        ))
+        .await;
+    let _ = sender.close().await;
+}
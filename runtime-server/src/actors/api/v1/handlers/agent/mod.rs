@@ -1,16 +1,29 @@
-pub(crate) mod protocol;
+pub(crate) mod commands;
+pub(crate) mod connect_guard;
+pub(crate) mod enrollment;
+pub(crate) mod output;
+pub(crate) mod revocation;
 pub(crate) mod types;
 
 use crate::actors::api::{
+    client_ip::ClientIp,
     state::{ApiState, V1ApiState},
     v1::{
+        auth::Principal,
         errors::ApiError,
-        handlers::agent::{
-            protocol::{Inbound, Outbound},
-            types::{AgentEntry, AgentInfo, AgentRegistry},
+        handlers::{
+            agent::{
+                enrollment::{enroll_agent, handle_enrollment_socket, Enrollment},
+                output::OutputEvent,
+                types::{AgentEntry, AgentInfo, AgentRegistry},
+            },
+            tenants::require_active_tenant,
         },
-        jwt::{generate_jwt, JwtType},
+        ip_access::{check_ip_access, IpAccessScope},
+        jwt::{generate_jwt, validate_jwt, JwtType},
+        rbac::{authorize, Permission},
         responses::ApiResponse,
+        tenant::Tenant,
     },
 };
 use axum::{
@@ -21,37 +34,83 @@ use axum::{
     response::IntoResponse,
     Extension,
 };
+use config_server::MtlsMode;
+use database_server::models::{
+    agent_certificates::{get_certificate_tenant, has_active_certificate},
+    agent_revocations::is_agent_revoked,
+    agent_tokens::{
+        delete_expired_agent_tokens, is_agent_token_revoked, record_agent_token, NewAgentToken,
+    },
+};
+use database_server::ALL_TENANTS;
 use futures_util::{SinkExt, StreamExt};
-use serde::Serialize;
+use runtime_shared::api_server::client_cert::ClientCertificate;
+use runtime_shared::protocol::{Inbound, Outbound};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tokio::sync::{mpsc, oneshot, Mutex};
-use tracing::{error, info, instrument};
+use tokio::sync::{mpsc, oneshot, Mutex, Notify};
+use tracing::{error, info, instrument, warn};
 use types::WSConnect;
 use uuid::Uuid;
 
 #[derive(Serialize)]
 pub struct AgentToken {
     token: String,
+    expires_at: usize,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AgentTokenRequest {
+    // An enrollment token, good for enrolling this one agent once, otherwise a token any
+    // agent of the tenant can connect with
+    agent_id: Option<String>,
+    #[serde(default)]
+    enrollment: bool,
 }
 
 #[instrument(name = "Agent Token Generator", level = "trace")]
 pub async fn get_agent_token_handler(
+    user: Principal,
     State(state): State<Arc<ApiState>>,
     Extension(v1_state): Extension<Arc<V1ApiState>>,
+    Query(request): Query<AgentTokenRequest>,
 ) -> Result<ApiResponse<AgentToken>, ApiError> {
-    // #TODO - need to ensure the tenant is selected from the auth bearer token
-    let tenant = "streamline_partners";
+    authorize(&state, &user, Permission::IssueAgentTokens, None)?;
+
+    // An empty agent_id would bind the token to an id no agent can have
+    let agent_id = request
+        .agent_id
+        .filter(|agent_id| !agent_id.trim().is_empty());
+    let jwt_type = match (&agent_id, request.enrollment) {
+        (Some(_), true) => JwtType::Enrollment,
+        (None, true) => {
+            return Err(ApiError::BadRequest(
+                "an enrollment token needs the agent_id it enrolls".to_string(),
+            ))
+        }
+        (_, false) => JwtType::Agent,
+    };
 
-    match generate_jwt(tenant, &state.agent_jwt_secret, JwtType::Agent) {
+    // Agents are enrolled into the tenant of the user asking for the token
+    match generate_jwt(
+        &user.tenant,
+        agent_id.as_deref(),
+        &state.agent_jwt_keys,
+        state.agent_jwt_lifetime_secs,
+        jwt_type,
+    ) {
         Ok(jwt) => {
-            let token = AgentToken { token: jwt };
+            let token = AgentToken {
+                token: jwt.token,
+                expires_at: jwt.expires_at,
+            };
             Ok(ApiResponse::ok(token))
         }
         Err(error) => {
             error!(error=%error,"Failed to generate Agent JWT");
             Err(ApiError::Internal(format!(
                 "Failed to generate Agent JWT - {}",
-                error.to_string()
+                error
             )))
         }
     }
@@ -63,10 +122,52 @@ pub async fn agent_connection_handler(
     Query(params): Query<WSConnect>,
     State(state): State<Arc<ApiState>>,
     Extension(v1_state): Extension<Arc<V1ApiState>>,
-) -> impl IntoResponse {
+    tenant: Option<Extension<Tenant>>,
+    client_ip: Option<Extension<ClientIp>>,
+    client_certificate: Option<Extension<Option<ClientCertificate>>>,
+) -> Result<impl IntoResponse, ApiError> {
     println!("PARAMS: {:?}", params);
 
-    let id = params.id;
+    let client_certificate = client_certificate.and_then(|Extension(certificate)| certificate);
+    let client_ip = client_ip.map(|Extension(client_ip)| client_ip);
+    let requested_tenant = tenant
+        .as_ref()
+        .map(|Extension(Tenant(tenant))| tenant.as_str());
+
+    // Anyone still backing off or banned after failing to connect is refused straight away.
+    // Failures below are counted against the client IP, and against the agent id only once a
+    // token bound to that id has verified - anyone can claim an id, and so get it banned.
+    let connect_guard = &state.agent_connect_guard;
+    let claimed_id = params.id.clone();
+    connect_guard.check(
+        client_ip.map(|ClientIp(ip)| ip),
+        requested_tenant,
+        claimed_id.as_deref(),
+    )?;
+    let failed = |agent_id: Option<&str>, error: ApiError| {
+        connect_guard.record_failure(
+            client_ip.map(|ClientIp(ip)| ip),
+            requested_tenant,
+            agent_id,
+            &error,
+        );
+        error
+    };
+
+    // Without a certificate an agent may still connect to enroll for one, provided we are its CA
+    let may_enroll = client_certificate.is_none() && state.certificate_authority.is_some();
+
+    let id = match may_enroll && state.agent_mtls_mode == MtlsMode::Required {
+        true => params
+            .id
+            .ok_or_else(|| ApiError::BadRequest("missing agent id".to_string()))?,
+        false => agent_identity(
+            state.agent_mtls_mode,
+            client_certificate.as_ref(),
+            params.id,
+        )
+        .map_err(|error| failed(None, error))?,
+    };
     let groups = params
         .groups
         .into_iter()
@@ -79,19 +180,263 @@ pub async fn agent_connection_handler(
         .collect::<Vec<_>>();
     let token = params.token;
 
+    // Only agents presenting a valid, unexpired token may connect
+    let claims = match validate_jwt(&token, &state.agent_jwt_keys, JwtType::Agent) {
+        Ok(claims) => claims,
+        Err(error) => {
+            warn!(agent = %id, error = %error, "agent presented an invalid token");
+            return Err(failed(
+                None,
+                ApiError::Unauthorized("invalid agent token".to_string()),
+            ));
+        }
+    };
+
+    // The id the token vouches for, the only one further failures may be counted against
+    let verified_id = claims.agent_id.as_deref().filter(|bound| *bound == id);
+
+    // Refreshed tokens are bound to the agent they were issued to
+    if let Some(bound_agent_id) = &claims.agent_id {
+        if bound_agent_id != &id {
+            warn!(agent = %id, token_agent = %bound_agent_id, "agent presented another agent's token");
+            return Err(failed(
+                verified_id,
+                ApiError::Unauthorized("token was not issued to this agent".to_string()),
+            ));
+        }
+    }
+
+    // Without a certificate an enrollment token only ever enrolls its agent, and in required
+    // mode enrolling is all an agent without one may do
+    let enrollment_only =
+        may_enroll && (state.agent_mtls_mode == MtlsMode::Required || claims.enrollment);
+    if client_certificate.is_none() {
+        if enrollment_only && (!claims.enrollment || claims.agent_id.is_none()) {
+            warn!(agent = %id, "agent without a certificate did not present an enrollment token");
+            return Err(failed(
+                verified_id,
+                ApiError::Unauthorized(
+                    "enrolling needs an enrollment token issued to this agent".to_string(),
+                ),
+            ));
+        }
+        if claims.enrollment && !enrollment_only {
+            warn!(agent = %id, "agent presented an enrollment token, but this server does not issue certificates");
+            return Err(failed(
+                verified_id,
+                ApiError::Unauthorized("enrollment tokens can only be used to enroll".to_string()),
+            ));
+        }
+    }
+
+    // An agent token is only good for the tenant it was issued in
+    if let Some(tenant) = requested_tenant {
+        if tenant != claims.aud {
+            warn!(agent = %id, %tenant, token_tenant = %claims.aud, "agent presented another tenant's token");
+            return Err(failed(
+                verified_id,
+                ApiError::Unauthorized("token was not issued for this tenant".to_string()),
+            ));
+        }
+    }
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    // Agent ids are only unique within a tenant, so a certificate we issued to an agent of
+    // one tenant must not be paired with a token from another
+    if let Some(certificate) = &client_certificate {
+        let serial = certificate.serial.replace(':', "").to_ascii_lowercase();
+        let issued_to = get_certificate_tenant(&mut db_conn, &serial)
+            .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+        if let Some(issued_to) = issued_to.filter(|issued_to| issued_to != ALL_TENANTS) {
+            if issued_to != claims.aud {
+                warn!(agent = %id, certificate_tenant = %issued_to, token_tenant = %claims.aud, "agent certificate belongs to another tenant");
+                return Err(failed(
+                    verified_id,
+                    ApiError::Unauthorized(
+                        "client certificate was not issued for this tenant".to_string(),
+                    ),
+                ));
+            }
+        }
+    }
+
+    // Without a certificate the id is only what the agent claims, so it can't be one that
+    // belongs to an agent holding a certificate
+    if client_certificate.is_none() && state.agent_mtls_mode == MtlsMode::Optional {
+        let certified = has_active_certificate(
+            &mut db_conn,
+            &claims.aud,
+            &id,
+            chrono::Utc::now().timestamp(),
+        )
+        .map_err(|error| {
+            error!(agent = %id, errorMsg = %error, "unable to check the agent certificates");
+            ApiError::Internal("unable to check the agent certificates".to_string())
+        })?;
+        if certified {
+            warn!(agent = %id, "agent claimed the id of an agent with a certificate without presenting one");
+            return Err(failed(
+                verified_id,
+                ApiError::Unauthorized(
+                    "this agent id must connect with its client certificate".to_string(),
+                ),
+            ));
+        }
+    }
+
+    // Fails closed, an agent is only let in once we know it hasn't been revoked
+    let revoked = is_agent_revoked(&mut db_conn, &claims.aud, &id).map_err(|error| {
+        error!(agent = %id, errorMsg = %error, "unable to check the agent revocation list");
+        ApiError::Internal("unable to check the agent revocation list".to_string())
+    })?;
+    if revoked {
+        warn!(agent = %id, "revoked agent attempted to connect");
+        return Err(failed(
+            verified_id,
+            ApiError::Forbidden("agent has been revoked".to_string()),
+        ));
+    }
+
+    // A token not bound to an agent works under any id, so once an agent that used it is
+    // revoked it is refused whatever id it claims
+    if claims.agent_id.is_none() {
+        let token_revoked = is_agent_token_revoked(&mut db_conn, &claims.jti).map_err(|error| {
+            error!(agent = %id, errorMsg = %error, "unable to check the agent token revocations");
+            ApiError::Internal("unable to check the agent revocation list".to_string())
+        })?;
+        if token_revoked {
+            warn!(agent = %id, "agent presented a token revoked along with another agent");
+            return Err(failed(
+                verified_id,
+                ApiError::Forbidden("agent token has been revoked".to_string()),
+            ));
+        }
+    }
+    connect_guard.record_success(requested_tenant, &id);
+
+    let agent_tenant = require_active_tenant(&mut db_conn, &claims.aud).inspect_err(|error| {
+        warn!(agent = %id, tenant = %claims.aud, error = %error, "agent of an inactive tenant attempted to connect");
+    })?;
+
+    // Only once the agent's tenant is known, so its own lists are checked along with the server's
+    check_ip_access(
+        &state,
+        IpAccessScope::Agent,
+        client_ip,
+        Some(&agent_tenant),
+        &id,
+    )?;
+
+    // Remembered so that revoking this agent revokes the token too
+    if claims.agent_id.is_none() {
+        let _ = delete_expired_agent_tokens(&mut db_conn, chrono::Utc::now().timestamp());
+        record_agent_token(
+            &mut db_conn,
+            NewAgentToken {
+                tenant: claims.aud.clone(),
+                agent_id: id.clone(),
+                jti: claims.jti.clone(),
+                expires_at: claims.exp as i64,
+            },
+        )
+        .map_err(|error| {
+            error!(agent = %id, errorMsg = %error, "unable to record the agent token");
+            ApiError::Internal("unable to record the agent token".to_string())
+        })?;
+    }
+
+    let seat_limit = agent_tenant
+        .agent_seat_limit
+        .map(|seat_limit| seat_limit.max(0) as usize);
+
+    if enrollment_only {
+        info!(agent = %id, "agent connected without a certificate, enrollment only");
+        return Ok(ws.on_upgrade(move |socket| {
+            handle_enrollment_socket(
+                socket,
+                claims.aud,
+                id,
+                Enrollment::Token {
+                    jti: claims.jti,
+                    expires_at: claims.exp as i64,
+                },
+                state,
+            )
+        }));
+    }
+
+    // Checked again when the session is registered, this just refuses early with a status code
+    if !v1_state
+        .agent_registry
+        .has_seat(&claims.aud, &id, seat_limit)
+    {
+        warn!(agent = %id, tenant = %claims.aud, "agent refused, the tenant's agent seats are all taken");
+        return Err(ApiError::Forbidden(
+            "the tenant's agent seat limit has been reached".to_string(),
+        ));
+    }
+
+    let info = Arc::new(AgentInfo {
+        id,
+        tenant: claims.aud,
+        groups,
+        last_seen: Mutex::new(chrono::Utc::now()),
+        pending_pong: Mutex::new(None),
+        token: Mutex::new(token),
+        token_expires_at: Mutex::new(claims.exp),
+        certified: client_certificate.is_some(),
+    });
+
     // capture owned values into the on_upgrade closure
-    ws.on_upgrade(move |socket| handle_socket(socket, id, token, groups, state, v1_state))
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, info, seat_limit, state, v1_state)))
+}
+
+// Work out who is connecting - the certificate subject wins over the query string whenever
+// mutual TLS is enabled and the agent presented a certificate
+fn agent_identity(
+    mtls_mode: MtlsMode,
+    client_certificate: Option<&ClientCertificate>,
+    query_id: Option<String>,
+) -> Result<String, ApiError> {
+    match (mtls_mode, client_certificate) {
+        (MtlsMode::Optional | MtlsMode::Required, Some(certificate)) => {
+            let Some(common_name) = &certificate.common_name else {
+                return Err(ApiError::Unauthorized(
+                    "client certificate has no subject common name".to_string(),
+                ));
+            };
+
+            if let Some(query_id) = query_id.filter(|query_id| query_id != common_name) {
+                warn!(agent = %common_name, %query_id, "agent id does not match its certificate");
+                return Err(ApiError::Unauthorized(
+                    "agent id does not match the client certificate".to_string(),
+                ));
+            }
+
+            Ok(common_name.clone())
+        }
+        (MtlsMode::Required, None) => Err(ApiError::Unauthorized(
+            "a client certificate is required".to_string(),
+        )),
+        _ => query_id.ok_or_else(|| ApiError::BadRequest("missing agent id".to_string())),
+    }
 }
 
 #[instrument(name = "Hande Agent Socket Connection", level = "trace")]
 async fn handle_socket(
     socket: WebSocket,
-    agent_id: String,
-    token: String,
-    groups: Vec<String>,
+    info: Arc<AgentInfo>,
+    seat_limit: Option<usize>,
     state: Arc<ApiState>,
     v1_state: Arc<V1ApiState>,
 ) {
+    let agent_id = info.id.clone();
+
     // split socket into sink and stream
     let (mut sender, mut receiver) = socket.split();
 
@@ -106,26 +451,24 @@ async fn handle_socket(
     });
 
     // create AgentEntry and insert into registry immediately
-    let info = Arc::new(AgentInfo {
-        id: agent_id.clone(),
-        groups: groups.clone(),
-        last_seen: Mutex::new(chrono::Utc::now()),
-        pending_pong: Mutex::new(None),
-        token: token,
-    });
-
     let entry = AgentEntry {
         info: info.clone(),
         tx: tx.clone(),
+        disconnect: Arc::new(Notify::new()),
     };
 
-    v1_state
-        .agent_registry
-        .insert(agent_id.clone(), entry.clone());
+    if !v1_state.agent_registry.insert(entry.clone(), seat_limit) {
+        warn!(agent = %agent_id, tenant = %info.tenant, "agent dropped, the tenant's agent seats were taken while it connected");
+        disconnect_agent(&entry, "agent seat limit reached");
+        drop(tx);
+        drop(entry);
+        let _ = write_task.await;
+        return;
+    }
     info!(%agent_id, "agent connected (from querystring)");
 
     // spawn heartbeat monitor
-    tokio::spawn(start_heartbeat(
+    let heartbeat = tokio::spawn(start_heartbeat(
         agent_id.clone(),
         entry.clone(),
         v1_state.agent_registry.clone(),
@@ -133,50 +476,145 @@ async fn handle_socket(
         state.agent_ping_timeout,
     ));
 
-    // read loop: handle Pong / Ack / Disconnect
-    while let Some(Ok(msg)) = receiver.next().await {
-        match msg {
-            Message::Text(t) => {
-                if let Ok(inbound) = serde_json::from_str::<Inbound>(&t) {
-                    match inbound {
-                        Inbound::Pong { nonce: _ } => {
-                            // resolve pending pong oneshot if present
-                            if let Some(entry) = v1_state.agent_registry.get(&agent_id) {
-                                let info = &entry.info;
-                                let mut last = info.last_seen.lock().await;
-                                *last = chrono::Utc::now();
-                                if let Some(sender) = info.pending_pong.lock().await.take() {
-                                    let _ = sender.send(());
+    // read loop: handle Pong / Ack / Disconnect / token refresh / certificate renewal
+    loop {
+        tokio::select! {
+            msg = receiver.next() => {
+                let Some(Ok(msg)) = msg else {
+                    break;
+                };
+
+                match msg {
+                    Message::Text(t) => {
+                        if let Ok(inbound) = serde_json::from_str::<Inbound>(&t) {
+                            match inbound {
+                                Inbound::Pong { nonce: _ } => {
+                                    // resolve pending pong oneshot if present
+                                    let mut last = info.last_seen.lock().await;
+                                    *last = chrono::Utc::now();
+                                    if let Some(sender) = info.pending_pong.lock().await.take() {
+                                        let _ = sender.send(());
+                                    }
+                                }
+                                Inbound::Disconnect { reason } => {
+                                    info!(agent = %agent_id, ?reason, "agent requested disconnect");
+                                    let _ = tx.send(
+                                        serde_json::to_string(&Outbound::Disconnect { reason }).unwrap(),
+                                    );
+                                    break;
+                                }
+                                Inbound::Ack { command_id } => {
+                                    info!(agent = %agent_id, %command_id, "ack received");
+                                }
+                                Inbound::Result { command_id, error, output } => {
+                                    match &error {
+                                        None => info!(agent = %agent_id, %command_id, "command completed"),
+                                        Some(error) => warn!(agent = %agent_id, %command_id, code = ?error.code, reason = %error.message, "command refused or failed on agent"),
+                                    }
+                                    v1_state.command_outputs.push(&info.tenant, &agent_id, &command_id, OutputEvent::Result { error, output });
+                                }
+                                Inbound::Output { command_id, stream, data } => {
+                                    v1_state.command_outputs.push(&info.tenant, &agent_id, &command_id, OutputEvent::Output { stream, data });
+                                }
+                                Inbound::RefreshToken => {
+                                    match refresh_agent_token(&info, &state).await {
+                                        Ok(outbound) => {
+                                            let _ = tx.send(serde_json::to_string(&outbound).unwrap());
+                                            info!(agent = %agent_id, "agent token refreshed");
+                                        }
+                                        Err(error) => {
+                                            warn!(agent = %agent_id, error = %error, "agent token refresh refused");
+                                            disconnect_agent(&entry, &error.to_string());
+                                        }
+                                    }
+                                }
+                                Inbound::Enroll { csr } => {
+                                    // Renewal - only an agent that proved who it is with its current
+                                    // certificate gets another, a claimed id is not enough
+                                    let outbound = match info.certified {
+                                        true => enroll_agent(&state, &info.tenant, &agent_id, &csr, Enrollment::Renewal),
+                                        false => {
+                                            warn!(agent = %agent_id, "agent without a client certificate asked for one on its session");
+                                            Outbound::EnrollFailed {
+                                                reason: "certificates are only renewed over a connection authenticated with the agent's current certificate".to_string(),
+                                            }
+                                        }
+                                    };
+                                    let _ = tx.send(serde_json::to_string(&outbound).unwrap());
                                 }
                             }
                         }
-                        Inbound::Disconnect { reason } => {
-                            info!(agent = %agent_id, ?reason, "agent requested disconnect");
-                            v1_state.agent_registry.remove(&agent_id);
-                            let _ = tx.send(
-                                serde_json::to_string(&Outbound::Disconnect { reason }).unwrap(),
-                            );
-                            break;
-                        }
-                        Inbound::Ack { command_id } => {
-                            info!(agent = %agent_id, %command_id, "ack received");
-                        }
                     }
+                    Message::Close(_) => {
+                        info!(agent = %agent_id, "socket closed by client");
+                        break;
+                    }
+                    _ => {}
                 }
             }
-            Message::Close(_) => {
-                info!(agent = %agent_id, "socket closed by client");
-                v1_state.agent_registry.remove(&agent_id);
+            _ = entry.disconnect.notified() => {
+                info!(agent = %agent_id, "agent disconnected by server");
                 break;
             }
-            _ => {}
         }
     }
 
-    // ensure writer finishes
+    // tear down the session, letting the writer flush anything still queued
+    heartbeat.abort();
+    remove_agent_entry(&v1_state.agent_registry, &info);
+    drop(tx);
+    drop(entry);
     let _ = write_task.await;
 }
 
+// Issue a new token bound to this agent, provided it has not been revoked
+async fn refresh_agent_token(info: &AgentInfo, state: &ApiState) -> Result<Outbound, ApiError> {
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    if is_agent_revoked(&mut db_conn, &info.tenant, &info.id)
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+    {
+        return Err(ApiError::Forbidden("agent has been revoked".to_string()));
+    }
+    require_active_tenant(&mut db_conn, &info.tenant)?;
+
+    let jwt = generate_jwt(
+        &info.tenant,
+        Some(&info.id),
+        &state.agent_jwt_keys,
+        state.agent_jwt_lifetime_secs,
+        JwtType::Agent,
+    )
+    .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    *info.token.lock().await = jwt.token.clone();
+    *info.token_expires_at.lock().await = jwt.expires_at;
+
+    Ok(Outbound::Token {
+        token: jwt.token,
+        expires_at: jwt.expires_at as u64,
+    })
+}
+
+/// Tell an agent why it is being dropped, then close its connection
+pub(crate) fn disconnect_agent(entry: &AgentEntry, reason: &str) {
+    let _ = entry.tx.send(
+        serde_json::to_string(&Outbound::Disconnect {
+            reason: Some(reason.to_string()),
+        })
+        .unwrap(),
+    );
+    entry.disconnect.notify_one();
+}
+
+// Remove this session from the registry, leaving any newer session for the same agent in place
+fn remove_agent_entry(registry: &AgentRegistry, info: &Arc<AgentInfo>) {
+    registry.remove_session(info);
+}
+
 // ---------- Heartbeat monitor (same as earlier) ----------
 #[instrument(name = "Agent Heartbeat", level = "trace")]
 async fn start_heartbeat(
@@ -200,7 +638,7 @@ async fn start_heartbeat(
         }
 
         if entry.tx.send(text).is_err() {
-            registry.remove(&agent_id);
+            remove_agent_entry(&registry, &entry.info);
             break;
         }
 
@@ -214,13 +652,8 @@ async fn start_heartbeat(
             }
             _ => {
                 info!(agent = %agent_id, "ping timeout - disconnecting agent");
-                registry.remove(&agent_id);
-                let _ = entry.tx.send(
-                    serde_json::to_string(&Outbound::Disconnect {
-                        reason: Some("ping timeout".into()),
-                    })
-                    .unwrap(),
-                );
+                remove_agent_entry(&registry, &entry.info);
+                disconnect_agent(&entry, "ping timeout");
                 break;
             }
         }
@@ -230,29 +663,64 @@ async fn start_heartbeat(
 }
 
 // ---------- Helpers: direct/group/broadcast sends ----------
-#[instrument(name = "Send to Agent", level = "trace")]
-async fn send_to_agent(registry: &AgentRegistry, id: &str, msg: Outbound) -> Result<(), ()> {
-    if let Some(entry) = registry.get(id) {
-        let s = serde_json::to_string(&msg).unwrap();
-        entry.tx.send(s).map_err(|_| ())?;
-        Ok(())
-    } else {
-        Err(())
-    }
+// Only agents of `tenant` are ever reached. Each returns the ids of the agents the message was handed to.
+// The message is built for each agent it is sent to, so it can be signed for that agent alone
+#[instrument(name = "Send to Agent", level = "trace", skip(msg))]
+pub(crate) fn send_to_agent(
+    registry: &AgentRegistry,
+    tenant: &str,
+    id: &str,
+    msg: impl Fn(&AgentInfo) -> Outbound,
+) -> Vec<String> {
+    registry
+        .get(tenant, id)
+        .into_iter()
+        .filter(|entry| {
+            entry
+                .tx
+                .send(serde_json::to_string(&msg(&entry.info)).unwrap())
+                .is_ok()
+        })
+        .map(|entry| entry.info.id.clone())
+        .collect()
 }
 
-#[instrument(name = "Send to Agent Group", level = "trace")]
-async fn send_to_group(registry: &AgentRegistry, group: &str, msg: Outbound) {
-    for r in registry.iter() {
-        if r.value().info.groups.contains(&group.to_string()) {
-            let _ = r.value().tx.send(serde_json::to_string(&msg).unwrap());
+#[instrument(name = "Send to Agent Group", level = "trace", skip(msg))]
+pub(crate) fn send_to_group(
+    registry: &AgentRegistry,
+    tenant: &str,
+    group: &str,
+    msg: impl Fn(&AgentInfo) -> Outbound,
+) -> Vec<String> {
+    let mut sent = vec![];
+    for entry in registry.tenant_agents(tenant) {
+        if entry.info.groups.iter().any(|g| g == group)
+            && entry
+                .tx
+                .send(serde_json::to_string(&msg(&entry.info)).unwrap())
+                .is_ok()
+        {
+            sent.push(entry.info.id.clone());
         }
     }
+    sent
 }
 
-#[instrument(name = "Broadcast to Agents", level = "trace")]
-async fn broadcast(registry: &AgentRegistry, msg: Outbound) {
-    for r in registry.iter() {
-        let _ = r.value().tx.send(serde_json::to_string(&msg).unwrap());
+#[instrument(name = "Broadcast to Agents", level = "trace", skip(msg))]
+pub(crate) fn broadcast(
+    registry: &AgentRegistry,
+    tenant: &str,
+    msg: impl Fn(&AgentInfo) -> Outbound,
+) -> Vec<String> {
+    let mut sent = vec![];
+    for entry in registry.tenant_agents(tenant) {
+        if entry
+            .tx
+            .send(serde_json::to_string(&msg(&entry.info)).unwrap())
+            .is_ok()
+        {
+            sent.push(entry.info.id.clone());
+        }
     }
+    sent
 }
@@ -0,0 +1,618 @@
+use crate::actors::api::{
+    state::{ApiState, V1ApiState},
+    v1::{
+        auth::Principal,
+        errors::ApiError,
+        rbac::{authorize, Permission},
+    },
+};
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event, KeepAlive, Sse},
+    Extension,
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use runtime_shared::protocol::{CommandError, CommandErrorCode, OutputStream};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{instrument, warn};
+
+// Output kept per command for clients that start tailing late, the oldest goes first
+const MAX_BUFFERED_OUTPUT: usize = 1024 * 1024;
+
+// Output kept across all of a tenant's commands, past this finished commands lose theirs first,
+// then those that have been quiet the longest
+const MAX_TENANT_BUFFERED_OUTPUT: usize = 64 * 1024 * 1024;
+
+// Commands are forgotten this long after they finish, or after they last produced anything
+const FINISHED_RETENTION: Duration = Duration::from_secs(10 * 60);
+const IDLE_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+// How often commands past their retention are forgotten
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Chunks a slow client may fall behind by before it misses some
+const LIVE_CAPACITY: usize = 64;
+
+// How long past its deadline an agent still gets to report on a command it stopped itself
+const DEADLINE_GRACE: Duration = Duration::from_secs(10);
+
+/// Something a command produced on an agent, as clients tailing it see it
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub(crate) enum OutputEvent {
+    Output {
+        stream: OutputStream,
+        data: String,
+    },
+    Result {
+        error: Option<CommandError>,
+        output: Option<serde_json::Value>,
+    },
+}
+
+impl OutputEvent {
+    fn to_sse(&self) -> Event {
+        let name = match self {
+            OutputEvent::Output { .. } => "output",
+            OutputEvent::Result { .. } => "result",
+        };
+        Event::default()
+            .event(name)
+            .json_data(self)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct CommandOutput {
+    events: VecDeque<OutputEvent>,
+    // Bytes of output held in events
+    buffered: usize,
+    // Whether output has been dropped to stay under the buffer limit
+    dropped: bool,
+    finished: bool,
+    updated_at: Instant,
+    // Only while someone is tailing the command
+    live: Option<broadcast::Sender<OutputEvent>>,
+}
+
+impl CommandOutput {
+    fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            buffered: 0,
+            dropped: false,
+            finished: false,
+            updated_at: Instant::now(),
+            live: None,
+        }
+    }
+
+    // Drop the oldest chunk of output, returning its size. The result is always kept.
+    fn drop_oldest(&mut self) -> Option<usize> {
+        match self.events.pop_front()? {
+            OutputEvent::Output { data, .. } => {
+                self.buffered -= data.len();
+                self.dropped = true;
+                Some(data.len())
+            }
+            result => {
+                self.events.push_front(result);
+                None
+            }
+        }
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        let retention = if self.finished {
+            FINISHED_RETENTION
+        } else {
+            IDLE_RETENTION
+        };
+        now.duration_since(self.updated_at) > retention
+    }
+}
+
+/// What a client tailing a command gets, everything so far and then the rest as it comes
+pub(crate) struct OutputTail {
+    dropped: bool,
+    events: Vec<OutputEvent>,
+    // None once the command has finished
+    live: Option<broadcast::Receiver<OutputEvent>>,
+}
+
+type CommandKey = (String, String, String);
+
+fn command_key(tenant: &str, agent_id: &str, command_id: &str) -> CommandKey {
+    (
+        tenant.to_string(),
+        agent_id.to_string(),
+        command_id.to_string(),
+    )
+}
+
+#[derive(Debug, Default)]
+struct Outputs {
+    commands: HashMap<CommandKey, CommandOutput>,
+    // Bytes of output held across each tenant's commands
+    tenant_buffered: HashMap<String, usize>,
+}
+
+impl Outputs {
+    fn forget_expired(&mut self, now: Instant) {
+        let tenant_buffered = &mut self.tenant_buffered;
+        self.commands.retain(|(tenant, _, _), output| {
+            if !output.expired(now) {
+                return true;
+            }
+            release(tenant_buffered, tenant, output.buffered);
+            false
+        });
+    }
+
+    // Drop the oldest output of the tenant's finished commands, then of those quiet the longest,
+    // until the tenant is back under its limit
+    fn trim_tenant(&mut self, tenant: &str, max_tenant_buffered: usize) {
+        while self
+            .tenant_buffered
+            .get(tenant)
+            .copied()
+            .unwrap_or_default()
+            > max_tenant_buffered
+        {
+            let Some(output) = self
+                .commands
+                .iter_mut()
+                .filter(|((command_tenant, _, _), output)| {
+                    command_tenant == tenant && output.buffered > 0
+                })
+                .map(|(_, output)| output)
+                .min_by_key(|output| (!output.finished, output.updated_at))
+            else {
+                break;
+            };
+
+            let mut freed = 0;
+            let over = self.tenant_buffered[tenant] - max_tenant_buffered;
+            while freed < over {
+                match output.drop_oldest() {
+                    Some(size) => freed += size,
+                    None => break,
+                }
+            }
+            release(&mut self.tenant_buffered, tenant, freed);
+        }
+    }
+}
+
+fn release(tenant_buffered: &mut HashMap<String, usize>, tenant: &str, size: usize) {
+    if let Some(buffered) = tenant_buffered.get_mut(tenant) {
+        *buffered -= size;
+        if *buffered == 0 {
+            tenant_buffered.remove(tenant);
+        }
+    }
+}
+
+/// Output streamed back by agents as their commands run, buffered per tenant, agent and
+/// command so operators can watch it live or catch up on what they missed. Only commands
+/// actually sent to an agent are buffered for it.
+#[derive(Debug)]
+pub(crate) struct CommandOutputs {
+    outputs: Mutex<Outputs>,
+    // Per command, in bytes
+    max_buffered: usize,
+    // Per tenant, in bytes
+    max_tenant_buffered: usize,
+}
+
+impl Default for CommandOutputs {
+    fn default() -> Self {
+        Self {
+            outputs: Mutex::default(),
+            max_buffered: MAX_BUFFERED_OUTPUT,
+            max_tenant_buffered: MAX_TENANT_BUFFERED_OUTPUT,
+        }
+    }
+}
+
+impl CommandOutputs {
+    /// Start buffering the output of a command about to be sent to an agent
+    pub fn expect(&self, tenant: &str, agent_id: &str, command_id: &str) {
+        let mut outputs = self.outputs.lock().unwrap();
+        outputs
+            .commands
+            .entry(command_key(tenant, agent_id, command_id))
+            .or_insert_with(CommandOutput::new);
+    }
+
+    /// Record something a command produced and hand it to anyone tailing it
+    pub fn push(&self, tenant: &str, agent_id: &str, command_id: &str, event: OutputEvent) {
+        let mut outputs = self.outputs.lock().unwrap();
+        let Outputs {
+            commands,
+            tenant_buffered,
+        } = &mut *outputs;
+
+        let Some(output) = commands.get_mut(&command_key(tenant, agent_id, command_id)) else {
+            warn!(agent = %agent_id, %command_id, "dropped output for a command the agent was not sent");
+            return;
+        };
+        if output.finished {
+            return;
+        }
+
+        let buffered = output.buffered;
+        match &event {
+            OutputEvent::Output { data, .. } => output.buffered += data.len(),
+            OutputEvent::Result { .. } => output.finished = true,
+        }
+        output.updated_at = Instant::now();
+        output.events.push_back(event.clone());
+        while output.buffered > self.max_buffered && output.drop_oldest().is_some() {}
+
+        if output.buffered >= buffered {
+            *tenant_buffered.entry(tenant.to_string()).or_default() += output.buffered - buffered;
+        } else {
+            release(tenant_buffered, tenant, buffered - output.buffered);
+        }
+
+        // Once nobody is listening the channel goes, as it does when the command finishes
+        if let Some(live) = &output.live {
+            if live.send(event).is_err() || output.finished {
+                output.live = None;
+            }
+        }
+
+        outputs.trim_tenant(tenant, self.max_tenant_buffered);
+    }
+
+    /// Forget commands that finished, or stopped producing anything, longer ago than they are
+    /// kept for
+    pub fn forget_expired(&self) {
+        self.outputs.lock().unwrap().forget_expired(Instant::now());
+    }
+
+    /// Mark the command timed out on each of the agents that hasn't reported its result shortly
+    /// after the deadline (seconds since the unix epoch), with whatever output it sent until then
+    pub fn watch_deadline(
+        self: &Arc<Self>,
+        tenant: String,
+        agent_ids: Vec<String>,
+        command_id: String,
+        deadline: u64,
+    ) {
+        let outputs = self.clone();
+        tokio::spawn(async move {
+            // A deadline past what the clock can hold is never reached
+            let Some(until_deadline) = UNIX_EPOCH.checked_add(Duration::from_secs(deadline)) else {
+                return;
+            };
+            let wait = until_deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            tokio::time::sleep(wait.saturating_add(DEADLINE_GRACE)).await;
+
+            for agent_id in agent_ids {
+                let Some(partial) = outputs.partial_output(&tenant, &agent_id, &command_id) else {
+                    continue;
+                };
+                warn!(agent = %agent_id, %command_id, "no result from agent before the command deadline, marked as timed out");
+                outputs.push(
+                    &tenant,
+                    &agent_id,
+                    &command_id,
+                    OutputEvent::Result {
+                        error: Some(CommandError {
+                            code: CommandErrorCode::TimedOut,
+                            message: "the agent did not report a result before the deadline"
+                                .to_string(),
+                        }),
+                        output: Some(partial),
+                    },
+                );
+            }
+        });
+    }
+
+    // What an unfinished command has written so far, None once it has finished or been forgotten
+    fn partial_output(
+        &self,
+        tenant: &str,
+        agent_id: &str,
+        command_id: &str,
+    ) -> Option<serde_json::Value> {
+        let outputs = self.outputs.lock().unwrap();
+        let output = outputs
+            .commands
+            .get(&command_key(tenant, agent_id, command_id))
+            .filter(|output| !output.finished)?;
+
+        let (mut stdout, mut stderr) = (String::new(), String::new());
+        for event in &output.events {
+            match event {
+                OutputEvent::Output {
+                    stream: OutputStream::Stdout,
+                    data,
+                } => stdout.push_str(data),
+                OutputEvent::Output {
+                    stream: OutputStream::Stderr,
+                    data,
+                } => stderr.push_str(data),
+                OutputEvent::Result { .. } => {}
+            }
+        }
+        Some(json!({ "stdout": stdout, "stderr": stderr, "truncated": output.dropped }))
+    }
+
+    /// Start tailing a command, which may not have produced anything yet. None when the command
+    /// was never sent to the agent, or has since been forgotten.
+    pub fn tail(&self, tenant: &str, agent_id: &str, command_id: &str) -> Option<OutputTail> {
+        let mut outputs = self.outputs.lock().unwrap();
+
+        // Subscribed under the same lock as the snapshot, so nothing is missed or repeated
+        let output = outputs
+            .commands
+            .get_mut(&command_key(tenant, agent_id, command_id))?;
+        let live = (!output.finished).then(|| {
+            output
+                .live
+                .get_or_insert_with(|| broadcast::channel(LIVE_CAPACITY).0)
+                .subscribe()
+        });
+        Some(OutputTail {
+            dropped: output.dropped,
+            events: output.events.iter().cloned().collect(),
+            live,
+        })
+    }
+}
+
+/// Forget commands past their retention, for as long as the server runs
+pub(crate) async fn sweep_command_outputs(command_outputs: Arc<CommandOutputs>) {
+    loop {
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+        command_outputs.forget_expired();
+    }
+}
+
+/// Server-sent events of a command's output on one agent, from the start (or as much as is still
+/// buffered), ending with its result
+#[instrument(name = "Tail Command Output", level = "trace", skip(state, v1_state))]
+pub async fn command_output_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Extension(v1_state): Extension<Arc<V1ApiState>>,
+    Path((agent_id, command_id)): Path<(String, String)>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    authorize(&state, &user, Permission::CommandAgents, Some(&agent_id))?;
+
+    let tail = v1_state
+        .command_outputs
+        .tail(&user.tenant, &agent_id, &command_id)
+        .ok_or_else(|| {
+            ApiError::NotFound(format!(
+                "command {} was not sent to agent {}, or has been forgotten",
+                command_id, agent_id
+            ))
+        })?;
+
+    let dropped = tail.dropped.then(|| {
+        Event::default()
+            .event("truncated")
+            .data("earlier output was dropped")
+    });
+    let buffered = stream::iter(
+        dropped
+            .into_iter()
+            .chain(tail.events.into_iter().map(|event| event.to_sse())),
+    );
+
+    let live = stream::unfold(tail.live, |live| async move {
+        let mut live = live?;
+        match live.recv().await {
+            Ok(event) => {
+                let finished = matches!(event, OutputEvent::Result { .. });
+                Some((event.to_sse(), (!finished).then_some(live)))
+            }
+            Err(RecvError::Lagged(missed)) => Some((
+                Event::default()
+                    .event("truncated")
+                    .data(format!("{} chunks were missed", missed)),
+                Some(live),
+            )),
+            Err(RecvError::Closed) => None,
+        }
+    });
+
+    Ok(Sse::new(buffered.chain(live).map(Ok)).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outputs(max_buffered: usize, max_tenant_buffered: usize) -> CommandOutputs {
+        CommandOutputs {
+            outputs: Mutex::default(),
+            max_buffered,
+            max_tenant_buffered,
+        }
+    }
+
+    fn output(data: &str) -> OutputEvent {
+        OutputEvent::Output {
+            stream: OutputStream::Stdout,
+            data: data.to_string(),
+        }
+    }
+
+    fn result() -> OutputEvent {
+        OutputEvent::Result {
+            error: None,
+            output: None,
+        }
+    }
+
+    fn data(events: &[OutputEvent]) -> Vec<&str> {
+        events
+            .iter()
+            .map(|event| match event {
+                OutputEvent::Output { data, .. } => data.as_str(),
+                OutputEvent::Result { .. } => "result",
+            })
+            .collect()
+    }
+
+    fn tenant_buffered(outputs: &CommandOutputs, tenant: &str) -> usize {
+        let outputs = outputs.outputs.lock().unwrap();
+        outputs
+            .tenant_buffered
+            .get(tenant)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn output_of_commands_not_sent_is_dropped() {
+        let outputs = outputs(100, 100);
+        outputs.expect("acme", "agent-1", "command-1");
+
+        outputs.push("acme", "agent-2", "command-1", output("hello"));
+        outputs.push("other", "agent-1", "command-1", output("hello"));
+
+        assert!(outputs.tail("acme", "agent-2", "command-1").is_none());
+        assert!(outputs
+            .tail("acme", "agent-1", "command-1")
+            .unwrap()
+            .events
+            .is_empty());
+        assert_eq!(tenant_buffered(&outputs, "acme"), 0);
+    }
+
+    #[test]
+    fn command_drops_its_oldest_output_past_its_cap() {
+        let outputs = outputs(10, 100);
+        outputs.expect("acme", "agent-1", "command-1");
+
+        for chunk in ["aaaa", "bbbb", "cccc"] {
+            outputs.push("acme", "agent-1", "command-1", output(chunk));
+        }
+        outputs.push("acme", "agent-1", "command-1", result());
+
+        let tail = outputs.tail("acme", "agent-1", "command-1").unwrap();
+        assert!(tail.dropped);
+        assert_eq!(data(&tail.events), vec!["bbbb", "cccc", "result"]);
+        assert!(tail.live.is_none());
+        assert_eq!(tenant_buffered(&outputs, "acme"), 8);
+    }
+
+    #[test]
+    fn tenant_cap_takes_from_finished_then_quietest_commands() {
+        let outputs = outputs(100, 12);
+        for command_id in ["finished", "quiet", "busy"] {
+            outputs.expect("acme", "agent-1", command_id);
+        }
+        outputs.expect("other", "agent-1", "busy");
+
+        outputs.push("acme", "agent-1", "quiet", output("qqqq"));
+        outputs.push("acme", "agent-1", "finished", output("ffff"));
+        outputs.push("acme", "agent-1", "finished", result());
+        outputs.push("other", "agent-1", "busy", output("oooooooooo"));
+        outputs.push("acme", "agent-1", "busy", output("bbbb"));
+        assert_eq!(tenant_buffered(&outputs, "acme"), 12);
+
+        outputs.push("acme", "agent-1", "busy", output("BBBB"));
+        let tail = |command_id| outputs.tail("acme", "agent-1", command_id).unwrap();
+        assert_eq!(data(&tail("finished").events), vec!["result"]);
+        assert!(tail("finished").dropped);
+        assert_eq!(data(&tail("quiet").events), vec!["qqqq"]);
+
+        outputs.push("acme", "agent-1", "busy", output("XXXX"));
+        assert_eq!(data(&tail("quiet").events), Vec::<&str>::new());
+        assert_eq!(data(&tail("busy").events), vec!["bbbb", "BBBB", "XXXX"]);
+        assert_eq!(tenant_buffered(&outputs, "acme"), 12);
+
+        // Other tenants keep theirs
+        let other = outputs.tail("other", "agent-1", "busy").unwrap();
+        assert_eq!(data(&other.events), vec!["oooooooooo"]);
+    }
+
+    #[test]
+    fn commands_are_forgotten_once_past_their_retention() {
+        let outputs = outputs(100, 100);
+        for command_id in ["finished", "idle", "running"] {
+            outputs.expect("acme", "agent-1", command_id);
+            outputs.push("acme", "agent-1", command_id, output("data"));
+        }
+        outputs.push("acme", "agent-1", "finished", result());
+
+        {
+            let mut locked = outputs.outputs.lock().unwrap();
+            let now = Instant::now();
+            for (command_id, age) in [
+                ("finished", FINISHED_RETENTION),
+                ("idle", IDLE_RETENTION),
+                ("running", FINISHED_RETENTION),
+            ] {
+                let key = command_key("acme", "agent-1", command_id);
+                locked.commands.get_mut(&key).unwrap().updated_at =
+                    now - age - Duration::from_secs(1);
+            }
+        }
+        outputs.forget_expired();
+
+        assert!(outputs.tail("acme", "agent-1", "finished").is_none());
+        assert!(outputs.tail("acme", "agent-1", "idle").is_none());
+        assert!(outputs.tail("acme", "agent-1", "running").is_some());
+        assert_eq!(tenant_buffered(&outputs, "acme"), 4);
+    }
+
+    #[test]
+    fn tail_gets_what_was_buffered_then_the_rest_live() {
+        let outputs = outputs(100, 100);
+        outputs.expect("acme", "agent-1", "command-1");
+        outputs.push("acme", "agent-1", "command-1", output("before"));
+
+        let tail = outputs.tail("acme", "agent-1", "command-1").unwrap();
+        let mut live = tail.live.unwrap();
+        outputs.push("acme", "agent-1", "command-1", output("after"));
+        outputs.push("acme", "agent-1", "command-1", result());
+
+        assert_eq!(data(&tail.events), vec!["before"]);
+        let received = [live.try_recv().unwrap(), live.try_recv().unwrap()];
+        assert_eq!(data(&received), vec!["after", "result"]);
+        assert!(matches!(
+            live.try_recv(),
+            Err(broadcast::error::TryRecvError::Closed)
+        ));
+    }
+
+    #[test]
+    fn live_channel_only_exists_while_someone_tails() {
+        let outputs = outputs(100, 100);
+        outputs.expect("acme", "agent-1", "command-1");
+        let has_live = || {
+            let locked = outputs.outputs.lock().unwrap();
+            locked.commands[&command_key("acme", "agent-1", "command-1")]
+                .live
+                .is_some()
+        };
+
+        outputs.push("acme", "agent-1", "command-1", output("one"));
+        assert!(!has_live());
+
+        let tail = outputs.tail("acme", "agent-1", "command-1").unwrap();
+        assert!(has_live());
+
+        drop(tail);
+        outputs.push("acme", "agent-1", "command-1", output("two"));
+        assert!(!has_live());
+    }
+}
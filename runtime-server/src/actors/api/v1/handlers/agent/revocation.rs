@@ -0,0 +1,182 @@
+use crate::actors::api::{
+    state::{ApiState, V1ApiState},
+    v1::{
+        auth::Principal,
+        errors::ApiError,
+        handlers::agent::{
+            disconnect_agent, enrollment::publish_revocation_list, types::AgentRegistry,
+        },
+        rbac::{authorize, Permission},
+        responses::ApiResponse,
+    },
+};
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use database_server::{
+    models::agent_certificates::revoke_agent_certificates,
+    models::agent_revocations::{
+        get_agent_revocations, is_agent_revoked, revoke_agent, unrevoke_agent, NewAgentRevocation,
+    },
+    models::agent_tokens::{revoke_agent_tokens, unrevoke_agent_tokens},
+    AgentRevocation, SqlitePool,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, instrument, warn};
+
+#[derive(Deserialize, Debug)]
+pub struct RevokeAgentRequest {
+    reason: Option<String>,
+}
+
+#[instrument(name = "Agent Revocation List", level = "trace")]
+pub async fn get_agent_revocations_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+) -> Result<ApiResponse<Vec<AgentRevocation>>, ApiError> {
+    authorize(&state, &user, Permission::ViewAgents, None)?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    match get_agent_revocations(&mut db_conn, &user.tenant) {
+        Ok(revocations) => Ok(ApiResponse::ok(revocations)),
+        Err(error) => Err(ApiError::Internal(error.to_string())),
+    }
+}
+
+#[instrument(name = "Revoke Agent", level = "trace")]
+pub async fn revoke_agent_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Extension(v1_state): Extension<Arc<V1ApiState>>,
+    Path(agent_id): Path<String>,
+    Json(payload): Json<RevokeAgentRequest>,
+) -> Result<ApiResponse<AgentRevocation>, ApiError> {
+    authorize(&state, &user, Permission::ManageAgents, Some(&agent_id))?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let revocation = revoke_agent(
+        &mut db_conn,
+        NewAgentRevocation {
+            tenant: user.tenant.clone(),
+            agent_id: agent_id.clone(),
+            reason: payload.reason.clone(),
+        },
+    )
+    .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    // Certificates are revoked for good - lifting the revocation means enrolling again
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    // Otherwise the agent could reconnect under a new id with a token that isn't bound to it
+    let revoked_tokens = revoke_agent_tokens(&mut db_conn, &user.tenant, &agent_id, now)
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    info!(agent = %agent_id, reason = ?revocation.reason, revoked_tokens, revoked_by = %user.name, "agent revoked");
+    let revoked_certificates =
+        revoke_agent_certificates(&mut db_conn, &user.tenant, &agent_id, now, payload.reason)
+            .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    if let Some(certificate_authority) = &state.certificate_authority {
+        if revoked_certificates > 0 {
+            publish_revocation_list(certificate_authority, &state.db_pool)
+                .map_err(|error| ApiError::Internal(error.to_string()))?;
+            state.tls_reload.notify_one();
+        }
+    }
+
+    // Drop a live session straight away rather than waiting for the monitor
+    if let Some(entry) = v1_state.agent_registry.get(&user.tenant, &agent_id) {
+        disconnect_agent(&entry, "agent revoked");
+    }
+
+    Ok(ApiResponse::ok(revocation))
+}
+
+#[instrument(name = "Unrevoke Agent", level = "trace")]
+pub async fn unrevoke_agent_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Path(agent_id): Path<String>,
+) -> Result<ApiResponse<String>, ApiError> {
+    authorize(&state, &user, Permission::ManageAgents, Some(&agent_id))?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    match unrevoke_agent(&mut db_conn, &user.tenant, &agent_id) {
+        Ok(true) => {
+            unrevoke_agent_tokens(&mut db_conn, &user.tenant, &agent_id)
+                .map_err(|error| ApiError::Internal(error.to_string()))?;
+            info!(agent = %agent_id, lifted_by = %user.name, "agent revocation lifted");
+            Ok(ApiResponse::ok(agent_id))
+        }
+        Ok(false) => Err(ApiError::NotFound(format!(
+            "agent {} is not revoked",
+            agent_id
+        ))),
+        Err(error) => Err(ApiError::Internal(error.to_string())),
+    }
+}
+
+/// Periodically re-check every live session, dropping agents that have been
+/// revoked or whose token has expired without being refreshed.
+#[instrument(name = "Agent Revocation Monitor", level = "trace", skip(db_pool))]
+pub(crate) async fn start_revocation_monitor(
+    registry: AgentRegistry,
+    db_pool: SqlitePool,
+    check_interval_seconds: u64,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(check_interval_seconds.max(1)));
+
+    loop {
+        interval.tick().await;
+
+        let mut db_conn = match db_pool.get() {
+            Ok(connection) => connection,
+            Err(error) => {
+                error!(errorMsg = %error, "revocation monitor unable to reach the database");
+                continue;
+            }
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as usize;
+
+        for entry in registry.all_agents() {
+            // A busy or locked database mustn't drop every agent, they are checked again next tick
+            let revoked = match is_agent_revoked(&mut db_conn, &entry.info.tenant, &entry.info.id) {
+                Ok(revoked) => revoked,
+                Err(error) => {
+                    error!(errorMsg = %error, "revocation monitor unable to read the revocation list, skipping this check");
+                    break;
+                }
+            };
+
+            if revoked {
+                warn!(agent = %entry.info.id, "disconnecting revoked agent");
+                disconnect_agent(&entry, "agent revoked");
+            } else if *entry.info.token_expires_at.lock().await <= now {
+                warn!(agent = %entry.info.id, "disconnecting agent with an expired token");
+                disconnect_agent(&entry, "agent token expired");
+            }
+        }
+    }
+}
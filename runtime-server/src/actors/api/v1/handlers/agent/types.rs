@@ -1,30 +1,106 @@
 use dashmap::DashMap;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::sync::Arc;
-use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::sync::{mpsc, oneshot, Mutex, Notify};
 
 #[derive(Debug)]
 pub struct AgentInfo {
     pub id: String,
+    pub tenant: String,
     pub groups: Vec<String>,
     // last_seen stored for dashboard; using Mutex for demo
     pub last_seen: Mutex<chrono::DateTime<chrono::Utc>>,
     // pending pong oneshot: server waits on this after sending ping
     pub pending_pong: Mutex<Option<oneshot::Sender<()>>>,
-    pub token: String,
+    // current token and its expiry, replaced whenever the agent refreshes
+    pub token: Mutex<String>,
+    pub token_expires_at: Mutex<usize>,
+    // Whether the agent's identity comes from its client certificate rather than its word
+    pub certified: bool,
 }
 
 #[derive(Clone, Debug)]
 pub struct AgentEntry {
     pub info: Arc<AgentInfo>,
     pub tx: mpsc::UnboundedSender<String>, // outbound JSON strings to writer task
+    pub disconnect: Arc<Notify>,           // signals the read loop to drop the connection
+}
+
+pub type AgentRegistry = Arc<TenantAgentRegistry>;
+
+/// Connected agents partitioned by tenant, so a lookup made on behalf of one tenant can
+/// never reach another tenant's agents - even when two tenants use the same agent id
+#[derive(Debug, Default)]
+pub struct TenantAgentRegistry {
+    tenants: DashMap<String, DashMap<String, AgentEntry>>,
+}
+
+impl TenantAgentRegistry {
+    /// Add a session, unless the tenant has already used up its agent seats. Returns whether
+    /// it was added - an agent reconnecting takes over its own seat.
+    pub fn insert(&self, entry: AgentEntry, seat_limit: Option<usize>) -> bool {
+        // Held for the check and the insert, so racing agents cannot both take the last seat
+        let agents = self.tenants.entry(entry.info.tenant.clone()).or_default();
+        if !has_seat(&agents, &entry.info.id, seat_limit) {
+            return false;
+        }
+
+        agents.insert(entry.info.id.clone(), entry);
+        true
+    }
+
+    /// Whether an agent could connect to its tenant without going over the seat limit
+    pub fn has_seat(&self, tenant: &str, id: &str, seat_limit: Option<usize>) -> bool {
+        match self.tenants.get(tenant) {
+            Some(agents) => has_seat(&agents, id, seat_limit),
+            None => seat_limit != Some(0),
+        }
+    }
+
+    pub fn get(&self, tenant: &str, id: &str) -> Option<AgentEntry> {
+        self.tenants
+            .get(tenant)
+            .and_then(|agents| agents.get(id).map(|entry| entry.value().clone()))
+    }
+
+    /// Remove this session, leaving any newer session for the same agent in place
+    pub fn remove_session(&self, info: &Arc<AgentInfo>) {
+        if let Some(agents) = self.tenants.get(&info.tenant) {
+            agents.remove_if(&info.id, |_, entry| Arc::ptr_eq(&entry.info, info));
+        }
+        self.tenants
+            .remove_if(&info.tenant, |_, agents| agents.is_empty());
+    }
+
+    /// Snapshot of a tenant's agents, so no registry guard is held across an await
+    pub fn tenant_agents(&self, tenant: &str) -> Vec<AgentEntry> {
+        self.tenants
+            .get(tenant)
+            .map(|agents| agents.iter().map(|r| r.value().clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Snapshot of the agents of every tenant, only for server-wide housekeeping
+    pub fn all_agents(&self) -> Vec<AgentEntry> {
+        self.tenants
+            .iter()
+            .flat_map(|agents| agents.iter().map(|r| r.value().clone()).collect::<Vec<_>>())
+            .collect()
+    }
 }
 
-pub type AgentRegistry = Arc<DashMap<String, AgentEntry>>;
+fn has_seat(agents: &DashMap<String, AgentEntry>, id: &str, seat_limit: Option<usize>) -> bool {
+    match seat_limit {
+        Some(seat_limit) => agents.contains_key(id) || agents.len() < seat_limit,
+        None => true,
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct WSConnect {
-    pub id: String,
+    // Ignored in favour of the certificate subject when the agent presents a client certificate
+    #[serde(default)]
+    pub id: Option<String>,
     pub token: String,
     #[serde(deserialize_with = "deserialize_groups")]
     pub groups: Vec<String>,
@@ -0,0 +1,165 @@
+use crate::actors::api::{
+    state::ApiState,
+    v1::{
+        auth::{generate_secret, hash_token, Principal, API_KEY_PREFIX},
+        errors::ApiError,
+        rbac::{authorize, Permission},
+        responses::ApiResponse,
+    },
+};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use database_server::{
+    models::api_keys::{create_api_key, get_tenant_api_keys, revoke_api_key, NewApiKey},
+    ApiKey,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, instrument};
+
+// Enough of the key to recognise it in a list, far too little to guess the rest
+const DISPLAYED_PREFIX_LENGTH: usize = 10;
+
+#[derive(Deserialize, Debug)]
+pub struct CreateApiKeyRequest {
+    name: String,
+    scopes: Vec<Permission>,
+    // Seconds since the unix epoch, the key never expires without one
+    expires_at: Option<i64>,
+}
+
+/// The created key - the only time the key itself is ever returned
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    key: String,
+    #[serde(flatten)]
+    details: ApiKey,
+}
+
+#[instrument(name = "API Key List", level = "trace", skip(state))]
+pub async fn get_api_keys_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+) -> Result<ApiResponse<Vec<ApiKey>>, ApiError> {
+    authorize(&state, &user, Permission::ManageApiKeys, None)?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    match get_tenant_api_keys(&mut db_conn, &user.tenant) {
+        Ok(keys) => Ok(ApiResponse::ok(keys)),
+        Err(error) => Err(ApiError::Internal(error.to_string())),
+    }
+}
+
+/// Create an API key in the caller's tenant. A key can only be given scopes
+/// the caller holds themselves.
+#[instrument(name = "Create API Key", level = "trace", skip(state))]
+pub async fn create_api_key_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<ApiResponse<CreatedApiKey>, ApiError> {
+    let name = payload.name.trim().to_string();
+    authorize(&state, &user, Permission::ManageApiKeys, Some(&name))?;
+
+    if name.is_empty() {
+        return Err(ApiError::BadRequest("name is required".to_string()));
+    }
+    if payload.scopes.is_empty() {
+        return Err(ApiError::BadRequest(
+            "at least one scope is required".to_string(),
+        ));
+    }
+    if let Some(scope) = payload.scopes.iter().find(|scope| !user.allows(**scope)) {
+        return Err(ApiError::Forbidden(format!(
+            "{} does not allow {}, so it cannot be granted",
+            user.credential, scope
+        )));
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    if payload
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= now)
+    {
+        return Err(ApiError::BadRequest(
+            "expires_at must be in the future".to_string(),
+        ));
+    }
+
+    let key = format!("{}{}", API_KEY_PREFIX, generate_secret());
+    let mut scopes = payload
+        .scopes
+        .iter()
+        .map(|scope| scope.to_string())
+        .collect::<Vec<_>>();
+    scopes.sort();
+    scopes.dedup();
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let details = create_api_key(
+        &mut db_conn,
+        NewApiKey {
+            tenant: user.tenant.clone(),
+            name,
+            prefix: key[..DISPLAYED_PREFIX_LENGTH].to_string(),
+            key_hash: hash_token(&key),
+            scopes: scopes.join(","),
+            created_by: user.name.clone(),
+            expires_at: payload.expires_at,
+        },
+    )
+    .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    info!(api_key = %details.name, id = details.id, scopes = %details.scopes, created_by = %user.name, "API key created");
+    Ok(ApiResponse::ok(CreatedApiKey { key, details }))
+}
+
+#[instrument(name = "Revoke API Key", level = "trace", skip(state))]
+pub async fn revoke_api_key_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Path(key_id): Path<i32>,
+) -> Result<ApiResponse<i32>, ApiError> {
+    authorize(
+        &state,
+        &user,
+        Permission::ManageApiKeys,
+        Some(&key_id.to_string()),
+    )?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    match revoke_api_key(&mut db_conn, &user.tenant, key_id, now) {
+        Ok(true) => {
+            info!(id = key_id, revoked_by = %user.name, "API key revoked");
+            Ok(ApiResponse::ok(key_id))
+        }
+        Ok(false) => Err(ApiError::NotFound(format!(
+            "API key {} does not exist or is already revoked",
+            key_id
+        ))),
+        Err(error) => Err(ApiError::Internal(error.to_string())),
+    }
+}
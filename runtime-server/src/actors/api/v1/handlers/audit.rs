@@ -0,0 +1,47 @@
+use crate::actors::api::{
+    state::ApiState,
+    v1::{
+        auth::Principal,
+        errors::ApiError,
+        rbac::{authorize, Permission},
+        responses::ApiResponse,
+    },
+};
+use axum::extract::{Query, State};
+use database_server::{models::audit_log::get_tenant_audit_log, AuditEntry};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::instrument;
+
+const DEFAULT_AUDIT_LIMIT: i64 = 100;
+const MAX_AUDIT_LIMIT: i64 = 1000;
+
+#[derive(Deserialize, Debug)]
+pub struct AuditQuery {
+    limit: Option<i64>,
+}
+
+/// The most recent permission decisions in the caller's tenant
+#[instrument(name = "Audit Log", level = "trace", skip(state))]
+pub async fn get_audit_log_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<AuditQuery>,
+) -> Result<ApiResponse<Vec<AuditEntry>>, ApiError> {
+    authorize(&state, &user, Permission::ViewAuditLog, None)?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    match get_tenant_audit_log(&mut db_conn, &user.tenant, limit) {
+        Ok(entries) => Ok(ApiResponse::ok(entries)),
+        Err(error) => Err(ApiError::Internal(error.to_string())),
+    }
+}
@@ -0,0 +1,322 @@
+use crate::actors::api::{
+    client_ip::ClientIp,
+    state::ApiState,
+    v1::{
+        auth::{generate_secret, hash_password, hash_token, verify_password, Principal},
+        errors::ApiError,
+        handlers::{tenants::require_active_tenant, users::MIN_PASSWORD_LENGTH},
+        ip_access::{check_ip_access, IpAccessScope},
+        jwt::session::generate_session_jwt,
+        responses::ApiResponse,
+        tenant::Tenant,
+    },
+};
+use axum::{extract::State, Extension, Json};
+use database_server::{
+    models::{
+        refresh_tokens::{
+            create_refresh_token, delete_expired_refresh_tokens, get_refresh_token,
+            revoke_user_refresh_tokens, use_refresh_token, NewRefreshToken,
+        },
+        users::{get_user, get_user_by_username, set_user_password},
+    },
+    User,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info, instrument, warn};
+
+// Never derive Debug, the password must not end up in the logs
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+// Never derive Debug, the passwords must not end up in the logs
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionTokens {
+    access_token: String,
+    token_type: String,
+    expires_at: usize,
+    refresh_token: String,
+    refresh_expires_at: i64,
+}
+
+#[instrument(name = "User Login", level = "trace", skip_all)]
+pub async fn login_handler(
+    State(state): State<Arc<ApiState>>,
+    tenant: Option<Extension<Tenant>>,
+    client_ip: Option<Extension<ClientIp>>,
+    Json(payload): Json<LoginRequest>,
+) -> Result<ApiResponse<SessionTokens>, ApiError> {
+    let (user, user_tenant) = {
+        let mut db_conn = state
+            .db_pool
+            .get()
+            .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+        // A username other tenants also use can only log in through its own tenant
+        let user = get_user_by_username(
+            &mut db_conn,
+            tenant
+                .as_ref()
+                .map(|Extension(Tenant(tenant))| tenant.as_str()),
+            &payload.username,
+        )
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+        let user_tenant = match &user {
+            Some(user) => require_active_tenant(&mut db_conn, &user.tenant).ok(),
+            None => None,
+        };
+        (user, user_tenant)
+    };
+
+    // Argon2 is deliberately slow, keep it off the async workers
+    let password = payload.password;
+    let password_hash = user.as_ref().map(|user| user.password_hash.clone());
+    let verified =
+        tokio::task::spawn_blocking(move || verify_password(&password, password_hash.as_deref()))
+            .await
+            .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    // The same answer whatever was wrong, so usernames cannot be probed - and users can
+    // only log in to their own tenant, while it is active and from where it allows
+    let user = match (user, user_tenant) {
+        (Some(user), Some(user_tenant))
+            if verified
+                && !user.disabled
+                && in_tenant(&user, tenant.as_ref())
+                && check_ip_access(
+                    &state,
+                    IpAccessScope::Admin,
+                    client_ip.map(|Extension(client_ip)| client_ip),
+                    Some(&user_tenant),
+                    &user.username,
+                )
+                .is_ok() =>
+        {
+            user
+        }
+        _ => {
+            warn!(username = %payload.username, "failed login attempt");
+            return Err(ApiError::Unauthorized(
+                "invalid username or password".to_string(),
+            ));
+        }
+    };
+
+    info!(user = %user.username, tenant = %user.tenant, "user logged in");
+    issue_session(&state, &user).map(ApiResponse::ok)
+}
+
+/// Exchange a refresh token for a new session token. Refresh tokens are single use,
+/// each refresh also returns the refresh token to use next time.
+#[instrument(name = "Session Refresh", level = "trace", skip_all)]
+pub async fn refresh_handler(
+    State(state): State<Arc<ApiState>>,
+    tenant: Option<Extension<Tenant>>,
+    client_ip: Option<Extension<ClientIp>>,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<ApiResponse<SessionTokens>, ApiError> {
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    // Only looked up here, so a refresh refused below doesn't end the session
+    let token_hash = hash_token(&payload.refresh_token);
+    let Some(refresh_token) = get_refresh_token(&mut db_conn, &token_hash, now())
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+    else {
+        return Err(ApiError::Unauthorized(
+            "invalid or expired refresh token".to_string(),
+        ));
+    };
+
+    let user = get_user(&mut db_conn, refresh_token.user_id)
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+        .filter(|user| !user.disabled)
+        .ok_or_else(|| {
+            ApiError::Unauthorized("user is disabled or no longer exists".to_string())
+        })?;
+
+    if !in_tenant(&user, tenant.as_ref()) {
+        warn!(user = %user.username, "refresh token presented for another tenant");
+        return Err(ApiError::Unauthorized(
+            "invalid or expired refresh token".to_string(),
+        ));
+    }
+    let user_tenant = require_active_tenant(&mut db_conn, &user.tenant)?;
+    check_ip_access(
+        &state,
+        IpAccessScope::Admin,
+        client_ip.map(|Extension(client_ip)| client_ip),
+        Some(&user_tenant),
+        &user.username,
+    )?;
+
+    // Every check has passed, now the token is used up - unless a racing refresh got there first
+    if use_refresh_token(&mut db_conn, &token_hash, now())
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+        .is_none()
+    {
+        return Err(ApiError::Unauthorized(
+            "invalid or expired refresh token".to_string(),
+        ));
+    }
+
+    issue_session(&state, &user).map(ApiResponse::ok)
+}
+
+/// Revoke every refresh token of the caller, ending all of their sessions once the
+/// session tokens already issued expire
+#[instrument(name = "User Logout", level = "trace", skip(state))]
+pub async fn logout_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+) -> Result<ApiResponse<String>, ApiError> {
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    match revoke_user_refresh_tokens(&mut db_conn, user.user_id()?, now()) {
+        Ok(revoked) => {
+            info!(user = %user.name, refresh_tokens = revoked, "user logged out");
+            Ok(ApiResponse::ok(user.name))
+        }
+        Err(error) => Err(ApiError::Internal(error.to_string())),
+    }
+}
+
+/// Change the caller's password, which also ends their other sessions
+#[instrument(name = "Change Password", level = "trace", skip(state, payload))]
+pub async fn change_password_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<ApiResponse<String>, ApiError> {
+    let user_id = user.user_id()?;
+    if payload.new_password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    let stored = {
+        let mut db_conn = state
+            .db_pool
+            .get()
+            .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+        get_user(&mut db_conn, user_id)
+            .map_err(|error| ApiError::Internal(error.to_string()))?
+            .map(|user| user.password_hash)
+    };
+
+    let (current_password, new_password) = (payload.current_password, payload.new_password);
+    let password_hash = tokio::task::spawn_blocking(move || {
+        match verify_password(&current_password, stored.as_deref()) {
+            true => hash_password(&new_password).map(Some),
+            false => Ok(None),
+        }
+    })
+    .await
+    .map_err(|error| ApiError::Internal(error.to_string()))?
+    .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let Some(password_hash) = password_hash else {
+        warn!(user = %user.name, "password change with the wrong current password");
+        return Err(ApiError::Unauthorized(
+            "current password is incorrect".to_string(),
+        ));
+    };
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    set_user_password(&mut db_conn, user_id, &password_hash)
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+    revoke_user_refresh_tokens(&mut db_conn, user_id, now())
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    info!(user = %user.name, "user changed their password");
+    Ok(ApiResponse::ok(user.name))
+}
+
+#[instrument(name = "Current User", level = "trace")]
+pub async fn current_user_handler(user: Principal) -> ApiResponse<Principal> {
+    ApiResponse::ok(user)
+}
+
+// Sign a session token and record a fresh refresh token for the user
+fn issue_session(state: &ApiState, user: &User) -> Result<SessionTokens, ApiError> {
+    let jwt = generate_session_jwt(
+        user,
+        &state.server_jwt_secret,
+        state.session_jwt_lifetime_secs,
+    )
+    .map_err(|error| {
+        error!(error=%error,"Failed to generate session JWT");
+        ApiError::Internal(format!("Failed to generate session JWT - {}", error))
+    })?;
+
+    let refresh_token = generate_secret();
+    let refresh_expires_at = now() + state.refresh_token_lifetime_secs as i64;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    // Tidy up as we go, expired tokens can never be used again
+    if let Err(error) = delete_expired_refresh_tokens(&mut db_conn, now()) {
+        warn!(errorMsg=%error, "unable to remove expired refresh tokens");
+    }
+
+    create_refresh_token(
+        &mut db_conn,
+        NewRefreshToken {
+            user_id: user.id,
+            token_hash: hash_token(&refresh_token),
+            expires_at: refresh_expires_at,
+        },
+    )
+    .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    Ok(SessionTokens {
+        access_token: jwt.token,
+        token_type: "Bearer".to_string(),
+        expires_at: jwt.expires_at,
+        refresh_token,
+        refresh_expires_at,
+    })
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+// Whether a user belongs to the tenant the request was made for, if it named one
+fn in_tenant(user: &User, tenant: Option<&Extension<Tenant>>) -> bool {
+    tenant.is_none_or(|Extension(Tenant(tenant))| &user.tenant == tenant)
+}
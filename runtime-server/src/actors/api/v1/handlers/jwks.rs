@@ -0,0 +1,12 @@
+use crate::actors::api::state::ApiState;
+use axum::{extract::State, Json};
+use jsonwebtoken::jwk::JwkSet;
+use std::sync::Arc;
+use tracing::instrument;
+
+/// Publish the public keys our tokens can be verified with.
+/// Returned as a bare JWKS document (not wrapped in ApiResponse) so standard clients can read it.
+#[instrument(name = "JWKS", level = "trace")]
+pub async fn get_jwks_handler(State(state): State<Arc<ApiState>>) -> Json<JwkSet> {
+    Json(state.agent_jwt_keys.jwks())
+}
@@ -1,6 +1,13 @@
 pub(crate) mod agent;
+pub(crate) mod api_keys;
+pub(crate) mod audit;
+pub(crate) mod auth;
 pub(crate) mod info;
+pub(crate) mod jwks;
+pub(crate) mod tenants;
+pub(crate) mod users;
 
 // Public re-exports
 pub use agent::agent_connection_handler;
 pub use info::get_info;
+pub use jwks::get_jwks_handler;
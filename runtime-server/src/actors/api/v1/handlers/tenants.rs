@@ -0,0 +1,595 @@
+use crate::actors::api::{
+    state::{ApiState, V1ApiState},
+    v1::{
+        auth::{hash_password, Principal},
+        errors::ApiError,
+        handlers::{
+            agent::{disconnect_agent, enrollment::publish_revocation_list},
+            users::MIN_PASSWORD_LENGTH,
+        },
+        ip_access::parse_tenant_networks,
+        rbac::{authorize, Permission, Role},
+        responses::ApiResponse,
+    },
+};
+use axum::{
+    extract::{Path, State},
+    http::HeaderValue,
+    Extension, Json,
+};
+use database_server::{
+    models::{
+        tenants::{
+            create_tenant, get_tenant, get_tenant_by_slug, get_tenants, get_tenants_with_status,
+            purge_tenant, update_tenant, NewTenant, TenantChanges,
+        },
+        users::{create_user, get_user_by_username, NewUser},
+    },
+    PooledSqliteConnection, SqlitePool, Tenant,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, instrument, warn};
+
+/// Whether a tenant's users and agents may connect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TenantStatus {
+    Active,
+    Suspended,
+    // Waiting for the background job to purge its data
+    Deleting,
+    // Purged, kept so the slug is never handed out again
+    Deleted,
+}
+
+impl TenantStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TenantStatus::Active => "active",
+            TenantStatus::Suspended => "suspended",
+            TenantStatus::Deleting => "deleting",
+            TenantStatus::Deleted => "deleted",
+        }
+    }
+
+    /// Unknown statuses are treated as suspended
+    pub fn parse(status: &str) -> Self {
+        match status {
+            "active" => TenantStatus::Active,
+            "deleting" => TenantStatus::Deleting,
+            "deleted" => TenantStatus::Deleted,
+            _ => TenantStatus::Suspended,
+        }
+    }
+}
+
+// Never derive Debug, the owner's password must not end up in the logs
+#[derive(Deserialize)]
+pub struct CreateTenantRequest {
+    slug: String,
+    name: String,
+    #[serde(default)]
+    allowed_origins: Vec<String>,
+    // No limit when absent
+    agent_seat_limit: Option<u32>,
+    // IPs or CIDRs, checked on top of the server wide lists
+    #[serde(default)]
+    agent_allowed_ips: Vec<String>,
+    #[serde(default)]
+    agent_denied_ips: Vec<String>,
+    #[serde(default)]
+    admin_allowed_ips: Vec<String>,
+    #[serde(default)]
+    admin_denied_ips: Vec<String>,
+    // The tenant's first user, without one only the provider can act for the tenant
+    owner: Option<TenantOwner>,
+}
+
+#[derive(Deserialize)]
+pub struct TenantOwner {
+    username: String,
+    password: String,
+}
+
+/// Replaces everything that can be changed about a tenant
+#[derive(Deserialize, Debug)]
+pub struct UpdateTenantRequest {
+    name: String,
+    #[serde(default)]
+    allowed_origins: Vec<String>,
+    agent_seat_limit: Option<u32>,
+    // IPs or CIDRs, checked on top of the server wide lists
+    #[serde(default)]
+    agent_allowed_ips: Vec<String>,
+    #[serde(default)]
+    agent_denied_ips: Vec<String>,
+    #[serde(default)]
+    admin_allowed_ips: Vec<String>,
+    #[serde(default)]
+    admin_denied_ips: Vec<String>,
+    status: TenantStatus,
+}
+
+#[instrument(name = "Tenant List", level = "trace", skip(state))]
+pub async fn get_tenants_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+) -> Result<ApiResponse<Vec<Tenant>>, ApiError> {
+    authorize(&state, &user, Permission::ManageTenants, None)?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    match get_tenants(&mut db_conn) {
+        Ok(tenants) => Ok(ApiResponse::ok(tenants)),
+        Err(error) => Err(ApiError::Internal(error.to_string())),
+    }
+}
+
+#[instrument(name = "Get Tenant", level = "trace", skip(state))]
+pub async fn get_tenant_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Path(tenant_id): Path<i32>,
+) -> Result<ApiResponse<Tenant>, ApiError> {
+    authorize(
+        &state,
+        &user,
+        Permission::ManageTenants,
+        Some(&tenant_id.to_string()),
+    )?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    find_tenant(&mut db_conn, tenant_id).map(ApiResponse::ok)
+}
+
+/// Add a tenant, along with its first owner when one is given
+#[instrument(name = "Create Tenant", level = "trace", skip(state, payload))]
+pub async fn create_tenant_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Json(payload): Json<CreateTenantRequest>,
+) -> Result<ApiResponse<Tenant>, ApiError> {
+    let slug = payload.slug.trim().to_ascii_lowercase();
+    authorize(&state, &user, Permission::ManageTenants, Some(&slug))?;
+
+    if !valid_slug(&slug) {
+        return Err(ApiError::BadRequest(
+            "slug must be 1 to 63 lowercase letters, digits or hyphens, not starting or ending with a hyphen"
+                .to_string(),
+        ));
+    }
+    let name = required_name(&payload.name)?;
+    let allowed_origins = parse_origins(payload.allowed_origins)?;
+    let agent_seat_limit = parse_seat_limit(payload.agent_seat_limit)?;
+    let agent_allowed_ips = parse_tenant_networks(payload.agent_allowed_ips)?;
+    let agent_denied_ips = parse_tenant_networks(payload.agent_denied_ips)?;
+    let admin_allowed_ips = parse_tenant_networks(payload.admin_allowed_ips)?;
+    let admin_denied_ips = parse_tenant_networks(payload.admin_denied_ips)?;
+
+    // Hash before taking a connection, Argon2 is deliberately slow
+    let owner = match payload.owner {
+        Some(owner) => {
+            let username = owner.username.trim().to_string();
+            if username.is_empty() {
+                return Err(ApiError::BadRequest(
+                    "owner username is required".to_string(),
+                ));
+            }
+            if owner.password.chars().count() < MIN_PASSWORD_LENGTH {
+                return Err(ApiError::BadRequest(format!(
+                    "password must be at least {} characters",
+                    MIN_PASSWORD_LENGTH
+                )));
+            }
+
+            let password = owner.password;
+            let password_hash = tokio::task::spawn_blocking(move || hash_password(&password))
+                .await
+                .map_err(|error| ApiError::Internal(error.to_string()))?
+                .map_err(|error| ApiError::Internal(error.to_string()))?;
+            Some((username, password_hash))
+        }
+        None => None,
+    };
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    // Deleted tenants keep their slug, tokens issued for it must never work for a new tenant
+    match get_tenant_by_slug(&mut db_conn, &slug)
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+    {
+        Some(existing) if TenantStatus::parse(&existing.status) == TenantStatus::Deleted => {
+            return Err(ApiError::BadRequest(format!(
+                "tenant {} was deleted, its slug cannot be reused",
+                slug
+            )))
+        }
+        Some(_) => {
+            return Err(ApiError::BadRequest(format!(
+                "tenant {} already exists",
+                slug
+            )))
+        }
+        None => {}
+    }
+    if let Some((username, _)) = &owner {
+        if get_user_by_username(&mut db_conn, Some(&slug), username)
+            .map_err(|error| ApiError::Internal(error.to_string()))?
+            .is_some()
+        {
+            return Err(ApiError::BadRequest(format!(
+                "username {} is already taken",
+                username
+            )));
+        }
+    }
+
+    let tenant = create_tenant(
+        &mut db_conn,
+        NewTenant {
+            slug,
+            name,
+            allowed_origins,
+            agent_seat_limit,
+            agent_allowed_ips,
+            agent_denied_ips,
+            admin_allowed_ips,
+            admin_denied_ips,
+        },
+    )
+    .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    if let Some((username, password_hash)) = owner {
+        let owner = create_user(
+            &mut db_conn,
+            NewUser {
+                username,
+                password_hash,
+                tenant: tenant.slug.clone(),
+                role: Role::Owner.as_str().to_string(),
+            },
+        )
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+        info!(user = %owner.username, tenant = %owner.tenant, "tenant owner created");
+    }
+
+    refresh_tenant_origins(&state);
+
+    info!(tenant = %tenant.slug, created_by = %user.name, "tenant created");
+    Ok(ApiResponse::ok(tenant))
+}
+
+/// Change a tenant. Suspending it drops its agents and refuses their connections, along with
+/// its users' logins, until it is made active again.
+#[instrument(name = "Update Tenant", level = "trace", skip(state, v1_state))]
+pub async fn update_tenant_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Extension(v1_state): Extension<Arc<V1ApiState>>,
+    Path(tenant_id): Path<i32>,
+    Json(payload): Json<UpdateTenantRequest>,
+) -> Result<ApiResponse<Tenant>, ApiError> {
+    authorize(
+        &state,
+        &user,
+        Permission::ManageTenants,
+        Some(&tenant_id.to_string()),
+    )?;
+
+    let changes = TenantChanges {
+        name: required_name(&payload.name)?,
+        allowed_origins: parse_origins(payload.allowed_origins)?,
+        agent_seat_limit: parse_seat_limit(payload.agent_seat_limit)?,
+        status: payload.status.as_str().to_string(),
+        agent_allowed_ips: parse_tenant_networks(payload.agent_allowed_ips)?,
+        agent_denied_ips: parse_tenant_networks(payload.agent_denied_ips)?,
+        admin_allowed_ips: parse_tenant_networks(payload.admin_allowed_ips)?,
+        admin_denied_ips: parse_tenant_networks(payload.admin_denied_ips)?,
+    };
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let tenant = find_tenant(&mut db_conn, tenant_id)?;
+    match (TenantStatus::parse(&tenant.status), payload.status) {
+        (TenantStatus::Deleting, _) => {
+            return Err(ApiError::BadRequest(format!(
+                "tenant {} is being deleted",
+                tenant.slug
+            )))
+        }
+        (TenantStatus::Deleted, _) => {
+            return Err(ApiError::BadRequest(format!(
+                "tenant {} was deleted",
+                tenant.slug
+            )))
+        }
+        (_, TenantStatus::Deleting | TenantStatus::Deleted) => {
+            return Err(ApiError::BadRequest(
+                "delete the tenant instead of setting its status to deleting".to_string(),
+            ))
+        }
+        (_, TenantStatus::Suspended) if tenant.slug == state.provider_tenant => {
+            return Err(ApiError::BadRequest(
+                "the provider tenant cannot be suspended".to_string(),
+            ))
+        }
+        _ => {}
+    }
+
+    let updated = update_tenant(&mut db_conn, tenant.id, changes)
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    if payload.status == TenantStatus::Suspended {
+        disconnect_tenant_agents(&v1_state, &updated.slug, "tenant suspended");
+    }
+    refresh_tenant_origins(&state);
+
+    info!(tenant = %updated.slug, status = %updated.status, updated_by = %user.name, "tenant updated");
+    Ok(ApiResponse::ok(updated))
+}
+
+/// Drop a tenant's agents and have a background job purge everything stored for it
+#[instrument(name = "Delete Tenant", level = "trace", skip(state, v1_state))]
+pub async fn delete_tenant_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Extension(v1_state): Extension<Arc<V1ApiState>>,
+    Path(tenant_id): Path<i32>,
+) -> Result<ApiResponse<Tenant>, ApiError> {
+    authorize(
+        &state,
+        &user,
+        Permission::ManageTenants,
+        Some(&tenant_id.to_string()),
+    )?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let tenant = find_tenant(&mut db_conn, tenant_id)?;
+    if tenant.slug == state.provider_tenant {
+        return Err(ApiError::BadRequest(
+            "the provider tenant cannot be deleted".to_string(),
+        ));
+    }
+    if matches!(
+        TenantStatus::parse(&tenant.status),
+        TenantStatus::Deleting | TenantStatus::Deleted
+    ) {
+        return Err(ApiError::BadRequest(format!(
+            "tenant {} is already deleted",
+            tenant.slug
+        )));
+    }
+
+    // Marked first, so the tenant is refused everywhere even if the purge has to be retried
+    let deleting = update_tenant(
+        &mut db_conn,
+        tenant.id,
+        TenantChanges {
+            name: tenant.name,
+            allowed_origins: tenant.allowed_origins,
+            agent_seat_limit: tenant.agent_seat_limit,
+            status: TenantStatus::Deleting.as_str().to_string(),
+            agent_allowed_ips: tenant.agent_allowed_ips,
+            agent_denied_ips: tenant.agent_denied_ips,
+            admin_allowed_ips: tenant.admin_allowed_ips,
+            admin_denied_ips: tenant.admin_denied_ips,
+        },
+    )
+    .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    disconnect_tenant_agents(&v1_state, &deleting.slug, "tenant deleted");
+    refresh_tenant_origins(&state);
+    state.tenant_purge.notify_one();
+
+    info!(tenant = %deleting.slug, deleted_by = %user.name, "tenant deletion started");
+    Ok(ApiResponse::ok(deleting))
+}
+
+/// The tenant an agent or user belongs to, provided it is allowed to connect
+pub(crate) fn require_active_tenant(
+    db_conn: &mut PooledSqliteConnection,
+    slug: &str,
+) -> Result<Tenant, ApiError> {
+    let tenant = get_tenant_by_slug(db_conn, slug)
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+        .ok_or_else(|| ApiError::Forbidden(format!("tenant {} does not exist", slug)))?;
+
+    match TenantStatus::parse(&tenant.status) {
+        TenantStatus::Active => Ok(tenant),
+        TenantStatus::Suspended => {
+            Err(ApiError::Forbidden(format!("tenant {} is suspended", slug)))
+        }
+        TenantStatus::Deleting => Err(ApiError::Forbidden(format!(
+            "tenant {} is being deleted",
+            slug
+        ))),
+        TenantStatus::Deleted => Err(ApiError::Forbidden(format!(
+            "tenant {} does not exist",
+            slug
+        ))),
+    }
+}
+
+/// Purge every tenant marked for deletion, at startup to finish any purge interrupted by a
+/// restart and then whenever a tenant is deleted. The one worker keeps purges from overlapping.
+pub(crate) async fn purge_deleted_tenants(state: Arc<ApiState>) {
+    loop {
+        purge_deleted_tenants_once(&state);
+        state.tenant_purge.notified().await;
+    }
+}
+
+fn purge_deleted_tenants_once(state: &ApiState) {
+    let purged = purge_tenants(&state.db_pool);
+
+    match purged {
+        Ok(revoked_certificates) if revoked_certificates > 0 => {
+            if let Some(certificate_authority) = &state.certificate_authority {
+                match publish_revocation_list(certificate_authority, &state.db_pool) {
+                    Ok(()) => state.tls_reload.notify_one(),
+                    Err(error) => {
+                        error!(errorMsg = %error, "unable to publish the revocation list after purging tenants")
+                    }
+                }
+            }
+        }
+        Ok(_) => {}
+        Err(error) => {
+            error!(errorMsg = %error, "unable to purge deleted tenants, retrying with the next deletion or start")
+        }
+    }
+}
+
+// Returns how many certificates were revoked across the purged tenants
+fn purge_tenants(db_pool: &SqlitePool) -> Result<usize, anyhow::Error> {
+    let mut db_conn = db_pool.get()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+    let mut revoked_certificates = 0;
+    for tenant in get_tenants_with_status(&mut db_conn, TenantStatus::Deleting.as_str())? {
+        let purge = purge_tenant(&mut db_conn, &tenant.slug, now)?;
+        info!(
+            tenant = %tenant.slug,
+            users = purge.users,
+            api_keys = purge.api_keys,
+            revocations = purge.revocations,
+            agent_tokens = purge.agent_tokens,
+            revoked_certificates = purge.revoked_certificates,
+            "tenant purged"
+        );
+        revoked_certificates += purge.revoked_certificates;
+    }
+
+    Ok(revoked_certificates)
+}
+
+/// Reload the origins CORS allows for the active tenants
+pub(crate) fn refresh_tenant_origins(state: &ApiState) {
+    let tenants = state
+        .db_pool
+        .get()
+        .map_err(anyhow::Error::from)
+        .and_then(|mut db_conn| {
+            get_tenants_with_status(&mut db_conn, TenantStatus::Active.as_str())
+        });
+
+    let tenants = match tenants {
+        Ok(tenants) => tenants,
+        Err(error) => {
+            warn!(errorMsg = %error, "unable to load the tenant origins, keeping the previous ones");
+            return;
+        }
+    };
+
+    let mut origins = HashSet::new();
+    for tenant in tenants {
+        if let Some(tenant_domain) = &state.tenant_domain {
+            origins.insert(format!("https://{}.{}", tenant.slug, tenant_domain.domain));
+        }
+        origins.extend(
+            tenant
+                .allowed_origins
+                .split(',')
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string),
+        );
+    }
+
+    let count = origins.len();
+    if state.tenant_origins.replace(origins) {
+        info!(origins = count, "tenant origins reloaded");
+    }
+}
+
+/// Reload the tenant origins periodically, picking up tenants changed outside this server
+pub(crate) async fn watch_tenant_origins(state: Arc<ApiState>, interval_seconds: u64) {
+    let refresh_interval = Duration::from_secs(interval_seconds);
+
+    loop {
+        tokio::time::sleep(refresh_interval).await;
+        refresh_tenant_origins(&state);
+    }
+}
+
+fn disconnect_tenant_agents(v1_state: &V1ApiState, tenant: &str, reason: &str) {
+    let agents = v1_state.agent_registry.tenant_agents(tenant);
+    for entry in &agents {
+        disconnect_agent(entry, reason);
+    }
+
+    if !agents.is_empty() {
+        info!(%tenant, agents = agents.len(), %reason, "tenant agents disconnected");
+    }
+}
+
+fn find_tenant(db_conn: &mut PooledSqliteConnection, tenant_id: i32) -> Result<Tenant, ApiError> {
+    get_tenant(db_conn, tenant_id)
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+        .ok_or_else(|| ApiError::NotFound(format!("tenant {}", tenant_id)))
+}
+
+// Slugs double as subdomains, so they must be valid DNS labels
+fn valid_slug(slug: &str) -> bool {
+    (1..=63).contains(&slug.len())
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+}
+
+fn required_name(name: &str) -> Result<String, ApiError> {
+    match name.trim() {
+        "" => Err(ApiError::BadRequest("name is required".to_string())),
+        name => Ok(name.to_string()),
+    }
+}
+
+// Origins are stored comma separated, each a scheme and host with an optional port
+fn parse_origins(origins: Vec<String>) -> Result<String, ApiError> {
+    let mut parsed: Vec<String> = Vec::with_capacity(origins.len());
+
+    for origin in origins {
+        let origin = origin.trim().trim_end_matches('/').to_ascii_lowercase();
+        let host = origin
+            .strip_prefix("https://")
+            .or_else(|| origin.strip_prefix("http://"))
+            .unwrap_or_default();
+
+        if host.is_empty() || host.contains(['/', ',']) || HeaderValue::from_str(&origin).is_err() {
+            return Err(ApiError::BadRequest(format!("invalid origin {}", origin)));
+        }
+        if !parsed.contains(&origin) {
+            parsed.push(origin);
+        }
+    }
+
+    Ok(parsed.join(","))
+}
+
+fn parse_seat_limit(agent_seat_limit: Option<u32>) -> Result<Option<i32>, ApiError> {
+    agent_seat_limit
+        .map(i32::try_from)
+        .transpose()
+        .map_err(|_| ApiError::BadRequest("agent seat limit is too large".to_string()))
+}
@@ -0,0 +1,187 @@
+use crate::actors::api::{
+    state::ApiState,
+    v1::{
+        auth::{hash_password, Principal},
+        errors::ApiError,
+        rbac::{authorize, Permission, Role},
+        responses::ApiResponse,
+    },
+};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use database_server::{
+    models::users::{
+        create_user, get_tenant_users, get_user, get_user_by_username, set_user_role, NewUser,
+    },
+    User,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{info, instrument};
+
+pub(crate) const MIN_PASSWORD_LENGTH: usize = 12;
+
+// Never derive Debug, the password must not end up in the logs
+#[derive(Deserialize)]
+pub struct CreateUserRequest {
+    username: String,
+    password: String,
+    // Defaults to read only
+    role: Option<Role>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetRoleRequest {
+    role: Role,
+}
+
+#[instrument(name = "User List", level = "trace", skip(state))]
+pub async fn get_users_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+) -> Result<ApiResponse<Vec<User>>, ApiError> {
+    authorize(&state, &user, Permission::ManageUsers, None)?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    match get_tenant_users(&mut db_conn, &user.tenant) {
+        Ok(users) => Ok(ApiResponse::ok(users)),
+        Err(error) => Err(ApiError::Internal(error.to_string())),
+    }
+}
+
+/// Add a user to the caller's tenant
+#[instrument(name = "Create User", level = "trace", skip(state, payload))]
+pub async fn create_user_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Json(payload): Json<CreateUserRequest>,
+) -> Result<ApiResponse<User>, ApiError> {
+    let username = payload.username.trim().to_string();
+    let role = payload.role.unwrap_or(Role::ReadOnly);
+    authorize_role_change(&state, &user, role, &username)?;
+
+    if username.is_empty() {
+        return Err(ApiError::BadRequest("username is required".to_string()));
+    }
+    if payload.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    let password = payload.password;
+    let password_hash = tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    if get_user_by_username(&mut db_conn, Some(&user.tenant), &username)
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+        .is_some()
+    {
+        return Err(ApiError::BadRequest(format!(
+            "username {} is already taken",
+            username
+        )));
+    }
+
+    let created = create_user(
+        &mut db_conn,
+        NewUser {
+            username,
+            password_hash,
+            tenant: user.tenant.clone(),
+            role: role.as_str().to_string(),
+        },
+    )
+    .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    info!(user = %created.username, tenant = %created.tenant, role = %created.role, created_by = %user.name, "user created");
+    Ok(ApiResponse::ok(created))
+}
+
+/// Change the role of a user in the caller's tenant
+#[instrument(name = "Set User Role", level = "trace", skip(state))]
+pub async fn set_user_role_handler(
+    user: Principal,
+    State(state): State<Arc<ApiState>>,
+    Path(user_id): Path<i32>,
+    Json(payload): Json<SetRoleRequest>,
+) -> Result<ApiResponse<User>, ApiError> {
+    let mut db_conn = state
+        .db_pool
+        .get()
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    // Users of other tenants are reported as missing rather than forbidden
+    let Some(target) = get_user(&mut db_conn, user_id)
+        .map_err(|error| ApiError::Internal(error.to_string()))?
+        .filter(|target| target.tenant == user.tenant)
+    else {
+        return Err(ApiError::NotFound(format!("user {}", user_id)));
+    };
+
+    authorize_role_change(&state, &user, payload.role, &target.username)?;
+
+    // Taking the owner role away is as privileged as granting it
+    if Role::parse(&target.role) == Role::Owner {
+        authorize(
+            &state,
+            &user,
+            Permission::ManageOwners,
+            Some(&target.username),
+        )?;
+    }
+    if user.user_id().ok() == Some(target.id) {
+        return Err(ApiError::BadRequest(
+            "you cannot change your own role".to_string(),
+        ));
+    }
+
+    let updated = set_user_role(&mut db_conn, target.id, payload.role.as_str())
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    info!(user = %updated.username, role = %updated.role, changed_by = %user.name, "user role changed");
+    Ok(ApiResponse::ok(updated))
+}
+
+// Managing users needs `ManageUsers`, handing out the owner role needs `ManageOwners` as well.
+// Like API key scopes, a role can only be handed out if the caller holds all of its permissions.
+fn authorize_role_change(
+    state: &ApiState,
+    user: &Principal,
+    role: Role,
+    username: &str,
+) -> Result<(), ApiError> {
+    authorize(state, user, Permission::ManageUsers, Some(username))?;
+
+    if role == Role::Owner {
+        authorize(state, user, Permission::ManageOwners, Some(username))?;
+    }
+
+    if let Some(permission) = Permission::ALL
+        .into_iter()
+        .find(|permission| role.allows(*permission) && !user.allows(*permission))
+    {
+        return Err(ApiError::Forbidden(format!(
+            "{} does not allow {}, so the {} role cannot be granted",
+            user.credential,
+            permission,
+            role.as_str()
+        )));
+    }
+
+    Ok(())
+}
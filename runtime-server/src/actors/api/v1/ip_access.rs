@@ -0,0 +1,217 @@
+use crate::actors::api::{
+    client_ip::{ClientIp, IpNetwork},
+    state::ApiState,
+    v1::{auth::Authentication, errors::ApiError, tenant::Tenant as RequestTenant},
+};
+use anyhow::{anyhow, Error};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use config_server::ApiConfiguration;
+use database_server::{models::tenants::get_tenant_by_slug, Tenant};
+use std::{fmt, net::IpAddr, sync::Arc};
+use tracing::warn;
+
+/// Tracing target IP access rejections are logged under, so they can be routed with other
+/// security events
+pub(crate) const SECURITY_TARGET: &str = "security";
+
+/// What an IP access list guards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IpAccessScope {
+    /// The `/agent` socket
+    Agent,
+    /// Everything but the agent socket and server info
+    Admin,
+}
+
+impl fmt::Display for IpAccessScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpAccessScope::Agent => write!(f, "agent"),
+            IpAccessScope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// CIDR allow and deny lists. A denied address is always refused, and when there is an allow
+/// list only the addresses on it are let through.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct IpAccessList {
+    allowed: Vec<IpNetwork>,
+    denied: Vec<IpNetwork>,
+}
+
+impl IpAccessList {
+    pub fn parse<S: AsRef<str>>(allowed: &[S], denied: &[S]) -> Result<Self, Error> {
+        let parse = |networks: &[S]| {
+            networks
+                .iter()
+                .map(|network| network.as_ref().parse::<IpNetwork>())
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            allowed: parse(allowed)?,
+            denied: parse(denied)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allowed.is_empty() && self.denied.is_empty()
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        !self.denied.iter().any(|network| network.contains(ip))
+            && (self.allowed.is_empty() || self.allowed.iter().any(|network| network.contains(ip)))
+    }
+}
+
+/// The lists configured for the whole server, applied to every tenant
+#[derive(Debug, Default)]
+pub(crate) struct IpAccessRules {
+    agent: IpAccessList,
+    admin: IpAccessList,
+}
+
+impl IpAccessRules {
+    pub fn from_config(api_config: &ApiConfiguration) -> Result<Self, Error> {
+        Ok(Self {
+            agent: IpAccessList::parse(&api_config.agent_allowed_ips, &api_config.agent_denied_ips)
+                .map_err(|error| anyhow!("invalid agent IP access list, {}", error))?,
+            admin: IpAccessList::parse(&api_config.admin_allowed_ips, &api_config.admin_denied_ips)
+                .map_err(|error| anyhow!("invalid admin IP access list, {}", error))?,
+        })
+    }
+
+    fn list(&self, scope: IpAccessScope) -> &IpAccessList {
+        match scope {
+            IpAccessScope::Agent => &self.agent,
+            IpAccessScope::Admin => &self.admin,
+        }
+    }
+}
+
+/// Refuse a client the server's lists or its tenant's lists don't permit, logging it as a
+/// security event. Without a known client IP nothing is permitted once there is a list to check.
+pub(crate) fn check_ip_access(
+    state: &ApiState,
+    scope: IpAccessScope,
+    client_ip: Option<ClientIp>,
+    tenant: Option<&Tenant>,
+    subject: &str,
+) -> Result<(), ApiError> {
+    // Tenant lists are validated before being stored, should one be unreadable since then
+    // everyone is refused rather than risk letting through someone the tenant meant to keep out
+    let tenant_list = tenant
+        .map(|tenant| tenant_access_list(tenant, scope))
+        .transpose();
+    let lists = [
+        Some(state.ip_access.list(scope)),
+        tenant_list.as_ref().ok().and_then(Option::as_ref),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|list| !list.is_empty())
+    .collect::<Vec<_>>();
+
+    let permitted = tenant_list.is_ok()
+        && match client_ip {
+            Some(ClientIp(ip)) => lists.iter().all(|list| list.permits(ip)),
+            None => lists.is_empty(),
+        };
+
+    if !permitted {
+        warn!(
+            target: SECURITY_TARGET,
+            %scope,
+            client_ip = ?client_ip.map(|ClientIp(ip)| ip),
+            tenant = ?tenant.map(|tenant| &tenant.slug),
+            %subject,
+            invalid_tenant_list = ?tenant_list.err().map(|error| error.to_string()),
+            "client IP refused"
+        );
+        return Err(ApiError::Forbidden(match scope {
+            IpAccessScope::Agent => "agents may not connect from this address".to_string(),
+            IpAccessScope::Admin => "the admin API may not be used from this address".to_string(),
+        }));
+    }
+
+    Ok(())
+}
+
+/// Guard the admin API with the server's admin lists, and those of the request's tenant
+pub(crate) async fn restrict_admin_ips(
+    State(state): State<Arc<ApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client_ip = request.extensions().get::<ClientIp>().copied();
+
+    let tenant = match request.extensions().get::<RequestTenant>() {
+        Some(RequestTenant(slug)) => {
+            let tenant = state
+                .db_pool
+                .get()
+                .map_err(anyhow::Error::from)
+                .and_then(|mut db_conn| get_tenant_by_slug(&mut db_conn, slug));
+            match tenant {
+                Ok(tenant) => tenant,
+                Err(error) => return ApiError::Internal(error.to_string()).into_response(),
+            }
+        }
+        None => None,
+    };
+
+    let subject = match request.extensions().get::<Authentication>() {
+        Some(Authentication(Ok(principal))) => principal.name.clone(),
+        _ => format!("anonymous {} {}", request.method(), request.uri().path()),
+    };
+
+    match check_ip_access(
+        &state,
+        IpAccessScope::Admin,
+        client_ip,
+        tenant.as_ref(),
+        &subject,
+    ) {
+        Ok(()) => next.run(request).await,
+        Err(error) => error.into_response(),
+    }
+}
+
+/// Validate IPs or CIDRs given for a tenant, returning them comma separated for storage
+pub(crate) fn parse_tenant_networks(networks: Vec<String>) -> Result<String, ApiError> {
+    let mut parsed: Vec<String> = Vec::with_capacity(networks.len());
+
+    for network in networks {
+        let network = network.trim().to_ascii_lowercase();
+        if let Err(error) = network.parse::<IpNetwork>() {
+            return Err(ApiError::BadRequest(error.to_string()));
+        }
+        if !parsed.contains(&network) {
+            parsed.push(network);
+        }
+    }
+
+    Ok(parsed.join(","))
+}
+
+fn tenant_access_list(tenant: &Tenant, scope: IpAccessScope) -> Result<IpAccessList, Error> {
+    let (allowed, denied) = match scope {
+        IpAccessScope::Agent => (&tenant.agent_allowed_ips, &tenant.agent_denied_ips),
+        IpAccessScope::Admin => (&tenant.admin_allowed_ips, &tenant.admin_denied_ips),
+    };
+    let split = |networks: &str| {
+        networks
+            .split(',')
+            .filter(|network| !network.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    IpAccessList::parse(&split(allowed), &split(denied))
+}
@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Context, Error};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    traits::PublicKeyParts,
+    RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use tracing::info;
+
+/// A key tokens can be verified with, along with the id published in the token header
+pub(crate) struct VerificationKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub key: DecodingKey,
+    // Only asymmetric keys are published
+    pub jwk: Option<Jwk>,
+}
+
+/// The key used to sign new tokens, plus every key still accepted when verifying them.
+/// Rotate by signing with a new key while keeping the old public key in the verification set
+/// until every token it signed has expired.
+pub(crate) struct JwtKeySet {
+    signing_kid: Option<String>,
+    signing_algorithm: Algorithm,
+    signing_key: EncodingKey,
+    verification_keys: Vec<VerificationKey>,
+}
+
+impl fmt::Debug for JwtKeySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKeySet")
+            .field("signing_kid", &self.signing_kid)
+            .field("signing_algorithm", &self.signing_algorithm)
+            .field(
+                "verification_kids",
+                &self
+                    .verification_keys
+                    .iter()
+                    .map(|key| &key.kid)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl JwtKeySet {
+    /// Load the signing key and any extra verification keys from PEM files.
+    /// Without a signing key, tokens are signed with HS512 using the shared secret instead.
+    pub fn load(
+        signing_key_file: Option<&str>,
+        verification_key_files: &[String],
+        secret: &str,
+    ) -> Result<Self, Error> {
+        let Some(signing_key_file) = signing_key_file else {
+            if !verification_key_files.is_empty() {
+                return Err(anyhow!(
+                    "verification keys were configured without a signing key"
+                ));
+            }
+
+            return Ok(Self {
+                signing_kid: None,
+                signing_algorithm: Algorithm::HS512,
+                signing_key: EncodingKey::from_secret(secret.as_bytes()),
+                verification_keys: vec![VerificationKey {
+                    kid: None,
+                    algorithm: Algorithm::HS512,
+                    key: DecodingKey::from_secret(secret.as_bytes()),
+                    jwk: None,
+                }],
+            });
+        };
+
+        let pem = std::fs::read_to_string(signing_key_file)
+            .with_context(|| format!("unable to read JWT signing key {}", signing_key_file))?;
+        let (signing_algorithm, signing_key, public_jwk) = load_private_key(&pem)
+            .with_context(|| format!("invalid JWT signing key {}", signing_key_file))?;
+        let signing_kid = public_jwk.common.key_id.clone();
+
+        let mut verification_keys = vec![verification_key(public_jwk)?];
+        for file in verification_key_files {
+            let pem = std::fs::read_to_string(file)
+                .with_context(|| format!("unable to read JWT verification key {}", file))?;
+            let jwk = load_public_key(&pem)
+                .with_context(|| format!("invalid JWT verification key {}", file))?;
+
+            // The signing key may also be listed while a rotation is being rolled out
+            if verification_keys.iter().any(|key| key.kid == jwk.common.key_id) {
+                continue;
+            }
+            verification_keys.push(verification_key(jwk)?);
+        }
+
+        info!(
+            kid = ?signing_kid,
+            algorithm = ?signing_algorithm,
+            verification_keys = verification_keys.len(),
+            "loaded JWT signing keys"
+        );
+
+        Ok(Self {
+            signing_kid,
+            signing_algorithm,
+            signing_key,
+            verification_keys,
+        })
+    }
+
+    pub fn signing_kid(&self) -> Option<&String> {
+        self.signing_kid.as_ref()
+    }
+
+    pub fn signing_algorithm(&self) -> Algorithm {
+        self.signing_algorithm
+    }
+
+    pub fn signing_key(&self) -> &EncodingKey {
+        &self.signing_key
+    }
+
+    /// Find the key a token was signed with from the `kid` in its header
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&VerificationKey> {
+        self.verification_keys
+            .iter()
+            .find(|key| key.kid.as_deref() == kid)
+    }
+
+    /// The public keys, in the JWKS format other services use to verify our tokens
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verification_keys
+                .iter()
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+fn verification_key(jwk: Jwk) -> Result<VerificationKey, Error> {
+    let algorithm = match jwk.common.key_algorithm {
+        Some(KeyAlgorithm::EdDSA) => Algorithm::EdDSA,
+        _ => Algorithm::RS256,
+    };
+
+    Ok(VerificationKey {
+        kid: jwk.common.key_id.clone(),
+        algorithm,
+        key: DecodingKey::from_jwk(&jwk)?,
+        jwk: Some(jwk),
+    })
+}
+
+// Accepts PKCS#8 Ed25519 keys, or PKCS#8 / PKCS#1 RSA keys
+fn load_private_key(pem: &str) -> Result<(Algorithm, EncodingKey, Jwk), Error> {
+    if let Ok(key) = ed25519_dalek::SigningKey::from_pkcs8_pem(pem) {
+        let encoding_key = EncodingKey::from_ed_pem(pem.as_bytes())?;
+        return Ok((
+            Algorithm::EdDSA,
+            encoding_key,
+            ed25519_jwk(&key.verifying_key()),
+        ));
+    }
+
+    let key = RsaPrivateKey::from_pkcs8_pem(pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+        .map_err(|_| anyhow!("expected an Ed25519 or RSA private key"))?;
+    let encoding_key = EncodingKey::from_rsa_pem(pem.as_bytes())?;
+
+    Ok((Algorithm::RS256, encoding_key, rsa_jwk(&key.to_public_key())))
+}
+
+fn load_public_key(pem: &str) -> Result<Jwk, Error> {
+    if let Ok(key) = ed25519_dalek::VerifyingKey::from_public_key_pem(pem) {
+        return Ok(ed25519_jwk(&key));
+    }
+
+    let key = RsaPublicKey::from_public_key_pem(pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+        .map_err(|_| anyhow!("expected an Ed25519 or RSA public key"))?;
+
+    Ok(rsa_jwk(&key))
+}
+
+fn ed25519_jwk(key: &ed25519_dalek::VerifyingKey) -> Jwk {
+    let x = URL_SAFE_NO_PAD.encode(key.as_bytes());
+    // RFC 7638 thumbprint members, in lexicographic order
+    let kid = thumbprint(&format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, x));
+
+    Jwk {
+        common: common_parameters(KeyAlgorithm::EdDSA, kid),
+        algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x,
+        }),
+    }
+}
+
+fn rsa_jwk(key: &RsaPublicKey) -> Jwk {
+    let n = URL_SAFE_NO_PAD.encode(key.n().to_bytes_be());
+    let e = URL_SAFE_NO_PAD.encode(key.e().to_bytes_be());
+    let kid = thumbprint(&format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, e, n));
+
+    Jwk {
+        common: common_parameters(KeyAlgorithm::RS256, kid),
+        algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n,
+            e,
+        }),
+    }
+}
+
+fn common_parameters(algorithm: KeyAlgorithm, kid: String) -> CommonParameters {
+    CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        key_algorithm: Some(algorithm),
+        key_id: Some(kid),
+        ..Default::default()
+    }
+}
+
+// Key ids are derived from the public key so every instance agrees on them without configuration
+fn thumbprint(members: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(members.as_bytes()))
+}
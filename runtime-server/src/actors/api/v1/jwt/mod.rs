@@ -0,0 +1,120 @@
+mod keys;
+pub(crate) mod session;
+
+pub(crate) use keys::JwtKeySet;
+
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    errors::{Error, ErrorKind},
+    Header, Validation,
+};
+use runtime_shared::RuntimeProperties;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+pub enum JwtType {
+    Agent,
+    // Bound to one agent and only good for enrolling it once, see `AgentClaims::enrollment`
+    Enrollment,
+}
+
+/// Our claims struct, it needs to derive `Serialize` and/or `Deserialize`
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct AgentClaims {
+    pub sub: String,
+    pub iat: usize,
+    pub aud: String,
+    pub exp: usize,
+    pub iss: String,
+    pub nbf: usize,
+    pub jti: String,
+    // Set once a token has been refreshed by a connected agent, binding it to that agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<String>,
+    // Without a client certificate the token can only be used to enroll its agent, once
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enrollment: bool,
+}
+
+/// A freshly signed token along with its expiry (seconds since the unix epoch)
+pub(crate) struct IssuedJwt {
+    pub token: String,
+    pub expires_at: usize,
+}
+
+fn get_claims(
+    tenant: &str,
+    agent_id: Option<&str>,
+    lifetime_secs: u64,
+    jwt_type: JwtType,
+) -> AgentClaims {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as usize;
+
+    AgentClaims {
+        sub: "Agent".to_string(),
+        iat: now,
+        aud: tenant.to_string(),
+        exp: now + lifetime_secs as usize,
+        iss: RuntimeProperties::global().app_name().to_string(),
+        nbf: now,
+        jti: Uuid::new_v4().to_string(),
+        agent_id: agent_id.map(|id| id.to_string()),
+        enrollment: matches!(jwt_type, JwtType::Enrollment),
+    }
+}
+
+pub fn generate_jwt(
+    tenant: &str,
+    agent_id: Option<&str>,
+    keys: &JwtKeySet,
+    lifetime_secs: u64,
+    jwt_type: JwtType,
+) -> Result<IssuedJwt, Error> {
+    match jwt_type {
+        JwtType::Agent | JwtType::Enrollment => {
+            let claims = get_claims(tenant, agent_id, lifetime_secs, jwt_type);
+            let mut header = Header::new(keys.signing_algorithm());
+            header.kid = keys.signing_kid().cloned();
+
+            match encode(&header, &claims, keys.signing_key()) {
+                Ok(token) => Ok(IssuedJwt {
+                    token,
+                    expires_at: claims.exp,
+                }),
+                Err(error) => Err(error),
+            }
+        }
+    }
+}
+
+/// Verify the signature, issuer and lifetime of a token and return its claims.
+/// The `kid` in the header selects the verification key, so tokens signed with a
+/// retired key keep working for as long as its public key is configured.
+pub fn validate_jwt(
+    token: &str,
+    keys: &JwtKeySet,
+    jwt_type: JwtType,
+) -> Result<AgentClaims, Error> {
+    match jwt_type {
+        JwtType::Agent | JwtType::Enrollment => {
+            let header = decode_header(token)?;
+            let Some(key) = keys.verification_key(header.kid.as_deref()) else {
+                return Err(ErrorKind::InvalidSignature.into());
+            };
+
+            // Only the algorithm of the matching key is accepted, whatever the header claims
+            let mut validation = Validation::new(key.algorithm);
+            validation.set_issuer(&[RuntimeProperties::global().app_name()]);
+            validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+            validation.validate_nbf = true;
+            // The audience is the tenant, which is only known once the token is decoded
+            validation.validate_aud = false;
+
+            decode::<AgentClaims>(token, &key.key, &validation).map(|data| data.claims)
+        }
+    }
+}
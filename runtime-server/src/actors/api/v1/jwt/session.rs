@@ -0,0 +1,78 @@
+use crate::actors::api::v1::jwt::IssuedJwt;
+use database_server::User;
+use jsonwebtoken::{
+    decode, encode, errors::Error, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use runtime_shared::RuntimeProperties;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+// Keeps session tokens and agent tokens apart should the two secrets ever be the same
+const SESSION_AUDIENCE: &str = "session";
+
+/// The claims of the session tokens issued to users when they log in
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SessionClaims {
+    // The user id
+    pub sub: String,
+    pub username: String,
+    pub tenant: String,
+    pub iat: usize,
+    pub aud: String,
+    pub exp: usize,
+    pub iss: String,
+    pub nbf: usize,
+    pub jti: String,
+}
+
+/// Sign a session token for a user with the server secret (HS512)
+pub fn generate_session_jwt(
+    user: &User,
+    secret: &str,
+    lifetime_secs: u64,
+) -> Result<IssuedJwt, Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as usize;
+
+    let claims = SessionClaims {
+        sub: user.id.to_string(),
+        username: user.username.clone(),
+        tenant: user.tenant.clone(),
+        iat: now,
+        aud: SESSION_AUDIENCE.to_string(),
+        exp: now + lifetime_secs as usize,
+        iss: RuntimeProperties::global().app_name().to_string(),
+        nbf: now,
+        jti: Uuid::new_v4().to_string(),
+    };
+
+    let token = encode(
+        &Header::new(Algorithm::HS512),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )?;
+
+    Ok(IssuedJwt {
+        token,
+        expires_at: claims.exp,
+    })
+}
+
+/// Verify the signature, issuer, audience and lifetime of a session token and return its claims
+pub fn validate_session_jwt(token: &str, secret: &str) -> Result<SessionClaims, Error> {
+    let mut validation = Validation::new(Algorithm::HS512);
+    validation.set_issuer(&[RuntimeProperties::global().app_name()]);
+    validation.set_audience(&[SESSION_AUDIENCE]);
+    validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+    validation.validate_nbf = true;
+
+    decode::<SessionClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .map(|data| data.claims)
+}
@@ -1,5 +1,9 @@
+pub(crate) mod auth;
 pub(crate) mod errors;
 pub(crate) mod handlers;
+pub(crate) mod ip_access;
 pub(crate) mod jwt;
+pub(crate) mod rbac;
 pub(crate) mod responses;
 pub(crate) mod routes;
+pub(crate) mod tenant;
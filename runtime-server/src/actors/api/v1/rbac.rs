@@ -0,0 +1,177 @@
+use crate::actors::api::{
+    state::ApiState,
+    v1::{auth::Principal, errors::ApiError},
+};
+use database_server::models::audit_log::{record_audit_entry, NewAuditEntry};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tracing::{error, warn};
+
+/// What a user may do within their tenant. Each role can do everything the one below it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Role {
+    ReadOnly,
+    Operator,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read_only",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+
+    /// Unknown roles are treated as read only
+    pub fn parse(role: &str) -> Self {
+        match role {
+            "owner" => Role::Owner,
+            "admin" => Role::Admin,
+            "operator" => Role::Operator,
+            _ => Role::ReadOnly,
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        use Permission::*;
+
+        match self {
+            Role::Owner => true,
+            Role::Admin => !matches!(permission, ManageOwners | ManageTenants),
+            Role::Operator => matches!(
+                permission,
+                ViewAgents | IssueAgentTokens | CommandAgents | CommandGroups
+            ),
+            Role::ReadOnly => matches!(permission, ViewAgents),
+        }
+    }
+}
+
+/// The operations routes are guarded by, also the scopes an API key can carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Permission {
+    /// List connected agents, their certificates and the revocation list
+    ViewAgents,
+    /// Issue the tokens new agents connect with
+    IssueAgentTokens,
+    /// Send a command to a single agent
+    CommandAgents,
+    /// Send a command to every agent in a group
+    CommandGroups,
+    /// Send a command to every agent of the tenant
+    Broadcast,
+    /// Revoke agents and lift revocations
+    ManageAgents,
+    /// Create users and change their roles
+    ManageUsers,
+    /// Make other users owners
+    ManageOwners,
+    /// Create and revoke API keys
+    ManageApiKeys,
+    ViewAuditLog,
+    /// Create, change, suspend and delete tenants - only ever granted within the provider tenant
+    ManageTenants,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 11] = [
+        Permission::ViewAgents,
+        Permission::IssueAgentTokens,
+        Permission::CommandAgents,
+        Permission::CommandGroups,
+        Permission::Broadcast,
+        Permission::ManageAgents,
+        Permission::ManageUsers,
+        Permission::ManageOwners,
+        Permission::ManageApiKeys,
+        Permission::ViewAuditLog,
+        Permission::ManageTenants,
+    ];
+
+    pub fn parse(permission: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|candidate| candidate.to_string() == permission)
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Permission::ViewAgents => "view_agents",
+            Permission::IssueAgentTokens => "issue_agent_tokens",
+            Permission::CommandAgents => "command_agents",
+            Permission::CommandGroups => "command_groups",
+            Permission::Broadcast => "broadcast",
+            Permission::ManageAgents => "manage_agents",
+            Permission::ManageUsers => "manage_users",
+            Permission::ManageOwners => "manage_owners",
+            Permission::ManageApiKeys => "manage_api_keys",
+            Permission::ViewAuditLog => "view_audit_log",
+            Permission::ManageTenants => "manage_tenants",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Check the caller's role (or API key scopes) allows `permission` within their tenant, recording the decision
+/// in the audit log either way. `resource` names what is being acted on, if anything.
+/// A decision that cannot be recorded is refused.
+pub(crate) fn authorize(
+    state: &ApiState,
+    user: &Principal,
+    permission: Permission,
+    resource: Option<&str>,
+) -> Result<(), ApiError> {
+    // Tenants are managed by the provider running this server, never by one of its tenants
+    let outside_provider =
+        permission == Permission::ManageTenants && user.tenant != state.provider_tenant;
+    let granted = user.allows(permission) && !outside_provider;
+
+    let recorded = state
+        .db_pool
+        .get()
+        .map_err(anyhow::Error::from)
+        .and_then(|mut db_conn| {
+            record_audit_entry(
+                &mut db_conn,
+                NewAuditEntry {
+                    tenant: user.tenant.clone(),
+                    principal: user.name.clone(),
+                    action: permission.to_string(),
+                    resource: resource.map(|resource| resource.to_string()),
+                    granted,
+                    client_ip: user.client_ip.map(|client_ip| client_ip.to_string()),
+                },
+            )
+        });
+
+    if let Err(error) = recorded {
+        error!(errorMsg=%error, user = %user.name, %permission, "unable to write the audit log");
+        return Err(ApiError::Internal(
+            "unable to write the audit log".to_string(),
+        ));
+    }
+
+    if !granted {
+        warn!(user = %user.name, tenant = %user.tenant, %permission, resource = ?resource, client_ip = ?user.client_ip, "permission denied");
+        if outside_provider {
+            return Err(ApiError::Forbidden(format!(
+                "only the {} tenant can manage tenants",
+                state.provider_tenant
+            )));
+        }
+        return Err(ApiError::Forbidden(format!(
+            "{} does not allow {}",
+            user.credential, permission
+        )));
+    }
+
+    Ok(())
+}
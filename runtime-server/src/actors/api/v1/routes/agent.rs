@@ -1,10 +1,63 @@
+use crate::actors::api::v1::handlers::agent::commands::{
+    broadcast_handler, cancel_command_handler, command_agent_handler, command_group_handler,
+    get_agents_handler,
+};
+use crate::actors::api::v1::handlers::agent::enrollment::get_agent_certificates_handler;
 use crate::actors::api::v1::handlers::agent::get_agent_token_handler;
-use crate::actors::api::{state::ApiState, v1::handlers::agent_connection_handler};
-use axum::{routing::get, Router};
+use crate::actors::api::v1::handlers::agent::output::command_output_handler;
+use crate::actors::api::v1::handlers::agent::revocation::{
+    get_agent_revocations_handler, revoke_agent_handler, unrevoke_agent_handler,
+};
+use crate::actors::api::{
+    rate_limiting::RouteRateLimits, state::ApiState, v1::handlers::agent_connection_handler,
+};
+use axum::{
+    routing::{get, post},
+    Router,
+};
 use std::sync::Arc;
 
-pub fn agent_router() -> Router<Arc<ApiState>> {
+// The socket agents connect to, kept apart from the admin API as it has its own IP lists
+pub fn agent_connection_router() -> Router<Arc<ApiState>> {
+    Router::new().route("/agent", get(agent_connection_handler))
+}
+
+pub(crate) fn agent_router(rate_limits: &RouteRateLimits) -> Router<Arc<ApiState>> {
+    let agent_commands = &rate_limits.agent_commands;
+
     Router::new()
-        .route("/agent", get(agent_connection_handler))
-        .route("/agent/token", get(get_agent_token_handler))
+        .route(
+            "/agent/token",
+            get(get_agent_token_handler).layer(rate_limits.token_issuance.clone()),
+        )
+        .route("/agents", get(get_agents_handler))
+        .route(
+            "/agents/broadcast",
+            post(broadcast_handler).layer(agent_commands.clone()),
+        )
+        .route(
+            "/agents/groups/{group}/command",
+            post(command_group_handler).layer(agent_commands.clone()),
+        )
+        .route(
+            "/agent/{id}/command",
+            post(command_agent_handler).layer(agent_commands.clone()),
+        )
+        .route(
+            "/agent/{id}/commands/{command_id}/output",
+            get(command_output_handler),
+        )
+        .route(
+            "/agent/{id}/commands/{command_id}/cancel",
+            post(cancel_command_handler).layer(agent_commands.clone()),
+        )
+        .route("/agent/revocations", get(get_agent_revocations_handler))
+        .route(
+            "/agent/{id}/certificates",
+            get(get_agent_certificates_handler),
+        )
+        .route(
+            "/agent/{id}/revoke",
+            post(revoke_agent_handler).delete(unrevoke_agent_handler),
+        )
 }
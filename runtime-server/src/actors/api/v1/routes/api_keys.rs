@@ -0,0 +1,22 @@
+use crate::actors::api::{
+    rate_limiting::RouteRateLimits,
+    state::ApiState,
+    v1::handlers::api_keys::{
+        create_api_key_handler, get_api_keys_handler, revoke_api_key_handler,
+    },
+};
+use axum::{
+    routing::{delete, get, post},
+    Router,
+};
+use std::sync::Arc;
+
+pub(crate) fn api_keys_router(rate_limits: &RouteRateLimits) -> Router<Arc<ApiState>> {
+    Router::new()
+        .route(
+            "/api-keys",
+            get(get_api_keys_handler)
+                .merge(post(create_api_key_handler).layer(rate_limits.token_issuance.clone())),
+        )
+        .route("/api-keys/{id}", delete(revoke_api_key_handler))
+}
@@ -0,0 +1,7 @@
+use crate::actors::api::{state::ApiState, v1::handlers::audit::get_audit_log_handler};
+use axum::{routing::get, Router};
+use std::sync::Arc;
+
+pub fn audit_router() -> Router<Arc<ApiState>> {
+    Router::new().route("/audit", get(get_audit_log_handler))
+}
@@ -0,0 +1,28 @@
+use crate::actors::api::{
+    rate_limiting::RouteRateLimits,
+    state::ApiState,
+    v1::handlers::auth::{
+        change_password_handler, current_user_handler, login_handler, logout_handler,
+        refresh_handler,
+    },
+};
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use std::sync::Arc;
+
+pub(crate) fn auth_router(rate_limits: &RouteRateLimits) -> Router<Arc<ApiState>> {
+    Router::new()
+        .route(
+            "/auth/login",
+            post(login_handler).layer(rate_limits.token_issuance.clone()),
+        )
+        .route(
+            "/auth/refresh",
+            post(refresh_handler).layer(rate_limits.token_issuance.clone()),
+        )
+        .route("/auth/logout", post(logout_handler))
+        .route("/auth/me", get(current_user_handler))
+        .route("/auth/password", post(change_password_handler))
+}
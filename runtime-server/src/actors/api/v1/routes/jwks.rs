@@ -0,0 +1,7 @@
+use crate::actors::api::{state::ApiState, v1::handlers::get_jwks_handler};
+use axum::{routing::get, Router};
+use std::sync::Arc;
+
+pub fn jwks_router() -> Router<Arc<ApiState>> {
+    Router::new().route("/.well-known/jwks.json", get(get_jwks_handler))
+}
@@ -1,27 +1,72 @@
 pub(crate) mod agent;
+pub(crate) mod api_keys;
+pub(crate) mod audit;
+pub(crate) mod auth;
 pub(crate) mod info;
+pub(crate) mod jwks;
+pub(crate) mod tenants;
+pub(crate) mod users;
 
-use axum::{Extension, Router};
+use axum::{middleware, Extension, Router};
 use std::sync::Arc;
 
 use crate::actors::api::{
+    rate_limiting::RouteRateLimits,
     state::{ApiState, V1ApiState},
-    v1::routes::{agent::agent_router, info::info_router},
+    v1::{
+        ip_access::restrict_admin_ips,
+        routes::{
+            agent::{agent_connection_router, agent_router},
+            api_keys::api_keys_router,
+            audit::audit_router,
+            auth::auth_router,
+            info::info_router,
+            jwks::jwks_router,
+            tenants::tenants_router,
+            users::users_router,
+        },
+    },
 };
 
-pub fn api_router() -> Router<Arc<ApiState>> {
+// Both mounts share one v1 state so they see the same connected agents
+pub(crate) fn api_router(
+    state: Arc<ApiState>,
+    v1_state: Arc<V1ApiState>,
+    rate_limits: RouteRateLimits,
+) -> Router<Arc<ApiState>> {
     Router::new()
-        .nest("/api/v1", v1_router())
-        .nest("/api", v1_router()) // transition to latest version
+        .merge(jwks_router())
+        .nest(
+            "/api/v1",
+            v1_router(state.clone(), v1_state.clone(), &rate_limits),
+        )
+        .nest("/api", v1_router(state, v1_state, &rate_limits)) // transition to latest version
 }
 
-fn v1_router() -> Router<Arc<ApiState>> {
+// Only login, refresh, info, the JWKS and the agent socket (which checks the agent's own token)
+// are public - every other handler takes a `Principal`
+fn v1_router(
+    state: Arc<ApiState>,
+    v1_state: Arc<V1ApiState>,
+    rate_limits: &RouteRateLimits,
+) -> Router<Arc<ApiState>> {
     let api_version = "v1".to_string();
-    let v1_state = Arc::new(V1ApiState::new());
     let api_id = v1_state.id.clone();
 
+    // Everything but the agent socket and server info is the admin API, which can be
+    // restricted to known IP ranges
+    let admin_router = Router::new()
+        .merge(auth_router(rate_limits))
+        .merge(agent_router(rate_limits))
+        .merge(users_router())
+        .merge(api_keys_router(rate_limits))
+        .merge(audit_router())
+        .merge(tenants_router())
+        .route_layer(middleware::from_fn_with_state(state, restrict_admin_ips));
+
     Router::new()
         .merge(info_router(api_version, api_id))
-        .merge(agent_router())
+        .merge(agent_connection_router())
+        .merge(admin_router)
         .layer(Extension(v1_state))
 }
@@ -0,0 +1,23 @@
+use crate::actors::api::{
+    state::ApiState,
+    v1::handlers::tenants::{
+        create_tenant_handler, delete_tenant_handler, get_tenant_handler, get_tenants_handler,
+        update_tenant_handler,
+    },
+};
+use axum::{routing::get, Router};
+use std::sync::Arc;
+
+pub fn tenants_router() -> Router<Arc<ApiState>> {
+    Router::new()
+        .route(
+            "/tenants",
+            get(get_tenants_handler).post(create_tenant_handler),
+        )
+        .route(
+            "/tenants/{id}",
+            get(get_tenant_handler)
+                .put(update_tenant_handler)
+                .delete(delete_tenant_handler),
+        )
+}
@@ -0,0 +1,15 @@
+use crate::actors::api::{
+    state::ApiState,
+    v1::handlers::users::{create_user_handler, get_users_handler, set_user_role_handler},
+};
+use axum::{
+    routing::{get, put},
+    Router,
+};
+use std::sync::Arc;
+
+pub fn users_router() -> Router<Arc<ApiState>> {
+    Router::new()
+        .route("/users", get(get_users_handler).post(create_user_handler))
+        .route("/users/{id}/role", put(set_user_role_handler))
+}
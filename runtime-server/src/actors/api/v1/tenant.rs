@@ -0,0 +1,152 @@
+use crate::actors::api::{
+    state::ApiState,
+    v1::{auth::Principal, errors::ApiError},
+};
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{
+        header::{AUTHORIZATION, HOST},
+        request::Parts,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use config_server::{CorsConfiguration, CorsMode};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Header a client can name its tenant with when it is not using a tenant subdomain
+pub(crate) const TENANT_HEADER: &str = "x-tenant-id";
+
+/// The tenant a request was resolved to. Present on every request that authenticated, named a
+/// tenant in the `x-tenant-id` header or was made to a tenant subdomain.
+#[derive(Debug, Clone)]
+pub(crate) struct Tenant(pub String);
+
+/// Where tenant subdomains live in multi-tenant mode e.g. "acme.yourmsp.com" is tenant "acme"
+#[derive(Debug, Clone)]
+pub(crate) struct TenantDomain {
+    pub domain: String,
+    // Served from the same parent domain, but never a tenant
+    pub admin_domain: String,
+}
+
+impl TenantDomain {
+    /// Derived from the multi-tenant CORS mode's `*.domain` tenant pattern
+    pub fn from_cors(cors: &CorsConfiguration) -> Option<Self> {
+        match &cors.mode {
+            CorsMode::MultiTenant {
+                admin_domain,
+                tenant_pattern,
+                ..
+            } => Some(Self {
+                domain: tenant_pattern
+                    .strip_prefix("*.")
+                    .unwrap_or(tenant_pattern)
+                    .to_ascii_lowercase(),
+                admin_domain: admin_domain.to_ascii_lowercase(),
+            }),
+            _ => None,
+        }
+    }
+
+    // "acme.yourmsp.com:8443" is tenant "acme", only a single label counts
+    fn tenant_of(&self, host: &str) -> Option<String> {
+        let host = host
+            .rsplit_once(':')
+            .map_or(host, |(host, _port)| host)
+            .to_ascii_lowercase();
+        if host == self.admin_domain {
+            return None;
+        }
+
+        let tenant = host.strip_suffix(&format!(".{}", self.domain))?;
+        match !tenant.is_empty() && !tenant.contains('.') {
+            true => Some(tenant.to_string()),
+            false => None,
+        }
+    }
+}
+
+/// Resolve the tenant of every request before it reaches a handler
+///
+/// The tenant comes from the authenticated principal, the `x-tenant-id` header or the tenant
+/// subdomain. Credentials belonging to a different tenant than the one asked for are refused
+/// outright, so a handler can rely on the principal's tenant being the request's tenant.
+pub(crate) async fn resolve_tenant(
+    State(state): State<Arc<ApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    let requested = match requested_tenant(&parts, state.tenant_domain.as_ref()) {
+        Ok(requested) => requested,
+        Err(error) => return error.into_response(),
+    };
+
+    // Authenticate up front when credentials were presented, handlers taking a `Principal`
+    // reuse the outcome. Bad credentials are left for those handlers to reject.
+    let principal = match parts.headers.contains_key(AUTHORIZATION) {
+        true => Principal::from_request_parts(&mut parts, &state).await.ok(),
+        false => None,
+    };
+
+    let tenant = match (principal, requested) {
+        (Some(principal), Some(requested)) if principal.tenant != requested => {
+            warn!(principal = %principal.name, tenant = %principal.tenant, %requested, path = %parts.uri.path(), "credentials presented for another tenant");
+            return ApiError::Forbidden(format!(
+                "these credentials do not belong to tenant {}",
+                requested
+            ))
+            .into_response();
+        }
+        (Some(principal), _) => Some(principal.tenant),
+        (None, requested) => requested,
+    };
+
+    if let Some(tenant) = tenant {
+        parts.extensions.insert(Tenant(tenant));
+    }
+
+    next.run(Request::from_parts(parts, body)).await
+}
+
+// The tenant the client asked for, if any. Naming one tenant in the header and another
+// through the subdomain is refused rather than guessing which was meant.
+fn requested_tenant(
+    parts: &Parts,
+    tenant_domain: Option<&TenantDomain>,
+) -> Result<Option<String>, ApiError> {
+    let from_header = match parts.headers.get(TENANT_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map(str::trim)
+                .ok()
+                .filter(|tenant| !tenant.is_empty())
+                .ok_or_else(|| ApiError::BadRequest(format!("invalid {} header", TENANT_HEADER)))?
+                .to_string(),
+        ),
+        None => None,
+    };
+
+    let from_subdomain = tenant_domain.and_then(|tenant_domain| {
+        // HTTP/2 clients send the host as the URI authority rather than a Host header
+        let host = match parts.headers.get(HOST) {
+            Some(host) => host.to_str().ok()?,
+            None => parts.uri.authority()?.as_str(),
+        };
+        tenant_domain.tenant_of(host)
+    });
+
+    match (from_header, from_subdomain) {
+        (Some(header), Some(subdomain)) if header != subdomain => {
+            Err(ApiError::BadRequest(format!(
+                "the {} header does not match the tenant subdomain",
+                TENANT_HEADER
+            )))
+        }
+        (header, subdomain) => Ok(header.or(subdomain)),
+    }
+}
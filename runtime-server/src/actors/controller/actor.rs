@@ -11,7 +11,9 @@ use crate::actors::{
 use ractor::Actor;
 use ractor::{ActorProcessingErr, ActorRef};
 // use ractor_supervisor::*;
+use crate::DATABASE_NAME;
 use config_server::{ApiConfiguration, CorsConfiguration, RateLimitingConfiguration};
+use database_server::{ensure_database_schema, get_db_connection_pool, SqlitePool};
 use runtime_shared::{initialise_logging, RuntimeProperties};
 use tracing::{error, event, info, instrument, warn};
 
@@ -54,6 +56,22 @@ impl Actor for Controller {
 
         state.tracing_worker_guards = tracing_worker_guards;
 
+        // Ensure our database is up to date and get access to its pool
+        let db_folder = RuntimeProperties::global().folders().supplementary_files();
+        if let Err(error) = ensure_database_schema(
+            db_folder.join(DATABASE_NAME).to_string_lossy().to_string(),
+        ) {
+            panic!("Database {} could not be migrated - {}", DATABASE_NAME, error);
+        }
+
+        match get_db_connection_pool(db_folder, DATABASE_NAME) {
+            Ok(db_pool) => state.db_pool = Some(db_pool),
+            Err(error) => panic!(
+                "Database {} does not exist or could not be created - {}",
+                DATABASE_NAME, error
+            ),
+        }
+
         Ok(state)
     }
 
@@ -77,6 +95,7 @@ impl Actor for Controller {
             state.api_configuration.clone(),
             state.cors_configuration.clone(),
             state.rate_limiter_config.clone(),
+            state.db_pool.clone().unwrap(),
         )
         .await;
 
@@ -129,11 +148,12 @@ impl Actor for Controller {
                 let api_cfg = state.api_configuration.clone();
                 let cors_cfg = state.cors_configuration.clone();
                 let rate_cfg = state.rate_limiter_config.clone();
+                let db_pool = state.db_pool.clone().unwrap();
 
                 // Fire-and-forget restart task; capture join result and log later
                 if name == ACTOR_API_SERVER_NAME {
                     tokio::spawn(async move {
-                        let restarted = start_api_server(ctrl, api_cfg, cors_cfg, rate_cfg).await;
+                        let restarted = start_api_server(ctrl, api_cfg, cors_cfg, rate_cfg, db_pool).await;
                         match restarted {
                             Some(_) => info!(actor = %name, "actor restart succeeded"),
                             None => error!(actor = %name, "actor restart failed"),
@@ -159,6 +179,7 @@ async fn start_api_server(
     api_config: ApiConfiguration,
     cors_config: CorsConfiguration,
     rate_limiter_config: RateLimitingConfiguration,
+    db_pool: SqlitePool,
 ) -> Option<ActorRef<ApiMessage>> {
     // Start the API Server as a linked actor i.e. Controller is the supervisor
     match controller
@@ -169,6 +190,7 @@ async fn start_api_server(
                 api_config: api_config.clone(),
                 cors: cors_config.clone(),
                 rate_limiting: rate_limiter_config.clone(),
+                db_pool,
             },
         )
         .await
@@ -1,5 +1,6 @@
 use crate::actors::api::ApiMessage;
 use config_server::{ApiConfiguration, CorsConfiguration, RateLimitingConfiguration};
+use database_server::SqlitePool;
 use ractor::ActorRef;
 use tracing_appender::non_blocking::WorkerGuard;
 
@@ -15,6 +16,7 @@ pub struct ControllerState {
     pub cors_configuration: CorsConfiguration,
     pub rate_limiter_config: RateLimitingConfiguration,
     pub spawned_actors: Actors,
+    pub db_pool: Option<SqlitePool>,
 }
 
 impl ControllerState {
@@ -29,6 +31,7 @@ impl ControllerState {
             cors_configuration,
             rate_limiter_config,
             spawned_actors: Actors { api_server: None },
+            db_pool: None,
         }
     }
 }
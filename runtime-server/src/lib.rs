@@ -1,5 +1,8 @@
 pub mod actors;
 
+// Global Constants
+pub const DATABASE_NAME: &str = "server.db";
+
 // Public re-exports
 pub use crate::actors::controller::actor::Controller as RuntimeController;
 pub use crate::actors::controller::arguments::ControllerArguments as RuntimeControllerArguments;
@@ -0,0 +1,82 @@
+use axum::Extension;
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::RustlsAcceptor;
+use futures_util::future::BoxFuture;
+use rustls::pki_types::CertificateDer;
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tower::Layer;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// The verified certificate a client presented during the TLS handshake.
+/// Handlers receive it as `Extension<Option<ClientCertificate>>`, which is `None`
+/// when the client connected without one.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    pub common_name: Option<String>,
+    pub serial: String,
+    // Seconds since the unix epoch
+    pub not_after: i64,
+    pub der: CertificateDer<'static>,
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &CertificateDer<'_>) -> Option<Self> {
+        let (_, certificate) = X509Certificate::from_der(der.as_ref()).ok()?;
+
+        let common_name = certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(|cn| cn.to_string());
+
+        Some(Self {
+            common_name,
+            serial: certificate.tbs_certificate.raw_serial_as_string(),
+            not_after: certificate.validity().not_after.timestamp(),
+            der: der.clone().into_owned(),
+        })
+    }
+}
+
+/// Completes the TLS handshake then attaches the client certificate (if any) to every request on the connection
+#[derive(Debug, Clone)]
+pub struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub fn new(inner: RustlsAcceptor) -> Self {
+        Self { inner }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = <Extension<Option<ClientCertificate>> as Layer<S>>::Service;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+
+            // The verifier has already checked the chain, we only need the leaf
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certificates| certificates.first())
+                .and_then(ClientCertificate::from_der);
+
+            Ok((stream, Extension(certificate).layer(service)))
+        })
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Stops every listener an `APIServer` started. TCP listeners are driven by an
+/// `axum_server::Handle`, unix socket listeners by axum's own server, so this fans out to both.
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    tcp: axum_server::Handle,
+    graceful_tx: Arc<watch::Sender<bool>>,
+    unix_servers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new() -> Self {
+        let (graceful_tx, _) = watch::channel(false);
+
+        Self {
+            tcp: axum_server::Handle::new(),
+            graceful_tx: Arc::new(graceful_tx),
+            unix_servers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Stop accepting connections and drop the open ones straight away
+    pub fn shutdown(&self) {
+        self.tcp.shutdown();
+        for server in self.unix_servers.lock().unwrap().drain(..) {
+            server.abort();
+        }
+    }
+
+    /// Stop accepting connections and let the open ones finish, for at most `duration` on TCP listeners
+    pub fn graceful_shutdown(&self, duration: Option<Duration>) {
+        self.tcp.graceful_shutdown(duration);
+        let _ = self.graceful_tx.send(true);
+    }
+
+    pub(crate) fn tcp(&self) -> axum_server::Handle {
+        self.tcp.clone()
+    }
+
+    // Resolves once a graceful shutdown has been requested
+    pub(crate) fn graceful_shutdown_requested(&self) -> impl std::future::Future<Output = ()> {
+        let mut graceful_rx = self.graceful_tx.subscribe();
+        async move {
+            let _ = graceful_rx.wait_for(|requested| *requested).await;
+        }
+    }
+
+    pub(crate) fn track_unix_server(&self, server: JoinHandle<()>) {
+        self.unix_servers.lock().unwrap().push(server);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::api_server::tls::TlsFiles;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+// Pending connections the kernel queues for us before accept
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Where an `APIServer` listens
+#[derive(Debug, Clone)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    /// A unix domain socket at `path`, created with the file permissions in `mode` e.g. `0o660`
+    #[cfg(unix)]
+    Unix {
+        path: PathBuf,
+        mode: u32,
+    },
+}
+
+impl From<SocketAddr> for ListenAddress {
+    fn from(address: SocketAddr) -> Self {
+        ListenAddress::Tcp(address)
+    }
+}
+
+/// One address an `APIServer` listens on, along with its own TLS settings.
+/// Listeners are plaintext unless given a certificate.
+#[derive(Debug, Clone)]
+pub struct Listener {
+    pub(crate) address: ListenAddress,
+    pub(crate) tls_files: Option<TlsFiles>,
+    pub(crate) ipv6_only: bool,
+}
+
+impl Listener {
+    pub fn new(address: impl Into<ListenAddress>) -> Self {
+        Self {
+            address: address.into(),
+            tls_files: None,
+            ipv6_only: false,
+        }
+    }
+
+    /// Serve TLS with this certificate chain and private key
+    pub fn with_certs(
+        mut self,
+        certificate_pem_file: PathBuf,
+        private_key_pem_file: PathBuf,
+    ) -> Self {
+        let tls_files = self.tls_files.get_or_insert_with(TlsFiles::default);
+        tls_files.certificate_pem_file = Some(certificate_pem_file);
+        tls_files.private_key_pem_file = Some(private_key_pem_file);
+        self
+    }
+
+    /// Ask clients for a certificate and verify any they present against the CA bundle
+    pub fn with_client_ca(mut self, ca_pem_file: PathBuf) -> Self {
+        self.tls_files
+            .get_or_insert_with(TlsFiles::default)
+            .client_ca_pem_file = Some(ca_pem_file);
+        self
+    }
+
+    /// Reject client certificates listed in a PEM CRL
+    pub fn with_crl(mut self, crl_pem_file: PathBuf) -> Self {
+        self.tls_files
+            .get_or_insert_with(TlsFiles::default)
+            .crl_pem_file = Some(crl_pem_file);
+        self
+    }
+
+    /// An IPv6 wildcard address accepts IPv4 connections too (dual-stack) unless this is set,
+    /// which is needed to run it next to an IPv4 listener on the same port
+    pub fn ipv6_only(mut self, ipv6_only: bool) -> Self {
+        self.ipv6_only = ipv6_only;
+        self
+    }
+}
+
+// Bind ourselves rather than leave it to the server so dual-stack behaviour does not
+// depend on the host's `bindv6only` setting
+pub(crate) fn bind_tcp(address: SocketAddr, ipv6_only: bool) -> io::Result<std::net::TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+
+    if address.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    Ok(socket.into())
+}
@@ -1,70 +1,206 @@
+pub mod client_cert;
 pub mod error;
+pub mod handle;
+pub mod listener;
+pub mod tls;
+#[cfg(unix)]
+pub mod unix;
 
 use axum::Router;
-use axum_server::tls_rustls::RustlsConfig;
-use axum_server::Handle;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use client_cert::ClientCertAcceptor;
 use error::ApiServerError;
+use handle::ServerHandle;
+pub use listener::{ListenAddress, Listener};
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use tracing::error;
+use std::sync::Arc;
+use tls::{TlsFiles, TlsReloader};
+use tracing::{error, info};
+
+// A listener along with the TLS configuration built from its files, if it serves TLS
+struct ConfiguredListener {
+    listener: Listener,
+    rust_ls_config: Option<RustlsConfig>,
+}
+
+impl ConfiguredListener {
+    fn new(listener: Listener) -> Result<Self, ApiServerError> {
+        let rust_ls_config = match &listener.tls_files {
+            Some(tls_files) => {
+                // Access to a unix socket is controlled by the file permissions, not TLS
+                #[cfg(unix)]
+                if let ListenAddress::Unix { .. } = listener.address {
+                    return Err(ApiServerError::CertError(
+                        "TLS is not supported on unix sockets".to_string(),
+                    ));
+                }
+
+                Some(RustlsConfig::from_config(Arc::new(tls_files.build()?)))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            listener,
+            rust_ls_config,
+        })
+    }
+}
 
 pub struct APIServer {
-    address: SocketAddr,
     router: Router,
-    rust_ls_config: Option<RustlsConfig>,
+    listeners: Vec<ConfiguredListener>,
 }
 
 impl APIServer {
-    pub fn new(address: SocketAddr, router: Router) -> Self {
+    pub fn new(address: impl Into<ListenAddress>, router: Router) -> Self {
         Self {
-            address,
             router,
-            rust_ls_config: None,
+            listeners: vec![ConfiguredListener {
+                listener: Listener::new(address),
+                rust_ls_config: None,
+            }],
+        }
+    }
+
+    /// A server listening on each of `listeners`, all serving the same router
+    pub fn from_listeners(
+        listeners: Vec<Listener>,
+        router: Router,
+    ) -> Result<Self, ApiServerError> {
+        if listeners.is_empty() {
+            return Err(ApiServerError::ServerError(
+                "no listeners configured".to_string(),
+            ));
         }
+
+        Ok(Self {
+            router,
+            listeners: listeners
+                .into_iter()
+                .map(ConfiguredListener::new)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Listen on another address as well, with its own TLS settings.
+    /// Every listener serves the same router and stops with the same `ServerHandle`.
+    pub fn add_listener(mut self, listener: Listener) -> Result<Self, ApiServerError> {
+        self.listeners.push(ConfiguredListener::new(listener)?);
+        Ok(self)
     }
 
+    /// Serve TLS on the listener given to `new`
     pub async fn add_certs(
-        mut self,
+        self,
         certificate_pem_file: PathBuf,
         private_key_pem_file: PathBuf,
     ) -> Result<Self, ApiServerError> {
-        match RustlsConfig::from_pem_file(certificate_pem_file, private_key_pem_file).await {
-            Ok(config) => {
-                self.rust_ls_config = Some(config);
-                Ok(self)
+        self.update_first_listener(|listener| {
+            listener.with_certs(certificate_pem_file, private_key_pem_file)
+        })
+    }
+
+    /// Ask clients for a certificate and verify any they present against the CA bundle.
+    /// Clients without a certificate can still connect, it is up to each handler to
+    /// insist on one via the `ClientCertificate` request extension.
+    pub async fn add_client_ca(self, ca_pem_file: PathBuf) -> Result<Self, ApiServerError> {
+        if !self.first_listener_has(|files| files.certificate_pem_file.is_some()) {
+            return Err(ApiServerError::CertError(
+                "client certificates require a server certificate".to_string(),
+            ));
+        }
+
+        self.update_first_listener(|listener| listener.with_client_ca(ca_pem_file))
+    }
+
+    /// Reject client certificates listed in a PEM CRL. The file is re-read whenever
+    /// the configuration is reloaded, see `tls_reloader`.
+    pub async fn add_crl(self, crl_pem_file: PathBuf) -> Result<Self, ApiServerError> {
+        if !self.first_listener_has(|files| files.client_ca_pem_file.is_some()) {
+            return Err(ApiServerError::CertError(
+                "a CRL requires a client CA".to_string(),
+            ));
+        }
+
+        self.update_first_listener(|listener| listener.with_crl(crl_pem_file))
+    }
+
+    /// A handle to rebuild the TLS configuration of every TLS listener once the server is running
+    pub fn tls_reloader(&self) -> Option<TlsReloader> {
+        let configs: Vec<(RustlsConfig, TlsFiles)> = self
+            .listeners
+            .iter()
+            .filter_map(|configured| {
+                Some((
+                    configured.rust_ls_config.clone()?,
+                    configured.listener.tls_files.clone()?,
+                ))
+            })
+            .collect();
+
+        (!configs.is_empty()).then(|| TlsReloader::new(configs))
+    }
+
+    pub async fn start(self) -> Result<ServerHandle, ApiServerError> {
+        // Create the shutdown handle, shared by every listener
+        let server_shutdown_handle = ServerHandle::new();
+
+        for configured in self.listeners {
+            let started = match configured.listener.address {
+                ListenAddress::Tcp(address) => Self::start_tcp(
+                    address,
+                    configured.listener.ipv6_only,
+                    configured.rust_ls_config,
+                    self.router.clone(),
+                    &server_shutdown_handle,
+                ),
+                #[cfg(unix)]
+                ListenAddress::Unix { path, mode } => {
+                    unix::serve_unix(&path, mode, self.router.clone(), &server_shutdown_handle)
+                        .await
+                }
+            };
+
+            // Don't leave the listeners we already started running on their own
+            if let Err(error) = started {
+                server_shutdown_handle.shutdown();
+                return Err(error);
             }
-            Err(error) => Err(ApiServerError::CertError(error.to_string())),
         }
+
+        Ok(server_shutdown_handle)
     }
 
-    pub async fn start(self) -> Result<Handle, ApiServerError> {
-        // Create the shutdown handle
-        let server_shutdown_handle = Handle::new();
+    fn start_tcp(
+        address: SocketAddr,
+        ipv6_only: bool,
+        rust_ls_config: Option<RustlsConfig>,
+        router: Router,
+        server_shutdown_handle: &ServerHandle,
+    ) -> Result<(), ApiServerError> {
+        let listener = match listener::bind_tcp(address, ipv6_only) {
+            Ok(listener) => listener,
+            Err(error) => {
+                error!(errorMsg=%error, %address, "APIServer listener failed to start");
+                return Err(ApiServerError::ServerError(format!(
+                    "{} - {}",
+                    address, error
+                )));
+            }
+        };
+
+        info!(%address, tls = rust_ls_config.is_some(), "APIServer listening");
+
+        let server = axum_server::from_tcp(listener).handle(server_shutdown_handle.tcp());
+        let service = router.into_make_service_with_connect_info::<SocketAddr>();
 
-        match self.rust_ls_config {
+        match rust_ls_config {
             Some(cert_config) => {
-                let listener = match tokio::net::TcpListener::bind(self.address.to_string()).await {
-                    Ok(listener) => listener.into_std().unwrap(),
-                    Err(error) => {
-                        error!(errorMsg=%error,"APIServer listener failed to start");
-                        return Err(ApiServerError::ServerError(error.to_string()));
-                    }
-                };
-
-                // let listener = match tokio::net::TcpListener::bind(self.address.to_string()).await {
-                //     Ok(listener) => listener.into_std().unwrap(),
-                //     Err(error) => {
-                //         error!(errorMsg=%error,"APIServer listener failed to start");
-                //         panic!("{}", error)
-                //     }
-                // };
-
-                let server = axum_server::from_tcp_rustls(listener, cert_config)
-                    .handle(server_shutdown_handle.clone())
-                    .serve(
-                        self.router
-                            .into_make_service_with_connect_info::<SocketAddr>(),
-                    );
+                // Exposes the client certificate (if any) to the handlers
+                let acceptor = ClientCertAcceptor::new(RustlsAcceptor::new(cert_config));
+                let server = server.acceptor(acceptor).serve(service);
 
                 tokio::spawn(async move {
                     if let Err(error) = server.await {
@@ -73,46 +209,8 @@ impl APIServer {
                 });
             }
             None => {
-                // let listener = match tokio::net::TcpListener::bind(self.address.to_string()).await {
-                //     Ok(listener) => listener.into_std().unwrap(),
-                //     Err(error) => {
-                //         error!(errorMsg=%error,"APIServer listener failed to start");
-                //         panic!("{}", error)
-                //     }
-                // };
-
-                let server = axum_server::bind(self.address)
-                    .handle(server_shutdown_handle.clone())
-                    .serve(
-                        self.router
-                            .into_make_service_with_connect_info::<SocketAddr>(),
-                    );
-
-                // tokio::spawn(async move {
-                //     if let Err(error) = axum_server::bind(self.address)
-                //         .handle(server_handle_clone)
-                //         .serve(
-                //             self.router
-                //                 .into_make_service_with_connect_info::<SocketAddr>(),
-                //         )
-                //         .await
-                //     {
-                //         error!(errorMsg=%error,"APIServer failed to start");
-                //     }
-                // });
-
-                // tokio::spawn(async move {
-                //     if let Err(error) = axum_server::bind(self.address)
-                //         .handle(server_handle_clone)
-                //         .serve(
-                //             self.router
-                //                 .into_make_service_with_connect_info::<SocketAddr>(),
-                //         )
-                //         .await
-                //     {
-                //         error!(errorMsg=%error,"APIServer failed to start");
-                //     }
-                // });
+                let server = server.serve(service);
+
                 tokio::spawn(async move {
                     if let Err(error) = server.await {
                         error!(errorMsg=%error,"APIServer failed to start");
@@ -121,30 +219,24 @@ impl APIServer {
             }
         }
 
-        // if let Err(error) = axum_server::bind(self.address)
-        //     .handle(server_handle_clone)
-        //     .serve(
-        //         self.router
-        //             .into_make_service_with_connect_info::<SocketAddr>(),
-        //     )
-        //     .await
-        // {
-        //     return Err(ApiServerError::ServerError(error.to_string()));
-        // }
-
-        // tokio::spawn(async move {
-        //     if let Err(error) = axum_server::bind(self.address)
-        //         .handle(server_handle_clone)
-        //         .serve(
-        //             self.router
-        //                 .into_make_service_with_connect_info::<SocketAddr>(),
-        //         )
-        //         .await
-        //     {
-        //         error!(errorMsg=%error, "Unable to startAPI Server");
-        //     }
-        // });
+        Ok(())
+    }
 
-        Ok(server_shutdown_handle)
+    fn first_listener_has(&self, check: impl Fn(&TlsFiles) -> bool) -> bool {
+        self.listeners[0]
+            .listener
+            .tls_files
+            .as_ref()
+            .is_some_and(check)
+    }
+
+    fn update_first_listener(
+        mut self,
+        update: impl FnOnce(Listener) -> Listener,
+    ) -> Result<Self, ApiServerError> {
+        let first = self.listeners.remove(0);
+        self.listeners
+            .insert(0, ConfiguredListener::new(update(first.listener))?);
+        Ok(self)
     }
 }
@@ -0,0 +1,155 @@
+use crate::api_server::error::ApiServerError;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::pki_types::{
+    pem::PemObject, CertificateDer, CertificateRevocationListDer, PrivateKeyDer,
+};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::info;
+
+/// The PEM files the server TLS configuration is built from
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TlsFiles {
+    pub certificate_pem_file: Option<PathBuf>,
+    pub private_key_pem_file: Option<PathBuf>,
+    pub client_ca_pem_file: Option<PathBuf>,
+    pub crl_pem_file: Option<PathBuf>,
+}
+
+impl TlsFiles {
+    pub fn build(&self) -> Result<ServerConfig, ApiServerError> {
+        let (Some(certificate_pem_file), Some(private_key_pem_file)) =
+            (&self.certificate_pem_file, &self.private_key_pem_file)
+        else {
+            return Err(ApiServerError::CertError(
+                "no server certificate configured".to_string(),
+            ));
+        };
+
+        let certificates = CertificateDer::pem_file_iter(certificate_pem_file)
+            .map_err(cert_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(cert_error)?;
+        let private_key = PrivateKeyDer::from_pem_file(private_key_pem_file).map_err(cert_error)?;
+
+        if self.crl_pem_file.is_some() && self.client_ca_pem_file.is_none() {
+            return Err(ApiServerError::CertError(
+                "a CRL requires a client CA".to_string(),
+            ));
+        }
+
+        let builder = ServerConfig::builder();
+        let builder = match &self.client_ca_pem_file {
+            Some(ca_pem_file) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in CertificateDer::pem_file_iter(ca_pem_file).map_err(cert_error)? {
+                    roots
+                        .add(certificate.map_err(cert_error)?)
+                        .map_err(cert_error)?;
+                }
+
+                let mut verifier =
+                    WebPkiClientVerifier::builder(Arc::new(roots)).allow_unauthenticated();
+
+                // The CRL only covers certificates our own CA issued, so anything else is let through
+                if let Some(crl_pem_file) = self.crl_pem_file.as_ref().filter(|file| file.exists())
+                {
+                    let crls = CertificateRevocationListDer::pem_file_iter(crl_pem_file)
+                        .map_err(cert_error)?
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(cert_error)?;
+                    verifier = verifier
+                        .with_crls(crls)
+                        .only_check_end_entity_revocation()
+                        .allow_unknown_revocation_status();
+                }
+
+                builder.with_client_cert_verifier(verifier.build().map_err(cert_error)?)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder
+            .with_single_cert(certificates, private_key)
+            .map_err(cert_error)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+}
+
+/// Rebuilds the TLS configuration of a running server's listeners from their PEM files,
+/// e.g. to pick up a renewed certificate or a new CRL. Connections already established are unaffected.
+#[derive(Debug, Clone)]
+pub struct TlsReloader {
+    configs: Vec<(RustlsConfig, TlsFiles)>,
+    // When each file was last modified as of the last reload, `None` if it did not exist
+    modified: Arc<Mutex<Vec<Option<SystemTime>>>>,
+}
+
+impl TlsReloader {
+    pub(crate) fn new(configs: Vec<(RustlsConfig, TlsFiles)>) -> Self {
+        let reloader = Self {
+            configs,
+            modified: Arc::new(Mutex::new(vec![])),
+        };
+        *reloader.modified.lock().unwrap() = reloader.modified_times();
+
+        reloader
+    }
+
+    pub fn reload(&self) -> Result<(), ApiServerError> {
+        *self.modified.lock().unwrap() = self.modified_times();
+
+        // Build everything first so a bad file leaves every listener on its old configuration
+        let mut rebuilt = vec![];
+        for (_, files) in &self.configs {
+            rebuilt.push(Arc::new(files.build()?));
+        }
+
+        for ((rust_ls_config, _), config) in self.configs.iter().zip(rebuilt) {
+            rust_ls_config.reload_from_config(config);
+        }
+        info!("APIServer TLS configuration reloaded");
+
+        Ok(())
+    }
+
+    /// Reload if any of the PEM files changed since the last reload, returning whether it did.
+    /// Call periodically to pick up certificates replaced on disk e.g. by certbot.
+    pub fn reload_if_changed(&self) -> Result<bool, ApiServerError> {
+        if *self.modified.lock().unwrap() == self.modified_times() {
+            return Ok(false);
+        }
+
+        self.reload()?;
+        Ok(true)
+    }
+
+    // Follows symlinks, so swapping the target of a linked certificate counts as a change
+    fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        self.configs
+            .iter()
+            .flat_map(|(_, files)| {
+                [
+                    &files.certificate_pem_file,
+                    &files.private_key_pem_file,
+                    &files.client_ca_pem_file,
+                    &files.crl_pem_file,
+                ]
+            })
+            .map(|file| {
+                file.as_ref()
+                    .and_then(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+            })
+            .collect()
+    }
+}
+
+fn cert_error(error: impl std::fmt::Display) -> ApiServerError {
+    ApiServerError::CertError(error.to_string())
+}
@@ -0,0 +1,93 @@
+use crate::api_server::{error::ApiServerError, handle::ServerHandle};
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use axum::Router;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::net::UnixListener;
+use tracing::{error, info};
+
+/// The process on the other end of a unix socket connection, as reported by `SO_PEERCRED`.
+/// Handlers served over a unix socket receive it as `ConnectInfo<UnixPeer>`.
+#[derive(Debug, Clone, Copy)]
+pub struct UnixPeer {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+impl Connected<IncomingStream<'_, UnixListener>> for UnixPeer {
+    fn connect_info(stream: IncomingStream<'_, UnixListener>) -> Self {
+        // The kernel always knows the peer of a connected unix socket, fall back to
+        // an id no one can be granted access as if it somehow does not
+        match stream.io().peer_cred() {
+            Ok(credentials) => Self {
+                uid: credentials.uid(),
+                gid: credentials.gid(),
+                pid: credentials.pid(),
+            },
+            Err(_) => Self {
+                uid: u32::MAX,
+                gid: u32::MAX,
+                pid: None,
+            },
+        }
+    }
+}
+
+/// Listen on a unix socket at `path`, replacing any socket left behind by a previous run,
+/// and restrict who may connect to it through the file `mode`
+pub(crate) async fn serve_unix(
+    path: &Path,
+    mode: u32,
+    router: Router,
+    handle: &ServerHandle,
+) -> Result<(), ApiServerError> {
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder).map_err(server_error)?;
+    }
+
+    // Only ever remove a socket, never a file someone has misconfigured us to point at
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        use std::os::unix::fs::FileTypeExt;
+
+        if !metadata.file_type().is_socket() {
+            return Err(ApiServerError::ServerError(format!(
+                "{} exists and is not a socket",
+                path.display()
+            )));
+        }
+        fs::remove_file(path).map_err(server_error)?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(|error| {
+        error!(errorMsg=%error, "APIServer listener failed to start");
+        server_error(error)
+    })?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(server_error)?;
+
+    info!(socket = %path.display(), mode = format!("{:o}", mode), "APIServer listening on unix socket");
+
+    let shutdown = handle.graceful_shutdown_requested();
+    let socket_path = path.to_path_buf();
+    handle.track_unix_server(tokio::spawn(async move {
+        if let Err(error) = axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<UnixPeer>(),
+        )
+        .with_graceful_shutdown(shutdown)
+        .await
+        {
+            error!(errorMsg=%error,"APIServer failed to start");
+        }
+
+        let _ = fs::remove_file(socket_path);
+    }));
+
+    Ok(())
+}
+
+fn server_error(error: impl std::fmt::Display) -> ApiServerError {
+    ApiServerError::ServerError(error.to_string())
+}
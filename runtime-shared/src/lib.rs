@@ -1,6 +1,7 @@
 pub mod api_server;
 pub mod logging;
 pub mod properties;
+pub mod protocol;
 
 // Public re-exports
 pub use crate::properties::RuntimeProperties;
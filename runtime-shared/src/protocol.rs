@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+
+/// Messages sent from an agent to the server over the agent websocket
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Inbound {
+    // Register {
+    //     id: String,
+    //     token: String,
+    //     groups: Vec<String>,
+    // },
+    Pong {
+        nonce: String,
+    },
+    Ack {
+        command_id: String,
+    },
+    Disconnect {
+        reason: Option<String>,
+    },
+    /// Ask the server for a fresh token before the current one expires
+    #[serde(rename = "refresh_token")]
+    RefreshToken,
+    /// Ask the server's CA to sign a PEM certificate signing request for this agent
+    Enroll {
+        csr: String,
+    },
+    /// The outcome of a command, an error when the agent refused or failed to run it and
+    /// whatever the command produced e.g. a script's output and exit code
+    Result {
+        command_id: String,
+        error: Option<CommandError>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        output: Option<serde_json::Value>,
+    },
+    /// A chunk of what a running command has written so far, sent as it is produced
+    Output {
+        command_id: String,
+        stream: OutputStream,
+        data: String,
+    },
+}
+
+/// Which of a command's output streams a chunk came from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Why an agent did not run a command
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CommandError {
+    pub code: CommandErrorCode,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandErrorCode {
+    /// The agent's policy disables the verb
+    VerbDisabled,
+    /// The verb is not on the agent's allow list
+    VerbNotAllowed,
+    /// The verb is allowed but this agent has no handler for it
+    UnknownVerb,
+    /// A path the command names is outside those the policy permits
+    PathDenied,
+    /// The payload is missing something the policy needs to check
+    InvalidPayload,
+    /// The agent's policy could not be read, so nothing is allowed
+    PolicyInvalid,
+    /// Someone at the agent refused the command
+    Rejected,
+    /// Nobody at the agent confirmed the command in time
+    ConfirmationTimedOut,
+    /// The script's hash is not in the agent's function hashes
+    HashNotAllowed,
+    /// The command was allowed but could not be run
+    ExecutionFailed,
+    /// The command ran for longer than it was allowed to and was stopped
+    TimedOut,
+    /// The server cancelled the command before it finished
+    Cancelled,
+}
+
+/// Messages sent from the server to an agent over the agent websocket
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Outbound {
+    Ping {
+        nonce: String,
+    },
+    /// Signed by the server so the agent can tell it wasn't injected along the way,
+    /// see [`command_signing_bytes`]
+    Command {
+        command_id: String,
+        verb: String,
+        payload: serde_json::Value,
+        // Seconds since the unix epoch, commands older than the agent allows are refused
+        issued_at: u64,
+        // Unique per command, a command seen before is a replay
+        nonce: String,
+        // Seconds since the unix epoch by which the command must have finished, it is stopped
+        // when it runs past it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        deadline: Option<u64>,
+        // Ed25519 signature, base64url without padding
+        signature: String,
+    },
+    /// Stop a command that has not finished yet, killing whatever it started. Signed like
+    /// `Command`, see [`cancel_signing_bytes`]
+    Cancel {
+        command_id: String,
+        issued_at: u64,
+        nonce: String,
+        signature: String,
+    },
+    Disconnect {
+        reason: Option<String>,
+    },
+    /// A refreshed agent token and its expiry (seconds since the unix epoch)
+    Token {
+        token: String,
+        expires_at: u64,
+    },
+    /// A client certificate issued in reply to `Enroll`, along with the CA that signed it
+    Certificate {
+        certificate: String,
+        ca: String,
+        expires_at: u64,
+    },
+    /// An enrollment request could not be completed
+    #[serde(rename = "enroll_failed")]
+    EnrollFailed {
+        reason: String,
+    },
+}
+
+// Binds a signature to agent commands, so it can't be passed off as one over something else
+const COMMAND_SIGNATURE_CONTEXT: &str = "agent-command-v1";
+
+// Commands with a deadline are signed under their own context, so an agent that doesn't know
+// about deadlines refuses them rather than running them without one
+const COMMAND_DEADLINE_SIGNATURE_CONTEXT: &str = "agent-command-v2";
+
+// Cancels are signed under their own context, so neither can be passed off as the other
+const CANCEL_SIGNATURE_CONTEXT: &str = "agent-cancel-v1";
+
+/// The agent a signed message is meant for. It is signed along with the message, so one
+/// captured on its way to an agent is refused by every other agent pinning the same key.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandRecipient<'a> {
+    pub tenant: &'a str,
+    pub agent_id: &'a str,
+}
+
+/// The bytes a command signature covers. Every field is JSON encoded into an array so that
+/// none can bleed into the next, whatever it contains.
+pub fn command_signing_bytes(
+    recipient: CommandRecipient,
+    command_id: &str,
+    verb: &str,
+    payload: &serde_json::Value,
+    issued_at: u64,
+    nonce: &str,
+    deadline: Option<u64>,
+) -> Vec<u8> {
+    match deadline {
+        None => serde_json::to_vec(&(
+            COMMAND_SIGNATURE_CONTEXT,
+            recipient.tenant,
+            recipient.agent_id,
+            command_id,
+            verb,
+            payload,
+            issued_at,
+            nonce,
+        )),
+        Some(deadline) => serde_json::to_vec(&(
+            COMMAND_DEADLINE_SIGNATURE_CONTEXT,
+            recipient.tenant,
+            recipient.agent_id,
+            command_id,
+            verb,
+            payload,
+            issued_at,
+            nonce,
+            deadline,
+        )),
+    }
+    .unwrap_or_default()
+}
+
+/// The bytes a cancel signature covers, encoded like [`command_signing_bytes`]
+pub fn cancel_signing_bytes(
+    recipient: CommandRecipient,
+    command_id: &str,
+    issued_at: u64,
+    nonce: &str,
+) -> Vec<u8> {
+    serde_json::to_vec(&(
+        CANCEL_SIGNATURE_CONTEXT,
+        recipient.tenant,
+        recipient.agent_id,
+        command_id,
+        issued_at,
+        nonce,
+    ))
+    .unwrap_or_default()
+}
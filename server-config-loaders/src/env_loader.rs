@@ -1,9 +1,13 @@
 use config_server::{
     ApiConfiguration, CorsConfiguration, CorsMode, LoadApiConfiguration, LoadCorsConfiguration,
-    LoadLoggingConfiguration, LoadRateLimitingConfiguration, LoggingConfiguration,
-    RateLimitingConfiguration,
+    LoadLoggingConfiguration, LoadRateLimitingConfiguration, LoggingConfiguration, MtlsMode,
+    RateLimitKey, RateLimitingConfiguration,
 };
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DOCKER_SECRETS_FOLDER: &str = "/run/secrets";
 
 pub struct EnvServerConfigLoader;
 
@@ -61,11 +65,59 @@ impl LoadApiConfiguration for EnvServerConfigLoader {
             .unwrap_or(api_configuration.port.to_string())
             .parse()
             .unwrap_or(api_configuration.port);
+        if let Ok(addresses) = env::var("API_BIND_ADDRESSES") {
+            api_configuration.bind_addresses = split_list(&addresses);
+        }
+
+        if let Ok(addresses) = env::var("API_PLAINTEXT_BIND_ADDRESSES") {
+            api_configuration.plaintext_bind_addresses = split_list(&addresses);
+        }
+
+        api_configuration.allow_insecure_plaintext = env::var("API_ALLOW_INSECURE_PLAINTEXT")
+            .unwrap_or(api_configuration.allow_insecure_plaintext.to_string())
+            .parse()
+            .unwrap_or(api_configuration.allow_insecure_plaintext);
+
+        api_configuration.tls_self_signed = env::var("API_TLS_SELF_SIGNED")
+            .unwrap_or(api_configuration.tls_self_signed.to_string())
+            .parse()
+            .unwrap_or(api_configuration.tls_self_signed);
+
+        api_configuration.tls_reload_interval_secs = env::var("API_TLS_RELOAD_INTERVAL_SECS")
+            .unwrap_or(api_configuration.tls_reload_interval_secs.to_string())
+            .parse()
+            .unwrap_or(api_configuration.tls_reload_interval_secs);
+
         api_configuration.behind_proxy = env::var("API_BEHIND_PROXY")
             .unwrap_or(api_configuration.behind_proxy.to_string())
             .parse()
             .unwrap_or(api_configuration.behind_proxy);
 
+        if let Ok(proxies) = env::var("API_TRUSTED_PROXIES") {
+            api_configuration.trusted_proxies = split_list(&proxies);
+        }
+
+        if let Ok(networks) = env::var("API_AGENT_ALLOWED_IPS") {
+            api_configuration.agent_allowed_ips = split_list(&networks);
+        }
+
+        if let Ok(networks) = env::var("API_AGENT_DENIED_IPS") {
+            api_configuration.agent_denied_ips = split_list(&networks);
+        }
+
+        if let Ok(networks) = env::var("API_ADMIN_ALLOWED_IPS") {
+            api_configuration.admin_allowed_ips = split_list(&networks);
+        }
+
+        if let Ok(networks) = env::var("API_ADMIN_DENIED_IPS") {
+            api_configuration.admin_denied_ips = split_list(&networks);
+        }
+
+        api_configuration.production_mode = env::var("API_PRODUCTION_MODE")
+            .unwrap_or(api_configuration.production_mode.to_string())
+            .parse()
+            .unwrap_or(api_configuration.production_mode);
+
         api_configuration.request_timeout_secs = env::var("API_REQUEST_TIMEOUT_SECS")
             .unwrap_or(api_configuration.request_timeout_secs.to_string())
             .parse()
@@ -81,15 +133,127 @@ impl LoadApiConfiguration for EnvServerConfigLoader {
             .parse()
             .unwrap_or(api_configuration.agent_ping_timeout);
 
-        api_configuration.agent_jwt_secret = env::var("API_AGENT_JWT_SECRET")
-            .unwrap_or(api_configuration.agent_jwt_secret.to_string())
+        api_configuration.agent_jwt_lifetime_secs = env::var("API_AGENT_JWT_LIFETIME_SECS")
+            .unwrap_or(api_configuration.agent_jwt_lifetime_secs.to_string())
+            .parse()
+            .unwrap_or(api_configuration.agent_jwt_lifetime_secs);
+
+        api_configuration.agent_command_timeout_secs = env::var("API_AGENT_COMMAND_TIMEOUT_SECS")
+            .unwrap_or(api_configuration.agent_command_timeout_secs.to_string())
+            .parse()
+            .unwrap_or(api_configuration.agent_command_timeout_secs);
+
+        api_configuration.agent_command_max_timeout_secs =
+            env::var("API_AGENT_COMMAND_MAX_TIMEOUT_SECS")
+                .unwrap_or(api_configuration.agent_command_max_timeout_secs.to_string())
+                .parse()
+                .unwrap_or(api_configuration.agent_command_max_timeout_secs);
+
+        api_configuration.agent_revocation_check_interval =
+            env::var("API_AGENT_REVOCATION_CHECK_INTERVAL")
+                .unwrap_or(
+                    api_configuration
+                        .agent_revocation_check_interval
+                        .to_string(),
+                )
+                .parse()
+                .unwrap_or(api_configuration.agent_revocation_check_interval);
+
+        api_configuration.agent_connect_max_failures = env::var("API_AGENT_CONNECT_MAX_FAILURES")
+            .unwrap_or(api_configuration.agent_connect_max_failures.to_string())
+            .parse()
+            .unwrap_or(api_configuration.agent_connect_max_failures);
+
+        api_configuration.agent_connect_max_failures_per_ip =
+            env::var("API_AGENT_CONNECT_MAX_FAILURES_PER_IP")
+                .unwrap_or(
+                    api_configuration
+                        .agent_connect_max_failures_per_ip
+                        .to_string(),
+                )
+                .parse()
+                .unwrap_or(api_configuration.agent_connect_max_failures_per_ip);
+
+        api_configuration.agent_connect_failure_window_secs =
+            env::var("API_AGENT_CONNECT_FAILURE_WINDOW_SECS")
+                .unwrap_or(
+                    api_configuration
+                        .agent_connect_failure_window_secs
+                        .to_string(),
+                )
+                .parse()
+                .unwrap_or(api_configuration.agent_connect_failure_window_secs);
+
+        api_configuration.agent_connect_ban_secs = env::var("API_AGENT_CONNECT_BAN_SECS")
+            .unwrap_or(api_configuration.agent_connect_ban_secs.to_string())
+            .parse()
+            .unwrap_or(api_configuration.agent_connect_ban_secs);
+
+        api_configuration.agent_connect_alert_bans = env::var("API_AGENT_CONNECT_ALERT_BANS")
+            .unwrap_or(api_configuration.agent_connect_alert_bans.to_string())
+            .parse()
+            .unwrap_or(api_configuration.agent_connect_alert_bans);
+
+        api_configuration.agent_jwt_secret =
+            load_secret("API_AGENT_JWT_SECRET").unwrap_or(api_configuration.agent_jwt_secret);
+
+        api_configuration.server_jwt_secret =
+            load_secret("API_SERVER_JWT_SECRET").unwrap_or(api_configuration.server_jwt_secret);
+
+        api_configuration.jwt_signing_key_file = env::var("API_JWT_SIGNING_KEY_FILE")
+            .ok()
+            .filter(|file| !file.trim().is_empty());
+
+        if let Ok(files) = env::var("API_JWT_VERIFICATION_KEY_FILES") {
+            api_configuration.jwt_verification_key_files = split_list(&files);
+        }
+
+        api_configuration.command_signing_key_file = env::var("API_COMMAND_SIGNING_KEY_FILE")
+            .ok()
+            .filter(|file| !file.trim().is_empty());
+
+        api_configuration.mtls_mode = match env::var("API_MTLS_MODE").as_deref() {
+            Ok("optional") => MtlsMode::Optional,
+            Ok("required") => MtlsMode::Required,
+            _ => MtlsMode::Disabled,
+        };
+
+        api_configuration.mtls_ca_file = env::var("API_MTLS_CA_FILE")
+            .ok()
+            .filter(|file| !file.trim().is_empty());
+
+        api_configuration.agent_certificate_lifetime_days =
+            env::var("API_AGENT_CERTIFICATE_LIFETIME_DAYS")
+                .unwrap_or(
+                    api_configuration
+                        .agent_certificate_lifetime_days
+                        .to_string(),
+                )
+                .parse()
+                .unwrap_or(api_configuration.agent_certificate_lifetime_days);
+
+        api_configuration.session_jwt_lifetime_secs = env::var("API_SESSION_JWT_LIFETIME_SECS")
+            .unwrap_or(api_configuration.session_jwt_lifetime_secs.to_string())
             .parse()
-            .unwrap_or(api_configuration.agent_jwt_secret);
+            .unwrap_or(api_configuration.session_jwt_lifetime_secs);
 
-        api_configuration.server_jwt_secret = env::var("API_SERVER_JWT_SECRET")
-            .unwrap_or(api_configuration.server_jwt_secret.to_string())
+        api_configuration.refresh_token_lifetime_secs = env::var("API_REFRESH_TOKEN_LIFETIME_SECS")
+            .unwrap_or(api_configuration.refresh_token_lifetime_secs.to_string())
             .parse()
-            .unwrap_or(api_configuration.server_jwt_secret);
+            .unwrap_or(api_configuration.refresh_token_lifetime_secs);
+
+        api_configuration.bootstrap_admin_username = env::var("API_BOOTSTRAP_ADMIN_USERNAME")
+            .ok()
+            .filter(|username| !username.trim().is_empty())
+            .unwrap_or(api_configuration.bootstrap_admin_username);
+
+        api_configuration.bootstrap_admin_password =
+            load_secret("API_BOOTSTRAP_ADMIN_PASSWORD").filter(|password| !password.is_empty());
+
+        api_configuration.bootstrap_tenant = env::var("API_BOOTSTRAP_TENANT")
+            .ok()
+            .filter(|tenant| !tenant.trim().is_empty())
+            .unwrap_or(api_configuration.bootstrap_tenant);
 
         api_configuration
     }
@@ -131,6 +295,11 @@ impl LoadCorsConfiguration for EnvServerConfigLoader {
             .unwrap_or(3600);
         cors_configuration.allowed_headers = Self::parse_list_from_env("CORS_ALLOWED_HEADERS");
         cors_configuration.allowed_methods = Self::parse_list_from_env("CORS_ALLOWED_METHODS");
+        cors_configuration.tenant_origins_refresh_secs =
+            env::var("CORS_TENANT_ORIGINS_REFRESH_SECS")
+                .unwrap_or(cors_configuration.tenant_origins_refresh_secs.to_string())
+                .parse()
+                .unwrap_or(cors_configuration.tenant_origins_refresh_secs);
 
         cors_configuration
     }
@@ -163,11 +332,97 @@ impl LoadRateLimitingConfiguration for EnvServerConfigLoader {
             .unwrap_or("60".to_owned())
             .parse()
             .unwrap_or(60);
+        rate_limiting.key = rate_limit_key("RATE_LIMITING_KEY", rate_limiting.key);
+
+        // Every request from a client IP, before its credentials are checked
+        rate_limiting.client_ip_burst_size = env::var("RATE_LIMITING_CLIENT_IP_BURST_SIZE")
+            .unwrap_or("400".to_owned())
+            .parse()
+            .unwrap_or(400);
+        rate_limiting.client_ip_per_second = env::var("RATE_LIMITING_CLIENT_IP_PER_SECOND")
+            .unwrap_or("1".to_owned())
+            .parse()
+            .unwrap_or(1);
+
+        // Login, session refresh, agent tokens and API keys
+        let token_issuance = &mut rate_limiting.token_issuance;
+        token_issuance.burst_size = env::var("RATE_LIMITING_TOKEN_BURST_SIZE")
+            .unwrap_or("10".to_owned())
+            .parse()
+            .unwrap_or(10);
+        token_issuance.per_second = env::var("RATE_LIMITING_TOKEN_PER_SECOND")
+            .unwrap_or("6".to_owned())
+            .parse()
+            .unwrap_or(6);
+        token_issuance.key = rate_limit_key("RATE_LIMITING_TOKEN_KEY", token_issuance.key);
+
+        // Commands sent to agents
+        let agent_commands = &mut rate_limiting.agent_commands;
+        agent_commands.burst_size = env::var("RATE_LIMITING_COMMAND_BURST_SIZE")
+            .unwrap_or("50".to_owned())
+            .parse()
+            .unwrap_or(50);
+        agent_commands.per_second = env::var("RATE_LIMITING_COMMAND_PER_SECOND")
+            .unwrap_or("1".to_owned())
+            .parse()
+            .unwrap_or(1);
+        agent_commands.key = rate_limit_key("RATE_LIMITING_COMMAND_KEY", agent_commands.key);
 
         rate_limiting
     }
 }
 
+// One of ip, tenant or credential - anything else keeps the default
+fn rate_limit_key(env_var: &str, default: RateLimitKey) -> RateLimitKey {
+    match env::var(env_var).as_deref() {
+        Ok("ip") => RateLimitKey::Ip,
+        Ok("tenant") => RateLimitKey::Tenant,
+        Ok("credential") => RateLimitKey::Credential,
+        _ => default,
+    }
+}
+
+// Secrets are looked up in order from:
+// - the variable itself e.g. API_AGENT_JWT_SECRET
+// - a file named by the variable with a _FILE suffix e.g. API_AGENT_JWT_SECRET_FILE
+// - a systemd credential of the same name in $CREDENTIALS_DIRECTORY
+// - a Docker secret named after the lowercased variable in /run/secrets
+fn load_secret(env_var: &str) -> Option<String> {
+    if let Ok(secret) = env::var(env_var) {
+        return Some(secret);
+    }
+
+    let mut candidates = vec![];
+    if let Ok(file) = env::var(format!("{}_FILE", env_var)) {
+        candidates.push(PathBuf::from(file));
+    }
+    if let Ok(credentials) = env::var("CREDENTIALS_DIRECTORY") {
+        candidates.push(Path::new(&credentials).join(env_var));
+    }
+    candidates.push(Path::new(DOCKER_SECRETS_FOLDER).join(env_var.to_lowercase()));
+
+    for candidate in candidates {
+        if !candidate.exists() {
+            continue;
+        }
+
+        match fs::read_to_string(&candidate) {
+            Ok(secret) if !secret.trim().is_empty() => return Some(secret.trim().to_string()),
+            Ok(_) => println!(
+                "EnvServerConfigLoader WARNING: secret file {} is empty, ignoring it!",
+                candidate.display()
+            ),
+            Err(error) => println!(
+                "EnvServerConfigLoader WARNING: unable to read secret file {} - {}",
+                candidate.display(),
+                error
+            ),
+        }
+    }
+
+    None
+}
+
 fn load_env() {
     // Load any environment variables from a .env file
     match dotenvy::dotenv() {
@@ -177,3 +432,12 @@ fn load_env() {
         }
     }
 }
+
+// Comma separated values, ignoring blanks
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
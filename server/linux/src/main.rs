@@ -3,13 +3,22 @@ use config_server::{
     LoadRateLimitingConfiguration,
 };
 use ractor::Actor;
+use rand::{distributions::Alphanumeric, Rng};
 use runtime_server::{RuntimeController, RuntimeControllerArguments};
 use runtime_shared::RuntimeProperties;
 use server_config_loaders::env_loader::EnvServerConfigLoader;
 use tokio::signal;
 
+const GENERATED_SECRET_LENGTH: usize = 96;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Print a fresh set of secrets rather than starting the server
+    if std::env::args().nth(1).as_deref() == Some("generate-secrets") {
+        generate_secrets();
+        return Ok(());
+    }
+
     // Initialise the rumtime properties we will be leveraging
     RuntimeProperties::init("Asseme");
 
@@ -17,6 +26,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Load all configs using type inference
     let api_config = LoadApiConfiguration::load_config(&env_loader);
+
+    // Never run production with secrets anyone can read from the source
+    let insecure_secrets = api_config.insecure_secrets();
+    for problem in &insecure_secrets {
+        eprintln!("WARNING: {}", problem);
+    }
+    if api_config.production_mode && !insecure_secrets.is_empty() {
+        return Err(
+            "refusing to start in production mode with insecure secrets, run `generate-secrets` to create new ones".into(),
+        );
+    }
     let cors_config = LoadCorsConfiguration::load_config(&env_loader);
     let logging_config = LoadLoggingConfiguration::load_config(&env_loader);
     let rate_limit_config = LoadRateLimitingConfiguration::load_config(&env_loader);
@@ -44,3 +64,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+// Output in .env format so it can be redirected straight into a file
+fn generate_secrets() {
+    for name in ["API_AGENT_JWT_SECRET", "API_SERVER_JWT_SECRET"] {
+        let secret: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(GENERATED_SECRET_LENGTH)
+            .map(char::from)
+            .collect();
+        println!("{}={}", name, secret);
+    }
+}